@@ -0,0 +1,142 @@
+//! Arpeggiator module.
+//!
+//! turns the set of held keys into a sequence of single notes. it only
+//! produces note on/off events, the instrument decides how they sound.
+//!
+
+use crate::input::NoteKey;
+use crate::audio::transport::swung_step;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum ArpMode { Up, Down, UpDown, Random }
+
+impl ArpMode {
+    pub fn next(self) -> ArpMode {
+        match self {
+            ArpMode::Up => ArpMode::Down,
+            ArpMode::Down => ArpMode::UpDown,
+            ArpMode::UpDown => ArpMode::Random,
+            ArpMode::Random => ArpMode::Up,
+        }
+    }
+}
+
+// `rate` is in steps per beat, `gate` the fraction of a step a note is held.
+// the tempo and the swing are the transport's.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct ArpSettings { pub mode: ArpMode, pub rate: f32, pub gate: f32 }
+
+impl Default for ArpSettings {
+    fn default() -> Self { ArpSettings { mode: ArpMode::Up, rate: 4.0, gate: 0.5 } }
+}
+
+pub struct Arpeggiator {
+    pub settings: ArpSettings,
+    pub enabled: bool,
+    step: usize,
+    next_step: f32,
+    playing: Option<(NoteKey, f32)>,
+}
+
+impl Arpeggiator {
+    pub fn new(settings: ArpSettings) -> Arpeggiator {
+        Arpeggiator { settings, enabled: false, step: 0, next_step: 0.0, playing: None }
+    }
+
+    pub fn step_length(&self, bpm: f32) -> f32 { 60.0 / bpm.max(1.0) / self.settings.rate.max(0.01) }
+
+    // the note currently held by the arpeggiator, if any.
+    pub fn playing(&self) -> Option<NoteKey> { self.playing.map(|(k, _)| k) }
+
+    /// Advances the arpeggiator to `now` (seconds), at `bpm`, every second
+    /// step delayed by `swing` like the drum steps.
+    ///
+    /// `held` are the held keys sorted from lowest to highest pitch. returns
+    /// the note to release and the note to press, in that order.
+    pub fn tick(&mut self, now: f32, bpm: f32, swing: f32, held: &[NoteKey]) -> (Option<NoteKey>, Option<NoteKey>) {
+        let mut off = None;
+        if let Some((key, off_time)) = self.playing {
+            if now >= off_time || held.is_empty() {
+                off = Some(key);
+                self.playing = None;
+            }
+        }
+
+        if held.is_empty() {
+            // start over, right away, on the next press.
+            self.step = 0;
+            self.next_step = now;
+            return (off, None);
+        }
+        if now < self.next_step { return (off, None); }
+
+        let len = self.step_length(bpm);
+        // an on-beat step lasts longer by the swing, the off-beat one after
+        // it shorter by as much.
+        let step = len * (swung_step(self.step + 1, swing) - swung_step(self.step, swing)) as f32;
+        // don't try to catch up on steps missed by a late tick.
+        self.next_step = if now - self.next_step > len { now + step } else { self.next_step + step };
+
+        let key = self.pick(held);
+        if let Some((prev, _)) = self.playing.take() { off = Some(prev); }
+        self.playing = Some((key, now + step * self.settings.gate.clamp(0.05, 1.0)));
+        (off, Some(key))
+    }
+
+    fn pick(&mut self, held: &[NoteKey]) -> NoteKey {
+        let n = held.len();
+        let i = match self.settings.mode {
+            ArpMode::Up => self.step % n,
+            ArpMode::Down => n - 1 - self.step % n,
+            ArpMode::UpDown if n == 1 => 0,
+            ArpMode::UpDown => {
+                let period = 2 * (n - 1);
+                let k = self.step % period;
+                if k < n { k } else { period - k }
+            },
+            ArpMode::Random => rand::thread_rng().gen_range(0..n),
+        };
+        self.step += 1;
+        held[i]
+    }
+}
+
+#[cfg(test)]
+mod arpeggiator_tests {
+    use super::*;
+
+    fn run(mode: ArpMode, held: &[NoteKey], steps: usize) -> Vec<NoteKey> {
+        let mut arp = Arpeggiator::new(ArpSettings { mode, rate: 1.0, gate: 0.5 });
+        (0..steps * 2).filter_map(|i| arp.tick(i as f32 * 0.5, 60.0, 0.0, held).1).collect()
+    }
+
+    #[test]
+    fn test_arp_modes() {
+        let held = [NoteKey::Midi(60), NoteKey::Midi(64), NoteKey::Midi(67)];
+        let [c, e, g] = held;
+        assert_eq!(run(ArpMode::Up, &held, 4), vec![c, e, g, c]);
+        assert_eq!(run(ArpMode::Down, &held, 4), vec![g, e, c, g]);
+        assert_eq!(run(ArpMode::UpDown, &held, 6), vec![c, e, g, e, c, e]);
+    }
+
+    #[test]
+    fn test_arp_gate_releases_note() {
+        let held = [NoteKey::Midi(60)];
+        let mut arp = Arpeggiator::new(ArpSettings { mode: ArpMode::Up, rate: 1.0, gate: 0.5 });
+        assert_eq!(arp.tick(0.0, 60.0, 0.0, &held), (None, Some(held[0])));
+        assert_eq!(arp.tick(0.25, 60.0, 0.0, &held), (None, None));
+        assert_eq!(arp.tick(0.5, 60.0, 0.0, &held), (Some(held[0]), None));
+        assert_eq!(arp.tick(0.6, 60.0, 0.0, &[]), (None, None));
+    }
+
+    #[test]
+    fn test_arp_swing() {
+        let held = [NoteKey::Midi(60), NoteKey::Midi(64)];
+        let mut arp = Arpeggiator::new(ArpSettings { mode: ArpMode::Up, rate: 1.0, gate: 0.5 });
+        // a second a step, the off-beat ones a quarter of a second late.
+        let notes: Vec<f32> = (0..16).map(|i| i as f32 * 0.25).filter(|t| arp.tick(*t, 60.0, 0.5, &held).1.is_some()).collect();
+        assert_eq!(notes, vec![0.0, 1.25, 2.0, 3.25]);
+    }
+}
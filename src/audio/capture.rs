@@ -0,0 +1,60 @@
+//! Capture module.
+//!
+//! records from an input device (microphone, line-in) into a queue that
+//! the output callback drains, so input samples can be played and
+//! processed by the engine like any other source.
+//!
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Host, SampleRate};
+
+// mono input samples, waiting for the output callback.
+pub type InputQueue = Arc<Mutex<VecDeque<f32>>>;
+
+// the most input that can wait in the queue, in seconds. if the output
+// falls behind, the oldest samples are dropped to keep the latency low.
+const MAX_QUEUED: f32 = 0.25;
+
+/// Starts capturing into `queue` at `sample_rate`, mixing the channels of
+/// the device down to mono. the capture stops when the stream is dropped.
+pub fn start_input<E>(host: &Host, device: Option<&str>, sample_rate: u32, queue: InputQueue, err_fn: E) -> Result<(cpal::Stream, String), String>
+where E: FnMut(cpal::StreamError) + Send + 'static {
+    let device = match device {
+        Some(name) => host.input_devices().map_err(|e| e.to_string())?
+            .find(|d| d.name().is_ok_and(|n| n == name))
+            .ok_or(format!("no input device {:?}", name))?,
+        None => host.default_input_device().ok_or("no default input device found")?,
+    };
+    let range = device.supported_input_configs().map_err(|e| e.to_string())?
+        .find(|r| (r.min_sample_rate().0..=r.max_sample_rate().0).contains(&sample_rate))
+        .ok_or(format!("input doesn't support {} Hz", sample_rate))?;
+    let supported = range.with_sample_rate(SampleRate(sample_rate));
+    let config = supported.config();
+    let channels = config.channels.max(1) as usize;
+    let max_queued = (MAX_QUEUED * sample_rate as f32) as usize;
+
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => build_input::<f32, E>(&device, &config, channels, max_queued, queue, err_fn),
+        cpal::SampleFormat::I16 => build_input::<i16, E>(&device, &config, channels, max_queued, queue, err_fn),
+        cpal::SampleFormat::U16 => build_input::<u16, E>(&device, &config, channels, max_queued, queue, err_fn),
+        format => return Err(format!("unsupported input sample format {}", format)),
+    }.map_err(|e| e.to_string())?;
+    stream.play().map_err(|e| e.to_string())?;
+    Ok((stream, device.name().unwrap_or_default()))
+}
+
+fn build_input<T, E>(device: &cpal::Device, config: &cpal::StreamConfig, channels: usize, max_queued: usize, queue: InputQueue,
+    err_fn: E) -> Result<cpal::Stream, cpal::BuildStreamError>
+where T: cpal::SizedSample, f32: cpal::FromSample<T>, E: FnMut(cpal::StreamError) + Send + 'static {
+    device.build_input_stream(config, move |data: &[T], _: &cpal::InputCallbackInfo| {
+        let mut queue = queue.lock().unwrap();
+        for frame in data.chunks(channels) {
+            let sum: f32 = frame.iter().map(|s| s.to_sample::<f32>()).sum();
+            queue.push_back(sum / channels as f32);
+        }
+        let excess = queue.len().saturating_sub(max_queued);
+        queue.drain(..excess);
+    }, err_fn, None)
+}
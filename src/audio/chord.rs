@@ -0,0 +1,107 @@
+//! Chord module.
+//!
+//! chord mode turns every note played into a chord: the intervals of the
+//! chord are pressed on top of it as notes of their own, before the voices
+//! or the arpeggiator see them.
+//!
+
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub enum Chord { #[default] Off, Major, Minor, Seventh, MinorSeventh, Sus4, User }
+
+impl Chord {
+    pub fn name(self) -> &'static str {
+        match self {
+            Chord::Off => "off",
+            Chord::Major => "maj",
+            Chord::Minor => "min",
+            Chord::Seventh => "7",
+            Chord::MinorSeventh => "m7",
+            Chord::Sus4 => "sus4",
+            Chord::User => "user",
+        }
+    }
+}
+
+// the chord played and the stack of the user chord, in semitones above
+// the note played.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ChordSettings { pub chord: Chord, pub user: Vec<u8> }
+
+impl ChordSettings {
+    pub const MAX_INTERVAL: u8 = 36;
+
+    /// Semitones above the note played of the notes added to it.
+    pub fn intervals(&self) -> &[u8] {
+        match self.chord {
+            Chord::Off => &[],
+            Chord::Major => &[4, 7],
+            Chord::Minor => &[3, 7],
+            Chord::Seventh => &[4, 7, 10],
+            Chord::MinorSeventh => &[3, 7, 10],
+            Chord::Sus4 => &[5, 7],
+            Chord::User => &self.user,
+        }
+    }
+
+    // the next chord, the user one only if there is a stack for it.
+    pub fn cycle(&mut self) -> Chord {
+        self.chord = match self.chord {
+            Chord::Off => Chord::Major,
+            Chord::Major => Chord::Minor,
+            Chord::Minor => Chord::Seventh,
+            Chord::Seventh => Chord::MinorSeventh,
+            Chord::MinorSeventh => Chord::Sus4,
+            Chord::Sus4 if !self.user.is_empty() => Chord::User,
+            Chord::Sus4 | Chord::User => Chord::Off,
+        };
+        self.chord
+    }
+}
+
+// a chord by name, or a stack of intervals like `0,4,7,11`. the root is
+// always played, a 0 in the stack is left out.
+impl std::str::FromStr for ChordSettings {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ChordSettings, String> {
+        let named = [Chord::Off, Chord::Major, Chord::Minor, Chord::Seventh, Chord::MinorSeventh, Chord::Sus4];
+        if let Some(chord) = named.into_iter().find(|c| c.name() == s) {
+            return Ok(ChordSettings { chord, user: Vec::new() });
+        }
+        let mut user = Vec::new();
+        for interval in s.split(',').map(str::trim) {
+            match interval.parse::<u8>() {
+                Ok(0) => (),
+                Ok(i) if i <= Self::MAX_INTERVAL => user.push(i),
+                _ => return Err(format!(
+                    "invalid chord '{}', expected off, maj, min, 7, m7, sus4 or semitones up to {} like 0,4,7", s, Self::MAX_INTERVAL,
+                )),
+            }
+        }
+        user.sort_unstable();
+        user.dedup();
+        Ok(ChordSettings { chord: Chord::User, user })
+    }
+}
+
+#[cfg(test)]
+mod chord_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_cycle() {
+        let mut chord: ChordSettings = "min".parse().unwrap();
+        assert_eq!(chord.intervals(), &[3, 7]);
+        let user: ChordSettings = "0, 7,4 ,11".parse().unwrap();
+        assert_eq!((user.chord, user.intervals()), (Chord::User, &[4, 7, 11][..]));
+        assert!("0,4,x".parse::<ChordSettings>().is_err());
+        assert!("0,40".parse::<ChordSettings>().is_err());
+
+        // without a stack the user chord is skipped.
+        let cycle: Vec<Chord> = (0..5).map(|_| chord.cycle()).collect();
+        assert_eq!(cycle, vec![Chord::Seventh, Chord::MinorSeventh, Chord::Sus4, Chord::Off, Chord::Major]);
+        chord.user = vec![2, 7];
+        chord.chord = Chord::Sus4;
+        assert_eq!(chord.cycle(), Chord::User);
+        assert_eq!(chord.intervals(), &[2, 7]);
+    }
+}
@@ -0,0 +1,202 @@
+//! Convolution module.
+//!
+//! uniformly partitioned convolution: the impulse response is cut into
+//! blocks of `BLOCK` samples, each transformed once, and every block of
+//! input is multiplied with all of them in the frequency domain. the output
+//! comes one block late, whatever the length of the impulse response.
+//!
+
+use std::path::Path;
+use std::sync::Arc;
+
+use rustfft::{Fft, FftPlanner};
+use rustfft::num_complex::Complex;
+
+use crate::audio::waves::read_wav_channels;
+
+/// Impulse response of a space, one or two channels at the sample rate it
+/// is convolved at.
+#[derive(Debug, Clone, Default)]
+pub struct ImpulseResponse { pub channels: Vec<Vec<f32>> }
+
+impl ImpulseResponse {
+    // longer impulse responses are cut, the tail of a real room is long
+    // gone by then.
+    pub const MAX_SECONDS: f32 = 10.0;
+
+    /// Reads the first two channels of a wav file, resampled to
+    /// `sample_rate`.
+    pub fn load<P: AsRef<Path>>(path: P, sample_rate: f32) -> Result<ImpulseResponse, hound::Error> {
+        Ok(ImpulseResponse::new(&Recording::read(path)?, sample_rate))
+    }
+
+    /// The recording resampled to `sample_rate`. it is scaled to unit
+    /// energy, so loud and quiet recordings of a space come out at about
+    /// the same level.
+    pub fn new(recording: &Recording, sample_rate: f32) -> ImpulseResponse {
+        let Some(first) = recording.channels.first() else { return ImpulseResponse::default() };
+        let ratio = recording.sample_rate as f32 / sample_rate;
+        let len = ((first.len() as f32 / ratio) as usize).min((ImpulseResponse::MAX_SECONDS * sample_rate) as usize);
+        let mut channels: Vec<Vec<f32>> = recording.channels.iter().map(|c| {
+            let at = |i: usize| c.get(i).copied().unwrap_or(0.0);
+            (0..len).map(|i| {
+                let pos = i as f32 * ratio;
+                at(pos as usize) * (1.0 - pos.fract()) + at(pos as usize + 1) * pos.fract()
+            }).collect()
+        }).collect();
+        let energy = channels.iter().map(|c| c.iter().map(|x| x * x).sum::<f32>()).fold(0.0f32, f32::max);
+        if energy > 0.0 {
+            channels.iter_mut().flatten().for_each(|x| *x /= energy.sqrt());
+        }
+        ImpulseResponse { channels }
+    }
+
+    // channel `c`, the left one for both sides of a mono response.
+    pub fn channel(&self, c: usize) -> &[f32] {
+        self.channels.get(c).or(self.channels.first()).map_or(&[], |c| c.as_slice())
+    }
+}
+
+/// The first two channels of an impulse response as its wav file has
+/// them, at the rate it was recorded at. read once, then made into an
+/// `ImpulseResponse` for whatever rate it is convolved at.
+#[derive(Debug, Clone, Default)]
+pub struct Recording { pub channels: Vec<Vec<f32>>, pub sample_rate: u32 }
+
+impl Recording {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Recording, hound::Error> {
+        let (mut channels, sample_rate) = read_wav_channels(path)?;
+        channels.truncate(2);
+        Ok(Recording { channels, sample_rate })
+    }
+}
+
+/// Convolves a single channel with an impulse response, `BLOCK` samples
+/// late.
+pub struct Convolver {
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    // the spectra of the impulse response blocks, and of as many input
+    // blocks, newest at `newest`.
+    partitions: Vec<Vec<Complex<f32>>>,
+    history: Vec<Vec<Complex<f32>>>,
+    newest: usize,
+    // the previous block of input then the one being filled, and the
+    // output of the previous block.
+    input: Vec<f32>,
+    output: Vec<f32>,
+    pos: usize,
+    sum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl Convolver {
+    pub const BLOCK: usize = 512;
+
+    pub fn new(ir: &[f32]) -> Convolver {
+        let n = 2 * Convolver::BLOCK;
+        let mut planner = FftPlanner::new();
+        let (fft, ifft) = (planner.plan_fft_forward(n), planner.plan_fft_inverse(n));
+        let scratch_len = fft.get_inplace_scratch_len().max(ifft.get_inplace_scratch_len());
+        let mut scratch = vec![Complex::default(); scratch_len];
+        // scaled here rather than after each inverse transform.
+        let partitions: Vec<Vec<Complex<f32>>> = ir.chunks(Convolver::BLOCK).map(|block| {
+            let mut spectrum = vec![Complex::default(); n];
+            spectrum.iter_mut().zip(block).for_each(|(s, x)| s.re = x / n as f32);
+            fft.process_with_scratch(&mut spectrum, &mut scratch);
+            spectrum
+        }).collect();
+        Convolver {
+            history: vec![vec![Complex::default(); n]; partitions.len()],
+            partitions,
+            newest: 0,
+            input: vec![0.0; n],
+            output: vec![0.0; Convolver::BLOCK],
+            pos: 0,
+            sum: vec![Complex::default(); n],
+            scratch,
+            fft,
+            ifft,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.history.iter_mut().for_each(|h| h.fill(Complex::default()));
+        self.input.fill(0.0);
+        self.output.fill(0.0);
+    }
+
+    pub fn tick(&mut self, x: f32) -> f32 {
+        self.input[Convolver::BLOCK + self.pos] = x;
+        let y = self.output[self.pos];
+        self.pos += 1;
+        if self.pos == Convolver::BLOCK {
+            self.pos = 0;
+            self.run_block();
+        }
+        y
+    }
+
+    // overlap-save: the last half of the circular convolution of the last
+    // two input blocks is the output of the newest one.
+    fn run_block(&mut self) {
+        let (b, count) = (Convolver::BLOCK, self.partitions.len());
+        if count > 0 {
+            self.newest = (self.newest + count - 1) % count;
+            let spectrum = &mut self.history[self.newest];
+            spectrum.iter_mut().zip(&self.input).for_each(|(s, x)| *s = Complex::new(*x, 0.0));
+            self.fft.process_with_scratch(spectrum, &mut self.scratch);
+
+            self.sum.fill(Complex::default());
+            for (k, partition) in self.partitions.iter().enumerate() {
+                let past = &self.history[(self.newest + k) % count];
+                self.sum.iter_mut().zip(past).zip(partition).for_each(|((s, x), h)| *s += x * h);
+            }
+            self.ifft.process_with_scratch(&mut self.sum, &mut self.scratch);
+            self.output.iter_mut().zip(&self.sum[b..]).for_each(|(y, s)| *y = s.re);
+        }
+        self.input.copy_within(b.., 0);
+    }
+}
+
+#[cfg(test)]
+mod convolution_tests {
+    use super::*;
+
+    #[test]
+    fn test_partitioned_convolution() {
+        // long enough for a few partitions, against the direct sum.
+        let ir: Vec<f32> = (0..1300).map(|i| ((i * 7919) % 101) as f32 / 50.0 - 1.0).collect();
+        let input: Vec<f32> = (0..3000).map(|i| ((i * 104729) % 67) as f32 / 33.0 - 1.0).collect();
+        let mut convolver = Convolver::new(&ir);
+        let out: Vec<f32> = input.iter().map(|x| convolver.tick(*x)).collect();
+        for i in (0..input.len() - Convolver::BLOCK).step_by(37) {
+            let direct: f32 = ir.iter().enumerate().filter(|(k, _)| *k <= i).map(|(k, h)| h * input[i - k]).sum();
+            assert!((out[i + Convolver::BLOCK] - direct).abs() < 1e-3, "{} {} {}", i, out[i + Convolver::BLOCK], direct);
+        }
+        // with nothing to convolve with, silence.
+        let mut empty = Convolver::new(&[]);
+        assert!((0..2000).all(|_| empty.tick(1.0) == 0.0));
+    }
+
+    #[test]
+    fn test_impulse_response_from_wav() {
+        let path = std::env::temp_dir().join(format!("rsynth-ir-{}.wav", std::process::id()));
+        let spec = hound::WavSpec { channels: 2, sample_rate: 2000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        // left a decaying pair, right half as loud.
+        for s in [16384, 8192, 8192, 4096, 0, 0] { writer.write_sample(s as i16).unwrap(); }
+        writer.finalize().unwrap();
+
+        // twice the sample rate, twice the samples, the louder side at unit
+        // energy.
+        let ir = ImpulseResponse::load(&path, 4000.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ir.channels.len(), 2);
+        assert_eq!(ir.channel(0).len(), 6);
+        let energy = |c: &[f32]| c.iter().map(|x| x * x).sum::<f32>();
+        assert!((energy(ir.channel(0)) - 1.0).abs() < 1e-5);
+        assert!((ir.channel(0)[1] - 2.0 * ir.channel(1)[1]).abs() < 1e-6);
+        assert!((ir.channel(0)[1] - (ir.channel(0)[0] + ir.channel(0)[2]) / 2.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,87 @@
+//! Device module.
+//!
+//! picks the output device and stream config asked for at startup. a choice
+//! that isn't available falls back to the default, with a warning.
+//!
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{BufferSize, Device, Host, SampleRate, StreamConfig, SupportedBufferSize, SupportedStreamConfig};
+
+// `None` leaves the choice to the host.
+#[derive(Debug, Default, Clone)]
+pub struct AudioConfig {
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    // capture from an input device, the default one when no name is given.
+    pub input: bool,
+    pub input_device: Option<String>,
+    // plays through jack instead of the device, see `jack`.
+    #[cfg(feature = "jack")]
+    pub jack: Option<crate::audio::jack::JackConfig>,
+}
+
+pub struct OutputSelection {
+    pub device: Device,
+    pub supported: SupportedStreamConfig,
+    pub config: StreamConfig,
+    // choices that couldn't be honoured.
+    pub warnings: Vec<String>,
+}
+
+/// One line per output device and supported config, for `--list-devices`.
+pub fn describe_output_devices(host: &Host) -> Result<Vec<String>, String> {
+    let default = host.default_output_device().and_then(|d| d.name().ok());
+    let mut lines = Vec::new();
+    for device in host.output_devices().map_err(|e| e.to_string())? {
+        let name = device.name().unwrap_or_default();
+        let marker = if Some(&name) == default.as_ref() { " (default)" } else { "" };
+        lines.push(format!("{}{}", name, marker));
+        for range in device.supported_output_configs().into_iter().flatten() {
+            let buffer = match range.buffer_size() {
+                SupportedBufferSize::Range { min, max } => format!("buffer {}-{}", min, max),
+                SupportedBufferSize::Unknown => String::from("buffer ?"),
+            };
+            lines.push(format!("  {} ch {} {}-{} Hz {}",
+                range.channels(), range.sample_format(), range.min_sample_rate().0, range.max_sample_rate().0, buffer));
+        }
+    }
+    Ok(lines)
+}
+
+pub fn select_output(host: &Host, wanted: &AudioConfig) -> Result<OutputSelection, String> {
+    let mut warnings = Vec::new();
+    let named = wanted.device.as_ref().and_then(|name| {
+        let found = host.output_devices().ok()?.find(|d| d.name().is_ok_and(|n| n == *name));
+        if found.is_none() { warnings.push(format!("no output device {:?}, using the default", name)); }
+        found
+    });
+    let device = match named {
+        Some(device) => device,
+        None => host.default_output_device().ok_or("no default output device found")?,
+    };
+
+    let ranges: Vec<_> = device.supported_output_configs().map_err(|e| e.to_string())?.collect();
+    let first = ranges.first().ok_or("no supported output config")?.clone();
+    let supported = match wanted.sample_rate {
+        Some(rate) => match ranges.iter().find(|r| (r.min_sample_rate().0..=r.max_sample_rate().0).contains(&rate)) {
+            Some(range) => range.clone().with_sample_rate(SampleRate(rate)),
+            None => {
+                warnings.push(format!("{} Hz is not supported, using {} Hz", rate, first.max_sample_rate().0));
+                first.with_max_sample_rate()
+            }
+        },
+        None => first.with_max_sample_rate(),
+    };
+
+    let mut config = supported.config();
+    if let Some(frames) = wanted.buffer_size {
+        match supported.buffer_size() {
+            SupportedBufferSize::Range { min, max } if !(*min..=*max).contains(&frames) => {
+                warnings.push(format!("buffer size {} is outside {}-{}, using the default", frames, min, max));
+            },
+            _ => config.buffer_size = BufferSize::Fixed(frames),
+        }
+    }
+    Ok(OutputSelection { device, supported, config, warnings })
+}
@@ -0,0 +1,526 @@
+//! Drums module.
+//!
+//! one-shot samples on pads, hit from a row of keys or by a step pattern.
+//! pads ignore the envelope and note releases, a hit plays to the end of
+//! its sample unless a pad of the same choke group cuts it.
+//!
+//! a kit holds several patterns. one loops on its own, or in song mode
+//! they play in the order of the song, each repeated a number of times.
+//!
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::audio::sampler::Sample;
+use crate::audio::waves::Loaded;
+use crate::audio::transport::swung_step;
+
+// bottom row of the keyboard, one pad per key.
+pub const DRUM_KEYS: &str = "zxcvbnm,./";
+// midi note of the first pad, the general midi kick.
+pub const FIRST_PAD_NOTE: u8 = 36;
+
+fn unity() -> f32 { 1.0 }
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DrumPad {
+    pub path: PathBuf,
+    #[serde(default = "unity")]
+    pub gain: f32,
+    // hitting a pad stops the other pads of its group, like a closed hat
+    // cutting an open one.
+    #[serde(default)]
+    pub choke: Option<u8>,
+    #[serde(skip)]
+    pub sample: Loaded<Sample>,
+}
+
+impl DrumPad {
+    // decodes the wav file of `path`. keeps the last one decoded if it fails.
+    pub fn read(&mut self) -> Result<(), hound::Error> {
+        self.sample = Loaded(Some(Arc::new(Sample::load(&self.path)?)));
+        Ok(())
+    }
+}
+
+// `rate` is in steps per beat, `steps` holds one row of hits per pad.
+// `probability` is the chance in percent each hit plays and `ratchets` how
+// many times it retriggers within its step, in rows like the hits. steps
+// missing from them play always, once.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Pattern {
+    pub length: usize,
+    pub rate: f32,
+    pub steps: Vec<Vec<bool>>,
+    #[serde(default)]
+    pub probability: Vec<Vec<u8>>,
+    #[serde(default)]
+    pub ratchets: Vec<Vec<u8>>,
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Pattern { length: 16, rate: 4.0, steps: Vec::new(), probability: Vec::new(), ratchets: Vec::new() }
+    }
+}
+
+// sets a value in rows of steps, growing them with `fill` as needed.
+fn set_step<T: Copy>(rows: &mut Vec<Vec<T>>, pad: usize, step: usize, length: usize, fill: T, value: T) {
+    if step >= length { return; }
+    if rows.len() <= pad { rows.resize(pad + 1, Vec::new()); }
+    let row = &mut rows[pad];
+    if row.len() < length { row.resize(length, fill); }
+    row[step] = value;
+}
+
+impl Pattern {
+    pub const MAX_RATCHETS: u8 = 8;
+
+    pub fn get(&self, pad: usize, step: usize) -> bool {
+        self.steps.get(pad).and_then(|row| row.get(step)).copied().unwrap_or(false)
+    }
+
+    pub fn set(&mut self, pad: usize, step: usize, on: bool) { set_step(&mut self.steps, pad, step, self.length, false, on) }
+
+    pub fn probability(&self, pad: usize, step: usize) -> u8 {
+        self.probability.get(pad).and_then(|row| row.get(step)).copied().unwrap_or(100).min(100)
+    }
+
+    pub fn set_probability(&mut self, pad: usize, step: usize, percent: u8) {
+        set_step(&mut self.probability, pad, step, self.length, 100, percent.min(100))
+    }
+
+    pub fn ratchet(&self, pad: usize, step: usize) -> u8 {
+        self.ratchets.get(pad).and_then(|row| row.get(step)).copied().unwrap_or(1).clamp(1, Pattern::MAX_RATCHETS)
+    }
+
+    pub fn set_ratchet(&mut self, pad: usize, step: usize, count: u8) {
+        set_step(&mut self.ratchets, pad, step, self.length, 1, count.clamp(1, Pattern::MAX_RATCHETS))
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+        self.probability.clear();
+        self.ratchets.clear();
+    }
+}
+
+// a pattern of the kit, by number, played `repeats` times over.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct SongEntry { pub pattern: usize, pub repeats: u32 }
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct DrumKit {
+    pub pads: Vec<DrumPad>,
+    // the first pattern, and the ones after it numbered from 1.
+    #[serde(default)]
+    pub pattern: Pattern,
+    #[serde(default)]
+    pub patterns: Vec<Pattern>,
+    #[serde(default)]
+    pub song: Vec<SongEntry>,
+}
+
+impl DrumKit {
+    pub const MAX_PATTERNS: usize = 16;
+
+    pub fn pattern_count(&self) -> usize { self.patterns.len() + 1 }
+
+    pub fn pattern(&self, index: usize) -> Option<&Pattern> {
+        match index {
+            0 => Some(&self.pattern),
+            i => self.patterns.get(i - 1),
+        }
+    }
+
+    pub fn pattern_mut(&mut self, index: usize) -> Option<&mut Pattern> {
+        match index {
+            0 => Some(&mut self.pattern),
+            i => self.patterns.get_mut(i - 1),
+        }
+    }
+
+    // beats an entry of the song lasts, none for a pattern that isn't there.
+    fn entry_beats(&self, entry: &SongEntry) -> Option<f64> {
+        let pattern = self.pattern(entry.pattern)?;
+        Some(pattern.length as f64 * entry.repeats as f64 / pattern.rate.max(0.01) as f64)
+    }
+
+    /// The pattern the song plays at `beat`, and the beat it started on.
+    /// the song loops once over.
+    pub fn song_position(&self, beat: f64) -> Option<(usize, f64)> {
+        let total: f64 = self.song.iter().filter_map(|e| self.entry_beats(e)).sum();
+        if total <= 0.0 { return None; }
+        let top = beat - beat.rem_euclid(total);
+        let mut start = top;
+        for entry in &self.song {
+            let Some(beats) = self.entry_beats(entry) else { continue };
+            if beats > 0.0 && beat < start + beats { return Some((entry.pattern, start)); }
+            start += beats;
+        }
+        None
+    }
+}
+
+// `wait` is the samples to go before a hit due later in the block starts.
+struct Hit { pad: usize, pos: f32, gain: f32, wait: usize }
+
+pub struct DrumMachine {
+    kit: DrumKit,
+    samples: Vec<Arc<Sample>>,
+    hits: Vec<Hit>,
+    // pads hit while writing are added to the pattern.
+    pub writing: bool,
+    // playing the song rather than looping the current pattern.
+    pub song_mode: bool,
+    // the pattern edited and looped, and the one that played last.
+    current: usize,
+    playing: usize,
+    step: usize,
+    // the step each pad last rolled its probability for, and whether it
+    // passed, so the ratchets of a step that didn't play stay quiet.
+    rolled: Vec<(usize, bool)>,
+    // the last hit written, which probability and ratchets are set on.
+    written: Option<(usize, usize)>,
+}
+
+impl Default for DrumMachine { fn default() -> Self { Self::new() } }
+
+impl DrumMachine {
+    pub fn new() -> DrumMachine {
+        DrumMachine {
+            kit: DrumKit::default(), samples: Vec::new(), hits: Vec::with_capacity(32),
+            writing: false, song_mode: false, current: 0, playing: 0, step: 0,
+            rolled: Vec::with_capacity(16), written: None,
+        }
+    }
+
+    // pads not read are decoded here.
+    pub fn load(kit: &DrumKit) -> Result<DrumMachine, hound::Error> {
+        let samples = kit.pads.iter()
+            .map(|p| p.sample.0.clone().map_or_else(|| Sample::load(&p.path).map(Arc::new), Ok))
+            .collect::<Result<_, _>>()?;
+        Ok(DrumMachine { kit: kit.clone(), samples, ..DrumMachine::new() })
+    }
+
+    pub fn kit(&self) -> &DrumKit { &self.kit }
+
+    pub fn current(&self) -> usize { self.current }
+    pub fn pattern_mut(&mut self) -> &mut Pattern {
+        match self.current {
+            0 => &mut self.kit.pattern,
+            i => &mut self.kit.patterns[i - 1],
+        }
+    }
+
+    pub fn pattern_mut_at(&mut self, index: usize) -> Option<&mut Pattern> { self.kit.pattern_mut(index) }
+
+    // moves on to the pattern `index`, adding empty ones up to it.
+    pub fn select_pattern(&mut self, index: usize) {
+        let index = index.min(DrumKit::MAX_PATTERNS - 1);
+        if index >= self.kit.pattern_count() { self.kit.patterns.resize(index, Pattern::default()); }
+        self.current = index;
+    }
+
+    /// Adds the current pattern to the end of the song, or repeats it once
+    /// more if it is the last one already. returns the entry.
+    pub fn chain_pattern(&mut self) -> SongEntry {
+        match self.kit.song.last_mut() {
+            Some(last) if last.pattern == self.current => last.repeats += 1,
+            _ => self.kit.song.push(SongEntry { pattern: self.current, repeats: 1 }),
+        }
+        self.kit.song[self.kit.song.len() - 1]
+    }
+
+    pub fn clear_song(&mut self) { self.kit.song.clear() }
+
+    // the pattern playing at `beat`, and the beat it started on.
+    fn pattern_at(&self, beat: f64) -> (usize, f64) {
+        match self.song_mode {
+            true => self.kit.song_position(beat).unwrap_or((self.current, 0.0)),
+            false => (self.current, 0.0),
+        }
+    }
+
+    // the pattern that played the last step.
+    pub fn playing(&self) -> usize { self.playing }
+    pub fn pads(&self) -> usize { self.samples.len() }
+
+    pub fn pad_for_key(c: char) -> Option<usize> { DRUM_KEYS.chars().position(|k| k == c) }
+    pub fn pad_for_note(note: u8) -> Option<usize> { note.checked_sub(FIRST_PAD_NOTE).map(|p| p as usize) }
+
+    // the step about to play.
+    pub fn step(&self) -> usize { self.step }
+
+    pub fn trigger(&mut self, pad: usize, velocity: f32) { self.trigger_after(pad, velocity, 0) }
+
+    // a hit `wait` samples on. it cuts the other pads of its choke group
+    // once it starts.
+    fn trigger_after(&mut self, pad: usize, velocity: f32, wait: usize) {
+        let Some(settings) = self.kit.pads.get(pad) else { return };
+        self.hits.push(Hit { pad, pos: 0.0, gain: settings.gain * velocity, wait });
+        if wait == 0 { self.choke(); }
+    }
+
+    // the hits starting cut the ones sounding in their choke groups.
+    fn choke(&mut self) {
+        let pads = &self.kit.pads;
+        let starting = |h: &Hit| h.wait == 0 && h.pos == 0.0;
+        let mut cut = [false; 256];
+        for h in self.hits.iter().filter(|h| starting(h)) {
+            if let Some(group) = pads[h.pad].choke { cut[group as usize] = true; }
+        }
+        self.hits.retain(|h| h.wait > 0 || starting(h) || !pads[h.pad].choke.is_some_and(|g| cut[g as usize]));
+    }
+
+    /// Hits a pad by hand. when writing, it goes to the step of the
+    /// pattern closest to `beat`, the position of the transport while it
+    /// plays.
+    pub fn hit(&mut self, pad: usize, velocity: f32, beat: Option<f64>) {
+        self.trigger(pad, velocity);
+        let Some(beat) = beat.filter(|_| self.writing) else { return };
+        let (index, start) = self.pattern_at(beat);
+        let Some(pattern) = self.kit.pattern_mut(index) else { return };
+        // past the middle of the step, it belongs to the next one.
+        let step = ((beat - start) * pattern.rate.max(0.01) as f64).round() as usize % pattern.length.max(1);
+        pattern.set(pad, step, true);
+        self.written = Some((pad, step));
+    }
+
+    // the pad and step of the last hit written to the current pattern.
+    pub fn written(&self) -> Option<(usize, usize)> { self.written }
+
+    /// Steps the probability of the last hit written down by a quarter,
+    /// from always back to always after 25%. returns the new one.
+    pub fn cycle_probability(&mut self) -> Option<u8> {
+        let (pad, step) = self.written?;
+        let pattern = self.pattern_mut();
+        let percent = match pattern.probability(pad, step) { 0..=25 => 100, p => p - 25 };
+        pattern.set_probability(pad, step, percent);
+        Some(percent)
+    }
+
+    // the same for the ratchets of the last hit, up to four.
+    pub fn cycle_ratchet(&mut self) -> Option<u8> {
+        let (pad, step) = self.written?;
+        let pattern = self.pattern_mut();
+        let count = pattern.ratchet(pad, step) % 4 + 1;
+        pattern.set_ratchet(pad, step, count);
+        Some(count)
+    }
+
+    /// Plays the step of the pattern that falls between the beats `from`
+    /// and `to`, steps being `rate` to a beat from the top of the pattern
+    /// and the off-beat ones delayed by `swing`. in song mode, the pattern
+    /// and its top come from the song.
+    ///
+    /// each hit starts as many samples into the next block as it fell
+    /// after `from`, `samples_per_beat` to a beat, like the notes played.
+    pub fn tick(&mut self, from: f64, to: f64, swing: f32, samples_per_beat: f64) {
+        let (index, start) = self.pattern_at(to);
+        let Some(pattern) = self.kit.pattern(index) else { return };
+        let rate = pattern.rate.max(0.01) as f64;
+        let length = pattern.length.max(1);
+        let samples_per_step = samples_per_beat / rate;
+        // in steps from the top. a tick across the top of the pattern
+        // starts from its first step.
+        let (from, to) = (((from - start) * rate).max(0.0), (to - start) * rate);
+        if to <= from { return; }
+        let mut step = to.ceil() as usize - 1;
+        if step > 0 && swung(step, length, swing) >= to { step -= 1; }
+        // the last ratchets of the step before can fall in this tick too,
+        // but don't try to catch up on steps missed by a late tick.
+        if step > 0 { self.play_step(index, step - 1, (from, to), swing, false, samples_per_step); }
+        self.play_step(index, step, (from, to), swing, true, samples_per_step);
+        self.playing = index;
+        self.step = (step + 1) % length;
+    }
+
+    // the hits of `step` of the pattern `index` that fall between the steps
+    // `from` and `to`, each retriggered by its ratchets over the step.
+    fn play_step(&mut self, index: usize, step: usize, (from, to): (f64, f64), swing: f32, first: bool, samples_per_step: f64) {
+        let Some(length) = self.kit.pattern(index).map(|p| p.length.max(1)) else { return };
+        let at = step % length;
+        let (start, end) = (swung(step, length, swing), swung(step + 1, length, swing));
+        if self.rolled.len() < self.kit.pads.len() { self.rolled.resize(self.kit.pads.len(), (usize::MAX, false)); }
+        for pad in 0..self.kit.pads.len() {
+            let Some(pattern) = self.kit.pattern(index).filter(|p| p.get(pad, at)) else { continue };
+            let (percent, ratchets) = (pattern.probability(pad, at), pattern.ratchet(pad, at));
+            if first && from <= start {
+                self.rolled[pad] = (step, percent >= 100 || rand::thread_rng().gen_range(0..100) < percent);
+            }
+            if self.rolled[pad] != (step, true) { continue; }
+            let skip = if first { 0 } else { 1 };
+            let hits = (skip..ratchets)
+                .map(|k| start + (end - start) * k as f64 / ratchets as f64)
+                .filter(|t| from <= *t && *t < to);
+            for t in hits { self.trigger_after(pad, 1.0, ((t - from) * samples_per_step).round() as usize); }
+        }
+    }
+
+    pub fn gen(&mut self, sr: f32) -> f32 {
+        let samples = &self.samples;
+        let (mut sum, mut starting) = (0.0, false);
+        self.hits.retain_mut(|h| {
+            if h.wait > 0 {
+                h.wait -= 1;
+                starting |= h.wait == 0;
+                return true;
+            }
+            let sample = &samples[h.pad];
+            sum += sample.read(h.pos) * h.gain;
+            h.pos += sample.sample_rate / sr;
+            (h.pos as usize) < sample.len()
+        });
+        if starting { self.choke(); }
+        sum
+    }
+}
+
+// `swung_step` for a step counted from the top of a pattern played over
+// and over, swinging the off-beats of the pattern. an odd length would
+// swing the others every other time round.
+fn swung(step: usize, length: usize, swing: f32) -> f64 {
+    (step - step % length) as f64 + swung_step(step % length, swing)
+}
+
+#[cfg(test)]
+mod drums_tests {
+    use super::*;
+
+    fn machine() -> DrumMachine {
+        let pad = |choke| DrumPad { path: PathBuf::new(), gain: 1.0, choke, sample: Loaded::default() };
+        let kit = DrumKit { pads: vec![pad(None), pad(Some(1)), pad(Some(1))], ..DrumKit::default() };
+        let samples = (0..3).map(|_| Arc::new(Sample { data: vec![1.0; 4], sample_rate: 100.0 })).collect();
+        DrumMachine { kit, samples, ..DrumMachine::new() }
+    }
+
+    #[test]
+    fn test_choke_groups() {
+        let mut drums = machine();
+        drums.trigger(0, 1.0);
+        drums.trigger(1, 1.0);
+        assert_eq!(drums.gen(100.0), 2.0);
+        // the open hat is cut by the closed one, the kick keeps going.
+        drums.trigger(2, 0.5);
+        assert_eq!(drums.gen(100.0), 1.5);
+        // one shots end with their sample.
+        (0..3).for_each(|_| { drums.gen(100.0); });
+        assert_eq!(drums.gen(100.0), 0.0);
+
+        // a hit due later in the block cuts its group once it starts.
+        drums.trigger(1, 1.0);
+        drums.trigger_after(2, 0.5, 2);
+        assert_eq!((drums.gen(100.0), drums.gen(100.0), drums.gen(100.0)), (1.0, 1.0, 0.5));
+    }
+
+    #[test]
+    fn test_pattern_steps_and_writing() {
+        let mut drums = machine();
+        drums.pattern_mut().set(0, 1, true);
+        // four steps per beat.
+        drums.tick(0.0, 0.1, 0.0, 0.0);
+        assert_eq!(drums.gen(100.0), 0.0);
+        drums.tick(0.1, 0.3, 0.0, 0.0);
+        assert_eq!(drums.gen(100.0), 1.0);
+        assert_eq!(drums.step(), 2);
+
+        drums.writing = true;
+        drums.hit(1, 1.0, Some(0.3));
+        drums.hit(2, 1.0, Some(0.45));
+        drums.hit(0, 1.0, None);
+        assert!(drums.kit().pattern.get(1, 1));
+        assert!(drums.kit().pattern.get(2, 2));
+        assert!(!drums.kit().pattern.get(0, 2));
+    }
+
+    #[test]
+    fn test_song_chains_patterns() {
+        let mut drums = machine();
+        drums.pattern_mut().set(0, 0, true);
+        drums.select_pattern(1);
+        drums.pattern_mut().set(1, 0, true);
+        drums.pattern_mut().length = 4;
+        // pattern 1 twice, then pattern 2 once: 8 beats, then 1 beat.
+        drums.select_pattern(0);
+        drums.chain_pattern();
+        drums.chain_pattern();
+        drums.select_pattern(1);
+        assert_eq!(drums.chain_pattern(), SongEntry { pattern: 1, repeats: 1 });
+        assert_eq!(drums.kit().song_position(4.5), Some((0, 0.0)));
+        assert_eq!(drums.kit().song_position(8.5), Some((1, 8.0)));
+        assert_eq!(drums.kit().song_position(9.5), Some((0, 9.0)));
+
+        drums.song_mode = true;
+        drums.tick(7.9, 8.1, 0.0, 0.0);
+        assert_eq!((drums.playing(), drums.gen(100.0)), (1, 1.0));
+        drums.tick(8.9, 9.1, 0.0, 0.0);
+        assert_eq!(drums.playing(), 0);
+        // out of song mode, the current pattern loops.
+        drums.song_mode = false;
+        drums.tick(8.9, 9.1, 0.0, 0.0);
+        assert_eq!(drums.playing(), 1);
+    }
+
+    #[test]
+    fn test_probability_and_ratchets() {
+        let mut drums = machine();
+        let pattern = drums.pattern_mut();
+        pattern.set(0, 0, true);
+        pattern.set(0, 1, true);
+        pattern.set_probability(0, 1, 0);
+        pattern.set(0, 2, true);
+        pattern.set_ratchet(0, 2, 4);
+        assert_eq!((pattern.probability(0, 0), pattern.ratchet(0, 3)), (100, 1));
+
+        // hits started by a tick.
+        let hits = |drums: &mut DrumMachine, from: f64, to: f64| {
+            drums.hits.clear();
+            drums.tick(from, to, 0.0, 0.0);
+            drums.hits.len()
+        };
+        assert_eq!(hits(&mut drums, 0.0, 0.1), 1);
+        // never plays at 0%.
+        assert_eq!(hits(&mut drums, 0.2, 0.3), 0);
+        // four hits over the step, the first on it.
+        assert_eq!(hits(&mut drums, 0.45, 0.51), 1);
+        assert_eq!(hits(&mut drums, 0.51, 0.57), 1);
+        assert_eq!(hits(&mut drums, 0.57, 0.7), 2);
+        assert_eq!(hits(&mut drums, 0.7, 0.75), 0);
+        // each on its own sample of the block, 100 to a step here.
+        drums.hits.clear();
+        drums.tick(0.57, 0.7, 0.0, 400.0);
+        assert_eq!(drums.hits.iter().map(|h| h.wait).collect::<Vec<_>>(), [22, 47]);
+
+        drums.writing = true;
+        drums.hit(2, 1.0, Some(1.0));
+        assert_eq!(drums.cycle_probability(), Some(75));
+        assert_eq!(drums.cycle_ratchet(), Some(2));
+        assert_eq!((drums.kit().pattern.probability(2, 4), drums.kit().pattern.ratchet(2, 4)), (75, 2));
+    }
+
+    #[test]
+    fn test_swing_delays_off_beats() {
+        let mut drums = machine();
+        (0..4).for_each(|step| drums.pattern_mut().set(0, step, true));
+        let hits = |drums: &mut DrumMachine, from: f64, to: f64| {
+            drums.hits.clear();
+            drums.tick(from, to, 0.5, 0.0);
+            drums.hits.len()
+        };
+        assert_eq!(hits(&mut drums, 0.0, 0.1), 1);
+        // the second step comes a quarter of a step late.
+        assert_eq!(hits(&mut drums, 0.1, 0.3), 0);
+        assert_eq!(drums.step(), 1);
+        assert_eq!(hits(&mut drums, 0.3, 0.4), 1);
+        assert_eq!(hits(&mut drums, 0.4, 0.55), 1);
+
+        // three steps, the second one late every time round.
+        drums.pattern_mut().length = 3;
+        assert_eq!(hits(&mut drums, 0.7, 0.8), 1);
+        assert_eq!(hits(&mut drums, 0.8, 1.05), 0);
+        assert_eq!(hits(&mut drums, 1.05, 1.1), 1);
+    }
+}
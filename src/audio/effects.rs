@@ -0,0 +1,1352 @@
+//! Effects module.
+//!
+//! effects process the summed instrument output. anything they need is
+//! allocated on creation, never while processing.
+//!
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+
+use crate::audio::convolution::{Convolver, ImpulseResponse, Recording};
+use crate::audio::filter::{Biquad, EqShape, Filter, FilterMode, FilterSettings};
+use crate::audio::tempo::{LfoRate, NoteDivision};
+use crate::audio::waves::Loaded;
+
+pub trait Effect: Send {
+    // processes a mono buffer in place.
+    fn process(&mut self, buf: &mut [f32]);
+    // mono effects process the mid signal, the side passes through dry.
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            (*l, *r) = ((*l + *r) * 0.5, (*l - *r) * 0.5);
+        }
+        self.process(left);
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            (*l, *r) = (*l + *r, *l - *r);
+        }
+    }
+    // `input` is the captured audio input aligned with the buffers, only
+    // effects driven by it (e.g. the vocoder) need to look at it.
+    fn process_with_input(&mut self, left: &mut [f32], right: &mut [f32], _input: &[f32]) {
+        self.process_stereo(left, right)
+    }
+    fn desc(&self) -> EffectDesc;
+    fn reset(&mut self) {}
+    // beats per minute, for effects synced to the tempo.
+    fn set_tempo(&mut self, _bpm: f32) {}
+    // takes new settings and keeps going, for effects that can change
+    // without being rebuilt. false if `desc` is not for this effect.
+    fn update(&mut self, _desc: &EffectDesc) -> bool { false }
+    // gain reduction in dB (zero or below), for effects that turn the
+    // level down.
+    fn reduction(&self) -> f32 { 0.0 }
+}
+
+/// Serializable description of an effect, used by presets and to rebuild
+/// the effect when the sample rate changes.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum EffectDesc { Delay(DelaySettings), Reverb(ReverbSettings), Vocoder(VocoderSettings), Wavefolder(WavefolderSettings), Flanger(FlangerSettings), PingPong(PingPongSettings), Eq(EqSettings), Compressor(CompressorSettings), Tremolo(TremoloSettings), AutoPan(AutoPanSettings), Saturation(SaturationSettings), Convolution(ConvolutionSettings) }
+
+impl EffectDesc {
+    pub fn build(&self, sample_rate: f32) -> Box<dyn Effect> {
+        match self {
+            EffectDesc::Delay(s) => Box::new(Delay::new(*s, sample_rate)),
+            EffectDesc::Reverb(s) => Box::new(Reverb::new(*s, sample_rate)),
+            EffectDesc::Vocoder(s) => Box::new(Vocoder::new(*s, sample_rate)),
+            EffectDesc::Wavefolder(s) => Box::new(Wavefolder { settings: *s }),
+            EffectDesc::Saturation(s) => Box::new(Saturation { settings: *s }),
+            EffectDesc::Convolution(s) => Box::new(Convolution::new(s.clone(), &s.impulse_response(sample_rate), sample_rate)),
+            EffectDesc::Flanger(s) => Box::new(Flanger::new(*s, sample_rate)),
+            EffectDesc::PingPong(s) => Box::new(PingPong::new(*s, sample_rate)),
+            EffectDesc::Eq(s) => Box::new(Equalizer::new(*s, sample_rate)),
+            EffectDesc::Compressor(s) => Box::new(Compressor::new(*s, sample_rate)),
+            EffectDesc::Tremolo(s) => Box::new(Tremolo { settings: *s, lfo: Lfo::new(sample_rate) }),
+            EffectDesc::AutoPan(s) => Box::new(AutoPan { settings: *s, lfo: Lfo::new(sample_rate) }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EffectDesc::Delay(_) => "delay",
+            EffectDesc::Reverb(_) => "reverb",
+            EffectDesc::Vocoder(_) => "vocoder",
+            EffectDesc::Wavefolder(_) => "wavefolder",
+            EffectDesc::Saturation(_) => "saturation",
+            EffectDesc::Convolution(_) => "convolution",
+            EffectDesc::Flanger(_) => "flanger",
+            EffectDesc::PingPong(_) => "ping pong",
+            EffectDesc::Eq(_) => "eq",
+            EffectDesc::Compressor(_) => "compressor",
+            EffectDesc::Tremolo(_) => "tremolo",
+            EffectDesc::AutoPan(_) => "auto pan",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EffectSlotDesc { pub effect: EffectDesc, pub bypass: bool }
+
+pub struct EffectSlot { pub effect: Box<dyn Effect>, pub bypass: bool }
+
+/// Ordered list of effects, each one processing the output of the previous.
+pub struct EffectChain {
+    slots: Vec<EffectSlot>,
+    sample_rate: f32,
+    bpm: f32,
+}
+
+impl EffectChain {
+    pub fn new(sample_rate: f32) -> EffectChain { EffectChain { slots: Vec::new(), sample_rate, bpm: 120.0 } }
+
+    pub fn from_desc(descs: &[EffectSlotDesc], sample_rate: f32) -> EffectChain {
+        let mut chain = EffectChain::new(sample_rate);
+        chain.load(descs);
+        chain
+    }
+
+    pub fn push(&mut self, effect: EffectDesc) -> usize {
+        self.slots.push(EffectSlot { effect: self.build(&effect), bypass: false });
+        self.slots.len() - 1
+    }
+
+    // rebuilds the effect at `index` with new settings.
+    pub fn replace(&mut self, index: usize, effect: EffectDesc) {
+        let built = self.build(&effect);
+        if let Some(slot) = self.slots.get_mut(index) { slot.effect = built; }
+    }
+
+    // new settings for the effect at `index`, rebuilt only if it can't take
+    // them as it runs.
+    pub fn update(&mut self, index: usize, effect: EffectDesc) {
+        if self.slots.get_mut(index).is_some_and(|s| !s.effect.update(&effect)) { self.replace(index, effect); }
+    }
+
+    fn build(&self, effect: &EffectDesc) -> Box<dyn Effect> {
+        let mut effect = effect.build(self.sample_rate);
+        effect.set_tempo(self.bpm);
+        effect
+    }
+
+    pub fn set_tempo(&mut self, bpm: f32) {
+        if bpm == self.bpm { return; }
+        self.bpm = bpm;
+        self.slots.iter_mut().for_each(|s| s.effect.set_tempo(bpm));
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<EffectSlot> {
+        if index < self.slots.len() { Some(self.slots.remove(index)) } else { None }
+    }
+
+    // moves the effect at `from` so it ends up at position `to`.
+    pub fn move_effect(&mut self, from: usize, to: usize) {
+        if from < self.slots.len() && to < self.slots.len() {
+            let slot = self.slots.remove(from);
+            self.slots.insert(to, slot);
+        }
+    }
+
+    pub fn set_bypass(&mut self, index: usize, bypass: bool) {
+        if let Some(slot) = self.slots.get_mut(index) {
+            if slot.bypass && !bypass { slot.effect.reset(); }
+            slot.bypass = bypass;
+        }
+    }
+
+    pub fn is_bypassed(&self, index: usize) -> bool { self.slots.get(index).is_none_or(|s| s.bypass) }
+
+    pub fn find(&self, name: &str) -> Option<usize> { self.slots.iter().position(|s| s.effect.desc().name() == name) }
+
+    pub fn slots(&self) -> &[EffectSlot] { &self.slots }
+    pub fn slots_mut(&mut self) -> &mut [EffectSlot] { &mut self.slots }
+
+    pub fn desc(&self) -> Vec<EffectSlotDesc> {
+        self.slots.iter().map(|s| EffectSlotDesc { effect: s.effect.desc(), bypass: s.bypass }).collect()
+    }
+
+    pub fn load(&mut self, descs: &[EffectSlotDesc]) {
+        self.slots = descs.iter()
+            .map(|d| EffectSlot { effect: self.build(&d.effect), bypass: d.bypass })
+            .collect();
+    }
+
+    // effects allocate for a sample rate, so they are rebuilt from scratch.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        let descs = self.desc();
+        self.sample_rate = sample_rate;
+        self.load(&descs);
+    }
+
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32], input: &[f32]) {
+        for slot in self.slots.iter_mut().filter(|s| !s.bypass) {
+            slot.effect.process_with_input(left, right, input);
+        }
+    }
+}
+
+pub fn default_effects() -> Vec<EffectSlotDesc> {
+    vec![
+        EffectSlotDesc { effect: EffectDesc::Delay(DelaySettings::default()), bypass: true },
+        EffectSlotDesc { effect: EffectDesc::Reverb(ReverbSettings::default()), bypass: true },
+    ]
+}
+
+pub const SOFT_CLIP_KNEE: f32 = 0.8;
+
+/// Soft clipper for the final output: linear up to `SOFT_CLIP_KNEE`, then
+/// a tanh curve that approaches but never crosses ±1.
+pub fn soft_clip(x: f32) -> f32 {
+    let (a, knee) = (x.abs(), SOFT_CLIP_KNEE);
+    if a <= knee { return x; }
+    x.signum() * (knee + (1.0 - knee) * ((a - knee) / (1.0 - knee)).tanh())
+}
+
+// `ceiling` in dB below full scale, `release` the seconds the gain takes
+// to come most of the way back up.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct LimiterSettings { pub ceiling: f32, pub release: f32 }
+
+impl Default for LimiterSettings {
+    // just under the knee of the soft clip, which then has nothing to do.
+    fn default() -> Self { LimiterSettings { ceiling: -2.0, release: 0.1 } }
+}
+
+/// Brickwall limiter, the last stage before the output. the gain each
+/// sample needs is held over the lookahead and averaged over it again, and
+/// the audio is delayed to match, so the gain is down before a peak
+/// arrives and never lets one past the ceiling.
+pub struct Limiter {
+    pub settings: LimiterSettings,
+    lookahead: usize,
+    delay: [Vec<f32>; 2],
+    write: usize,
+    // candidates for the lowest gain in the window, lowest first.
+    lowest: VecDeque<(usize, f32)>,
+    held: Vec<f32>,
+    held_sum: f64,
+    n: usize,
+    gain: f32,
+    release: f32,
+}
+
+impl Limiter {
+    pub const LOOKAHEAD: f32 = 0.005;
+
+    pub fn new(settings: LimiterSettings, sample_rate: f32) -> Limiter {
+        let lookahead = ((Limiter::LOOKAHEAD * sample_rate) as usize).max(1);
+        let release = (-1.0 / (settings.release.max(0.001) * sample_rate)).exp();
+        Limiter {
+            settings, lookahead, delay: [vec![0.0; lookahead], vec![0.0; lookahead]], write: 0,
+            lowest: VecDeque::with_capacity(lookahead + 1), held: vec![1.0; lookahead], held_sum: lookahead as f64,
+            n: 0, gain: 1.0, release,
+        }
+    }
+
+    // samples the output is behind the input.
+    pub fn latency(&self) -> usize { self.lookahead - 1 }
+
+    pub fn set_release(&mut self, release: f32, sample_rate: f32) {
+        self.settings.release = release;
+        self.release = (-1.0 / (release.max(0.001) * sample_rate)).exp();
+    }
+
+    // gain reduction in dB, zero or below.
+    pub fn reduction(&self) -> f32 { 20.0 * self.gain.max(1e-6).log10() }
+
+    pub fn tick(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let ceiling = 10f32.powf(self.settings.ceiling.min(0.0) / 20.0);
+        let peak = left.abs().max(right.abs());
+        let needed = if peak > ceiling { ceiling / peak } else { 1.0 };
+
+        // lowest gain needed over the last `lookahead` samples.
+        while self.lowest.back().is_some_and(|(_, g)| *g >= needed) { self.lowest.pop_back(); }
+        self.lowest.push_back((self.n, needed));
+        while self.lowest.front().is_some_and(|(i, _)| *i + self.lookahead <= self.n) { self.lowest.pop_front(); }
+        let held = self.lowest.front().map_or(1.0, |(_, g)| *g);
+
+        // averaged over the same window, it is at or under what every
+        // sample still in the delay needs.
+        let slot = self.n % self.lookahead;
+        self.held_sum += (held - self.held[slot]) as f64;
+        self.held[slot] = held;
+        let target = (self.held_sum / self.lookahead as f64).min(1.0) as f32;
+        self.gain = if target < self.gain { target } else { target + (self.gain - target) * self.release };
+        self.n += 1;
+
+        let [dl, dr] = &mut self.delay;
+        let read = (self.write + 1) % self.lookahead;
+        dl[self.write] = left;
+        dr[self.write] = right;
+        let out = (dl[read] * self.gain, dr[read] * self.gain);
+        self.write = read;
+        out
+    }
+
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) { (*l, *r) = self.tick(*l, *r); }
+    }
+}
+
+// `threshold` and `makeup` in dB, `attack` and `release` in seconds.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct CompressorSettings { pub threshold: f32, pub ratio: f32, pub attack: f32, pub release: f32, pub makeup: f32 }
+
+impl Default for CompressorSettings {
+    fn default() -> Self { CompressorSettings { threshold: -18.0, ratio: 4.0, attack: 0.01, release: 0.15, makeup: 0.0 } }
+}
+
+/// Feed-forward compressor, both channels turned down together by the
+/// louder one. the reduction above the threshold follows the level with
+/// the attack time when it grows and the release time when it shrinks.
+pub struct Compressor {
+    pub settings: CompressorSettings,
+    sample_rate: f32,
+    attack: f32,
+    release: f32,
+    // current gain reduction in dB, positive.
+    reduction: f32,
+}
+
+impl Compressor {
+    pub fn new(settings: CompressorSettings, sample_rate: f32) -> Compressor {
+        let mut c = Compressor { settings, sample_rate, attack: 0.0, release: 0.0, reduction: 0.0 };
+        c.set_settings(settings);
+        c
+    }
+
+    pub fn set_settings(&mut self, settings: CompressorSettings) {
+        let coef = |time: f32| (-1.0 / (time.max(0.0001) * self.sample_rate)).exp();
+        (self.settings, self.attack, self.release) = (settings, coef(settings.attack), coef(settings.release));
+    }
+
+    pub fn tick(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let s = &self.settings;
+        let level = 20.0 * left.abs().max(right.abs()).max(1e-6).log10();
+        let target = (level - s.threshold).max(0.0) * (1.0 - 1.0 / s.ratio.max(1.0));
+        let coef = if target > self.reduction { self.attack } else { self.release };
+        self.reduction = target + (self.reduction - target) * coef;
+        let gain = 10f32.powf((s.makeup - self.reduction) / 20.0);
+        (left * gain, right * gain)
+    }
+}
+
+impl Effect for Compressor {
+    fn process(&mut self, buf: &mut [f32]) { buf.iter_mut().for_each(|x| *x = self.tick(*x, *x).0) }
+
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) { (*l, *r) = self.tick(*l, *r); }
+    }
+
+    fn desc(&self) -> EffectDesc { EffectDesc::Compressor(self.settings) }
+    fn reset(&mut self) { self.reduction = 0.0 }
+
+    fn update(&mut self, desc: &EffectDesc) -> bool {
+        let EffectDesc::Compressor(settings) = desc else { return false };
+        self.set_settings(*settings);
+        true
+    }
+
+    fn reduction(&self) -> f32 { -self.reduction }
+}
+
+// `gain` in dB, `freq` in Hz and `q` the width of the mid band or the
+// steepness of the shelves.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct EqBand { pub freq: f32, pub gain: f32, pub q: f32 }
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct EqSettings { pub low: EqBand, pub mid: EqBand, pub high: EqBand }
+
+impl Default for EqSettings {
+    fn default() -> Self {
+        EqSettings {
+            low: EqBand { freq: 120.0, gain: 0.0, q: std::f32::consts::FRAC_1_SQRT_2 },
+            mid: EqBand { freq: 1000.0, gain: 0.0, q: 1.0 },
+            high: EqBand { freq: 8000.0, gain: 0.0, q: std::f32::consts::FRAC_1_SQRT_2 },
+        }
+    }
+}
+
+impl EqSettings {
+    pub fn bands(&self) -> [(EqShape, EqBand); 3] {
+        [(EqShape::LowShelf, self.low), (EqShape::Peak, self.mid), (EqShape::HighShelf, self.high)]
+    }
+
+    // low, mid and high by number.
+    pub fn band(&self, band: u8) -> EqBand { self.bands()[band.min(2) as usize].1 }
+
+    pub fn band_mut(&mut self, band: u8) -> &mut EqBand {
+        match band { 0 => &mut self.low, 1 => &mut self.mid, _ => &mut self.high }
+    }
+}
+
+/// Three band equalizer: a low shelf, a peaking mid and a high shelf, in
+/// series on each channel.
+pub struct Equalizer {
+    pub settings: EqSettings,
+    filters: [[Biquad; 3]; 2],
+}
+
+impl Equalizer {
+    pub fn new(settings: EqSettings, sample_rate: f32) -> Equalizer {
+        let filters = std::array::from_fn(|_| std::array::from_fn(|_| Biquad::new(FilterSettings::default(), sample_rate)));
+        let mut eq = Equalizer { settings, filters };
+        eq.set_settings(settings);
+        eq
+    }
+
+    pub fn set_settings(&mut self, settings: EqSettings) {
+        self.settings = settings;
+        for channel in self.filters.iter_mut() {
+            for (filter, (shape, band)) in channel.iter_mut().zip(settings.bands()) {
+                filter.set_equalizer(shape, band.freq, band.gain, band.q);
+            }
+        }
+    }
+
+    fn tick(&mut self, channel: usize, x: f32) -> f32 {
+        self.filters[channel].iter_mut().fold(x, |x, f| f.process(x))
+    }
+}
+
+impl Effect for Equalizer {
+    fn process(&mut self, buf: &mut [f32]) { buf.iter_mut().for_each(|x| *x = self.tick(0, *x)) }
+
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        left.iter_mut().for_each(|x| *x = self.tick(0, *x));
+        right.iter_mut().for_each(|x| *x = self.tick(1, *x));
+    }
+
+    fn desc(&self) -> EffectDesc { EffectDesc::Eq(self.settings) }
+    fn reset(&mut self) { self.filters.iter_mut().flatten().for_each(|f| f.reset()) }
+
+    fn update(&mut self, desc: &EffectDesc) -> bool {
+        let EffectDesc::Eq(settings) = desc else { return false };
+        self.set_settings(*settings);
+        true
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct DelaySettings { pub time: f32, pub feedback: f32, pub mix: f32 }
+
+impl Default for DelaySettings {
+    fn default() -> Self { DelaySettings { time: 0.35, feedback: 0.4, mix: 0.3 } }
+}
+
+/// Feedback delay. `time` is in seconds and can't exceed the maximum time
+/// the delay line was allocated for.
+pub struct Delay {
+    pub settings: DelaySettings,
+    line: Vec<f32>,
+    write: usize,
+    sample_rate: f32,
+}
+
+impl Delay {
+    pub const MAX_TIME: f32 = 2.0;
+
+    pub fn new(settings: DelaySettings, sample_rate: f32) -> Delay {
+        let len = (Delay::MAX_TIME * sample_rate) as usize + 2;
+        Delay { settings, line: vec![0.0; len], write: 0, sample_rate }
+    }
+
+    pub fn clear(&mut self) { self.line.fill(0.0) }
+
+    pub fn tick(&mut self, x: f32) -> f32 {
+        let len = self.line.len();
+        let d = (self.settings.time.clamp(0.0, Delay::MAX_TIME) * self.sample_rate).max(1.0);
+        let read = (self.write as f32 - d).rem_euclid(len as f32);
+        let (i, frac) = (read as usize % len, read.fract());
+        let delayed = self.line[i] * (1.0 - frac) + self.line[(i + 1) % len] * frac;
+
+        let feedback = self.settings.feedback.clamp(-0.99, 0.99);
+        self.line[self.write] = x + delayed * feedback;
+        self.write = (self.write + 1) % len;
+
+        let mix = self.settings.mix.clamp(0.0, 1.0);
+        x * (1.0 - mix) + delayed * mix
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, buf: &mut [f32]) { buf.iter_mut().for_each(|x| *x = self.tick(*x)) }
+    fn desc(&self) -> EffectDesc { EffectDesc::Delay(self.settings) }
+    fn reset(&mut self) { self.clear() }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct ReverbSettings { pub room_size: f32, pub damping: f32, pub mix: f32 }
+
+impl Default for ReverbSettings {
+    fn default() -> Self { ReverbSettings { room_size: 0.7, damping: 0.5, mix: 0.25 } }
+}
+
+struct Comb { line: Vec<f32>, pos: usize, store: f32 }
+impl Comb {
+    fn tick(&mut self, x: f32, feedback: f32, damp: f32) -> f32 {
+        let y = self.line[self.pos];
+        self.store = y * (1.0 - damp) + self.store * damp;
+        self.line[self.pos] = x + self.store * feedback;
+        self.pos = (self.pos + 1) % self.line.len();
+        y
+    }
+}
+
+struct Allpass { line: Vec<f32>, pos: usize }
+impl Allpass {
+    fn tick(&mut self, x: f32) -> f32 {
+        let buffered = self.line[self.pos];
+        self.line[self.pos] = x + buffered * 0.5;
+        self.pos = (self.pos + 1) % self.line.len();
+        buffered - x
+    }
+}
+
+/// Mono Freeverb: eight damped comb filters in parallel feeding four
+/// allpasses in series. delay lengths are the original 44.1kHz tunings
+/// scaled to the sample rate.
+pub struct Reverb {
+    pub settings: ReverbSettings,
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Reverb {
+    const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+    const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+
+    pub fn new(settings: ReverbSettings, sample_rate: f32) -> Reverb {
+        let scale = |n: usize| ((n as f32 * sample_rate / 44100.0) as usize).max(1);
+        Reverb {
+            settings,
+            combs: Reverb::COMBS.iter().map(|n| Comb { line: vec![0.0; scale(*n)], pos: 0, store: 0.0 }).collect(),
+            allpasses: Reverb::ALLPASSES.iter().map(|n| Allpass { line: vec![0.0; scale(*n)], pos: 0 }).collect(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.combs.iter_mut().for_each(|c| { c.line.fill(0.0); c.store = 0.0; });
+        self.allpasses.iter_mut().for_each(|a| a.line.fill(0.0));
+    }
+
+    pub fn tick(&mut self, x: f32) -> f32 {
+        let feedback = 0.7 + 0.28 * self.settings.room_size.clamp(0.0, 1.0);
+        let damp = 0.4 * self.settings.damping.clamp(0.0, 1.0);
+        let input = x * 0.015;
+        let mut wet: f32 = self.combs.iter_mut().map(|c| c.tick(input, feedback, damp)).sum();
+        for a in self.allpasses.iter_mut() { wet = a.tick(wet); }
+
+        let mix = self.settings.mix.clamp(0.0, 1.0);
+        x * (1.0 - mix) + wet * mix
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, buf: &mut [f32]) { buf.iter_mut().for_each(|x| *x = self.tick(*x)) }
+    fn desc(&self) -> EffectDesc { EffectDesc::Reverb(self.settings) }
+    fn reset(&mut self) { self.clear() }
+}
+
+// `bands` is fixed when the vocoder is built, changing it means building a
+// new one from its desc.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct VocoderSettings { pub bands: u8, pub mix: f32 }
+
+impl Default for VocoderSettings {
+    fn default() -> Self { VocoderSettings { bands: 16, mix: 1.0 } }
+}
+
+struct VocoderBand { modulator: Biquad, carrier: Biquad, level: f32 }
+
+/// Channel vocoder: the audio input (modulator) is split into bands, and
+/// the level of each band sets the level of the same band of the synth
+/// output (carrier). bands are spread evenly in octaves from 100 Hz to 8 kHz.
+pub struct Vocoder {
+    pub settings: VocoderSettings,
+    bands: Vec<VocoderBand>,
+    attack: f32,
+    release: f32,
+}
+
+impl Vocoder {
+    pub const MIN_BANDS: u8 = 4;
+    pub const MAX_BANDS: u8 = 32;
+    const LOW: f32 = 100.0;
+    const HIGH: f32 = 8000.0;
+
+    pub fn new(settings: VocoderSettings, sample_rate: f32) -> Vocoder {
+        let n = settings.bands.clamp(Vocoder::MIN_BANDS, Vocoder::MAX_BANDS) as usize;
+        let octaves = (Vocoder::HIGH / Vocoder::LOW).log2();
+        let width = octaves / n as f32;
+        // q of a band pass `width` octaves wide.
+        let q = 2f32.powf(width / 2.0) / (2f32.powf(width) - 1.0);
+        let bands = (0..n).map(|i| {
+            let cutoff = Vocoder::LOW * 2f32.powf(width * (i as f32 + 0.5));
+            let settings = FilterSettings { mode: FilterMode::BandPass, cutoff, resonance: q, ..FilterSettings::default() };
+            VocoderBand { modulator: Biquad::new(settings, sample_rate), carrier: Biquad::new(settings, sample_rate), level: 0.0 }
+        }).collect();
+        // envelope followers, 5ms attack and 30ms release.
+        let coef = |time: f32| (-1.0 / (time * sample_rate)).exp();
+        Vocoder { settings: VocoderSettings { bands: n as u8, ..settings }, bands, attack: coef(0.005), release: coef(0.03) }
+    }
+
+    pub fn clear(&mut self) {
+        for band in self.bands.iter_mut() {
+            band.modulator.reset();
+            band.carrier.reset();
+            band.level = 0.0;
+        }
+    }
+
+    pub fn tick(&mut self, carrier: f32, modulator: f32) -> f32 {
+        let (attack, release) = (self.attack, self.release);
+        let out: f32 = self.bands.iter_mut().map(|band| {
+            let m = band.modulator.process(modulator).abs();
+            let coef = if m > band.level { attack } else { release };
+            band.level = m + (band.level - m) * coef;
+            band.carrier.process(carrier) * band.level
+        }).sum();
+        // band levels of a full scale modulator hover around a half.
+        out * 2.0
+    }
+}
+
+impl Effect for Vocoder {
+    // without an input there is nothing to shape the carrier with.
+    fn process(&mut self, buf: &mut [f32]) { buf.fill(0.0) }
+
+    fn process_with_input(&mut self, left: &mut [f32], right: &mut [f32], input: &[f32]) {
+        let mix = self.settings.mix.clamp(0.0, 1.0);
+        for (i, (l, r)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+            let wet = self.tick((*l + *r) * 0.5, input.get(i).copied().unwrap_or(0.0));
+            *l = *l * (1.0 - mix) + wet * mix;
+            *r = *r * (1.0 - mix) + wet * mix;
+        }
+    }
+
+    fn desc(&self) -> EffectDesc { EffectDesc::Vocoder(self.settings) }
+    fn reset(&mut self) { self.clear() }
+}
+
+// `fold` is the gain into the folder, the wave starts folding above 1.
+// `symmetry` offsets it first, so the peaks on one side fold before the
+// other's.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct WavefolderSettings { pub fold: f32, pub symmetry: f32, pub mix: f32 }
+
+impl Default for WavefolderSettings {
+    fn default() -> Self { WavefolderSettings { fold: 3.0, symmetry: 0.0, mix: 1.0 } }
+}
+
+impl WavefolderSettings {
+    pub const MAX_FOLD: f32 = 10.0;
+
+    // the same folder with nothing mixed in, the default of the voice stage.
+    pub fn off() -> WavefolderSettings { WavefolderSettings { mix: 0.0, ..WavefolderSettings::default() } }
+
+    /// West coast style sine folder: each time the driven wave goes past a
+    /// peak it turns back, adding harmonics as the fold goes up. the offset
+    /// of the symmetry is taken back out, so silence stays silent.
+    pub fn fold(&self, x: f32) -> f32 {
+        let (gain, bias) = (self.fold.clamp(1.0, WavefolderSettings::MAX_FOLD), self.symmetry.clamp(-1.0, 1.0));
+        let shape = |x: f32| (std::f32::consts::FRAC_PI_2 * (gain * x + bias)).sin();
+        let mix = self.mix.clamp(0.0, 1.0);
+        x * (1.0 - mix) + (shape(x) - shape(0.0)) * mix
+    }
+}
+
+pub struct Wavefolder { pub settings: WavefolderSettings }
+
+impl Effect for Wavefolder {
+    fn process(&mut self, buf: &mut [f32]) { buf.iter_mut().for_each(|x| *x = self.settings.fold(*x)) }
+    // folding the mid and side apart would not give the folded channels.
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        self.process(left);
+        self.process(right);
+    }
+    fn desc(&self) -> EffectDesc { EffectDesc::Wavefolder(self.settings) }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub enum SaturationCurve { #[default] Tanh, Hard, Tube }
+
+impl SaturationCurve {
+    pub const ALL: [SaturationCurve; 3] = [SaturationCurve::Tanh, SaturationCurve::Hard, SaturationCurve::Tube];
+
+    pub fn next(&self) -> SaturationCurve {
+        SaturationCurve::ALL[(SaturationCurve::ALL.iter().position(|c| c == self).unwrap_or(0) + 1) % SaturationCurve::ALL.len()]
+    }
+}
+
+// `drive` is the gain in dB into the curve and `output` the gain in dB of
+// what comes out of it, to bring the level back down.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct SaturationSettings { pub curve: SaturationCurve, pub drive: f32, pub output: f32, pub mix: f32 }
+
+impl Default for SaturationSettings {
+    fn default() -> Self { SaturationSettings { curve: SaturationCurve::Tanh, drive: 12.0, output: -6.0, mix: 1.0 } }
+}
+
+impl SaturationSettings {
+    // how far the tube curve is pushed off center, the negative side
+    // clips later and softer than the positive one.
+    pub const TUBE_BIAS: f32 = 0.4;
+
+    // nothing mixed in, the default of the voice stage.
+    pub fn off() -> SaturationSettings { SaturationSettings { mix: 0.0, ..SaturationSettings::default() } }
+
+    /// Overdrive: tanh rounds the peaks off, hard clips them flat and tube
+    /// is an offset tanh, lopsided for even harmonics. the offset is taken
+    /// back out, so silence stays silent.
+    pub fn saturate(&self, x: f32) -> f32 {
+        let x_driven = x * 10f32.powf(self.drive / 20.0);
+        let shaped = match self.curve {
+            SaturationCurve::Tanh => x_driven.tanh(),
+            SaturationCurve::Hard => x_driven.clamp(-1.0, 1.0),
+            SaturationCurve::Tube => (x_driven + SaturationSettings::TUBE_BIAS).tanh() - SaturationSettings::TUBE_BIAS.tanh(),
+        };
+        let mix = self.mix.clamp(0.0, 1.0);
+        x * (1.0 - mix) + shaped * 10f32.powf(self.output / 20.0) * mix
+    }
+}
+
+pub struct Saturation { pub settings: SaturationSettings }
+
+impl Effect for Saturation {
+    fn process(&mut self, buf: &mut [f32]) { buf.iter_mut().for_each(|x| *x = self.settings.saturate(*x)) }
+    // like the wavefolder, each channel on its own.
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        self.process(left);
+        self.process(right);
+    }
+    fn desc(&self) -> EffectDesc { EffectDesc::Saturation(self.settings) }
+
+    fn update(&mut self, desc: &EffectDesc) -> bool {
+        let EffectDesc::Saturation(settings) = desc else { return false };
+        self.settings = *settings;
+        true
+    }
+}
+
+// `manual` is the delay in seconds the sweep is centered on and `depth`
+// how far it moves either way, `rate` in Hz. negative feedback hollows
+// the sound out where positive feedback rings.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct FlangerSettings { pub rate: f32, pub depth: f32, pub manual: f32, pub feedback: f32, pub mix: f32 }
+
+impl Default for FlangerSettings {
+    fn default() -> Self { FlangerSettings { rate: 0.25, depth: 0.002, manual: 0.003, feedback: 0.6, mix: 0.5 } }
+}
+
+/// Flanger: a short delay swept by a sine lfo, fed back into itself and
+/// mixed with the dry signal. each channel has its own line, the right
+/// sweeping a quarter cycle behind the left.
+pub struct Flanger {
+    pub settings: FlangerSettings,
+    lines: [Vec<f32>; 2],
+    write: usize,
+    phase: f32,
+    sample_rate: f32,
+}
+
+impl Flanger {
+    pub const MAX_TIME: f32 = 0.02;
+
+    pub fn new(settings: FlangerSettings, sample_rate: f32) -> Flanger {
+        let len = (Flanger::MAX_TIME * sample_rate) as usize + 2;
+        Flanger { settings, lines: [vec![0.0; len], vec![0.0; len]], write: 0, phase: 0.0, sample_rate }
+    }
+
+    pub fn clear(&mut self) { self.lines.iter_mut().for_each(|l| l.fill(0.0)) }
+
+    // one sample of channel `c`, at `offset` cycles of the lfo.
+    fn tap(&mut self, c: usize, x: f32, offset: f32) -> f32 {
+        let s = &self.settings;
+        let line = &mut self.lines[c];
+        let len = line.len();
+        let sweep = (std::f32::consts::TAU * (self.phase + offset)).sin();
+        let time = (s.manual + s.depth * sweep).clamp(1.0 / self.sample_rate, Flanger::MAX_TIME);
+        let read = (self.write as f32 - time * self.sample_rate).rem_euclid(len as f32);
+        let (i, frac) = (read as usize % len, read.fract());
+        let delayed = line[i] * (1.0 - frac) + line[(i + 1) % len] * frac;
+        line[self.write] = x + delayed * s.feedback.clamp(-0.95, 0.95);
+        let mix = s.mix.clamp(0.0, 1.0);
+        x * (1.0 - mix) + delayed * mix
+    }
+
+    fn advance(&mut self) {
+        self.write = (self.write + 1) % self.lines[0].len();
+        self.phase = (self.phase + self.settings.rate / self.sample_rate).fract();
+    }
+}
+
+impl Effect for Flanger {
+    fn process(&mut self, buf: &mut [f32]) {
+        for x in buf.iter_mut() {
+            *x = self.tap(0, *x, 0.0);
+            self.advance();
+        }
+    }
+
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            *l = self.tap(0, *l, 0.0);
+            *r = self.tap(1, *r, 0.25);
+            self.advance();
+        }
+    }
+
+    fn desc(&self) -> EffectDesc { EffectDesc::Flanger(self.settings) }
+    fn reset(&mut self) { self.clear() }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct PingPongSettings { pub division: NoteDivision, pub feedback: f32, pub mix: f32 }
+
+impl Default for PingPongSettings {
+    fn default() -> Self { PingPongSettings { division: NoteDivision::default(), feedback: 0.5, mix: 0.3 } }
+}
+
+/// Stereo delay bouncing between the channels, its time a note division
+/// at the tempo. the input goes into the left line, each line feeds the
+/// other, so the echoes alternate left and right.
+pub struct PingPong {
+    pub settings: PingPongSettings,
+    lines: [Vec<f32>; 2],
+    write: usize,
+    bpm: f32,
+    sample_rate: f32,
+}
+
+impl PingPong {
+    // longest echo, a whole note at 60 bpm.
+    pub const MAX_TIME: f32 = 4.0;
+
+    pub fn new(settings: PingPongSettings, sample_rate: f32) -> PingPong {
+        let len = (PingPong::MAX_TIME * sample_rate) as usize + 2;
+        PingPong { settings, lines: [vec![0.0; len], vec![0.0; len]], write: 0, bpm: 120.0, sample_rate }
+    }
+
+    pub fn clear(&mut self) { self.lines.iter_mut().for_each(|l| l.fill(0.0)) }
+
+    pub fn time(&self) -> f32 { self.settings.division.seconds(self.bpm).min(PingPong::MAX_TIME) }
+
+    pub fn tick(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let len = self.lines[0].len();
+        let d = (self.time() * self.sample_rate).max(1.0);
+        let read = (self.write as f32 - d).rem_euclid(len as f32);
+        let (i, frac) = (read as usize % len, read.fract());
+        let [l, r] = self.lines.each_ref().map(|line| line[i] * (1.0 - frac) + line[(i + 1) % len] * frac);
+
+        let feedback = self.settings.feedback.clamp(0.0, 0.99);
+        self.lines[0][self.write] = (left + right) * 0.5 + r * feedback;
+        self.lines[1][self.write] = l;
+        self.write = (self.write + 1) % len;
+
+        let mix = self.settings.mix.clamp(0.0, 1.0);
+        (left * (1.0 - mix) + l * mix, right * (1.0 - mix) + r * mix)
+    }
+}
+
+impl Effect for PingPong {
+    fn process(&mut self, buf: &mut [f32]) { buf.iter_mut().for_each(|x| *x = self.tick(*x, *x).0) }
+
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) { (*l, *r) = self.tick(*l, *r); }
+    }
+
+    fn desc(&self) -> EffectDesc { EffectDesc::PingPong(self.settings) }
+    fn reset(&mut self) { self.clear() }
+    fn set_tempo(&mut self, bpm: f32) { self.bpm = bpm }
+
+    // the lines are long enough for any division, the echoes already in
+    // them keep going.
+    fn update(&mut self, desc: &EffectDesc) -> bool {
+        let EffectDesc::PingPong(settings) = desc else { return false };
+        self.settings = *settings;
+        true
+    }
+}
+
+// `ir` is the wav file of the impulse response, `predelay` the seconds
+// before the reverb starts. `recording` is what `read` got from `ir`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ConvolutionSettings {
+    pub ir: PathBuf,
+    pub mix: f32,
+    pub predelay: f32,
+    #[serde(skip)]
+    pub recording: Loaded<Recording>,
+}
+
+impl Default for ConvolutionSettings {
+    fn default() -> Self { ConvolutionSettings { ir: PathBuf::new(), mix: 0.3, predelay: 0.02, recording: Loaded::default() } }
+}
+
+impl ConvolutionSettings {
+    // reads the wav file of `ir`. keeps the last one read if it fails.
+    pub fn read(&mut self) -> Result<(), hound::Error> {
+        self.recording = match self.ir.as_os_str().is_empty() {
+            true => Loaded(None),
+            false => Loaded(Some(Arc::new(Recording::read(&self.ir)?))),
+        };
+        Ok(())
+    }
+
+    // an impulse response not read (or without a path) leaves only the
+    // dry signal.
+    pub fn impulse_response(&self, sample_rate: f32) -> ImpulseResponse {
+        self.recording.0.as_ref().map_or_else(ImpulseResponse::default, |r| ImpulseResponse::new(r, sample_rate))
+    }
+}
+
+/// Reads the impulse responses the convolutions of `effects` name, before
+/// they are built. the others are still read when one fails.
+pub fn read_impulse_responses(effects: &mut [EffectSlotDesc]) -> Result<(), String> {
+    let mut result = Ok(());
+    for slot in effects.iter_mut() {
+        let EffectDesc::Convolution(settings) = &mut slot.effect else { continue };
+        if let Err(e) = settings.read() { result = result.and(Err(format!("{}: {}", settings.ir.display(), e))); }
+    }
+    result
+}
+
+/// Convolution reverb: each channel convolved with the recorded impulse
+/// response of a space, after the pre-delay. the convolution is a block
+/// late on its own, so pre-delays shorter than that come out as a block.
+pub struct Convolution {
+    pub settings: ConvolutionSettings,
+    convolvers: [Convolver; 2],
+    lines: [Vec<f32>; 2],
+    write: usize,
+    sample_rate: f32,
+}
+
+impl Convolution {
+    pub const MAX_PREDELAY: f32 = 0.5;
+
+    pub fn new(settings: ConvolutionSettings, ir: &ImpulseResponse, sample_rate: f32) -> Convolution {
+        let len = (Convolution::MAX_PREDELAY * sample_rate) as usize + 1;
+        let convolvers = [Convolver::new(ir.channel(0)), Convolver::new(ir.channel(1))];
+        Convolution { settings, convolvers, lines: [vec![0.0; len], vec![0.0; len]], write: 0, sample_rate }
+    }
+
+    pub fn clear(&mut self) {
+        self.convolvers.iter_mut().for_each(|c| c.clear());
+        self.lines.iter_mut().for_each(|l| l.fill(0.0));
+    }
+
+    fn tap(&mut self, c: usize, x: f32) -> f32 {
+        let line = &mut self.lines[c];
+        let len = line.len();
+        line[self.write] = x;
+        let predelay = self.settings.predelay.clamp(0.0, Convolution::MAX_PREDELAY) * self.sample_rate;
+        let d = (predelay as usize).saturating_sub(Convolver::BLOCK).min(len - 1);
+        let wet = self.convolvers[c].tick(line[(self.write + len - d) % len]);
+        let mix = self.settings.mix.clamp(0.0, 1.0);
+        x * (1.0 - mix) + wet * mix
+    }
+
+    fn advance(&mut self) { self.write = (self.write + 1) % self.lines[0].len() }
+}
+
+impl Effect for Convolution {
+    fn process(&mut self, buf: &mut [f32]) {
+        for x in buf.iter_mut() {
+            *x = self.tap(0, *x);
+            self.advance();
+        }
+    }
+
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            (*l, *r) = (self.tap(0, *l), self.tap(1, *r));
+            self.advance();
+        }
+    }
+
+    fn desc(&self) -> EffectDesc { EffectDesc::Convolution(self.settings.clone()) }
+    fn reset(&mut self) { self.clear() }
+
+    // another impulse response is convolved with by rebuilding.
+    fn update(&mut self, desc: &EffectDesc) -> bool {
+        match desc {
+            EffectDesc::Convolution(settings) if settings.ir == self.settings.ir => {
+                self.settings = settings.clone();
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+// sine lfo for the tremolo and the auto pan.
+struct Lfo { phase: f32, bpm: f32, sample_rate: f32 }
+
+impl Lfo {
+    fn new(sample_rate: f32) -> Lfo { Lfo { phase: 0.0, bpm: 120.0, sample_rate } }
+
+    fn tick(&mut self, rate: LfoRate) -> f32 {
+        let y = (std::f32::consts::TAU * self.phase).sin();
+        self.phase = (self.phase + rate.hz(self.bpm) / self.sample_rate).fract();
+        y
+    }
+}
+
+// `depth` from 0 to 1, how far the level (or the position) swings.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct TremoloSettings { pub rate: LfoRate, pub depth: f32 }
+
+impl Default for TremoloSettings {
+    fn default() -> Self { TremoloSettings { rate: LfoRate::Free(4.0), depth: 0.5 } }
+}
+
+/// Tremolo: the level dips by up to `depth` once per lfo cycle, both
+/// channels together.
+pub struct Tremolo { pub settings: TremoloSettings, lfo: Lfo }
+
+impl Tremolo {
+    fn gain(&mut self) -> f32 { 1.0 - self.settings.depth.clamp(0.0, 1.0) * 0.5 * (1.0 - self.lfo.tick(self.settings.rate)) }
+}
+
+impl Effect for Tremolo {
+    fn process(&mut self, buf: &mut [f32]) { buf.iter_mut().for_each(|x| *x *= self.gain()) }
+
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let gain = self.gain();
+            (*l, *r) = (*l * gain, *r * gain);
+        }
+    }
+
+    fn desc(&self) -> EffectDesc { EffectDesc::Tremolo(self.settings) }
+    fn reset(&mut self) { self.lfo.phase = 0.0 }
+    fn set_tempo(&mut self, bpm: f32) { self.lfo.bpm = bpm }
+
+    fn update(&mut self, desc: &EffectDesc) -> bool {
+        let EffectDesc::Tremolo(settings) = desc else { return false };
+        self.settings = *settings;
+        true
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct AutoPanSettings { pub rate: LfoRate, pub depth: f32 }
+
+impl Default for AutoPanSettings {
+    fn default() -> Self { AutoPanSettings { rate: LfoRate::Free(0.25), depth: 0.8 } }
+}
+
+/// Auto pan: moves the sound from side to side with equal power gains,
+/// unity in the center. a mono buffer has nowhere to move and passes
+/// through.
+pub struct AutoPan { pub settings: AutoPanSettings, lfo: Lfo }
+
+impl Effect for AutoPan {
+    fn process(&mut self, _buf: &mut [f32]) {}
+
+    fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let pan = self.settings.depth.clamp(0.0, 1.0) * self.lfo.tick(self.settings.rate);
+            let angle = (1.0 + pan) * std::f32::consts::FRAC_PI_4;
+            (*l, *r) = (*l * angle.cos() * std::f32::consts::SQRT_2, *r * angle.sin() * std::f32::consts::SQRT_2);
+        }
+    }
+
+    fn desc(&self) -> EffectDesc { EffectDesc::AutoPan(self.settings) }
+    fn reset(&mut self) { self.lfo.phase = 0.0 }
+    fn set_tempo(&mut self, bpm: f32) { self.lfo.bpm = bpm }
+
+    fn update(&mut self, desc: &EffectDesc) -> bool {
+        let EffectDesc::AutoPan(settings) = desc else { return false };
+        self.settings = *settings;
+        true
+    }
+}
+
+#[cfg(test)]
+mod effects_tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_echoes_impulse() {
+        let mut d = Delay::new(DelaySettings { time: 0.01, feedback: 0.5, mix: 1.0 }, 1000.0);
+        let out: Vec<f32> = (0..40).map(|i| d.tick(if i == 0 { 1.0 } else { 0.0 })).collect();
+        assert_eq!(out[0], 0.0);
+        assert!((out[10] - 1.0).abs() < 1e-6);
+        assert!((out[20] - 0.5).abs() < 1e-6);
+        assert!((out[30] - 0.25).abs() < 1e-6);
+        assert_eq!(out[15], 0.0);
+    }
+
+    #[test]
+    fn test_reverb_tail_decays() {
+        let mut r = Reverb::new(ReverbSettings { room_size: 0.5, damping: 0.5, mix: 1.0 }, 44100.0);
+        let out: Vec<f32> = (0..88200).map(|i| r.tick(if i == 0 { 1.0 } else { 0.0 })).collect();
+        let energy = |s: &[f32]| s.iter().map(|x| x * x).sum::<f32>();
+        assert!(energy(&out[..4410]) > 0.0);
+        assert!(energy(&out[44100..48510]) < energy(&out[2000..6410]));
+        assert!(out.iter().all(|x| x.is_finite() && x.abs() < 1.0));
+    }
+
+    #[test]
+    fn test_chain_order_and_bypass() {
+        let mut chain = EffectChain::from_desc(&default_effects(), 44100.0);
+        assert_eq!(chain.find("reverb"), Some(1));
+        chain.move_effect(1, 0);
+        assert_eq!(chain.find("reverb"), Some(0));
+
+        // everything bypassed, the buffers must come out untouched.
+        let (mut left, mut right) = ([0.5, -0.25, 1.0], [0.5, 0.25, 0.0]);
+        chain.process(&mut left, &mut right, &[]);
+        assert_eq!((left, right), ([0.5, -0.25, 1.0], [0.5, 0.25, 0.0]));
+
+        chain.set_bypass(1, false);
+        chain.process(&mut left, &mut right, &[]);
+        assert_ne!(left, [0.5, -0.25, 1.0]);
+        assert_eq!(EffectChain::from_desc(&chain.desc(), 48000.0).desc(), chain.desc());
+    }
+
+    #[test]
+    fn test_vocoder_follows_modulator() {
+        let sr = 16000.0;
+        let carrier: Vec<f32> = (0..8000).map(|i| (i as f32 * 0.37).sin() + (i as f32 * 0.11).sin()).collect();
+        let mut v = Vocoder::new(VocoderSettings { bands: 8, mix: 1.0 }, sr);
+        let silent: f32 = carrier.iter().map(|c| v.tick(*c, 0.0).abs()).sum();
+        assert_eq!(silent, 0.0);
+
+        let modulator = |i: usize| (std::f32::consts::TAU * 440.0 * i as f32 / sr).sin();
+        let voiced: Vec<f32> = carrier.iter().enumerate().map(|(i, c)| v.tick(*c, modulator(i))).collect();
+        assert!(voiced[4000..].iter().any(|x| x.abs() > 0.01));
+        assert!(voiced.iter().all(|x| x.is_finite()));
+        assert_eq!(Vocoder::new(VocoderSettings { bands: 100, mix: 1.0 }, sr).settings.bands, Vocoder::MAX_BANDS);
+    }
+
+    #[test]
+    fn test_soft_clip() {
+        assert_eq!(soft_clip(0.5), 0.5);
+        assert_eq!(soft_clip(-0.8), -0.8);
+        assert!(soft_clip(1.0) > 0.8 && soft_clip(1.0) < 1.0);
+        assert!(soft_clip(100.0) <= 1.0 && soft_clip(-100.0) >= -1.0);
+        assert!(soft_clip(0.81) > soft_clip(0.8) && soft_clip(2.0) > soft_clip(1.5));
+    }
+
+    #[test]
+    fn test_wavefolder() {
+        let gentle = WavefolderSettings { fold: 1.0, ..WavefolderSettings::default() };
+        assert!((gentle.fold(1.0) - 1.0).abs() < 1e-6 && gentle.fold(-1.0) < 0.0);
+        // past the peak the wave comes back down, a full fold at 3.
+        let folded = WavefolderSettings::default();
+        assert!((folded.fold(1.0) + 1.0).abs() < 1e-6);
+        let skewed = WavefolderSettings { symmetry: 0.5, ..folded };
+        assert_eq!(skewed.fold(0.0), 0.0);
+        assert!((skewed.fold(0.3) - skewed.fold(-0.3)).abs() > 0.1);
+        assert_eq!(WavefolderSettings::off().fold(0.7), 0.7);
+
+        let mut folder = Wavefolder { settings: folded };
+        let (mut left, mut right) = ([1.0], [0.0]);
+        folder.process_stereo(&mut left, &mut right);
+        assert!((left[0] + 1.0).abs() < 1e-6 && right[0] == 0.0);
+    }
+
+    #[test]
+    fn test_flanger_feedback() {
+        // no sweep, a 5 ms comb at 1 kHz: the impulse comes back every 5
+        // samples, flipping sign with negative feedback.
+        let settings = FlangerSettings { rate: 0.0, depth: 0.0, manual: 0.005, feedback: -0.5, mix: 1.0 };
+        let mut f = Flanger::new(settings, 1000.0);
+        let mut buf: Vec<f32> = (0..16).map(|i| if i == 0 { 1.0 } else { 0.0 }).collect();
+        f.process(&mut buf);
+        assert_eq!((buf[0], buf[5], buf[10], buf[15]), (0.0, 1.0, -0.5, 0.25));
+
+        // a swept line stays finite, even at the feedback limit.
+        let mut f = Flanger::new(FlangerSettings { feedback: 2.0, rate: 5.0, ..FlangerSettings::default() }, 48000.0);
+        let mut left: Vec<f32> = (0..48000).map(|i| (i as f32 * 0.05).sin()).collect();
+        let mut right = left.clone();
+        f.process_stereo(&mut left, &mut right);
+        assert!(left.iter().chain(&right).all(|x| x.is_finite() && x.abs() < 20.0));
+        // the channels sweep apart.
+        assert!((left[30000] - right[30000]).abs() > 1e-3);
+    }
+
+    #[test]
+    fn test_ping_pong_bounces() {
+        // a quarter note at 120 bpm is half a second, 50 samples at 100 Hz.
+        let settings = PingPongSettings { division: NoteDivision::new(4, crate::audio::tempo::Feel::Straight), feedback: 0.5, mix: 1.0 };
+        let mut chain = EffectChain::from_desc(&[EffectSlotDesc { effect: EffectDesc::PingPong(settings), bypass: false }], 100.0);
+        let (mut left, mut right) = (vec![0.0; 200], vec![0.0; 200]);
+        (left[0], right[0]) = (1.0, 1.0);
+        chain.process(&mut left, &mut right, &[]);
+        assert_eq!((left[50], right[50]), (1.0, 0.0));
+        assert_eq!((left[100], right[100]), (0.0, 1.0));
+        assert_eq!((left[150], right[150]), (0.5, 0.0));
+
+        // new settings keep the echoes going.
+        chain.update(0, EffectDesc::PingPong(PingPongSettings { mix: 0.5, ..settings }));
+        let (mut left, mut right) = (vec![0.0; 10], vec![0.0; 10]);
+        chain.process(&mut left, &mut right, &[]);
+        assert_eq!((left[0], right[0]), (0.0, 0.25));
+        chain.update(0, EffectDesc::PingPong(settings));
+
+        // twice the tempo, half the time.
+        chain.set_tempo(240.0);
+        chain.slots_mut()[0].effect.reset();
+        let (mut left, mut right) = (vec![0.0; 30], vec![0.0; 30]);
+        left[0] = 2.0;
+        chain.process(&mut left, &mut right, &[]);
+        assert_eq!(left[25], 1.0);
+    }
+
+    #[test]
+    fn test_limiter_ceiling() {
+        let sr = 48000.0;
+        let mut limiter = Limiter::new(LimiterSettings { ceiling: -6.0, release: 0.05 }, sr);
+        let ceiling = 10f32.powf(-6.0 / 20.0);
+        // quiet, then a burst four times over full scale.
+        let input: Vec<f32> = (0..24000).map(|i| {
+            let x = (i as f32 * 0.03).sin();
+            if (9000..9500).contains(&i) { 4.0 * x } else { 0.2 * x }
+        }).collect();
+        let (mut left, mut right) = (input.clone(), input.clone());
+        let (burst, rest) = (9400, 24000);
+        limiter.process(&mut left[..burst], &mut right[..burst]);
+        assert!(limiter.reduction() < -15.0);
+        limiter.process(&mut left[burst..rest], &mut right[burst..rest]);
+        assert!(limiter.reduction() > -0.1);
+        assert!(left.iter().all(|x| x.abs() <= ceiling + 1e-5));
+        // untouched under the ceiling, before the burst and once the gain
+        // has recovered.
+        let d = limiter.latency();
+        assert_eq!(left[d + 100], input[100]);
+        assert!((left[23000] - input[23000 - d]).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_eq_bands() {
+        let sr = 48000.0;
+        // steady state gain of a sine through the equalizer, in dB.
+        let gain = |settings: EqSettings, freq: f32| {
+            let mut eq = Equalizer::new(settings, sr);
+            let mut buf: Vec<f32> = (0..48000).map(|i| (std::f32::consts::TAU * freq * i as f32 / sr).sin()).collect();
+            eq.process(&mut buf);
+            20.0 * buf[24000..].iter().fold(0.0f32, |m, x| m.max(x.abs())).log10()
+        };
+        let flat = EqSettings::default();
+        assert!([50.0, 1000.0, 12000.0].iter().all(|f| gain(flat, *f).abs() < 0.05));
+
+        let mut boosted = flat;
+        boosted.band_mut(0).gain = 6.0;
+        boosted.band_mut(1).gain = -12.0;
+        boosted.band_mut(2).gain = 3.0;
+        assert!((gain(boosted, 30.0) - 6.0).abs() < 0.3);
+        assert!((gain(boosted, 1000.0) + 12.0).abs() < 0.3);
+        assert!((gain(boosted, 18000.0) - 3.0).abs() < 0.3);
+
+        // new settings are taken in place rather than by a rebuild.
+        let mut chain = EffectChain::from_desc(&[EffectSlotDesc { effect: EffectDesc::Eq(flat), bypass: false }], sr);
+        chain.update(0, EffectDesc::Eq(boosted));
+        assert_eq!(chain.desc()[0].effect, EffectDesc::Eq(boosted));
+    }
+
+    #[test]
+    fn test_compressor() {
+        let sr = 1000.0;
+        let settings = CompressorSettings { threshold: -20.0, ratio: 4.0, attack: 0.001, release: 0.1, makeup: 0.0 };
+        let mut c = Compressor::new(settings, sr);
+        // a steady 0 dB signal, 20 dB over, comes out 15 dB down.
+        let out: Vec<f32> = (0..500).map(|_| c.tick(1.0, -1.0).0).collect();
+        assert!((20.0 * out[499].log10() + 15.0).abs() < 0.01);
+        assert!((c.reduction() + 15.0).abs() < 0.01);
+        // under the threshold it recovers, over the release time.
+        c.tick(0.01, 0.01);
+        assert!(c.reduction() < -14.0);
+        (0..1000).for_each(|_| { c.tick(0.01, 0.01); });
+        assert!(c.reduction() > -0.01);
+
+        let mut c = Compressor::new(CompressorSettings { makeup: 6.0, ..settings }, sr);
+        assert!((c.tick(0.05, 0.0).0 - 0.05 * 10f32.powf(0.3)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_tremolo_and_auto_pan() {
+        // a quarter note at 120 bpm, two cycles a second: 500 samples at 1 kHz.
+        let synced = LfoRate::Synced(NoteDivision::new(4, crate::audio::tempo::Feel::Straight));
+        let desc = EffectDesc::Tremolo(TremoloSettings { rate: synced, depth: 0.5 });
+        let mut chain = EffectChain::from_desc(&[EffectSlotDesc { effect: desc, bypass: false }], 1000.0);
+        let (mut left, mut right) = (vec![1.0; 1000], vec![1.0; 1000]);
+        chain.process(&mut left, &mut right, &[]);
+        assert!((left[125] - 1.0).abs() < 1e-4 && (left[375] - 0.5).abs() < 1e-4 && (right[875] - 0.5).abs() < 1e-4);
+        // twice the tempo, twice as fast.
+        chain.set_tempo(240.0);
+        chain.slots_mut()[0].effect.reset();
+        let mut left = vec![1.0; 1000];
+        chain.process(&mut left, &mut vec![1.0; 1000], &[]);
+        assert!((left[187] - 0.5).abs() < 1e-3);
+
+        let desc = EffectDesc::AutoPan(AutoPanSettings { rate: LfoRate::Free(1.0), depth: 1.0 });
+        let mut chain = EffectChain::from_desc(&[EffectSlotDesc { effect: desc, bypass: false }], 1000.0);
+        let (mut left, mut right) = (vec![1.0; 1000], vec![1.0; 1000]);
+        chain.process(&mut left, &mut right, &[]);
+        // unity in the center, all the way right a quarter cycle in.
+        assert!((left[0] - 1.0).abs() < 1e-6 && (right[0] - 1.0).abs() < 1e-6);
+        assert!(left[250].abs() < 1e-4 && (right[250] - std::f32::consts::SQRT_2).abs() < 1e-4);
+        assert!(right[750].abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_saturation_curves() {
+        let tanh = SaturationSettings { drive: 0.0, output: 0.0, ..SaturationSettings::default() };
+        assert!((tanh.saturate(0.5) - 0.5f32.tanh()).abs() < 1e-6 && (tanh.saturate(-0.5) + 0.5f32.tanh()).abs() < 1e-6);
+        // 20 dB in takes 0.5 to 5, clipped to 1, then 6 dB down.
+        let hard = SaturationSettings { curve: SaturationCurve::Hard, drive: 20.0, output: -6.0, mix: 1.0 };
+        assert!((hard.saturate(0.5) - 10f32.powf(-0.3)).abs() < 1e-6 && (hard.saturate(0.01) - 0.1 * 10f32.powf(-0.3)).abs() < 1e-6);
+        let tube = SaturationSettings { curve: SaturationCurve::Tube, ..tanh };
+        assert_eq!(tube.saturate(0.0), 0.0);
+        assert!(tube.saturate(4.0) < -tube.saturate(-4.0));
+        assert_eq!(SaturationSettings::off().saturate(0.7), 0.7);
+        assert_eq!(SaturationCurve::Tube.next(), SaturationCurve::Tanh);
+
+        let mut drive = Saturation { settings: hard };
+        let (mut left, mut right) = ([0.5], [0.0]);
+        drive.process_stereo(&mut left, &mut right);
+        assert!((left[0] - 10f32.powf(-0.3)).abs() < 1e-6 && right[0] == 0.0);
+    }
+
+    #[test]
+    fn test_convolution_predelay() {
+        let ir = ImpulseResponse { channels: vec![vec![0.0, 1.0], vec![0.0, 0.0, 0.5]] };
+        let settings = ConvolutionSettings { mix: 1.0, predelay: 0.0, ..ConvolutionSettings::default() };
+        let mut c = Convolution::new(settings.clone(), &ir, 1000.0);
+        let (mut left, mut right) = (vec![0.0; 2000], vec![0.0; 2000]);
+        (left[0], right[0]) = (1.0, 1.0);
+        c.process_stereo(&mut left, &mut right);
+        // a block late, then each channel its own response.
+        let b = Convolver::BLOCK;
+        assert!((left[b + 1] - 1.0).abs() < 1e-5 && (right[b + 2] - 0.5).abs() < 1e-5);
+        assert!(left[b + 2].abs() < 1e-5 && right[b + 1].abs() < 1e-5);
+
+        // a quarter second of pre-delay, the block included.
+        let mut c = Convolution::new(settings.clone(), &ir, 4000.0);
+        assert!(c.update(&EffectDesc::Convolution(ConvolutionSettings { predelay: 0.25, ..settings.clone() })));
+        let mut buf = vec![0.0; 2000];
+        buf[0] = 1.0;
+        c.process(&mut buf);
+        assert!((buf[1001] - 1.0).abs() < 1e-5 && buf[b + 1].abs() < 1e-5);
+        // a mono response plays on both sides.
+        let mono = ImpulseResponse { channels: vec![vec![1.0]] };
+        assert_eq!(mono.channel(1), &[1.0]);
+        assert!(!c.update(&EffectDesc::Convolution(ConvolutionSettings { ir: PathBuf::from("hall.wav"), ..settings })));
+    }
+
+    #[test]
+    fn test_mono_effect_keeps_side() {
+        let mut d = Delay::new(DelaySettings { time: 0.01, feedback: 0.0, mix: 1.0 }, 1000.0);
+        // opposite channels cancel in the mid, only the dry side is left.
+        let (mut left, mut right) = ([1.0, 0.5], [-1.0, -0.5]);
+        d.process_stereo(&mut left, &mut right);
+        assert_eq!((left, right), ([1.0, 0.5], [-1.0, -0.5]));
+    }
+}
@@ -0,0 +1,258 @@
+//! Expr module.
+//!
+//! waveforms typed in as a formula of `t`, the seconds into a cycle, and
+//! `f`, the frequency of the note, like `sin(2*pi*f*t) + 0.3*sin(4*pi*f*t)`.
+//! the formula is parsed once and rendered into tables like a script, with
+//! no scripting engine needed. it's kept in the patch as written.
+//!
+//! numbers, `t`, `f` (or `freq`), `pi`, `tau` and `e`, `+ - * / % ^` and
+//! parentheses, and the functions sin, cos, tan, tanh, abs, sqrt, exp, ln,
+//! floor, fract, sign, min, max and pow.
+//!
+
+use crate::audio::waves::{OctaveTables, WaveDesc, WaveGenerator};
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ParseError { pub message: String, pub at: usize }
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message, self.at + 1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Op { Add, Sub, Mul, Div, Rem, Pow }
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Func { Sin, Cos, Tan, Tanh, Abs, Sqrt, Exp, Ln, Floor, Fract, Sign, Min, Max, Pow }
+
+impl Func {
+    const ALL: [(&'static str, Func); 14] = [
+        ("sin", Func::Sin), ("cos", Func::Cos), ("tan", Func::Tan), ("tanh", Func::Tanh), ("abs", Func::Abs),
+        ("sqrt", Func::Sqrt), ("exp", Func::Exp), ("ln", Func::Ln), ("floor", Func::Floor), ("fract", Func::Fract),
+        ("sign", Func::Sign), ("min", Func::Min), ("max", Func::Max), ("pow", Func::Pow),
+    ];
+
+    fn arity(self) -> usize { if matches!(self, Func::Min | Func::Max | Func::Pow) { 2 } else { 1 } }
+
+    fn apply(self, x: f64, y: f64) -> f64 {
+        match self {
+            Func::Sin => x.sin(),
+            Func::Cos => x.cos(),
+            Func::Tan => x.tan(),
+            Func::Tanh => x.tanh(),
+            Func::Abs => x.abs(),
+            Func::Sqrt => x.sqrt(),
+            Func::Exp => x.exp(),
+            Func::Ln => x.ln(),
+            Func::Floor => x.floor(),
+            Func::Fract => x - x.floor(),
+            Func::Sign => if x == 0.0 { 0.0 } else { x.signum() },
+            Func::Min => x.min(y),
+            Func::Max => x.max(y),
+            Func::Pow => x.powf(y),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Expr {
+    Num(f64),
+    Time,
+    Freq,
+    Neg(Box<Expr>),
+    Bin(Op, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+impl Expr {
+    pub fn eval(&self, t: f64, freq: f64) -> f64 {
+        match self {
+            Expr::Num(x) => *x,
+            Expr::Time => t,
+            Expr::Freq => freq,
+            Expr::Neg(x) => -x.eval(t, freq),
+            Expr::Bin(op, a, b) => {
+                let (a, b) = (a.eval(t, freq), b.eval(t, freq));
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    Op::Rem => a.rem_euclid(b),
+                    Op::Pow => a.powf(b),
+                }
+            },
+            Expr::Call(func, args) => {
+                let x = args.first().map_or(0.0, |a| a.eval(t, freq));
+                func.apply(x, args.get(1).map_or(0.0, |a| a.eval(t, freq)))
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for Expr {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Expr, ParseError> {
+        let mut parser = Parser { text: s.as_bytes(), at: 0 };
+        let expr = parser.sum()?;
+        parser.skip_space();
+        match parser.peek() {
+            None => Ok(expr),
+            Some(c) => Err(parser.error(format!("unexpected '{}'", c as char))),
+        }
+    }
+}
+
+// recursive descent, from the loosest binding operators to the tightest.
+struct Parser<'a> { text: &'a [u8], at: usize }
+
+impl Parser<'_> {
+    fn error(&self, message: String) -> ParseError { ParseError { message, at: self.at } }
+
+    fn skip_space(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) { self.at += 1; }
+    }
+
+    fn peek(&self) -> Option<u8> { self.text.get(self.at).copied() }
+
+    // the next character if it's one of `chars`, past spaces.
+    fn eat(&mut self, chars: &[u8]) -> Option<u8> {
+        self.skip_space();
+        let c = self.peek().filter(|c| chars.contains(c))?;
+        self.at += 1;
+        Some(c)
+    }
+
+    fn sum(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.product()?;
+        while let Some(c) = self.eat(b"+-") {
+            let op = if c == b'+' { Op::Add } else { Op::Sub };
+            expr = Expr::Bin(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+        while let Some(c) = self.eat(b"*/%") {
+            let op = match c { b'*' => Op::Mul, b'/' => Op::Div, _ => Op::Rem };
+            expr = Expr::Bin(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    // a minus binds looser than a power, -2^2 is -4.
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat(b"-").is_some() { return Ok(Expr::Neg(Box::new(self.unary()?))); }
+        let base = self.atom()?;
+        match self.eat(b"^") {
+            Some(_) => Ok(Expr::Bin(Op::Pow, Box::new(base), Box::new(self.unary()?))),
+            None => Ok(base),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        self.skip_space();
+        let start = self.at;
+        match self.peek() {
+            Some(b'(') => {
+                self.at += 1;
+                let expr = self.sum()?;
+                self.eat(b")").ok_or_else(|| self.error(String::from("expected ')'")))?;
+                Ok(expr)
+            },
+            Some(c) if c.is_ascii_digit() || c == b'.' => {
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == b'.') { self.at += 1; }
+                let text = std::str::from_utf8(&self.text[start..self.at]).unwrap_or_default();
+                text.parse().map(Expr::Num).map_err(|_| ParseError { message: format!("invalid number '{}'", text), at: start })
+            },
+            Some(c) if c.is_ascii_alphabetic() => {
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_') { self.at += 1; }
+                let name = std::str::from_utf8(&self.text[start..self.at]).unwrap_or_default();
+                self.name(name, start)
+            },
+            Some(c) => Err(self.error(format!("unexpected '{}'", c as char))),
+            None => Err(self.error(String::from("unexpected end"))),
+        }
+    }
+
+    fn name(&mut self, name: &str, start: usize) -> Result<Expr, ParseError> {
+        match name {
+            "t" => return Ok(Expr::Time),
+            "f" | "freq" => return Ok(Expr::Freq),
+            "pi" => return Ok(Expr::Num(std::f64::consts::PI)),
+            "tau" => return Ok(Expr::Num(std::f64::consts::TAU)),
+            "e" => return Ok(Expr::Num(std::f64::consts::E)),
+            _ => (),
+        }
+        let func = Func::ALL.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+            .ok_or_else(|| ParseError { message: format!("unknown name '{}'", name), at: start })?;
+        self.eat(b"(").ok_or_else(|| self.error(format!("expected '(' after {}", name)))?;
+        let mut args = vec![self.sum()?];
+        while self.eat(b",").is_some() { args.push(self.sum()?); }
+        self.eat(b")").ok_or_else(|| self.error(String::from("expected ')'")))?;
+        if args.len() != func.arity() {
+            return Err(ParseError { message: format!("{} takes {} argument(s)", name, func.arity()), at: start });
+        }
+        Ok(Expr::Call(func, args))
+    }
+}
+
+pub struct ExprWave {
+    source: String,
+    tables: OctaveTables,
+}
+
+impl ExprWave {
+    pub fn parse(source: &str) -> Result<ExprWave, ParseError> {
+        let expr: Expr = source.parse()?;
+        let tables = OctaveTables::render(|t, freq| Ok::<_, ParseError>(expr.eval(t, freq)))?;
+        Ok(ExprWave { source: source.trim().to_string(), tables })
+    }
+}
+
+impl WaveGenerator for ExprWave {
+    fn gen(&mut self, t: f32) -> f32 { self.tables.gen(t) }
+    fn desc(&self) -> WaveDesc { WaveDesc::Expr(self.source.clone()) }
+    fn set_increment(&mut self, dt: f32) { self.tables.set_increment(dt) }
+}
+
+#[cfg(test)]
+mod expr_tests {
+    use super::*;
+
+    fn eval(s: &str) -> f64 { s.parse::<Expr>().unwrap().eval(0.5, 3.0) }
+
+    #[test]
+    fn test_parse_and_eval() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("2^3^2"), 512.0);
+        assert_eq!(eval("7 % 3 - 8 / 4"), -1.0);
+        assert_eq!(eval("t * f + freq"), 4.5);
+        assert_eq!(eval("max(sign(-t), pow(2, 3)) + floor(fract(1.25) * 8)"), 10.0);
+        assert!((eval("sin(2*pi*f*t/6) + tau/2 - pi") - 1.0).abs() < 1e-12);
+
+        let error = |s: &str| s.parse::<Expr>().unwrap_err();
+        assert_eq!(error("sin(t").message, "expected ')'");
+        assert_eq!(error("2 * x"), ParseError { message: String::from("unknown name 'x'"), at: 4 });
+        assert_eq!(error("min(1)").message, "min takes 2 argument(s)");
+        assert_eq!(error("1 2").to_string(), "unexpected '2' at 3");
+        assert_eq!(error("1 +").message, "unexpected end");
+    }
+
+    #[test]
+    fn test_expr_wave() {
+        let mut wave = ExprWave::parse(" sin(2*pi*f*t) + 0.2*sin(4*pi*f*t) ").unwrap();
+        assert_eq!(wave.desc(), WaveDesc::Expr(String::from("sin(2*pi*f*t) + 0.2*sin(4*pi*f*t)")));
+        wave.set_increment(440.0 / OctaveTables::RATE);
+        assert!((wave.gen(0.125) - (std::f32::consts::FRAC_1_SQRT_2 + 0.2)).abs() < 1e-3);
+        // division by zero is silence rather than a blown up speaker.
+        assert_eq!(ExprWave::parse("1/0 + sqrt(-1)").unwrap().gen(0.3), 0.0);
+    }
+}
@@ -0,0 +1,479 @@
+//! Filter module.
+//!
+//! filters process one sample at a time and keep their own state, so each
+//! voice needs its own instance.
+//!
+
+use serde::{Serialize, Deserialize};
+
+use crate::audio::waves::Envelope;
+
+pub trait Filter {
+    fn process(&mut self, x: f32) -> f32;
+    fn reset(&mut self) {}
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum FilterMode { LowPass, HighPass, BandPass, Notch }
+
+impl FilterMode {
+    pub fn next(self) -> FilterMode {
+        match self {
+            FilterMode::LowPass => FilterMode::HighPass,
+            FilterMode::HighPass => FilterMode::BandPass,
+            FilterMode::BandPass => FilterMode::Notch,
+            FilterMode::Notch => FilterMode::LowPass,
+        }
+    }
+}
+
+// the filter model voices run through.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum FilterKind { #[default] Biquad, Svf, Ladder }
+
+impl FilterKind {
+    pub fn next(self) -> FilterKind {
+        match self {
+            FilterKind::Biquad => FilterKind::Svf,
+            FilterKind::Svf => FilterKind::Ladder,
+            FilterKind::Ladder => FilterKind::Biquad,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct FilterSettings {
+    pub mode: FilterMode,
+    pub cutoff: f32,
+    pub resonance: f32,
+    #[serde(default)]
+    pub kind: FilterKind,
+    // gain into the ladder, which saturates. the other kinds are linear.
+    #[serde(default = "default_drive")]
+    pub drive: f32,
+    // how much the cutoff follows the note, 1 moving it an octave per
+    // octave away from `KEYTRACK_NOTE`.
+    #[serde(default)]
+    pub keytrack: f32,
+}
+
+impl FilterSettings {
+    // note the cutoff is set for, midi middle c.
+    pub const KEYTRACK_NOTE: f32 = 60.0;
+
+    // octaves the cutoff moves for `note`.
+    pub fn tracking(&self, note: f32) -> f32 { self.keytrack * (note - FilterSettings::KEYTRACK_NOTE) / 12.0 }
+}
+
+fn default_drive() -> f32 { 1.0 }
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        FilterSettings {
+            mode: FilterMode::LowPass, cutoff: 20000.0, resonance: std::f32::consts::FRAC_1_SQRT_2,
+            kind: FilterKind::Biquad, drive: default_drive(), keytrack: 0.0,
+        }
+    }
+}
+
+// a second adsr for the cutoff of each voice, moving it `amount` octaves
+// at the top of the envelope. negative amounts sweep it down.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct FilterEnvelope { pub envelope: Envelope, pub amount: f32 }
+
+impl FilterEnvelope {
+    pub const MAX_AMOUNT: f32 = 8.0;
+
+    // octaves the cutoff moves by, `t` seconds in.
+    pub fn octaves(&self, t: f32, t0: f32, t1: Option<f32>) -> f32 {
+        if self.amount == 0.0 { return 0.0; }
+        self.envelope.sample(t, t0, t1) * self.amount
+    }
+}
+
+impl Default for FilterEnvelope {
+    fn default() -> Self { FilterEnvelope { envelope: Envelope(0.005, 0.3, 0.0, 0.3), amount: 0.0 } }
+}
+
+// shelving and peaking responses for equalizers, set directly on a
+// biquad rather than through `FilterSettings`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum EqShape { LowShelf, Peak, HighShelf }
+
+/// RBJ cookbook biquad in transposed direct form II.
+///
+/// `resonance` is the filter Q, 0.707 being a flat response. coefficients
+/// are only recomputed when the settings change.
+pub struct Biquad {
+    settings: FilterSettings,
+    sample_rate: f32,
+    b0: f32, b1: f32, b2: f32, a1: f32, a2: f32,
+    z1: f32, z2: f32,
+}
+
+impl Biquad {
+    pub fn new(settings: FilterSettings, sample_rate: f32) -> Biquad {
+        let mut b = Biquad { settings, sample_rate, b0: 1.0, b1: 0.0, b2: 0.0, a1: 0.0, a2: 0.0, z1: 0.0, z2: 0.0 };
+        b.update_coefficients();
+        b
+    }
+
+    pub fn settings(&self) -> FilterSettings { self.settings }
+
+    pub fn set_settings(&mut self, settings: FilterSettings) {
+        if settings != self.settings {
+            self.settings = settings;
+            self.update_coefficients();
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.update_coefficients();
+        }
+    }
+
+    fn update_coefficients(&mut self) {
+        let nyquist = self.sample_rate * 0.5;
+        let cutoff = self.settings.cutoff.clamp(10.0, nyquist * 0.99);
+        let q = self.settings.resonance.max(0.1);
+        let w0 = std::f32::consts::TAU * cutoff / self.sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+
+        let (b0, b1, b2) = match self.settings.mode {
+            FilterMode::LowPass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0),
+            FilterMode::HighPass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0),
+            FilterMode::BandPass => (alpha, 0.0, -alpha),
+            FilterMode::Notch => (1.0, -2.0 * cos, 1.0),
+        };
+        let a0 = 1.0 + alpha;
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = -2.0 * cos / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+
+    /// Makes this an equalizer band, `gain` dB at `freq` (the middle of
+    /// the slope for shelves). the state is kept, so bands can move while
+    /// audio runs through them.
+    pub fn set_equalizer(&mut self, shape: EqShape, freq: f32, gain: f32, q: f32) {
+        let freq = freq.clamp(10.0, self.sample_rate * 0.5 * 0.99);
+        let a = 10f32.powf(gain / 40.0);
+        let (sin, cos) = (std::f32::consts::TAU * freq / self.sample_rate).sin_cos();
+        let alpha = sin / (2.0 * q.max(0.1));
+        let shelf = 2.0 * a.sqrt() * alpha;
+        let (b0, b1, b2, a0, a1, a2) = match shape {
+            EqShape::Peak => (1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a),
+            EqShape::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf), 2.0 * a * ((a - 1.0) - (a + 1.0) * cos), a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf, -2.0 * ((a - 1.0) + (a + 1.0) * cos), (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            EqShape::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf), -2.0 * a * ((a - 1.0) + (a + 1.0) * cos), a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf, 2.0 * ((a - 1.0) - (a + 1.0) * cos), (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+        };
+        (self.b0, self.b1, self.b2, self.a1, self.a2) = (b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0);
+    }
+}
+
+impl Filter for Biquad {
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    fn reset(&mut self) { self.z1 = 0.0; self.z2 = 0.0; }
+}
+
+/// Topology-preserving state variable filter (Zavalishin, Simper).
+///
+/// all four responses come out of the same two integrators, so switching
+/// modes doesn't disturb the state, and it stays well behaved however fast
+/// the cutoff moves.
+pub struct Svf {
+    settings: FilterSettings,
+    sample_rate: f32,
+    k: f32, a1: f32, a2: f32, a3: f32,
+    ic1eq: f32, ic2eq: f32,
+}
+
+impl Svf {
+    pub fn new(settings: FilterSettings, sample_rate: f32) -> Svf {
+        let mut svf = Svf { settings, sample_rate, k: 0.0, a1: 0.0, a2: 0.0, a3: 0.0, ic1eq: 0.0, ic2eq: 0.0 };
+        svf.update_coefficients();
+        svf
+    }
+
+    pub fn settings(&self) -> FilterSettings { self.settings }
+
+    pub fn set_settings(&mut self, settings: FilterSettings) {
+        let retune = settings.cutoff != self.settings.cutoff || settings.resonance != self.settings.resonance;
+        self.settings = settings;
+        if retune { self.update_coefficients(); }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.update_coefficients();
+        }
+    }
+
+    fn update_coefficients(&mut self) {
+        let cutoff = self.settings.cutoff.clamp(10.0, self.sample_rate * 0.49);
+        let g = (std::f32::consts::PI * cutoff / self.sample_rate).tan();
+        self.k = 1.0 / self.settings.resonance.max(0.1);
+        self.a1 = 1.0 / (1.0 + g * (g + self.k));
+        self.a2 = g * self.a1;
+        self.a3 = g * self.a2;
+    }
+}
+
+impl Filter for Svf {
+    fn process(&mut self, x: f32) -> f32 {
+        let v3 = x - self.ic2eq;
+        let v1 = self.a1 * self.ic1eq + self.a2 * v3;
+        let v2 = self.ic2eq + self.a2 * self.ic1eq + self.a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+        match self.settings.mode {
+            FilterMode::LowPass => v2,
+            // scaled to unity at the center, like the biquad.
+            FilterMode::BandPass => self.k * v1,
+            FilterMode::HighPass => x - self.k * v1 - v2,
+            FilterMode::Notch => x - self.k * v1,
+        }
+    }
+
+    fn reset(&mut self) { self.ic1eq = 0.0; self.ic2eq = 0.0; }
+}
+
+/// Four pole ladder in zero delay feedback form (Zavalishin), saturating
+/// where the feedback meets the input.
+///
+/// the resonance Q maps onto the feedback so that 0.707 is gentle and 5
+/// and up self-oscillates, held in check by the saturation. the drive
+/// pushes the input into it. low pass is the ladder itself, the other
+/// modes mix its stages.
+pub struct Ladder {
+    settings: FilterSettings,
+    sample_rate: f32,
+    // gain of one stage, and the feedback.
+    g: f32, k: f32,
+    stages: [f32; 4],
+}
+
+impl Ladder {
+    // feedback at which the ladder rings on its own.
+    pub const SELF_OSCILLATION: f32 = 4.0;
+
+    pub fn new(settings: FilterSettings, sample_rate: f32) -> Ladder {
+        let mut ladder = Ladder { settings, sample_rate, g: 0.0, k: 0.0, stages: [0.0; 4] };
+        ladder.update_coefficients();
+        ladder
+    }
+
+    pub fn settings(&self) -> FilterSettings { self.settings }
+
+    pub fn set_settings(&mut self, settings: FilterSettings) {
+        let retune = settings.cutoff != self.settings.cutoff || settings.resonance != self.settings.resonance;
+        self.settings = settings;
+        if retune { self.update_coefficients(); }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.update_coefficients();
+        }
+    }
+
+    fn update_coefficients(&mut self) {
+        let cutoff = self.settings.cutoff.clamp(10.0, self.sample_rate * 0.45);
+        let g = (std::f32::consts::PI * cutoff / self.sample_rate).tan();
+        self.g = g / (1.0 + g);
+        self.k = Ladder::SELF_OSCILLATION * (self.settings.resonance / 5.0).clamp(0.0, 1.1);
+    }
+}
+
+impl Filter for Ladder {
+    fn process(&mut self, x: f32) -> f32 {
+        let (g, k) = (self.g, self.k);
+        // the output is g^4 u plus what the stages hold, which solves the
+        // feedback for this sample. half the input is fed back along with
+        // the output, so the pass band keeps its level as the resonance
+        // goes up.
+        let held = self.stages.iter().fold(0.0, |acc, s| acc * g + s * (1.0 - g));
+        let x = x * self.settings.drive.max(0.0) * (1.0 + 0.5 * k);
+        let u = ((x - k * held) / (1.0 + k * g.powi(4))).tanh();
+        let mut input = u;
+        let mut out = [0.0; 4];
+        for (stage, y) in self.stages.iter_mut().zip(out.iter_mut()) {
+            let v = (input - *stage) * g;
+            *y = v + *stage;
+            *stage = *y + v;
+            input = *y;
+        }
+        let [y1, y2, y3, y4] = out;
+        match self.settings.mode {
+            FilterMode::LowPass => y4,
+            FilterMode::HighPass => u - 4.0 * y1 + 6.0 * y2 - 4.0 * y3 + y4,
+            FilterMode::BandPass => 4.0 * (y2 - 2.0 * y3 + y4),
+            FilterMode::Notch => u - 4.0 * y1 + 6.0 * y2 - 4.0 * y3 + 2.0 * y4,
+        }
+    }
+
+    fn reset(&mut self) { self.stages = [0.0; 4]; }
+}
+
+/// The filter of one voice channel, of the kind its settings ask for.
+///
+/// changing kind starts the new filter from silence.
+pub enum VoiceFilter { Biquad(Biquad), Svf(Svf), Ladder(Ladder) }
+
+impl VoiceFilter {
+    pub fn new(settings: FilterSettings, sample_rate: f32) -> VoiceFilter {
+        match settings.kind {
+            FilterKind::Biquad => VoiceFilter::Biquad(Biquad::new(settings, sample_rate)),
+            FilterKind::Svf => VoiceFilter::Svf(Svf::new(settings, sample_rate)),
+            FilterKind::Ladder => VoiceFilter::Ladder(Ladder::new(settings, sample_rate)),
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: FilterSettings) {
+        match self {
+            VoiceFilter::Biquad(f) if settings.kind == FilterKind::Biquad => f.set_settings(settings),
+            VoiceFilter::Svf(f) if settings.kind == FilterKind::Svf => f.set_settings(settings),
+            VoiceFilter::Ladder(f) if settings.kind == FilterKind::Ladder => f.set_settings(settings),
+            VoiceFilter::Biquad(Biquad { sample_rate, .. }) | VoiceFilter::Svf(Svf { sample_rate, .. })
+                | VoiceFilter::Ladder(Ladder { sample_rate, .. }) => {
+                *self = VoiceFilter::new(settings, *sample_rate);
+            },
+        }
+    }
+}
+
+impl Filter for VoiceFilter {
+    fn process(&mut self, x: f32) -> f32 {
+        match self {
+            VoiceFilter::Biquad(f) => f.process(x),
+            VoiceFilter::Svf(f) => f.process(x),
+            VoiceFilter::Ladder(f) => f.process(x),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            VoiceFilter::Biquad(f) => f.reset(),
+            VoiceFilter::Svf(f) => f.reset(),
+            VoiceFilter::Ladder(f) => f.reset(),
+        }
+    }
+}
+
+/// One pole high-pass at a few Hz, removing the dc offset some random
+/// oscillators produce: y[n] = x[n] - x[n-1] + r * y[n-1].
+pub struct DcBlocker { r: f32, x1: f32, y1: f32 }
+
+impl DcBlocker {
+    pub const CUTOFF: f32 = 10.0;
+
+    pub fn new(sample_rate: f32) -> DcBlocker {
+        let mut blocker = DcBlocker { r: 0.0, x1: 0.0, y1: 0.0 };
+        blocker.set_sample_rate(sample_rate);
+        blocker
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.r = (-std::f32::consts::TAU * DcBlocker::CUTOFF / sample_rate.max(1.0)).exp();
+    }
+}
+
+impl Filter for DcBlocker {
+    fn process(&mut self, x: f32) -> f32 {
+        let y = x - self.x1 + self.r * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+
+    fn reset(&mut self) { self.x1 = 0.0; self.y1 = 0.0; }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::*;
+
+    // peak output amplitude for a sine input, after the filter settles.
+    fn response(filter: &mut dyn Filter, freq: f32, sr: f32) -> f32 {
+        (0..4800).map(|i| filter.process((std::f32::consts::TAU * freq * i as f32 / sr).sin()))
+            .skip(2400).fold(0.0, |m: f32, y| m.max(y.abs()))
+    }
+
+    #[test]
+    fn test_biquad_low_pass() {
+        let settings = FilterSettings { mode: FilterMode::LowPass, cutoff: 1000.0, resonance: 0.707, ..FilterSettings::default() };
+        assert!(response(&mut Biquad::new(settings, 48000.0), 100.0, 48000.0) > 0.95);
+        assert!(response(&mut Biquad::new(settings, 48000.0), 10000.0, 48000.0) < 0.05);
+    }
+
+    #[test]
+    fn test_biquad_high_pass() {
+        let settings = FilterSettings { mode: FilterMode::HighPass, cutoff: 1000.0, resonance: 0.707, ..FilterSettings::default() };
+        assert!(response(&mut Biquad::new(settings, 48000.0), 100.0, 48000.0) < 0.05);
+        assert!(response(&mut Biquad::new(settings, 48000.0), 10000.0, 48000.0) > 0.95);
+    }
+
+    #[test]
+    fn test_svf_modes() {
+        let settings = FilterSettings { cutoff: 1000.0, resonance: 0.707, kind: FilterKind::Svf, ..FilterSettings::default() };
+        let svf = |mode| VoiceFilter::new(FilterSettings { mode, ..settings }, 48000.0);
+        assert!(response(&mut svf(FilterMode::LowPass), 100.0, 48000.0) > 0.95);
+        assert!(response(&mut svf(FilterMode::LowPass), 10000.0, 48000.0) < 0.05);
+        assert!(response(&mut svf(FilterMode::HighPass), 100.0, 48000.0) < 0.05);
+        assert!(response(&mut svf(FilterMode::BandPass), 1000.0, 48000.0) > 0.95);
+        assert!(response(&mut svf(FilterMode::Notch), 1000.0, 48000.0) < 0.05);
+        assert!(response(&mut svf(FilterMode::Notch), 10000.0, 48000.0) > 0.95);
+
+        // sweeping the cutoff every sample stays bounded.
+        let mut filter = svf(FilterMode::LowPass);
+        let peak = (0..48000).fold(0.0f32, |peak, i| {
+            let cutoff = 20.0 * 1000f32.powf(((i as f32 * 0.01).sin() + 1.0) / 2.0);
+            filter.set_settings(FilterSettings { cutoff, resonance: 10.0, ..settings });
+            peak.max(filter.process(if i % 100 < 50 { 1.0 } else { -1.0 }).abs())
+        });
+        assert!(peak.is_finite() && peak < 20.0);
+    }
+
+    #[test]
+    fn test_ladder() {
+        let settings = FilterSettings { cutoff: 1000.0, kind: FilterKind::Ladder, ..FilterSettings::default() };
+        let ladder = |resonance| Ladder::new(FilterSettings { resonance, ..settings }, 48000.0);
+        // four poles: 24 dB per octave above the cutoff.
+        assert!(response(&mut ladder(0.707), 100.0, 48000.0) > 0.3);
+        assert!(response(&mut ladder(0.707), 10000.0, 48000.0) < 0.01);
+
+        // past the threshold a single click keeps it ringing.
+        let mut filter = ladder(10.0);
+        filter.process(1.0);
+        let ringing = (0..48000).map(|_| filter.process(0.0)).skip(47000).fold(0.0f32, |m, y| m.max(y.abs()));
+        assert!(ringing > 0.1 && ringing < 2.0, "{}", ringing);
+        let mut filter = ladder(2.0);
+        filter.process(1.0);
+        assert!((0..48000).map(|_| filter.process(0.0)).last().unwrap().abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_dc_blocker() {
+        let mut blocker = DcBlocker::new(48000.0);
+        let settled = (0..48000).map(|_| blocker.process(0.5)).last().unwrap();
+        assert!(settled.abs() < 1e-3);
+        assert!(response(&mut DcBlocker::new(48000.0), 100.0, 48000.0) > 0.95);
+    }
+}
@@ -0,0 +1,140 @@
+//! Fm module.
+//!
+//! four operator phase modulation synthesis, as on the DX family. each
+//! operator is a sine with its own frequency ratio, level and envelope,
+//! and the algorithm decides which operators modulate which.
+//!
+
+use std::f32::consts::TAU;
+use serde::{Serialize, Deserialize};
+
+use crate::audio::waves::Envelope;
+
+// operators are numbered 1 to 4 as on the synths, `a -> b` reads "a
+// modulates b". the operators left at the end of the chains are heard.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum FmAlgorithm {
+    // 4 -> 3 -> 2 -> 1
+    Stack,
+    // 2 -> 1, 4 -> 3
+    Pairs,
+    // 2, 3, 4 -> 1
+    Branch,
+    // 4 -> 3 -> 1, 2 -> 1
+    Y,
+    // no modulation, four sines mixed.
+    Additive,
+}
+
+impl FmAlgorithm {
+    pub fn next(self) -> FmAlgorithm {
+        match self {
+            FmAlgorithm::Stack => FmAlgorithm::Pairs,
+            FmAlgorithm::Pairs => FmAlgorithm::Branch,
+            FmAlgorithm::Branch => FmAlgorithm::Y,
+            FmAlgorithm::Y => FmAlgorithm::Additive,
+            FmAlgorithm::Additive => FmAlgorithm::Stack,
+        }
+    }
+}
+
+// `level` is the output level of a carrier, or the modulation index (in
+// units of `MAX_INDEX`) of a modulator.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Operator { pub ratio: f32, pub level: f32, pub envelope: Envelope }
+
+impl Operator {
+    pub fn new(ratio: f32, level: f32, envelope: Envelope) -> Operator { Operator { ratio, level, envelope } }
+}
+
+/// A four operator voice. the phases of the operators are kept by each
+/// note, so one voice description plays every note.
+///
+/// `feedback` feeds operator 4 back into itself, turning its sine into
+/// something closer to a saw.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct FmVoice { pub operators: [Operator; 4], pub algorithm: FmAlgorithm, pub feedback: f32 }
+
+impl FmVoice {
+    // modulation index, in radians, of a modulator at full level.
+    pub const MAX_INDEX: f32 = 4.0;
+
+    /// Sample of a note with operators at `phases`, in cycles. `now`, `t0`
+    /// and `t1` are the envelope times, as in `Envelope::sample`.
+    pub fn sample(&self, phases: &[f64; 4], now: f32, t0: f32, t1: Option<f32>) -> f32 {
+        let phase = |i: usize| TAU * phases[i] as f32;
+        let op = |i: usize, modulation: f32| {
+            let o = &self.operators[i];
+            (phase(i) + modulation).sin() * o.level * o.envelope.sample(now, t0, t1)
+        };
+        let index = FmVoice::MAX_INDEX;
+        // single step approximation of the feedback loop.
+        let op4 = op(3, self.feedback * phase(3).sin());
+        match self.algorithm {
+            FmAlgorithm::Stack => op(0, index * op(1, index * op(2, index * op4))),
+            FmAlgorithm::Pairs => (op(0, index * op(1, 0.0)) + op(2, index * op4)) * 0.5,
+            FmAlgorithm::Branch => op(0, index * (op(1, 0.0) + op(2, 0.0) + op4)),
+            FmAlgorithm::Y => op(0, index * (op(2, index * op4) + op(1, 0.0))),
+            FmAlgorithm::Additive => (op(0, 0.0) + op(1, 0.0) + op(2, 0.0) + op4) * 0.25,
+        }
+    }
+
+    // moves the operators of a note at `freq` on by a sample.
+    pub fn advance(&self, phases: &mut [f64; 4], freq: f32, sample_rate: f32) {
+        for (p, o) in phases.iter_mut().zip(&self.operators) {
+            *p = (*p + (freq * o.ratio / sample_rate) as f64).rem_euclid(1.0);
+        }
+    }
+}
+
+// an electric piano: a soft tine pair and a bright, quickly decaying bell
+// pair an octave and a bit above.
+impl Default for FmVoice {
+    fn default() -> Self {
+        FmVoice {
+            operators: [
+                Operator::new(1.0, 1.0, Envelope(0.002, 1.5, 0.3, 0.4)),
+                Operator::new(1.0, 0.35, Envelope(0.002, 0.8, 0.1, 0.4)),
+                Operator::new(1.0, 0.6, Envelope(0.002, 1.2, 0.2, 0.3)),
+                Operator::new(14.0, 0.12, Envelope(0.001, 0.15, 0.0, 0.1)),
+            ],
+            algorithm: FmAlgorithm::Pairs,
+            feedback: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod fm_tests {
+    use super::*;
+
+    fn flat(ratio: f32, level: f32) -> Operator { Operator::new(ratio, level, Envelope(0.0, 0.0, 1.0, 0.0)) }
+
+    #[test]
+    fn test_fm_algorithms() {
+        let mut voice = FmVoice {
+            operators: [flat(1.0, 1.0), flat(2.0, 0.0), flat(3.0, 0.0), flat(4.0, 0.0)],
+            algorithm: FmAlgorithm::Stack,
+            feedback: 0.0,
+        };
+        // a tenth of a second into a note at 1 Hz.
+        let t = 0.1;
+        let mut phases = [0.0; 4];
+        (0..10).for_each(|_| voice.advance(&mut phases, 1.0, 100.0));
+        assert!(phases.iter().zip([0.1, 0.2, 0.3, 0.4]).all(|(p, q)| (p - q).abs() < 1e-6));
+
+        // modulators at zero level leave a plain sine on the carrier.
+        assert!((voice.sample(&phases, 1.0, 0.0, None) - (TAU * t).sin()).abs() < 1e-5);
+
+        voice.operators[1].level = 0.5;
+        assert!((voice.sample(&phases, 1.0, 0.0, None) - (TAU * t).sin()).abs() > 0.01);
+
+        voice.algorithm = FmAlgorithm::Additive;
+        let sum = ((TAU * t).sin() + 0.5 * (TAU * 2.0 * t).sin()) * 0.25;
+        assert!((voice.sample(&phases, 1.0, 0.0, None) - sum).abs() < 1e-5);
+
+        // everything fades with the operator envelopes once released.
+        let fm = FmVoice::default();
+        assert_eq!(fm.sample(&[0.3; 4], 5.0, 0.0, Some(1.0)), 0.0);
+    }
+}
@@ -0,0 +1,155 @@
+//! Generator module.
+//!
+//! plays notes by itself, on the steps of the transport: a random walk up
+//! and down the scale, or a markov chain between the degrees of it. the
+//! rng is seeded, so a seed plays the same line again.
+//!
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::audio::scale::Scale;
+
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub enum GeneratorMode { #[default] Walk, Markov }
+
+pub struct Generator {
+    pub enabled: bool,
+    pub mode: GeneratorMode,
+    // the chance a step plays a note, the steps per beat, and the part of
+    // a step a note is held.
+    pub density: f32,
+    pub rate: f32,
+    pub gate: f32,
+    seed: u64,
+    rng: StdRng,
+    // degree of the scale from the root of the 4th octave.
+    degree: i32,
+    // weights of going from a degree of the scale to another, drawn from
+    // the seed.
+    transitions: [[f32; 12]; 12],
+    // the note held and the beat it's let go at.
+    playing: Option<(u8, f64)>,
+}
+
+impl Generator {
+    pub const DENSITIES: [f32; 4] = [0.25, 0.5, 0.75, 1.0];
+    // how far the line wanders, in octaves from the root.
+    const OCTAVES: i32 = 2;
+
+    pub fn new(seed: u64) -> Generator {
+        let mut generator = Generator {
+            enabled: false, mode: GeneratorMode::Walk, density: 0.75, rate: 2.0, gate: 0.8,
+            seed, rng: StdRng::seed_from_u64(seed), degree: 0, transitions: [[0.0; 12]; 12], playing: None,
+        };
+        generator.reseed(seed);
+        generator
+    }
+
+    pub fn seed(&self) -> u64 { self.seed }
+
+    /// Starts the line over from the root, as the seed plays it.
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+        self.degree = 0;
+        for row in self.transitions.iter_mut() {
+            row.iter_mut().for_each(|w| *w = self.rng.gen_range(0.05..1.0));
+        }
+    }
+
+    // lets go of the note held, if any.
+    pub fn release(&mut self) -> Option<u8> { self.playing.take().map(|(note, _)| note) }
+
+    // the midi note of a degree of the scale.
+    fn note(scale: &Scale, degree: i32) -> u8 {
+        let steps = scale.kind.steps();
+        let n = steps.len() as i32;
+        let note = 60 + scale.root as i32 + 12 * degree.div_euclid(n) + steps[degree.rem_euclid(n) as usize] as i32;
+        note.clamp(0, 127) as u8
+    }
+
+    fn next_degree(&mut self, n: i32) -> i32 {
+        let step = match self.mode {
+            GeneratorMode::Walk => [-2, -1, -1, 0, 1, 1, 2][self.rng.gen_range(0..7)],
+            // a degree near the one playing is likelier than a far one.
+            GeneratorMode::Markov => {
+                let from = self.degree.rem_euclid(n);
+                let row = self.transitions[from as usize];
+                let distance = |to: i32| (to - from).rem_euclid(n).min((from - to).rem_euclid(n));
+                let weight = |to: i32| row[to as usize] / (1 + distance(to)) as f32;
+                let total: f32 = (0..n).map(weight).sum();
+                let mut pick = self.rng.gen_range(0.0..total);
+                let to = (0..n).find(|&to| { pick -= weight(to); pick < 0.0 }).unwrap_or(from);
+                // the nearest way there, up or down.
+                let up = (to - from).rem_euclid(n);
+                if up <= n / 2 { up } else { up - n }
+            },
+        };
+        // turn back from the ends of the range.
+        let (low, high) = (-n * Self::OCTAVES / 2, n * Self::OCTAVES);
+        match self.degree + step {
+            d if d < low || d > high => self.degree - step,
+            d => d,
+        }
+    }
+
+    /// Plays the steps between the beats `from` and `to` of the transport,
+    /// calling `play` with the note, velocity and whether it's pressed.
+    pub fn tick(&mut self, from: f64, to: f64, scale: &Scale, mut play: impl FnMut(u8, f32, bool)) {
+        if !self.enabled || to <= from { return; }
+        let rate = self.rate.max(0.01) as f64;
+        let n = scale.kind.steps().len() as i32;
+        let mut step = (from * rate).ceil();
+        while step / rate < to {
+            let beat = step / rate;
+            if let Some((note, off)) = self.playing {
+                if off <= beat { play(note, 0.0, false); self.playing = None; }
+            }
+            if self.rng.gen::<f32>() < self.density {
+                if let Some(note) = self.release() { play(note, 0.0, false); }
+                self.degree = self.next_degree(n);
+                let note = Self::note(scale, self.degree);
+                play(note, self.rng.gen_range(0.6..1.0), true);
+                self.playing = Some((note, beat + self.gate.clamp(0.05, 1.0) as f64 / rate));
+            }
+            step += 1.0;
+        }
+        if let Some((note, off)) = self.playing {
+            if off < to { play(note, 0.0, false); self.playing = None; }
+        }
+    }
+}
+
+#[cfg(test)]
+mod generator_tests {
+    use super::*;
+
+    fn line(generator: &mut Generator, scale: &Scale) -> Vec<(u8, bool)> {
+        let mut notes = Vec::new();
+        (0..32).for_each(|b| generator.tick(b as f64 * 0.5, (b + 1) as f64 * 0.5, scale, |n, _, on| notes.push((n, on))));
+        notes
+    }
+
+    #[test]
+    fn test_seeded_lines_in_key() {
+        let scale: Scale = "d minor".parse().unwrap();
+        for mode in [GeneratorMode::Walk, GeneratorMode::Markov] {
+            let (mut a, mut b) = (Generator::new(7), Generator::new(7));
+            for g in [&mut a, &mut b] { (g.enabled, g.mode) = (true, mode); }
+            let notes = line(&mut a, &scale);
+            assert_eq!(notes, line(&mut b, &scale));
+            assert!(notes.len() > 8);
+            assert!(notes.iter().all(|(n, _)| scale.contains(*n as f32) && (50..=86).contains(n)));
+            // every note is let go before the next one.
+            assert!(notes.chunks(2).all(|p| p[0].0 == p[1].0 && p[0].1 && !p[1].1));
+        }
+
+        let mut other = Generator::new(8);
+        other.enabled = true;
+        let mut seven = Generator::new(7);
+        seven.enabled = true;
+        assert_ne!(line(&mut other, &scale), line(&mut seven, &scale));
+        other.density = 0.0;
+        assert!(line(&mut other, &scale).is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+//! Glide module.
+//!
+//! portamento on the voice pitch. pitches are midi note numbers, so a
+//! glide covers every octave in the same time.
+//!
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum GlideCurve { Linear, Exponential }
+
+// `time` in seconds, zero disables the glide.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct GlideSettings { pub time: f32, pub curve: GlideCurve }
+
+impl Default for GlideSettings {
+    fn default() -> Self { GlideSettings { time: 0.0, curve: GlideCurve::Linear } }
+}
+
+/// Pitch of a voice on its way to a target note.
+///
+/// linear glides reach the target in exactly `time`, exponential ones
+/// approach it with a time constant of a fifth of `time`.
+#[derive(Debug, Copy, Clone)]
+pub struct Glide {
+    from: f32,
+    target: f32,
+    current: f32,
+    elapsed: f32,
+}
+
+impl Glide {
+    pub fn new(note: f32) -> Glide { Glide { from: note, target: note, current: note, elapsed: 0.0 } }
+
+    pub fn current(&self) -> f32 { self.current }
+
+    // advances by `dt` seconds towards `target`, a new target restarts the
+    // glide from the current pitch.
+    pub fn tick(&mut self, settings: &GlideSettings, target: f32, dt: f32) -> f32 {
+        if target != self.target {
+            self.from = self.current;
+            self.target = target;
+            self.elapsed = 0.0;
+        }
+        if settings.time <= 0.0 {
+            self.current = target;
+            return target;
+        }
+        self.elapsed += dt;
+        self.current = match settings.curve {
+            GlideCurve::Linear => {
+                let x = (self.elapsed / settings.time).min(1.0);
+                self.from + (target - self.from) * x
+            },
+            GlideCurve::Exponential => {
+                let k = 1.0 - (-dt * 5.0 / settings.time).exp();
+                self.current + (target - self.current) * k
+            },
+        };
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod glide_tests {
+    use super::*;
+
+    #[test]
+    fn test_glide_curves() {
+        let linear = GlideSettings { time: 1.0, curve: GlideCurve::Linear };
+        let mut g = Glide::new(60.0);
+        assert_eq!(g.tick(&linear, 72.0, 0.5), 66.0);
+        assert_eq!(g.tick(&linear, 72.0, 0.5), 72.0);
+        assert_eq!(g.tick(&linear, 72.0, 0.5), 72.0);
+
+        let exponential = GlideSettings { time: 1.0, curve: GlideCurve::Exponential };
+        let mut g = Glide::new(60.0);
+        let half = (0..50).map(|_| g.tick(&exponential, 72.0, 0.01)).last().unwrap();
+        assert!(half > 66.0 && half < 72.0);
+        let end = (0..50).map(|_| g.tick(&exponential, 72.0, 0.01)).last().unwrap();
+        assert!((end - 72.0).abs() < 0.1);
+
+        assert_eq!(Glide::new(60.0).tick(&GlideSettings::default(), 48.0, 0.01), 48.0);
+    }
+}
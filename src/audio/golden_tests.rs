@@ -0,0 +1,80 @@
+//! Golden tests.
+//!
+//! renders fixed patches and note sequences offline and compares them with
+//! the reference renders in `tests/golden`. after a change that is meant to
+//! change the sound, run the tests with `RSYNTH_BLESS=1` to rewrite them.
+//!
+
+use std::path::PathBuf;
+
+use crate::audio::instrument::{Instrument, NoteEvent};
+use crate::audio::waves::{AdditiveDesc, BlepShape, Envelope, WaveDesc, WavetableDesc, WavetableSource};
+use crate::preset::Patch;
+use crate::render::write_wav;
+
+const SAMPLE_RATE: u32 = 11025;
+// far below anything audible, but above float noise between platforms.
+const TOLERANCE: f32 = 1e-4;
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.wav", name))
+}
+
+fn patch(wave: WaveDesc, envelope: Envelope) -> Patch {
+    let mut patch = Instrument::new().patch();
+    patch.oscillator.otf = wave;
+    patch.envelope = envelope;
+    patch
+}
+
+fn check(name: &str, patch: &Patch, events: &[NoteEvent], seconds: f32) {
+    let mut instrument = Instrument::new();
+    instrument.apply_patch(patch);
+    let rendered = instrument.render(events, SAMPLE_RATE, (seconds * SAMPLE_RATE as f32) as usize);
+
+    let path = reference_path(name);
+    if std::env::var_os("RSYNTH_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        write_wav(&path, &rendered, SAMPLE_RATE).unwrap();
+        return;
+    }
+    let reference: Vec<f32> = hound::WavReader::open(&path)
+        .unwrap_or_else(|e| panic!("{}: {}, run with RSYNTH_BLESS=1 to create it", path.display(), e))
+        .samples::<f32>().collect::<Result<_, _>>().unwrap();
+    assert_eq!(rendered.len(), reference.len(), "{} changed length", name);
+    let (i, diff) = rendered.iter().zip(&reference).map(|(a, b)| (a - b).abs()).enumerate()
+        .fold((0, 0.0), |max, (i, d)| if d > max.1 { (i, d) } else { max });
+    assert!(diff <= TOLERANCE, "{} differs from its reference by {} at frame {}", name, diff, i / 2);
+}
+
+fn pluck() -> Envelope { Envelope(0.005, 0.1, 0.3, 0.05) }
+
+// a c major arpeggio, held together for a moment.
+fn arpeggio() -> Vec<NoteEvent> {
+    vec![
+        NoteEvent::on(0.0, 60, 100), NoteEvent::on(0.05, 64, 90), NoteEvent::on(0.1, 67, 80),
+        NoteEvent::off(0.2, 60), NoteEvent::off(0.2, 64), NoteEvent::off(0.2, 67),
+    ]
+}
+
+#[test]
+fn golden_waveforms() {
+    let waves = [
+        ("sine", WaveDesc::Sin),
+        ("saw", WaveDesc::PolyBlep(BlepShape::Saw)),
+        ("pulse", WaveDesc::PolyBlep(BlepShape::Pulse(0.3))),
+        ("triangle", WaveDesc::PolyBlep(BlepShape::Triangle)),
+        ("wavetable", WaveDesc::Wavetable(WavetableDesc::new(WavetableSource::Basic, 0.4))),
+        ("additive", WaveDesc::Additive(AdditiveDesc::default())),
+    ];
+    for (name, wave) in waves {
+        check(name, &patch(wave, pluck()), &arpeggio(), 0.3);
+    }
+}
+
+#[test]
+fn golden_envelopes() {
+    let note = [NoteEvent::on(0.0, 57, 127), NoteEvent::off(0.15, 57)];
+    check("envelope_pluck", &patch(WaveDesc::Sin, pluck()), &note, 0.25);
+    check("envelope_pad", &patch(WaveDesc::Sin, Envelope(0.08, 0.05, 0.6, 0.08)), &note, 0.25);
+}
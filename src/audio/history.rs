@@ -0,0 +1,167 @@
+//! History module.
+//!
+//! undo and redo over the edits of the patch and of the drum patterns. an
+//! edit is what a key or a control changed: the parameters it moved, the
+//! oscillator it reshaped and the pattern it wrote, each from and to, so
+//! it can be played back either way.
+//!
+
+use crate::audio::drums::Pattern;
+use crate::audio::params::ParamId;
+use crate::audio::waves::OscillatorDesc;
+
+// what an edit can change, compared before and after it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct EditState {
+    pub params: Vec<f32>,
+    pub oscillator: OscillatorDesc,
+    // the pattern being edited and its steps.
+    pub pattern: (usize, Pattern),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Change {
+    Param(ParamId, f32, f32),
+    Oscillator(Box<OscillatorDesc>, Box<OscillatorDesc>),
+    Pattern(usize, Box<Pattern>, Box<Pattern>),
+}
+
+impl Change {
+    fn reversed(&self) -> Change {
+        match self {
+            Change::Param(id, from, to) => Change::Param(*id, *to, *from),
+            Change::Oscillator(from, to) => Change::Oscillator(to.clone(), from.clone()),
+            Change::Pattern(index, from, to) => Change::Pattern(*index, to.clone(), from.clone()),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Change::Param(id, ..) => id.param().name.to_string(),
+            Change::Oscillator(..) => String::from("oscillator"),
+            Change::Pattern(index, ..) => format!("drum pattern {}", index + 1),
+        }
+    }
+}
+
+impl EditState {
+    /// What changed from this state to `after`, `ParamId::ALL` giving the
+    /// order of the parameters. moving on to another pattern isn't an edit.
+    pub fn changes(&self, after: &EditState) -> Vec<Change> {
+        let mut changes: Vec<Change> = ParamId::ALL.iter().zip(self.params.iter().zip(&after.params))
+            .filter(|(_, (from, to))| from != to)
+            .map(|(id, (from, to))| Change::Param(*id, *from, *to))
+            .collect();
+        if self.oscillator != after.oscillator {
+            changes.push(Change::Oscillator(Box::new(self.oscillator.clone()), Box::new(after.oscillator.clone())));
+        }
+        if self.pattern.0 == after.pattern.0 && self.pattern.1 != after.pattern.1 {
+            changes.push(Change::Pattern(self.pattern.0, Box::new(self.pattern.1.clone()), Box::new(after.pattern.1.clone())));
+        }
+        changes
+    }
+}
+
+struct Edit { changes: Vec<Change>, time: f32 }
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    // times it was cleared, which an empty history doesn't show otherwise.
+    cleared: usize,
+}
+
+impl History {
+    pub const DEPTH: usize = 100;
+    // moves of the same parameters this close together, like a key held
+    // or a knob turned, are one edit.
+    const MERGE: f32 = 0.5;
+
+    // edits that can be undone and redone.
+    pub fn steps(&self) -> (usize, usize) { (self.undo.len(), self.redo.len()) }
+
+    // changes with every undo, redo and clear, even a clear of an empty
+    // history.
+    pub fn version(&self) -> (usize, usize, usize) { (self.undo.len(), self.redo.len(), self.cleared) }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.cleared += 1;
+    }
+
+    /// Records an edit made at `time`, dropping the ones undone.
+    pub fn push(&mut self, changes: Vec<Change>, time: f32) {
+        if changes.is_empty() { return; }
+        self.redo.clear();
+        if let Some(last) = self.undo.last_mut() {
+            let params = |c: &[Change]| c.iter().map(|c| match c { Change::Param(id, ..) => Some(*id), _ => None }).collect::<Option<Vec<_>>>();
+            if time - last.time < Self::MERGE && params(&last.changes).is_some() && params(&last.changes) == params(&changes) {
+                for (last, change) in last.changes.iter_mut().zip(changes) {
+                    if let (Change::Param(_, _, to), Change::Param(_, _, moved)) = (last, change) { *to = moved; }
+                }
+                last.time = time;
+                return;
+            }
+        }
+        if self.undo.len() == Self::DEPTH { self.undo.remove(0); }
+        self.undo.push(Edit { changes, time });
+    }
+
+    /// The changes taking the last edit back, in the order to make them.
+    pub fn undo(&mut self) -> Option<Vec<Change>> {
+        let edit = self.undo.pop()?;
+        let changes = edit.changes.iter().rev().map(Change::reversed).collect();
+        self.redo.push(edit);
+        Some(changes)
+    }
+
+    pub fn redo(&mut self) -> Option<Vec<Change>> {
+        let mut edit = self.redo.pop()?;
+        let changes = edit.changes.clone();
+        // a redo is never merged with the next edit.
+        edit.time = f32::NEG_INFINITY;
+        self.undo.push(edit);
+        Some(changes)
+    }
+}
+
+/// A few words on what changes, for the status line.
+pub fn describe(changes: &[Change]) -> String {
+    match changes {
+        [] => String::new(),
+        [change] => change.name(),
+        [change, rest @ ..] => format!("{} and {} more", change.name(), rest.len()),
+    }
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_redo_and_merge() {
+        let mut history = History::default();
+        assert!(history.undo().is_none());
+        history.push(vec![Change::Param(ParamId::Cutoff, 1000.0, 900.0)], 0.0);
+        // held down, a key moving the cutoff is one edit.
+        history.push(vec![Change::Param(ParamId::Cutoff, 900.0, 800.0)], 0.3);
+        history.push(vec![Change::Param(ParamId::Cutoff, 800.0, 700.0)], 0.6);
+        history.push(vec![Change::Param(ParamId::Cutoff, 700.0, 600.0)], 2.0);
+        assert_eq!(history.steps(), (2, 0));
+
+        assert_eq!(history.undo().unwrap(), [Change::Param(ParamId::Cutoff, 600.0, 700.0)]);
+        assert_eq!(history.undo().unwrap(), [Change::Param(ParamId::Cutoff, 700.0, 1000.0)]);
+        assert_eq!(history.steps(), (0, 2));
+        assert_eq!(history.redo().unwrap(), [Change::Param(ParamId::Cutoff, 1000.0, 700.0)]);
+
+        // a new edit drops what was undone, and isn't merged with a redo.
+        history.push(vec![Change::Param(ParamId::Cutoff, 700.0, 650.0)], 2.1);
+        assert_eq!(history.steps(), (2, 0));
+        let changes = [Change::Param(ParamId::Attack, 0.1, 0.2), Change::Param(ParamId::Decay, 0.1, 0.3)];
+        assert_eq!(describe(&changes), format!("{} and 1 more", ParamId::Attack.param().name));
+        (0..History::DEPTH).for_each(|i| history.push(changes.to_vec(), 10.0 * i as f32));
+        assert_eq!(history.steps(), (History::DEPTH, 0));
+    }
+}
@@ -1,147 +1,4058 @@
-/// Instrument module.
-///
-/// implements input handling and buffer data generation to be passed
-/// to the sound card.
-///
+//! Instrument module.
+//!
+//! implements input handling and buffer data generation to be passed
+//! to the sound card.
+//!
 
-use cpal::{self, traits::{HostTrait, DeviceTrait, StreamTrait}};
-use std::{sync::{Arc, Mutex}, collections::HashMap};
-use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use cpal::{self, traits::{DeviceTrait, StreamTrait}};
+use std::{sync::{Arc, Mutex, mpsc::Sender}, collections::HashMap};
+use serde::{Serialize, Deserialize};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 
-use crate::input::{KeyboardBuffer, KeyboardHandler};
-use crate::audio::waves::{WaveGenerator, Envelope};
-use crate::audio::waves::{Oscillator, LinearTransform, NullWave, ConstantWave};
+use crate::input::{KeyboardBuffer, KeyboardBufferEvent, KeyboardHandler, KeyboardVelocity, NoteKey};
+use crate::midi::{MidiClock, MidiHandler, MidiMessage, MidiSink};
+use crate::audio::waves::{Envelope, Oscillator, OscillatorDesc, Lfo, LfoDestination, WaveDesc, WavetableOscillator, AdditiveDesc, BlepShape, NoiseColor, NoiseLayer, SubOscillator, SubShape, WaveGenerator};
+use crate::audio::sampler::{Sampler, SamplerDesc, SampleZone, SamplerVoice};
+use crate::audio::drums::{DrumMachine, DrumKit, DRUM_KEYS};
+use crate::visual::{Meter, OutputTap};
+use crate::audio::fm::{FmAlgorithm, FmVoice};
+use crate::audio::filter::{Filter, DcBlocker, FilterEnvelope, FilterSettings, VoiceFilter};
+use crate::audio::capture::{InputQueue, start_input};
+use crate::audio::device::{AudioConfig, OutputSelection, select_output};
+use crate::audio::effects::{AutoPanSettings, CompressorSettings, ConvolutionSettings, EffectChain, EffectDesc, EqBand, EqSettings, FlangerSettings, Limiter, LimiterSettings, PingPongSettings, SaturationCurve, SaturationSettings, TremoloSettings, VocoderSettings, WavefolderSettings, default_effects, soft_clip};
+use crate::audio::arpeggiator::{Arpeggiator, ArpSettings};
+use crate::audio::glide::{Glide, GlideCurve, GlideSettings};
+use crate::audio::unison::{UnisonSettings, pan_gains};
+use crate::audio::oscillators::{self, VoiceOscillators, VoiceOscillatorsDesc};
+use crate::audio::smooth::SmoothedParam;
+use crate::audio::tempo::{LfoRate, NoteDivision};
+use crate::audio::transport::Transport;
+use crate::audio::looper::{Looper, LoopNote, LooperState};
+use crate::audio::chord::{Chord, ChordSettings};
+use crate::audio::scale::{Scale, ScaleLock};
+use crate::audio::generator::{Generator, GeneratorMode};
+use crate::audio::performance::{Performance, default_performance_path};
+use crate::audio::parts::{Part, PartDesc};
+use crate::audio::history::{self, Change, EditState, History};
+use crate::audio::macros::{Macro, MACROS, default_macros};
+use crate::audio::mutate::{self, Locks, Section};
+use crate::audio::expr::{ExprWave, ParseError};
+use crate::audio::params::{CcMapping, CcMode, ParamId};
+use crate::audio::modulation::{ModMatrix, ModRoute, ModInputs, ModDestination, freq_to_note, note_to_freq};
+use crate::audio::recorder::{Recorder, default_recording_path};
+use crate::disk;
+use crate::preset::{self, Patch, PresetError};
+use crate::project::{self, Project, ProjectPart, TransportSettings};
+use crate::keymap::{Keymap, KeymapError, note_name};
+use crate::shutdown::Shutdown;
 
-use super::waves::{SinWave, IdentityWave, Randomize, Voicing, RepeatedVoicing, Test};
+use super::waves::{SinWave, Randomize};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
-pub fn thread_audio(mtx_instrmnt: Arc<Mutex<Instrument>>) {
+// plays until shutdown, then stops the streams. the thread sleeps while
+// the streams run on their own callbacks.
+pub fn thread_audio(mtx_instrmnt: Arc<Mutex<Instrument>>, audio_config: AudioConfig, shutdown: Shutdown) {
+    // without a jack server, the audio device plays instead.
+    #[cfg(feature = "jack")]
+    let fallback = match &audio_config.jack {
+        Some(jack) => match crate::audio::jack::start_jack(&mtx_instrmnt, jack) {
+            Ok(client) => {
+                shutdown.wait();
+                return client.stop();
+            },
+            Err(e) => Some(e),
+        },
+        None => None,
+    };
+    #[cfg(not(feature = "jack"))]
+    let fallback: Option<String> = None;
+    match start_audio(&mtx_instrmnt, &audio_config) {
+        Ok(streams) => {
+            if let Some(e) = fallback {
+                let mut instrument = mtx_instrmnt.lock().unwrap();
+                let status = format!("{} ({})", instrument.status, e);
+                instrument.set_status(status);
+            }
+            shutdown.wait();
+            streams.stop();
+        },
+        Err(e) => mtx_instrmnt.lock().unwrap().set_status(match fallback {
+            Some(jack) => format!("{}, {}", jack, e),
+            None => e,
+        }),
+    }
+}
+
+/// The running streams of the instrument. they play until stopped or
+/// dropped.
+pub struct AudioStreams {
+    output: cpal::Stream,
+    input: Option<cpal::Stream>,
+}
+
+impl AudioStreams {
+    // a last callback may still be running, pausing waits for it on most
+    // backends.
+    pub fn stop(self) {
+        if let Some(input) = &self.input { let _ = input.pause(); }
+        let _ = self.output.pause();
+    }
+}
+
+/// Opens the output (and input, if asked for) and starts playing the
+/// instrument on it.
+pub fn start_audio(mtx_instrmnt: &Arc<Mutex<Instrument>>, audio_config: &AudioConfig) -> Result<AudioStreams, String> {
     let host: cpal::Host = cpal::default_host();
-    let device = host.default_output_device().expect("No default output device found.");
-    let cfg_output = device.supported_output_configs().expect("No supported output config.").next().expect("No supported output config.").with_max_sample_rate();
-    let err_fn = |err| println!("error occurred on output stream: {}", err);
-    println!("{:?}", cfg_output);
-    println!("{:?}", device.name());
+    let OutputSelection { device, supported: cfg_output, config, warnings } = select_output(&host, audio_config)
+        .map_err(|e| format!("audio: {}", e))?;
 
     {
         let mut instrument = mtx_instrmnt.lock().unwrap();
         instrument.set_sample_rate(cfg_output.sample_rate());
+        instrument.set_channels(cfg_output.channels());
+        let mut status = format!("{} @ {} Hz {}", device.name().unwrap_or_default(), cfg_output.sample_rate().0, cfg_output.sample_format());
+        warnings.iter().for_each(|w| status.push_str(&format!(" ({})", w)));
+        instrument.set_status(status);
+    }
+
+    let input = audio_config.input.then(|| {
+        let queue = mtx_instrmnt.lock().unwrap().input_queue();
+        let mtx_err = Arc::clone(mtx_instrmnt);
+        let err_fn = move |err| mtx_err.lock().unwrap().set_status(format!("error occurred on input stream: {}", err));
+        let input = start_input(&host, audio_config.input_device.as_deref(), cfg_output.sample_rate().0, queue, err_fn);
+        let mut instrument = mtx_instrmnt.lock().unwrap();
+        let status = match &input {
+            Ok((_, name)) => format!("{} (input: {})", instrument.status, name),
+            Err(e) => format!("{} (input: {})", instrument.status, e),
+        };
+        instrument.set_status(status);
+        input.ok().map(|(stream, _)| stream)
+    }).flatten();
+
+    let build = |config: &cpal::StreamConfig| match cfg_output.sample_format() {
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, config, mtx_instrmnt),
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, config, mtx_instrmnt),
+        cpal::SampleFormat::U16 => build_stream::<u16>(&device, config, mtx_instrmnt),
+        _ => Err(cpal::BuildStreamError::StreamConfigNotSupported),
+    };
+    let mut stream = build(&config);
+    if stream.is_err() && config.buffer_size != cpal::BufferSize::Default {
+        // some backends refuse fixed sizes they report as supported.
+        stream = build(&cpal::StreamConfig { buffer_size: cpal::BufferSize::Default, ..config.clone() });
+        let mut instrument = mtx_instrmnt.lock().unwrap();
+        let status = format!("{} (fixed buffer size refused, using the default)", instrument.status);
+        instrument.set_status(status);
+    }
+    let output = stream.map_err(|e| format!("{} ({})", e, cfg_output.sample_format()))
+        .and_then(|stream| stream.play().map(|_| stream).map_err(|e| e.to_string()))
+        .map_err(|e| format!("could not start the output stream: {}", e))?;
+    mtx_instrmnt.lock().unwrap().set_scheduling(true);
+    Ok(AudioStreams { output, input })
+}
+
+// scratch buffers of the audio callback. they only grow, so after the first
+// few callbacks nothing allocates.
+#[derive(Default)]
+struct CallbackBuffers { left: Vec<f32>, right: Vec<f32>, frames: Vec<f32> }
+
+// phases of a voice, in cycles, one set per unison voice. they are kept in
+// f64 and wrapped, so pitch stays exact however long the synth runs.
+#[derive(Debug, Clone, Copy, Default)]
+struct VoicePhases {
+    osc: [f64; UnisonSettings::MAX_VOICES as usize],
+    fm: [[f64; 4]; UnisonSettings::MAX_VOICES as usize],
+    extra: [[f64; oscillators::EXTRA]; UnisonSettings::MAX_VOICES as usize],
+    sub: f64,
+}
+
+// what the engine plays of the parameters that can move while notes play.
+// they chase the values set on the instrument, so changes ramp in.
+#[derive(Debug, Clone, Copy)]
+struct Smoothed {
+    volume: SmoothedParam,
+    cutoff: SmoothedParam,
+    resonance: SmoothedParam,
+    bend: SmoothedParam,
+    mod_wheel: SmoothedParam,
+    input_gain: SmoothedParam,
+}
+
+impl Smoothed {
+    fn new(sample_rate: f32) -> Smoothed {
+        let filter = FilterSettings::default();
+        let param = |value| SmoothedParam::new(value, sample_rate);
+        Smoothed {
+            volume: param(1.0), cutoff: param(filter.cutoff), resonance: param(filter.resonance),
+            bend: param(0.0), mod_wheel: param(0.0), input_gain: param(1.0),
+        }
+    }
+
+    fn params(&mut self) -> [&mut SmoothedParam; 6] {
+        [&mut self.volume, &mut self.cutoff, &mut self.resonance, &mut self.bend, &mut self.mod_wheel, &mut self.input_gain]
+    }
+}
+
+// per sample values of the block `gen_block` is working on, shared by all
+// voices or reused by each in turn. grown like the callback buffers.
+#[derive(Default)]
+struct BlockBuffers {
+    t: Vec<f32>,
+    now: Vec<f32>,
+    pitch: Vec<f32>,
+    gain: Vec<f32>,
+    cutoff: Vec<f32>,
+    resonance: Vec<f32>,
+    mod_wheel: Vec<f32>,
+    input_gain: Vec<f32>,
+    volume: Vec<f32>,
+    freq: Vec<f32>,
+    detuned: Vec<f32>,
+    extra_freq: Vec<f32>,
+    extra_out: Vec<f32>,
+    amp: Vec<f32>,
+    settings: Vec<FilterSettings>,
+    out: Vec<f32>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+impl BlockBuffers {
+    fn resize(&mut self, n: usize) {
+        for buf in [&mut self.t, &mut self.now, &mut self.pitch, &mut self.gain, &mut self.cutoff, &mut self.resonance,
+            &mut self.mod_wheel, &mut self.input_gain, &mut self.volume, &mut self.freq, &mut self.detuned,
+            &mut self.extra_freq, &mut self.extra_out, &mut self.amp, &mut self.out, &mut self.left, &mut self.right] {
+            if buf.len() < n { buf.resize(n, 0.0); }
+        }
+        if self.settings.len() < n { self.settings.resize(n, FilterSettings::default()); }
+    }
+}
+
+fn build_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, mtx_instrmnt: &Arc<Mutex<Instrument>>) -> Result<cpal::Stream, cpal::BuildStreamError>
+where T: cpal::SizedSample + cpal::FromSample<f32> {
+    let mtx_err = Arc::clone(mtx_instrmnt);
+    let err_fn = move |err| mtx_err.lock().unwrap().set_status(format!("error occurred on output stream: {}", err));
+    let mtx_data = Arc::clone(mtx_instrmnt);
+    let mut buffers = CallbackBuffers::default();
+    device.build_output_stream(config, move |d: &mut [T], o| generate_audio(d, o, &mtx_data, &mut buffers), err_fn, None)
+}
+
+// the instrument renders f32, converted to the sample type of the device.
+fn generate_audio<T>(data: &mut [T], info: &cpal::OutputCallbackInfo, mti: &Arc<Mutex<Instrument>>, buffers: &mut CallbackBuffers)
+where T: cpal::Sample + cpal::FromSample<f32> {
+    let CallbackBuffers { left, right, frames } = buffers;
+    let mut instrmnt = mti.lock().unwrap();
+    let channels = instrmnt.channels() as usize;
+    left.resize(data.len() / channels, 0.0);
+    right.resize(data.len() / channels, 0.0);
+    frames.resize(data.len(), 0.0);
+    let timestamp = info.timestamp();
+    let latency = timestamp.playback.duration_since(&timestamp.callback).unwrap_or_default();
+    render_callback(&mut instrmnt, left, right, latency.as_secs_f32());
+    for (frame, (l, r)) in frames.chunks_mut(channels).zip(left.iter().zip(right.iter())) {
+        match frame {
+            [mono] => *mono = (l + r) * 0.5,
+            [fl, fr, rest @ ..] => {
+                (*fl, *fr) = (*l, *r);
+                rest.fill((l + r) * 0.5);
+            },
+            [] => (),
+        }
+    }
+    data.iter_mut().zip(frames.iter()).for_each(|(d, x)| *d = T::from_sample(*x));
+    instrmnt.record(frames);
+}
+
+// one callback of the instrument into `left` and `right`, whatever the
+// backend. `latency` is the time until they're heard.
+pub(crate) fn render_callback(instrmnt: &mut Instrument, left: &mut [f32], right: &mut [f32], latency: f32) {
+    instrmnt.set_callback_stats(left.len(), latency);
+    instrmnt.pull_input(left.len());
+    instrmnt.tick_arpeggiator();
+    instrmnt.tick_parts();
+    instrmnt.tick_transport();
+    instrmnt.play_block(left, right);
+    instrmnt.process_master(left, right);
+    instrmnt.tap_output(left, right);
+}
+
+/// A midi message `time` seconds into an offline render.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteEvent { pub time: f32, pub message: MidiMessage }
+
+impl NoteEvent {
+    pub fn on(time: f32, note: u8, velocity: u8) -> NoteEvent {
+        NoteEvent { time, message: MidiMessage::NoteOn { channel: 0, note, velocity } }
+    }
+    pub fn off(time: f32, note: u8) -> NoteEvent {
+        NoteEvent { time, message: MidiMessage::NoteOff { channel: 0, note } }
+    }
+}
+
+// what envelopes and note events are timed against. live it's the wall
+// clock, offline renders step it along with the samples they generate.
+#[derive(Debug, Clone, Copy)]
+enum Clock { Wall(std::time::Instant), Offline(f32) }
+
+impl Clock {
+    fn now(&self) -> f32 {
+        match self {
+            Clock::Wall(start) => start.elapsed().as_secs_f32(),
+            Clock::Offline(t) => *t,
+        }
+    }
+}
+
+// a key or midi event on its way to the audio callback.
+#[derive(Debug, Clone, Copy)]
+enum LiveEvent { Key(KeyEvent), Midi(MidiMessage) }
+
+// what makes the sound of a voice.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum Engine { #[default] Oscillator, Fm, Sampler }
+
+impl Engine {
+    pub fn next(self) -> Engine {
+        match self {
+            Engine::Oscillator => Engine::Fm,
+            Engine::Fm => Engine::Sampler,
+            Engine::Sampler => Engine::Oscillator,
+        }
+    }
+}
+
+// mono retriggers the envelope on every note, legato only when no other
+// key was held.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum PlayMode { #[default] Poly, Mono, Legato }
+
+impl PlayMode {
+    pub fn next(self) -> PlayMode {
+        match self {
+            PlayMode::Poly => PlayMode::Mono,
+            PlayMode::Mono => PlayMode::Legato,
+            PlayMode::Legato => PlayMode::Poly,
+        }
+    }
+}
+
+// which of the held keys plays in mono and legato modes.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum NotePriority { #[default] Last, Low, High }
+
+impl NotePriority {
+    pub fn next(self) -> NotePriority {
+        match self {
+            NotePriority::Last => NotePriority::Low,
+            NotePriority::Low => NotePriority::High,
+            NotePriority::High => NotePriority::Last,
+        }
     }
+}
 
-    // might need to generalize data type depending on platform.
-    fn generate_audio(data: &mut [f32], _: &cpal::OutputCallbackInfo, mti: Arc<Mutex<Instrument>>) {
-        let mut instrmnt = mti.lock().unwrap();
-        for (i, sample) in data.iter_mut().enumerate() {
-            *sample = instrmnt.gen(i as u128);
+// which voice makes room when more notes play than the polyphony allows.
+// oldest takes released voices before held ones, same note takes a voice
+// already playing the new pitch and falls back to the oldest.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum VoiceStealing { #[default] Oldest, Quietest, SameNote }
+
+impl VoiceStealing {
+    pub fn next(self) -> VoiceStealing {
+        match self {
+            VoiceStealing::Oldest => VoiceStealing::Quietest,
+            VoiceStealing::Quietest => VoiceStealing::SameNote,
+            VoiceStealing::SameNote => VoiceStealing::Oldest,
         }
-        instrmnt.advance_cursor(data.len() as u128);
     }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct Polyphony { pub max_voices: usize, pub stealing: VoiceStealing }
+
+impl Polyphony {
+    pub const MAX_VOICES: usize = 32;
+}
+
+impl Default for Polyphony {
+    fn default() -> Self { Polyphony { max_voices: 16, stealing: VoiceStealing::Oldest } }
+}
+
+#[derive(Debug, Clone)]
+pub struct NoteState { pub key: NoteKey, pub freq: f32, pub velocity: f32, pub level: f32, pub released: bool }
 
-    let mtx_build_data = Arc::clone(&mtx_instrmnt);
-    let stream = device.build_output_stream(
-        &cfg_output.config(), 
-        move |d, o| generate_audio(d, o, mtx_build_data.clone()), 
-        err_fn, None)
-    .expect("error building output stream");
-    stream.play().unwrap();     
-    loop {}
+// copy of the instrument state for the ui, so it doesn't need to hold
+// the instrument lock (and stall the audio callback) while drawing.
+#[derive(Debug, Clone)]
+pub struct InstrumentSnapshot {
+    pub notes: Vec<NoteState>,
+    pub envelope: Envelope,
+    pub engine: Engine,
+    pub oscillator: OscillatorDesc,
+    pub oscillators: VoiceOscillatorsDesc,
+    pub fm_algorithm: FmAlgorithm,
+    pub sample_zones: usize,
+    pub drum_mode: bool,
+    // step of the drum pattern about to play, while it plays, the pattern
+    // edited and whether the song plays.
+    pub drum_step: Option<usize>,
+    pub drum_pattern: usize,
+    pub song_mode: bool,
+    // the transport, its position as bar and beat, and whether it follows
+    // a midi clock.
+    pub bpm: f32,
+    pub swing: f32,
+    pub playing: bool,
+    pub following: bool,
+    pub position: (u64, u32),
+    // the looper, its length and how many layers it plays.
+    pub looper: LooperState,
+    pub loop_bars: u32,
+    pub loop_layers: usize,
+    pub chord: Chord,
+    pub scale: Scale,
+    pub generator: Option<GeneratorMode>,
+    pub sustain: bool,
+    pub capturing: bool,
+    // the name of each part and how many voices it plays.
+    pub parts: Vec<(String, usize)>,
+    pub split: Option<u8>,
+    // the seed the patch was rolled from, if it was.
+    pub patch_seed: Option<u64>,
+    pub filter: FilterSettings,
+    pub filter_envelope: FilterEnvelope,
+    pub master_volume: f32,
+    // gain reduction of the compressor and the limiter in dB.
+    pub compressor_reduction: f32,
+    pub limiter_reduction: f32,
+    pub pitch_bend: f32,
+    pub mod_wheel: f32,
+    pub octave: i32,
+    pub transpose: i32,
+    pub play_mode: PlayMode,
+    // voices sounding, out of the polyphony.
+    pub voices: usize,
+    pub polyphony: Polyphony,
+    pub recording: bool,
+    pub preset_name: String,
+    pub project_name: String,
+    pub status: String,
+    // the waveform expression being typed, if it is.
+    pub prompt: Option<String>,
+    // frames per audio callback and the time until they are played.
+    pub buffer_frames: usize,
+    pub latency: f32,
+    pub sample_rate: u32,
+    // midi note played by each character key, and the notes held down.
+    pub keys: Vec<(char, i32)>,
+    pub held: Vec<i32>,
 }
 
 pub struct Instrument {
     sr: cpal::SampleRate,
+    channels: u16,
     freq: f32,
     cursor: u128,
+    engine: Engine,
     oscillator: Oscillator,
+    // the second and third oscillator of each voice.
+    oscillators: VoiceOscillators,
+    fm: FmVoice,
+    sampler: Sampler,
+    // where each voice is in its sample.
+    sampler_voices: HashMap<NoteKey, SamplerVoice>,
+    phases: HashMap<NoteKey, VoicePhases>,
+    // in drum mode the bottom row of keys hits the drum pads.
+    drums: DrumMachine,
+    drum_mode: bool,
+    noise: NoiseLayer,
+    noise_source: Box<dyn WaveGenerator + Send>,
+    sub: SubOscillator,
+    // wavefolder on each voice, between the oscillators and the filter.
+    voice_fold: WavefolderSettings,
+    // overdrive on each voice, after the wavefolder.
+    voice_drive: SaturationSettings,
+    // keys held on the keyboard, and the notes actually sounding. they
+    // differ when the arpeggiator generates the notes.
     keyboard_buffer: KeyboardBuffer,
+    voices: KeyboardBuffer,
+    arpeggiator: Arpeggiator,
+    transport: Transport,
+    // what the transport does with midi clock, and where clock and other
+    // messages go out.
+    midi_clock: MidiClock,
+    midi_out: Option<Box<dyn MidiSink>>,
+    // where the presets and projects keys load and save are sent, to be
+    // read and written out of the lock. done on the spot without it.
+    disk: Option<Sender<disk::Request>>,
+    // notes played over the transport, and the ones it plays back in a
+    // callback.
+    looper: Looper,
+    loop_notes: Vec<LoopNote>,
+    // the chord added to the notes played, and the keys pressed with the
+    // midi note their chord was built on.
+    chord: ChordSettings,
+    chord_roots: HashMap<NoteKey, u8>,
+    // the key the notes played are kept in.
+    scale: Scale,
+    // plays notes in that key by itself, handed over through a scratch
+    // buffer like the looper's.
+    generator: Generator,
+    generated: Vec<(u8, f32, bool)>,
+    // the notes played captured for a midi file.
+    performance: Performance,
+    // instruments played along with this one, the computer keys playing
+    // them with the note they pressed, and the block they render into.
+    parts: Vec<Part>,
+    part_keys: HashMap<KeyCode, u8>,
+    // the note each key held plays on the layers.
+    layer_keys: HashMap<NoteKey, u8>,
+    // the part playing the keys below the split point, if split.
+    split: Option<usize>,
+    part_block: (Vec<f32>, Vec<f32>),
+    arp_held: Vec<NoteKey>,
+    play_mode: PlayMode,
+    note_priority: NotePriority,
+    polyphony: Polyphony,
+    // voices fading out to make room for others, and since when.
+    stolen: HashMap<NoteKey, f32>,
     envelope: Envelope,
-    key_to_freq: HashMap<KeyCode, f32>,
-    clock: std::time::Instant
+    filter: FilterSettings,
+    filter_envelope: FilterEnvelope,
+    // one filter per held key and channel, filters keep state between samples.
+    filters: HashMap<NoteKey, [VoiceFilter; 2]>,
+    glide: GlideSettings,
+    unison: UnisonSettings,
+    // pitch of each voice, new voices glide from the last note played.
+    glides: HashMap<NoteKey, Glide>,
+    last_note: Option<f32>,
+    lfos: Vec<Lfo>,
+    mod_matrix: ModMatrix,
+    // -1..1, scaled by the bend range in semitones.
+    pitch_bend: f32,
+    bend_range: f32,
+    mod_wheel: f32,
+    effects: EffectChain,
+    limiter: Limiter,
+    // left and right, ahead of the effects.
+    dc_blockers: [DcBlocker; 2],
+    smoothed: Smoothed,
+    // midi note of each computer key, before octave and transpose.
+    key_to_note: HashMap<KeyCode, f32>,
+    sustain_key: Option<KeyCode>,
+    octave: i32,
+    transpose: i32,
+    // cents every note is played off by, for a layer detuned against the
+    // instrument it's stacked on.
+    detune: f32,
+    clock: Clock,
+    // live events wait here for the next callback, which plays them at the
+    // offset they came in at after the callback before it.
+    scheduling: bool,
+    scheduled: Vec<(f32, LiveEvent)>,
+    last_callback: Option<f32>,
+    recorder: Option<Recorder>,
+    preset_name: String,
+    // the seed of the last random patch, to roll it again, the sections
+    // rolls and mutations leave alone, and how far a mutation goes.
+    patch_seed: Option<u64>,
+    locks: Locks,
+    // the waveform expression being typed, keys edit it rather than play.
+    prompt: Option<String>,
+    mutation: f32,
+    project_name: String,
+    // parameter the keys and midi learn act on, and the mode of a control
+    // being learned.
+    selected_param: ParamId,
+    learning: Option<CcMode>,
+    // knobs turning several parameters at once.
+    macros: Vec<Macro>,
+    // the edits made by keys and controls, to undo.
+    history: History,
+    cc_mappings: Vec<CcMapping>,
+    master_volume: f32,
+    buffer_frames: usize,
+    latency: f32,
+    // captured input, and the part of it for the buffer being generated.
+    input: InputQueue,
+    input_block: Vec<f32>,
+    monitor_input: bool,
+    input_gain: f32,
+    status: String,
+    snapshot: Arc<Mutex<InstrumentSnapshot>>,
+    tap: Arc<OutputTap>,
+    meter: Arc<Meter>,
+    block: BlockBuffers,
 }
 
 impl Instrument {
     pub fn new() -> Instrument { 
-        let mut k2f = HashMap::<KeyCode, f32>::new();
-        k2f.insert(KeyCode::Char('z'), 130.81); // C
-        k2f.insert(KeyCode::Char('s'), 138.59); // #
-        k2f.insert(KeyCode::Char('x'), 146.83);
-        k2f.insert(KeyCode::Char('d'), 155.56);
-        k2f.insert(KeyCode::Char('c'), 164.81);
-        k2f.insert(KeyCode::Char('v'), 174.61);
-        k2f.insert(KeyCode::Char('g'), 185.00);
-        k2f.insert(KeyCode::Char('b'), 196.00);
-        k2f.insert(KeyCode::Char('h'), 207.65);
-        k2f.insert(KeyCode::Char('n'), 220.00);
-        k2f.insert(KeyCode::Char('j'), 233.08);
-        k2f.insert(KeyCode::Char('m'), 246.94);
-        let i = Instrument { 
+        let oscillator = Oscillator::new(Box::new(SinWave));
+        let snapshot = InstrumentSnapshot {
+            notes: Vec::new(),
+            envelope: Envelope::new(),
+            engine: Engine::Oscillator,
+            oscillator: oscillator.desc(),
+            oscillators: VoiceOscillatorsDesc::default(),
+            fm_algorithm: FmAlgorithm::Pairs,
+            sample_zones: 0,
+            drum_mode: false,
+            drum_step: None,
+            drum_pattern: 0,
+            song_mode: false,
+            bpm: 120.0,
+            swing: 0.0,
+            playing: false,
+            following: false,
+            position: (1, 1),
+            looper: LooperState::Stopped,
+            loop_bars: 2,
+            loop_layers: 0,
+            chord: Chord::Off,
+            scale: Scale::default(),
+            generator: None,
+            sustain: false,
+            capturing: false,
+            parts: Vec::new(),
+            split: None,
+            patch_seed: None,
+            filter: FilterSettings::default(),
+            filter_envelope: FilterEnvelope::default(),
+            master_volume: 1.0,
+            compressor_reduction: 0.0,
+            limiter_reduction: 0.0,
+            pitch_bend: 0.0,
+            mod_wheel: 0.0,
+            octave: 0,
+            transpose: 0,
+            play_mode: PlayMode::Poly,
+            voices: 0,
+            polyphony: Polyphony::default(),
+            recording: false,
+            preset_name: String::from("default"),
+            project_name: String::from("session"),
+            status: String::new(),
+            prompt: None,
+            buffer_frames: 0,
+            latency: 0.0,
+            sample_rate: 0,
+            keys: Vec::new(),
+            held: Vec::new(),
+        };
+        Instrument { 
             cursor: 0, 
             freq: 220., 
             sr: cpal::SampleRate(0),
-            // wave_generator: Box::new(crate::audio::waves::RandomWave::new()),
-            oscillator: Oscillator { 
-                ttf: LinearTransform { alpha: Box::new(IdentityWave), beta: Box::new(NullWave) },
-                wtf: LinearTransform { alpha: Box::new(IdentityWave), beta: Box::new(NullWave) },
-                otf: Box::new(SinWave),
-            },
+            channels: 1,
+            engine: Engine::Oscillator,
+            oscillator,
+            oscillators: VoiceOscillators::default(),
+            fm: FmVoice::default(),
+            sampler: Sampler::new(),
+            sampler_voices: HashMap::new(),
+            phases: HashMap::new(),
+            drums: DrumMachine::new(),
+            drum_mode: false,
+            noise: NoiseLayer::default(),
+            noise_source: NoiseLayer::default().color.build(),
+            sub: SubOscillator::default(),
+            voice_fold: WavefolderSettings::off(),
+            voice_drive: SaturationSettings::off(),
             keyboard_buffer: KeyboardBuffer::new(),
+            voices: KeyboardBuffer::new(),
+            arpeggiator: Arpeggiator::new(ArpSettings::default()),
+            transport: Transport::new(),
+            midi_clock: MidiClock::Off,
+            midi_out: None,
+            disk: None,
+            looper: Looper::new(),
+            loop_notes: Vec::with_capacity(64),
+            chord: ChordSettings::default(),
+            chord_roots: HashMap::new(),
+            scale: Scale::default(),
+            generator: Generator::new(rand::random()),
+            generated: Vec::with_capacity(16),
+            performance: Performance::default(),
+            parts: Vec::new(),
+            part_keys: HashMap::new(),
+            layer_keys: HashMap::new(),
+            split: None,
+            part_block: (Vec::new(), Vec::new()),
+            arp_held: Vec::with_capacity(32),
+            play_mode: PlayMode::Poly,
+            note_priority: NotePriority::Last,
+            polyphony: Polyphony::default(),
+            stolen: HashMap::new(),
             envelope: Envelope::new(),
-            key_to_freq: k2f,
-            clock: std::time::Instant::now()
-        };
-        return i;
+            filter: FilterSettings::default(),
+            filter_envelope: FilterEnvelope::default(),
+            filters: HashMap::new(),
+            glide: GlideSettings::default(),
+            unison: UnisonSettings::default(),
+            glides: HashMap::new(),
+            last_note: None,
+            lfos: Vec::new(),
+            mod_matrix: ModMatrix::new(),
+            pitch_bend: 0.0,
+            bend_range: 2.0,
+            mod_wheel: 0.0,
+            effects: EffectChain::from_desc(&default_effects(), 44100.0),
+            limiter: Limiter::new(LimiterSettings::default(), 44100.0),
+            dc_blockers: [DcBlocker::new(44100.0), DcBlocker::new(44100.0)],
+            smoothed: Smoothed::new(44100.0),
+            key_to_note: Keymap::default().notes().unwrap_or_default(),
+            sustain_key: Keymap::default().sustain_key().unwrap_or_default(),
+            octave: 0,
+            transpose: 0,
+            detune: 0.0,
+            clock: Clock::Wall(std::time::Instant::now()),
+            scheduling: false,
+            scheduled: Vec::new(),
+            last_callback: None,
+            recorder: None,
+            preset_name: String::from("default"),
+            patch_seed: None,
+            locks: Locks::default(),
+            prompt: None,
+            mutation: 0.1,
+            project_name: String::from("session"),
+            selected_param: ParamId::Cutoff,
+            macros: default_macros(),
+            history: History::default(),
+            learning: None,
+            cc_mappings: Vec::new(),
+            master_volume: 1.0,
+            buffer_frames: 0,
+            latency: 0.0,
+            input: InputQueue::default(),
+            input_block: Vec::with_capacity(8192),
+            monitor_input: false,
+            input_gain: 1.0,
+            status: String::new(),
+            snapshot: Arc::new(Mutex::new(snapshot)),
+            tap: Arc::new(OutputTap::new()),
+            meter: Arc::new(Meter::new()),
+            block: BlockBuffers::default(),
+        }
     }
 
     pub fn keyboard_buffer(&mut self) -> &mut KeyboardBuffer { &mut self.keyboard_buffer }
+
+    pub fn apply_keymap(&mut self, keymap: &Keymap) -> Result<(), KeymapError> {
+        self.key_to_note = keymap.notes()?;
+        self.sustain_key = keymap.sustain_key()?;
+        Ok(())
+    }
+
+    // octave and transpose only shift the computer keyboard, midi notes
+    // play at their own pitch.
+    pub fn set_octave(&mut self, octave: i32) { self.octave = octave.clamp(-4, 4) }
+    pub fn octave(&self) -> i32 { self.octave }
+    pub fn set_transpose(&mut self, semitones: i32) { self.transpose = semitones.clamp(-12, 12) }
+    pub fn detune(&self) -> f32 { self.detune }
+    pub fn set_detune(&mut self, cents: f32) { self.detune = cents.clamp(-100.0, 100.0) }
+    pub fn transpose(&self) -> i32 { self.transpose }
+
+    // seconds since the instrument started, or into an offline render.
+    pub fn now(&self) -> f32 { self.clock.now() }
+
+    /// Renders `frames` stereo frames at `sample_rate`, playing `events`
+    /// sorted by time.
+    ///
+    /// runs on the offline clock rather than real time, so the same patch
+    /// and events always give the same samples (noise and random waves
+    /// aside). returns interleaved left and right samples, lined up with
+    /// the events: the latency of the limiter is rendered past the end and
+    /// cut off the start.
+    pub fn render(&mut self, events: &[NoteEvent], sample_rate: u32, frames: usize) -> Vec<f32> {
+        const BLOCK: usize = 256;
+        self.clock = Clock::Offline(0.0);
+        self.cursor = 0;
+        self.channels = 2;
+        self.set_sample_rate(cpal::SampleRate(sample_rate));
+        let (mut left, mut right) = (vec![0.0; BLOCK], vec![0.0; BLOCK]);
+        let latency = self.limiter.latency();
+        let mut out = Vec::with_capacity((frames + latency) * 2);
+        let mut events = events.iter().peekable();
+        for start in (0..frames + latency).step_by(BLOCK) {
+            let end = (frames + latency).min(start + BLOCK);
+            let mut pos = start;
+            // blocks are split at events, so notes start on their sample.
+            while pos < end {
+                self.clock = Clock::Offline(self.t(0));
+                while let Some(event) = events.next_if(|e| e.time <= self.now()) {
+                    self.apply_midi_message(event.message);
+                }
+                if pos == start {
+                    self.cleanup_events();
+                    self.tick_arpeggiator();
+                    self.tick_parts();
+                    self.tick_transport();
+                }
+                let n = match events.peek() {
+                    Some(e) => (1..end - pos).find(|&i| self.t(i as u128) >= e.time).unwrap_or(end - pos),
+                    None => end - pos,
+                };
+                self.pull_input(n);
+                self.gen_block(&mut left[..n], &mut right[..n]);
+                self.mix_parts(&mut left[..n], &mut right[..n]);
+                self.process_master(&mut left[..n], &mut right[..n]);
+                out.extend(left[..n].iter().zip(&right[..n]).flat_map(|(l, r)| [*l, *r]));
+                self.advance_cursor(n as u128);
+                pos += n;
+            }
+        }
+        out.drain(..latency * 2);
+        out
+    }
+
+    fn key_shift(&self) -> f32 { (12 * self.octave + self.transpose) as f32 }
+
+    pub fn note_freq(&self, key: &NoteKey) -> f32 {
+        let note = match key {
+            NoteKey::Key(k) => match self.key_to_note.get(k) {
+                Some(n) => n + self.key_shift(),
+                None => return 0.0,
+            },
+            NoteKey::Midi(n) | NoteKey::Loop(n) | NoteKey::Generated(n) => *n as f32,
+            NoteKey::Chord(n, i) => *n as f32 + *i as f32,
+        };
+        note_to_freq(self.scale.lock(note) + self.detune / 100.0)
+    }
+
+    pub fn scale(&self) -> Scale { self.scale }
+    pub fn set_scale(&mut self, scale: Scale) { self.scale = scale; }
+
+    // a note the scale leaves out. keys that play no note aren't.
+    fn out_of_key(&self, key: NoteKey) -> bool {
+        let freq = self.note_freq(&key);
+        freq > 0.0 && self.scale.filters(freq_to_note(freq))
+    }
+
+    // velocity for notes played on the computer keyboard.
+    pub fn set_keyboard_velocity(&mut self, velocity: KeyboardVelocity) {
+        self.keyboard_buffer.velocity = velocity;
+        self.voices.velocity = velocity;
+    }
+
+    pub fn note_on(&mut self, key: NoteKey, velocity: f32) {
+        if let Some(pad) = self.drum_pad(key) { return self.hit_pad(pad, velocity); }
+        if self.out_of_key(key) { return; }
+        self.loop_note(key, velocity, true);
+        self.capture_note(key, velocity, true);
+        let now = self.now();
+        self.keyboard_buffer.press(key, velocity, now);
+        if self.arpeggiator.enabled { return; }
+        match self.play_mode {
+            PlayMode::Poly => self.press_voice(key, velocity, now),
+            _ => self.update_mono_voice(),
+        }
+        self.layer_note(key, velocity, true);
+        self.chord_on(key, velocity);
+    }
+
+    pub fn note_off(&mut self, key: NoteKey) {
+        if self.drum_pad(key).is_some() { return; }
+        self.loop_note(key, 0.0, false);
+        self.capture_note(key, 0.0, false);
+        let now = self.now();
+        self.keyboard_buffer.release(key, now);
+        if self.arpeggiator.enabled { return; }
+        match self.play_mode {
+            PlayMode::Poly => self.voices.note_off(key, now),
+            _ => self.update_mono_voice(),
+        }
+        self.layer_note(key, 0.0, false);
+        self.chord_off(key);
+    }
+
+    pub fn chord(&self) -> &ChordSettings { &self.chord }
+    // takes effect on the next notes, the chords held are left alone.
+    pub fn set_chord(&mut self, chord: ChordSettings) { self.chord = chord; }
+
+    // presses the intervals of the chord over a note played, as notes of
+    // their own. the ones the looper plays back were recorded with theirs.
+    fn chord_on(&mut self, key: NoteKey, velocity: f32) {
+        if self.chord.intervals().is_empty() || matches!(key, NoteKey::Loop(_) | NoteKey::Chord(..)) { return; }
+        let freq = self.note_freq(&key);
+        if freq <= 0.0 { return; }
+        let root = freq_to_note(freq).round().clamp(0.0, 127.0) as u8;
+        self.chord_roots.insert(key, root);
+        for i in 0..self.chord.intervals().len() {
+            let interval = self.chord.intervals()[i];
+            if root as u32 + interval as u32 <= 127 { self.note_on(NoteKey::Chord(root, interval), velocity); }
+        }
+    }
+
+    // releases the chord of a note, whatever the chord is by now.
+    fn chord_off(&mut self, key: NoteKey) {
+        let Some(root) = self.chord_roots.remove(&key) else { return };
+        let chord: Vec<NoteKey> = self.keyboard_buffer.held().filter(|k| matches!(k, NoteKey::Chord(r, _) if *r == root)).copied().collect();
+        chord.into_iter().for_each(|k| self.note_off(k));
+    }
+
+    /// Panic: forgets every key held, releases every voice and centers the
+    /// pitch bend. gets rid of notes stuck by a key release the terminal
+    /// never delivered.
+    pub fn all_notes_off(&mut self) {
+        let now = self.now();
+        self.keyboard_buffer.event_buffer.clear();
+        self.voices.set_sustain(false, now);
+        self.voices.release_all(now);
+        self.arp_held.clear();
+        self.chord_roots.clear();
+        self.part_keys.clear();
+        self.layer_keys.clear();
+        self.parts.iter_mut().for_each(|p| p.instrument.all_notes_off());
+        self.set_pitch_bend(0.0);
+        self.publish_snapshot();
+    }
+
+    // pad played by a key or midi note in drum mode.
+    fn drum_pad(&self, key: NoteKey) -> Option<usize> {
+        if !self.drum_mode { return None; }
+        let pad = match key {
+            NoteKey::Key(KeyCode::Char(c)) => DrumMachine::pad_for_key(c),
+            NoteKey::Midi(n) => DrumMachine::pad_for_note(n),
+            _ => None,
+        };
+        pad.filter(|&p| p < self.drums.pads())
+    }
+
+    pub fn play_mode(&self) -> PlayMode { self.play_mode }
+    pub fn set_play_mode(&mut self, mode: PlayMode) {
+        if mode == self.play_mode { return; }
+        self.play_mode = mode;
+        if self.arpeggiator.enabled { return; }
+        let now = self.now();
+        self.voices.release_all(now);
+        match mode {
+            PlayMode::Poly => self.press_held_keys(now),
+            _ => self.update_mono_voice(),
+        }
+    }
+
+    pub fn note_priority(&self) -> NotePriority { self.note_priority }
+    pub fn set_note_priority(&mut self, priority: NotePriority) {
+        self.note_priority = priority;
+        if !self.arpeggiator.enabled && self.play_mode != PlayMode::Poly { self.update_mono_voice(); }
+    }
+
+    fn press_held_keys(&mut self, now: f32) {
+        let held: Vec<(NoteKey, f32)> = self.keyboard_buffer.event_buffer.values()
+            .filter(|e| e.time_release.is_none()).map(|e| (e.key, e.velocity)).collect();
+        held.into_iter().for_each(|(k, v)| self.press_voice(k, v, now));
+    }
+
+    pub fn polyphony(&self) -> Polyphony { self.polyphony }
+    pub fn set_polyphony(&mut self, polyphony: Polyphony) {
+        self.polyphony = Polyphony { max_voices: polyphony.max_voices.clamp(1, Polyphony::MAX_VOICES), ..polyphony };
+        let now = self.now();
+        while self.steal_voice(None, now) {}
+    }
+
+    fn press_voice(&mut self, key: NoteKey, velocity: f32, now: f32) {
+        self.voices.press(key, velocity, now);
+        self.voice_pressed(key, now);
+    }
+
+    // a key struck again while its voice fades out takes it back from the
+    // start, then voices are stolen until the new one fits.
+    fn voice_pressed(&mut self, key: NoteKey, now: f32) {
+        if self.stolen.remove(&key).is_some() {
+            if let Some(e) = self.voices.event_buffer.get_mut(&key) {
+                (e.time_press, e.time_release) = (now, None);
+            }
+        }
+        while self.steal_voice(Some(key), now) {}
+    }
+
+    // fades out one voice if too many are sounding, never the one just
+    // pressed. false when there was no need.
+    fn steal_voice(&mut self, pressed: Option<NoteKey>, now: f32) -> bool {
+        let playing = self.voices.event_buffer.values()
+            .filter(|e| !self.stolen.contains_key(&e.key) && self.note_freq(&e.key) > 0.0);
+        if playing.clone().count() <= self.polyphony.max_voices { return false; }
+        let candidates = playing.filter(|e| Some(e.key) != pressed);
+        let level = |e: &KeyboardBufferEvent| self.envelope.sample(now, e.time_press, e.time_release) * e.velocity;
+        let freq = pressed.map(|k| self.note_freq(&k));
+        let same_note = match self.polyphony.stealing {
+            VoiceStealing::SameNote => candidates.clone().find(|e| Some(self.note_freq(&e.key)) == freq),
+            _ => None,
+        };
+        let victim = match self.polyphony.stealing {
+            VoiceStealing::Quietest => candidates.min_by(|a, b| level(a).total_cmp(&level(b))),
+            _ => same_note.or_else(|| candidates.min_by(|a, b| {
+                (a.time_release.is_none(), a.time_press).partial_cmp(&(b.time_release.is_none(), b.time_press)).unwrap_or(std::cmp::Ordering::Equal)
+            })),
+        }.map(|e| e.key);
+        let Some(victim) = victim else { return false };
+        self.voices.release(victim, now);
+        self.stolen.insert(victim, now);
+        true
+    }
+
+    // the held key that should sound in mono and legato modes.
+    fn mono_key(&self) -> Option<NoteKey> {
+        let held = self.keyboard_buffer.event_buffer.values()
+            .filter(|e| e.time_release.is_none() && self.note_freq(&e.key) > 0.0);
+        match self.note_priority {
+            NotePriority::Last => held.max_by(|a, b| a.time_press.total_cmp(&b.time_press)).map(|e| e.key),
+            NotePriority::Low => held.map(|e| e.key).min_by(|a, b| self.note_freq(a).total_cmp(&self.note_freq(b))),
+            NotePriority::High => held.map(|e| e.key).max_by(|a, b| self.note_freq(a).total_cmp(&self.note_freq(b))),
+        }
+    }
+
+    // a single voice follows the held keys. its filter and glide state move
+    // along with it, so changing notes doesn't click and glides from the
+    // current pitch.
+    fn update_mono_voice(&mut self) {
+        let now = self.now();
+        let Some(key) = self.mono_key() else {
+            // the sustain keeps the last note sounding.
+            if !self.voices.sustain() { self.voices.release_all(now); }
+            return;
+        };
+        let previous = self.voices.event_buffer.values()
+            .max_by(|a, b| a.time_press.total_cmp(&b.time_press))
+            .map(|e| (e.key, e.time_press, e.time_release.is_none()));
+        if previous.is_some_and(|(k, _, held)| k == key && held) { return; }
+
+        let velocity = self.keyboard_buffer.event_buffer.get(&key).map_or(1.0, |e| e.velocity);
+        let mut time_press = now;
+        if let Some((prev, prev_press, held)) = previous {
+            if prev != key {
+                if let Some(f) = self.filters.remove(&prev) { self.filters.insert(key, f); }
+                if let Some(g) = self.glides.remove(&prev) { self.glides.insert(key, g); }
+                if let Some(v) = self.sampler_voices.remove(&prev) { self.sampler_voices.insert(key, v); }
+                if let Some(p) = self.phases.remove(&prev) { self.phases.insert(key, p); }
+            }
+            if held && self.play_mode == PlayMode::Legato { time_press = prev_press; }
+        }
+        self.voices.event_buffer.clear();
+        self.stolen.clear();
+        self.voices.press(key, velocity, time_press);
+    }
+    pub fn voices(&mut self) -> &mut KeyboardBuffer { &mut self.voices }
+
+    /// Sustain pedal: while it's down the notes let go keep sounding, they
+    /// are released once it's up.
+    pub fn set_sustain(&mut self, on: bool) {
+        if on == self.voices.sustain() { return; }
+        let now = self.now();
+        self.voices.set_sustain(on, now);
+        if !on && self.play_mode != PlayMode::Poly { self.update_mono_voice(); }
+    }
+
+    pub fn arpeggiator(&self) -> &Arpeggiator { &self.arpeggiator }
+    pub fn arpeggiator_mut(&mut self) -> &mut Arpeggiator { &mut self.arpeggiator }
+
+    pub fn set_arpeggiator_enabled(&mut self, on: bool) {
+        if on == self.arpeggiator.enabled { return; }
+        let now = self.now();
+        self.arpeggiator.enabled = on;
+        self.voices.release_all(now);
+        if !on {
+            // hand the keys still held back to the voices.
+            match self.play_mode {
+                PlayMode::Poly => self.press_held_keys(now),
+                _ => self.update_mono_voice(),
+            }
+        }
+    }
+
+    // called once per buffer, so arpeggiated notes are quantized to it.
+    pub fn tick_arpeggiator(&mut self) {
+        if !self.arpeggiator.enabled { return; }
+        let now = self.now();
+
+        let mut held = std::mem::take(&mut self.arp_held);
+        held.clear();
+        held.extend(self.keyboard_buffer.held().filter(|k| self.note_freq(k) > 0.0));
+        held.sort_by(|a, b| self.note_freq(a).total_cmp(&self.note_freq(b)));
+
+        let (off, on) = self.arpeggiator.tick(now, self.transport.bpm(), self.transport.swing(), &held);
+        if let Some(k) = off {
+            self.voices.release(k, now);
+            self.layer_note(k, 0.0, false);
+        }
+        if let Some(k) = on {
+            let velocity = self.keyboard_buffer.event_buffer.get(&k).map_or(1.0, |e| e.velocity);
+            self.press_voice(k, velocity, now);
+            self.layer_note(k, velocity, true);
+        }
+        self.arp_held = held;
+    }
     
     // the cursor is used to advance through buffer samples and prevent
     // the wave from repeating on each buffer request from the sound card.
     pub fn advance_cursor(&mut self, n: u128) { self.cursor = (self.cursor + n) % u128::MAX }
     pub fn cursor(&self) -> u128 { self.cursor }
-    pub fn set_sample_rate(&mut self, sr: cpal::SampleRate) { self.sr = sr }
+    pub fn set_sample_rate(&mut self, sr: cpal::SampleRate) {
+        self.sr = sr;
+        self.parts.iter_mut().for_each(|p| p.instrument.set_sample_rate(sr));
+        self.oscillator.sample_rate = sr.0 as f32;
+        self.oscillators.set_sample_rate(sr.0 as f32);
+        self.filters.clear();
+        self.glides.clear();
+        self.sampler_voices.clear();
+        self.effects.set_sample_rate(sr.0 as f32);
+        self.limiter = Limiter::new(self.limiter.settings, sr.0 as f32);
+        self.dc_blockers.iter_mut().for_each(|b| b.set_sample_rate(sr.0 as f32));
+        self.smooth_params();
+        self.smoothed.params().into_iter().for_each(|p| p.set_sample_rate(sr.0 as f32));
+    }
     pub fn set_frequency(&mut self, f: f32) { self.freq = f }
     pub fn sample_rate(&self) -> u128 { self.sr.0 as u128 }
+    // what the device actually asks for, whatever buffer size was requested.
+    pub fn set_callback_stats(&mut self, frames: usize, latency: f32) {
+        self.buffer_frames = frames;
+        self.latency = latency;
+    }
+    pub fn buffer_frames(&self) -> usize { self.buffer_frames }
+    pub fn latency(&self) -> f32 { self.latency }
+    pub fn input_queue(&self) -> InputQueue { Arc::clone(&self.input) }
+
+    // takes the input for the next `frames` samples, padded with silence
+    // when the capture is behind.
+    pub fn pull_input(&mut self, frames: usize) {
+        let mut queue = self.input.lock().unwrap();
+        self.input_block.clear();
+        self.input_block.extend((0..frames).map(|_| queue.pop_front().unwrap_or(0.0)));
+    }
 
-    fn t(&self, i: u128) -> f32 { return ((self.cursor+i) as f32)/(self.sample_rate() as f32); }
+    pub fn input_sample(&self, i: u128) -> f32 { self.input_block.get(i as usize).copied().unwrap_or(0.0) }
 
-    pub fn gen(&mut self, i: u128) -> f32 {  
-        let t = self.t(i);
-        let now = self.clock.elapsed().as_secs_f32();
+    // the input of a block played in parts is shifted along with them.
+    fn shift_input(&mut self, n: usize) { self.input_block.drain(..n.min(self.input_block.len())); }
 
-        self.keyboard_buffer.event_buffer.iter()
-            .map(|event| {
-                let freq = self.key_to_freq.get(event.0).unwrap_or(&0.0);
-                let env = self.envelope.sample(now, event.1.time_press, event.1.time_release);
-                self.oscillator.gen(t, *freq)*env
-            }).sum()
+    // monitored input is mixed into the output before the effect chain.
+    pub fn set_input_monitoring(&mut self, on: bool) { self.monitor_input = on }
+    pub fn input_monitoring(&self) -> bool { self.monitor_input }
+    pub fn set_input_gain(&mut self, gain: f32) { self.input_gain = gain.max(0.0) }
+    pub fn input_gain(&self) -> f32 { self.input_gain }
+
+    pub fn set_channels(&mut self, channels: u16) { self.channels = channels.max(1) }
+    pub fn channels(&self) -> u16 { self.channels }
+
+    pub fn start_recording<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), hound::Error> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::start(path, self.sr.0, self.channels)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<(), hound::Error> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.stop(),
+            None => Ok(())
+        }
     }
-}
 
+    pub fn patch(&self) -> Patch {
+        Patch {
+            engine: self.engine,
+            oscillator: self.oscillator.desc(),
+            oscillators: self.oscillators.desc(),
+            fm: self.fm.clone(),
+            sampler: self.sampler.desc().clone(),
+            drums: self.drums.kit().clone(),
+            noise: self.noise,
+            sub: self.sub,
+            voice_fold: self.voice_fold,
+            voice_drive: self.voice_drive,
+            envelope: self.envelope.clone(),
+            filter: self.filter,
+            filter_envelope: self.filter_envelope.clone(),
+            lfos: self.lfos.clone(),
+            modulation: self.mod_matrix.routes().to_vec(),
+            effects: self.effects.desc(),
+            glide: self.glide,
+            unison: self.unison,
+            play_mode: self.play_mode,
+            note_priority: self.note_priority,
+            polyphony: self.polyphony,
+            cc_mappings: self.cc_mappings.clone(),
+            macros: self.macros.clone(),
+            keymap: self.key_to_note.iter()
+                .filter_map(|(k, n)| match k { KeyCode::Char(c) => Some((c.to_string(), note_to_freq(*n))), _ => None })
+                .collect(),
+        }
+    }
 
-unsafe impl Sync for Instrument { }
-impl KeyboardHandler for Instrument {
-    fn handle_key_event(&mut self, event: KeyEvent, timestamp: f32) {
+    pub fn apply_patch(&mut self, patch: &Patch) {
+        self.engine = patch.engine;
+        self.oscillator.apply_desc(&patch.oscillator);
+        self.oscillators.apply_desc(&patch.oscillators);
+        self.fm.clone_from(&patch.fm);
+        if let Err(e) = self.load_samples(&patch.sampler) { self.status = format!("could not load samples: {}", e); }
+        if let Err(e) = self.load_drum_kit(&patch.drums) { self.status = format!("could not load drum kit: {}", e); }
+        self.set_noise(patch.noise);
+        self.sub = patch.sub;
+        self.voice_fold = patch.voice_fold;
+        self.voice_drive = patch.voice_drive;
+        self.envelope = patch.envelope.clone();
+        self.filter = patch.filter;
+        self.filter_envelope.clone_from(&patch.filter_envelope);
+        self.lfos.clone_from(&patch.lfos);
+        self.mod_matrix.set_routes(&patch.modulation);
+        self.effects.load(&patch.effects);
+        self.glide = patch.glide;
+        self.set_unison(patch.unison);
+        self.set_play_mode(patch.play_mode);
+        self.set_note_priority(patch.note_priority);
+        self.set_polyphony(patch.polyphony);
+        self.cc_mappings.clone_from(&patch.cc_mappings);
+        self.macros.clone_from(&patch.macros);
+        self.macros.resize(MACROS, Macro::default());
+        if !patch.keymap.is_empty() {
+            self.key_to_note = patch.keymap.iter()
+                .filter_map(|(k, f)| k.chars().next().map(|c| (KeyCode::Char(c), freq_to_note(*f))))
+                .collect();
+        }
+    }
 
-        match event {
-            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('r'), .. } => {
-                self.oscillator.randomize();
-                self.envelope.randomize();
+    pub fn load_preset(&mut self, name: &str) -> Result<(), PresetError> {
+        let mut patch = preset::load_patch(name)?;
+        let read = preset::read_files(&mut patch);
+        self.use_preset(name, &patch);
+        if let Err(e) = read { self.status = format!("could not read {}", e); }
+        Ok(())
+    }
+
+    /// Plays `patch`, loaded as the preset `name`, as a fresh start: there
+    /// is nothing to undo and no seed it was rolled from.
+    pub fn use_preset(&mut self, name: &str, patch: &Patch) {
+        self.apply_patch(patch);
+        self.history.clear();
+        self.patch_seed = None;
+        self.preset_name = name.to_string();
+    }
+
+    pub fn preset_name(&self) -> &str { &self.preset_name }
+
+    /// Plays the waveform `source` gives of `t` and `f`, see `expr`.
+    pub fn set_expression(&mut self, source: &str) -> Result<(), ParseError> {
+        self.oscillator.otf = Box::new(ExprWave::parse(source)?);
+        Ok(())
+    }
+
+    // a key typed into the expression prompt.
+    fn prompt_key(&mut self, event: KeyEvent) {
+        let Some(text) = &mut self.prompt else { return };
+        if event.kind == KeyEventKind::Release { return; }
+        match event.code {
+            KeyCode::Char(c) if !event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => text.push(c),
+            KeyCode::Backspace => { text.pop(); },
+            KeyCode::Esc => {
+                self.prompt = None;
+                self.status = String::from("waveform unchanged");
             },
-            _ => { self.keyboard_buffer.handle_key_event(event, timestamp); }
+            // a mistake keeps the prompt open to be fixed.
+            KeyCode::Enter => {
+                let source = text.clone();
+                self.status = match self.set_expression(&source) {
+                    Ok(()) => { self.prompt = None; format!("waveform {}", source.trim()) },
+                    Err(e) => format!("invalid expression: {}", e),
+                };
+            },
+            _ => (),
         }
     }
 
-    fn cleanup_events(&mut self) {
-        self.keyboard_buffer.clean_stale_events(self.clock.elapsed().as_secs_f32(), Some(self.envelope.3));
+    /// Plays the script at `path`, relative to the scripts dir, as the
+    /// waveform of the oscillator.
+    #[cfg(feature = "script")]
+    pub fn load_script<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), crate::audio::script::ScriptError> {
+        self.oscillator.otf = Box::new(crate::audio::script::ScriptWave::load(path)?);
+        Ok(())
     }
-}
 
+    /// The script the oscillator plays, if it plays one.
+    pub fn script(&self) -> Option<std::path::PathBuf> {
+        match self.oscillator.otf.desc() { WaveDesc::Script(path) => Some(path), _ => None }
+    }
 
-impl std::fmt::Debug for Instrument {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // self.sr.fmt(f);
-        // self.freq.fmt(f);
-        let _=self.cursor.fmt(f);
-        // self.keyboard_buffer.fmt(f);
-        // self.envelope.fmt(f);
-        return std::fmt::Result::Ok(());
+    /// Plays `wave`, its script read again, if the oscillator still plays
+    /// that script. true if it does.
+    #[cfg(feature = "script")]
+    pub fn reload_script(&mut self, wave: crate::audio::script::ScriptWave) -> bool {
+        if self.script().as_deref() != Some(wave.path()) { return false; }
+        self.oscillator.otf = Box::new(wave);
+        true
+    }
+
+    /// Applies `patch`, the preset in use read again, if it no longer
+    /// matches the patch, as after its file was edited. true if it was
+    /// applied.
+    pub fn reload_preset(&mut self, patch: &Patch) -> bool {
+        // a save from here comes back unchanged, and keeps the history.
+        let current = Patch { keymap: patch.keymap.clone(), ..self.patch() };
+        if *patch == current { return false; }
+        self.apply_patch(patch);
+        self.history.clear();
+        self.patch_seed = None;
+        true
+    }
+
+    /// Rolls a random oscillator, envelope and filter from `seed`, the same
+    /// patch for the same seed. locked sections are kept.
+    pub fn randomize(&mut self, seed: u64) {
+        // each section rolls from a seed of its own, so locking one doesn't
+        // change how the others roll.
+        let rng = |section: Section| StdRng::seed_from_u64(seed.wrapping_add(section as u64));
+        if !self.locks.locked(Section::Oscillator) { self.oscillator.randomize(&mut rng(Section::Oscillator)); }
+        if !self.locks.locked(Section::Envelope) { self.envelope.randomize(&mut rng(Section::Envelope)); }
+        if !self.locks.locked(Section::Filter) {
+            let mut rng = rng(Section::Filter);
+            self.set_param_normalized(ParamId::Cutoff, rng.gen_range(0.4..1.0));
+            self.set_param_normalized(ParamId::Resonance, rng.gen_range(0.0..0.5));
+        }
+        self.patch_seed = Some(seed);
+    }
+
+    pub fn patch_seed(&self) -> Option<u64> { self.patch_seed }
+
+    /// Moves the parameters of the unlocked sections by up to the mutation
+    /// amount, and now and then rolls a transform of the oscillator again.
+    pub fn mutate(&mut self, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let locks = self.locks;
+        for section in Section::ALL.into_iter().filter(|s| !locks.locked(*s)) {
+            // a width only for a pulse, the waveform stays what it is.
+            let pulse = self.oscillator.pulse_width().is_some();
+            for id in section.params().iter().filter(|id| **id != ParamId::PulseWidth || pulse) {
+                let x = mutate::mutate(&mut rng, self.param_normalized(*id), self.mutation);
+                self.set_param_normalized(*id, x);
+            }
+        }
+        if !self.locks.locked(Section::Oscillator) && rng.gen::<f32>() < self.mutation {
+            match rng.gen_bool(0.5) {
+                true => self.oscillator.ttf.randomize(&mut rng),
+                false => self.oscillator.wtf.randomize(&mut rng),
+            }
+        }
+    }
+
+    pub fn locks(&self) -> Locks { self.locks }
+    pub fn set_locks(&mut self, locks: Locks) { self.locks = locks }
+    pub fn mutation(&self) -> f32 { self.mutation }
+    pub fn set_mutation(&mut self, amount: f32) { self.mutation = amount.clamp(0.0, 1.0) }
+
+    /// The session as it is: the patches of this instrument and its parts,
+    /// the tempo and how the parts are played.
+    pub fn project(&self) -> Project {
+        Project {
+            master_volume: self.master_volume,
+            preset: self.preset_name.clone(),
+            split: self.split,
+            transport: TransportSettings { bpm: self.transport.bpm(), swing: self.transport.swing(), song_mode: self.drums.song_mode },
+            patch: self.patch(),
+            parts: self.parts.iter().map(|p| ProjectPart { desc: p.desc.clone(), patch: p.instrument.patch() }).collect(),
+        }
+    }
+
+    /// Picks a session up where it was left, replacing the parts.
+    pub fn apply_project(&mut self, project: &Project) {
+        self.all_notes_off();
+        self.apply_patch(&project.patch);
+        self.preset_name.clone_from(&project.preset);
+        self.set_master_volume(project.master_volume);
+        self.transport.set_bpm(project.transport.bpm);
+        self.transport.set_swing(project.transport.swing);
+        self.drums.song_mode = project.transport.song_mode && !self.drums.kit().song.is_empty();
+        (self.parts, self.split) = (Vec::new(), None);
+        for part in &project.parts {
+            let mut part = Part::from_patch(part.desc.clone(), &part.patch);
+            part.instrument.set_sample_rate(self.sr);
+            self.parts.push(part);
+        }
+        self.split = project.split.filter(|i| self.parts.get(*i).is_some_and(|p| p.desc.zone.is_some()));
+        self.history.clear();
+        self.patch_seed = None;
+    }
+
+    pub fn project_name(&self) -> &str { &self.project_name }
+    // the name the project is saved as from now on.
+    pub fn set_project_name(&mut self, name: &str) { self.project_name = name.to_string() }
+
+    pub fn load_project(&mut self, name: &str) -> Result<(), PresetError> {
+        let mut project = project::load_project(name)?;
+        let read = project::read_files(&mut project);
+        self.apply_project(&project);
+        if let Err(e) = read { self.status = format!("could not read {}", e); }
+        self.project_name = name.to_string();
+        Ok(())
+    }
+
+    pub fn envelope(&self) -> &Envelope { &self.envelope }
+    pub fn filter_settings(&self) -> FilterSettings { self.filter }
+    pub fn set_filter_settings(&mut self, settings: FilterSettings) { self.filter = settings }
+    pub fn set_cutoff(&mut self, cutoff: f32) { self.filter.cutoff = cutoff.clamp(20.0, 20000.0) }
+    pub fn set_resonance(&mut self, q: f32) { self.filter.resonance = q.clamp(0.1, 20.0) }
+
+    pub fn glide(&self) -> GlideSettings { self.glide }
+    pub fn set_glide(&mut self, glide: GlideSettings) { self.glide = GlideSettings { time: glide.time.max(0.0), ..glide } }
+
+    pub fn engine(&self) -> Engine { self.engine }
+    pub fn set_engine(&mut self, engine: Engine) { self.engine = engine }
+    pub fn fm(&self) -> &FmVoice { &self.fm }
+    pub fn fm_mut(&mut self) -> &mut FmVoice { &mut self.fm }
+
+    pub fn sampler(&self) -> &Sampler { &self.sampler }
+    // keeps the current samples if any of the new ones fails to load.
+    pub fn load_samples(&mut self, desc: &SamplerDesc) -> Result<(), hound::Error> {
+        if desc == self.sampler.desc() { return Ok(()); }
+        self.sampler = Sampler::load(desc)?;
+        self.sampler_voices.clear();
+        Ok(())
+    }
+    pub fn add_sample_zone(&mut self, zone: SampleZone) -> Result<(), hound::Error> {
+        self.sampler.add_zone(zone)?;
+        self.sampler_voices.clear();
+        Ok(())
+    }
+
+    pub fn drums(&self) -> &DrumMachine { &self.drums }
+    pub fn drums_mut(&mut self) -> &mut DrumMachine { &mut self.drums }
+    pub fn load_drum_kit(&mut self, kit: &DrumKit) -> Result<(), hound::Error> {
+        if kit == self.drums.kit() { return Ok(()); }
+        self.drums = DrumMachine::load(kit)?;
+        Ok(())
+    }
+    pub fn drum_mode(&self) -> bool { self.drum_mode }
+    pub fn set_drum_mode(&mut self, on: bool) { self.drum_mode = on }
+
+    pub fn hit_pad(&mut self, pad: usize, velocity: f32) {
+        let beat = self.transport.playing().then(|| self.transport.position_at(self.now()));
+        self.drums.hit(pad, velocity, beat);
+    }
+
+    pub fn transport(&self) -> &Transport { &self.transport }
+    pub fn transport_mut(&mut self) -> &mut Transport { &mut self.transport }
+
+    // moves the transport on, once per buffer like the arpeggiator, and
+    // plays the drum steps it went over. sending clock, the ticks it went
+    // over go out too.
+    pub fn tick_transport(&mut self) {
+        let Some((from, to)) = self.transport.advance(self.now()) else {
+            if let Some(note) = self.generator.release() { self.note_off(NoteKey::Generated(note)); }
+            return self.release_loop_notes();
+        };
+        let samples_per_beat = 60.0 / self.transport.bpm() as f64 * self.sample_rate() as f64;
+        self.drums.tick(from, to, self.transport.swing(), samples_per_beat);
+        if self.midi_clock == MidiClock::Out { self.send_clock(from, to); }
+        let mut notes = std::mem::take(&mut self.loop_notes);
+        self.looper.tick(from, to, self.looper.length(Transport::BEATS_PER_BAR), |n| notes.push(n));
+        for n in notes.drain(..) {
+            match n.on {
+                true => self.note_on(NoteKey::Loop(n.note), n.velocity),
+                false => self.note_off(NoteKey::Loop(n.note)),
+            }
+        }
+        self.loop_notes = notes;
+
+        let mut generated = std::mem::take(&mut self.generated);
+        self.generator.tick(from, to, &self.scale, |note, velocity, on| generated.push((note, velocity, on)));
+        for (note, velocity, on) in generated.drain(..) {
+            match on {
+                true => self.note_on(NoteKey::Generated(note), velocity),
+                false => self.note_off(NoteKey::Generated(note)),
+            }
+        }
+        self.generated = generated;
+    }
+
+    pub fn generator(&self) -> &Generator { &self.generator }
+    pub fn generator_mut(&mut self) -> &mut Generator { &mut self.generator }
+
+    fn set_generator_enabled(&mut self, on: bool) {
+        self.generator.enabled = on;
+        if let Some(note) = self.generator.release() { self.note_off(NoteKey::Generated(note)); }
+        if on && !self.transport.playing() { self.toggle_transport(); }
+    }
+
+    pub fn looper(&self) -> &Looper { &self.looper }
+    pub fn looper_mut(&mut self) -> &mut Looper { &mut self.looper }
+
+    // a note played while the looper records, at the position of the
+    // transport. the notes it plays back itself aren't recorded again.
+    fn loop_note(&mut self, key: NoteKey, velocity: f32, on: bool) {
+        if !self.looper.recording() || !self.transport.playing() || matches!(key, NoteKey::Loop(_)) { return; }
+        let freq = self.note_freq(&key);
+        if freq <= 0.0 { return; }
+        let note = freq_to_note(freq).round().clamp(0.0, 127.0) as u8;
+        let (beat, length) = (self.transport.position_at(self.now()), self.looper.length(Transport::BEATS_PER_BAR));
+        self.looper.note(beat, length, note, velocity, on);
+    }
+
+    pub fn performance(&self) -> &Performance { &self.performance }
+
+    pub fn parts(&self) -> &[Part] { &self.parts }
+    pub fn parts_mut(&mut self) -> &mut [Part] { &mut self.parts }
+
+    /// Adds a part playing its preset next to this instrument.
+    pub fn add_part(&mut self, desc: PartDesc) -> Result<(), PresetError> {
+        let mut part = Part::new(desc)?;
+        part.instrument.set_sample_rate(self.sr);
+        self.parts.push(part);
+        Ok(())
+    }
+
+    pub fn remove_part(&mut self, index: usize) -> Option<Part> {
+        if index >= self.parts.len() { return None; }
+        self.split = match self.split {
+            Some(split) if split == index => None,
+            Some(split) if split > index => Some(split - 1),
+            split => split,
+        };
+        Some(self.parts.remove(index))
+    }
+
+    /// The note the keyboard is split at: the keys below it play the split
+    /// part, the ones from it up this instrument.
+    pub fn split_point(&self) -> Option<u8> {
+        self.split.and_then(|i| self.parts[i].desc.zone).map(|(_, high)| high + 1)
+    }
+
+    /// Splits the keyboard at `point`, with a part playing `preset` below
+    /// it, the patch of this instrument if empty, or moves the split.
+    /// `None` takes the split part away.
+    pub fn set_split(&mut self, point: Option<u8>, preset: &str) -> Result<(), PresetError> {
+        let Some(point) = point else {
+            if let Some(i) = self.split { self.remove_part(i); }
+            return Ok(());
+        };
+        let zone = Some((0, point.clamp(1, 127) - 1));
+        match self.split {
+            Some(i) => self.parts[i].desc.zone = zone,
+            None => {
+                self.add_part(PartDesc { preset: preset.to_string(), zone, ..PartDesc::default() })?;
+                self.split = Some(self.parts.len() - 1);
+                if preset.is_empty() {
+                    let patch = self.patch();
+                    if let Some(part) = self.parts.last_mut() { part.instrument.apply_patch(&patch); }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // a part runs on the clock of the instrument it plays in.
+    fn part_mut(&mut self, index: usize) -> &mut Instrument {
+        let (clock, cursor) = (self.clock, self.cursor);
+        let part = &mut self.parts[index].instrument;
+        (part.clock, part.cursor) = (clock, cursor);
+        part
+    }
+
+    // the arpeggiators of the parts follow the tempo of the transport.
+    pub fn tick_parts(&mut self) {
+        let bpm = self.transport.bpm();
+        for i in 0..self.parts.len() {
+            let part = self.part_mut(i);
+            part.transport.set_bpm(bpm);
+            part.tick_arpeggiator();
+        }
+    }
+
+    // notes on the channel or in the zone of a part play it instead of
+    // this instrument, so do the controllers and pitch bend of its own
+    // channel. a part of a zone alone shares those of the channel.
+    fn route_to_parts(&mut self, message: MidiMessage) -> bool {
+        let (channel, note) = match message {
+            MidiMessage::NoteOn { channel, note, .. } | MidiMessage::NoteOff { channel, note } => (channel, Some(note)),
+            MidiMessage::PitchBend { channel, .. } | MidiMessage::ControlChange { channel, .. } => (channel, None),
+            _ => return false,
+        };
+        let mut routed = false;
+        for i in 0..self.parts.len() {
+            let desc = &self.parts[i].desc;
+            let (plays, owned) = match note {
+                Some(note) => (desc.accepts(Some(channel), note), true),
+                None => (desc.listens(channel), desc.channel == Some(channel)),
+            };
+            if plays {
+                self.part_mut(i).apply_midi_message(message);
+                routed |= owned;
+            }
+        }
+        routed
+    }
+
+    // computer keys in the zone of a part play it instead, the release
+    // goes to the parts the press went to.
+    fn route_key_to_parts(&mut self, event: KeyEvent) -> bool {
+        if self.parts.is_empty() { return false; }
+        let (note, on) = match event.kind {
+            KeyEventKind::Repeat => return self.part_keys.contains_key(&event.code),
+            KeyEventKind::Release => match self.part_keys.remove(&event.code) {
+                Some(note) => (note, false),
+                None => return false,
+            },
+            KeyEventKind::Press => {
+                if self.part_keys.contains_key(&event.code) { return true; }
+                let freq = self.note_freq(&NoteKey::Key(event.code));
+                if freq <= 0.0 { return false; }
+                (freq_to_note(freq).round().clamp(0.0, 127.0) as u8, true)
+            },
+        };
+        let velocity = self.keyboard_buffer.velocity.sample();
+        let mut routed = false;
+        for i in 0..self.parts.len() {
+            if !self.parts[i].desc.accepts(None, note) { continue; }
+            match on {
+                true => self.part_mut(i).note_on(NoteKey::Midi(note), velocity),
+                false => self.part_mut(i).note_off(NoteKey::Midi(note)),
+            }
+            routed = true;
+        }
+        if routed && on { self.part_keys.insert(event.code, note); }
+        routed
+    }
+
+    // plays a note of this instrument on the layers too, as the midi note
+    // it sounds. the release goes to the note the press played.
+    fn layer_note(&mut self, key: NoteKey, velocity: f32, on: bool) {
+        if !self.parts.iter().any(|p| p.desc.layer) { return; }
+        if let Some(note) = self.layer_keys.remove(&key) {
+            for i in 0..self.parts.len() {
+                if self.parts[i].desc.layers(note) { self.part_mut(i).note_off(NoteKey::Midi(note)); }
+            }
+        }
+        let freq = self.note_freq(&key);
+        if !on || freq <= 0.0 { return; }
+        let note = (freq_to_note(freq) - self.detune / 100.0).round().clamp(0.0, 127.0) as u8;
+        for i in 0..self.parts.len() {
+            if self.parts[i].desc.layers(note) { self.part_mut(i).note_on(NoteKey::Midi(note), velocity); }
+        }
+        self.layer_keys.insert(key, note);
+    }
+
+    /// Stacks a layer playing `preset` on every note, the patch of this
+    /// instrument if empty.
+    pub fn add_layer(&mut self, preset: &str, detune: f32, pan: f32) -> Result<(), PresetError> {
+        self.add_part(PartDesc { preset: preset.to_string(), layer: true, detune, pan, ..PartDesc::default() })?;
+        if preset.is_empty() {
+            let patch = self.patch();
+            if let Some(part) = self.parts.last_mut() { part.instrument.apply_patch(&patch); }
+        }
+        Ok(())
+    }
+
+    // renders the voices of the parts over the block and mixes them in.
+    fn mix_parts(&mut self, left: &mut [f32], right: &mut [f32]) {
+        if self.parts.is_empty() { return; }
+        let n = left.len().min(right.len());
+        let (mut part_left, mut part_right) = std::mem::take(&mut self.part_block);
+        if part_left.len() < n { part_left.resize(n, 0.0); part_right.resize(n, 0.0); }
+        for i in 0..self.parts.len() {
+            self.part_mut(i).gen_block(&mut part_left[..n], &mut part_right[..n]);
+            let (gain_left, gain_right) = self.parts[i].desc.gains();
+            left[..n].iter_mut().zip(&part_left[..n]).for_each(|(x, p)| *x += p * gain_left);
+            right[..n].iter_mut().zip(&part_right[..n]).for_each(|(x, p)| *x += p * gain_right);
+        }
+        self.part_block = (part_left, part_right);
+    }
+
+    /// Captures the notes played from the top of the bar the transport is
+    /// in, starting it if it isn't playing.
+    pub fn start_performance(&mut self) {
+        if !self.transport.playing() { self.toggle_transport(); }
+        let beat = self.transport.position_at(self.now());
+        self.performance.start(beat, Transport::BEATS_PER_BAR);
+    }
+
+    /// Stops the capture and writes it to a midi file at the tempo of the
+    /// transport. returns how many notes it holds.
+    pub fn stop_performance<P: AsRef<std::path::Path>>(&mut self, path: P) -> std::io::Result<usize> {
+        let beat = self.transport.position_at(self.now());
+        self.performance.stop(beat);
+        self.performance.save(path, self.transport.bpm())?;
+        Ok(self.performance.notes().iter().filter(|n| n.on).count())
+    }
+
+    // every note sounding, whoever plays it, goes into the capture while
+    // the transport plays.
+    fn capture_note(&mut self, key: NoteKey, velocity: f32, on: bool) {
+        if !self.performance.recording() || !self.transport.playing() { return; }
+        let beat = self.transport.position_at(self.now());
+        if !on { return self.performance.note_off(key, beat); }
+        let freq = self.note_freq(&key);
+        if freq <= 0.0 { return; }
+        let note = freq_to_note(freq).round().clamp(0.0, 127.0) as u8;
+        self.performance.note_on(key, beat, note, velocity);
+    }
+
+    // lets go of the notes the looper holds, once it stops playing them.
+    fn release_loop_notes(&mut self) {
+        let held = |k: &&NoteKey| matches!(k, NoteKey::Loop(_));
+        if !self.keyboard_buffer.held().any(|k| held(&k)) { return; }
+        let keys: Vec<NoteKey> = self.keyboard_buffer.held().filter(held).copied().collect();
+        keys.into_iter().for_each(|k| self.note_off(k));
+    }
+
+    /// Starts or stops the transport, telling whoever follows the clock.
+    /// while it follows a clock itself, that clock starts and stops it.
+    pub fn toggle_transport(&mut self) -> bool {
+        if self.transport.following() { return false; }
+        if self.transport.playing() {
+            self.transport.stop();
+            if self.midi_clock == MidiClock::Out { self.send_midi(MidiMessage::Stop); }
+        } else {
+            self.transport.start(self.now());
+            if self.midi_clock == MidiClock::Out { self.send_midi(MidiMessage::Start); }
+        }
+        true
+    }
+
+    pub fn midi_clock(&self) -> MidiClock { self.midi_clock }
+    pub fn set_midi_clock(&mut self, clock: MidiClock) {
+        self.midi_clock = clock;
+        self.transport.set_following(clock == MidiClock::In);
+    }
+
+    pub fn set_midi_output(&mut self, out: Box<dyn MidiSink>) { self.midi_out = Some(out); }
+
+    /// Sends the disk work of the keys to `requests`, see `disk`.
+    pub fn set_disk(&mut self, requests: Sender<disk::Request>) { self.disk = Some(requests) }
+
+    // done on the spot if there's nothing to send it to.
+    fn request(&mut self, request: disk::Request) {
+        let request = match &self.disk {
+            Some(disk) => match disk.send(request) { Ok(()) => return, Err(e) => e.0 },
+            None => request,
+        };
+        request.run().apply(self);
+    }
+
+    fn send_midi(&mut self, message: MidiMessage) {
+        if let Some(out) = self.midi_out.as_mut() { out.send(message); }
+    }
+
+    // the clock ticks between the beats `from` and `to`, gone over in the
+    // last block. they go out spaced over the next one as they fell in it,
+    // rather than at once.
+    fn send_clock(&mut self, from: f64, to: f64) {
+        let Some(out) = self.midi_out.as_mut() else { return };
+        let (ppqn, seconds_per_beat) = (Transport::PPQN as f64, 60.0 / self.transport.bpm() as f64);
+        let first = (from * ppqn).ceil();
+        for tick in 0..Transport::ticks_between(from, to) {
+            out.send_after(MidiMessage::Clock, ((first + tick as f64) / ppqn - from) as f32 * seconds_per_beat as f32);
+        }
+    }
+
+    pub fn noise(&self) -> NoiseLayer { self.noise }
+    pub fn set_noise(&mut self, noise: NoiseLayer) {
+        if noise.color != self.noise.color { self.noise_source = noise.color.build(); }
+        self.noise = noise;
+    }
+
+    pub fn sub(&self) -> SubOscillator { self.sub }
+    pub fn set_sub(&mut self, sub: SubOscillator) { self.sub = sub; }
+
+    pub fn unison(&self) -> UnisonSettings { self.unison }
+    pub fn set_unison(&mut self, unison: UnisonSettings) {
+        self.unison = UnisonSettings { voices: unison.voices() as u8, detune: unison.detune.max(0.0), spread: unison.spread.clamp(0.0, 1.0) }
+    }
+
+    pub fn add_lfo(&mut self, lfo: Lfo) -> usize { self.lfos.push(lfo); self.lfos.len() - 1 }
+    pub fn remove_lfo(&mut self, index: usize) -> Option<Lfo> {
+        if index < self.lfos.len() { Some(self.lfos.remove(index)) } else { None }
+    }
+    pub fn lfos(&self) -> &[Lfo] { &self.lfos }
+    pub fn lfos_mut(&mut self) -> &mut Vec<Lfo> { &mut self.lfos }
+
+    pub fn set_pitch_bend(&mut self, bend: f32) { self.pitch_bend = bend.clamp(-1.0, 1.0) }
+    pub fn pitch_bend(&self) -> f32 { self.pitch_bend }
+    pub fn set_bend_range(&mut self, semitones: f32) { self.bend_range = semitones.max(0.0) }
+    pub fn bend_range(&self) -> f32 { self.bend_range }
+    pub fn set_mod_wheel(&mut self, value: f32) { self.mod_wheel = value.clamp(0.0, 1.0) }
+    pub fn mod_wheel(&self) -> f32 { self.mod_wheel }
+
+    pub fn add_mod_route(&mut self, route: ModRoute) -> usize { self.mod_matrix.add_route(route) }
+    pub fn remove_mod_route(&mut self, index: usize) -> Option<ModRoute> { self.mod_matrix.remove_route(index) }
+    pub fn mod_matrix(&self) -> &ModMatrix { &self.mod_matrix }
+
+    // summed lfo output per destination at time t.
+    fn lfo_modulation(&self, t: f32, destination: LfoDestination) -> f32 {
+        self.lfos.iter().filter(|l| l.destination == destination).map(|l| match destination {
+            LfoDestination::Amplitude => l.depth.clamp(0.0, 1.0) * (0.5 * l.value(t) - 0.5),
+            _ => l.sample(t),
+        }).sum()
+    }
+
+    pub fn effects(&self) -> &EffectChain { &self.effects }
+    pub fn effects_mut(&mut self) -> &mut EffectChain { &mut self.effects }
+
+    // adds `effect` bypassed at `index` (or last), for the effects that
+    // aren't in the default chain, unless there is one already.
+    fn ensure_effect(&mut self, effect: EffectDesc, index: Option<usize>) {
+        if self.effects.find(effect.name()).is_some() { return; }
+        let last = self.effects.push(effect);
+        let index = index.unwrap_or(last).min(last);
+        self.effects.move_effect(last, index);
+        self.effects.set_bypass(index, true);
+    }
+
+    fn effect_desc(&self, name: &str) -> Option<EffectDesc> {
+        self.effects.find(name).map(|i| self.effects.slots()[i].effect.desc())
+    }
+
+    // changes the settings of an effect in the chain through its
+    // parameters, leaving it on or off. its key adds it and turns it on,
+    // moving a parameter doesn't.
+    fn set_effect(&mut self, name: &str, change: impl FnOnce(&mut EffectDesc)) {
+        let Some(index) = self.effects.find(name) else { return };
+        let mut desc = self.effects.slots()[index].effect.desc();
+        change(&mut desc);
+        self.effects.update(index, desc);
+    }
+
+    // settings of the master eq, the defaults while there is none.
+    fn eq_band(&self, band: u8) -> EqBand {
+        match self.effect_desc("eq") {
+            Some(EffectDesc::Eq(settings)) => settings.band(band),
+            _ => EqSettings::default().band(band),
+        }
+    }
+
+    fn set_eq_band(&mut self, band: u8, change: impl FnOnce(&mut EqBand)) {
+        self.set_effect("eq", |desc| {
+            if let EffectDesc::Eq(settings) = desc { change(settings.band_mut(band)) }
+        });
+    }
+
+    fn compressor(&self) -> CompressorSettings {
+        match self.effect_desc("compressor") {
+            Some(EffectDesc::Compressor(settings)) => settings,
+            _ => CompressorSettings::default(),
+        }
+    }
+
+    fn set_compressor(&mut self, change: impl FnOnce(&mut CompressorSettings)) {
+        self.set_effect("compressor", |desc| {
+            if let EffectDesc::Compressor(settings) = desc { change(settings) }
+        });
+    }
+
+    fn tremolo(&self) -> TremoloSettings {
+        match self.effect_desc("tremolo") {
+            Some(EffectDesc::Tremolo(settings)) => settings,
+            _ => TremoloSettings::default(),
+        }
+    }
+
+    fn auto_pan(&self) -> AutoPanSettings {
+        match self.effect_desc("auto pan") {
+            Some(EffectDesc::AutoPan(settings)) => settings,
+            _ => AutoPanSettings::default(),
+        }
+    }
+
+    fn set_tremolo(&mut self, change: impl FnOnce(&mut TremoloSettings)) {
+        self.set_effect("tremolo", |desc| {
+            if let EffectDesc::Tremolo(settings) = desc { change(settings) }
+        });
+    }
+
+    fn set_auto_pan(&mut self, change: impl FnOnce(&mut AutoPanSettings)) {
+        self.set_effect("auto pan", |desc| {
+            if let EffectDesc::AutoPan(settings) = desc { change(settings) }
+        });
+    }
+
+    // an lfo rate written as `hz` through its parameter. the rate it is
+    // synced to, as an undo or a macro writes it back, stays synced.
+    fn lfo_rate(&self, id: ParamId, rate: LfoRate, hz: f32) -> LfoRate {
+        let synced = id.param().clamp(rate.hz(self.transport.bpm()));
+        match rate {
+            LfoRate::Synced(_) if (synced - hz).abs() <= synced * 1e-3 => rate,
+            _ => LfoRate::Free(hz),
+        }
+    }
+
+    // turns the tremolo or the auto pan on at its free rate, steps through
+    // the synced rates, then turns it off. none once off. both go ahead of
+    // the delay, so the echoes keep moving.
+    fn step_lfo_effect(&mut self, default: EffectDesc) -> Option<LfoRate> {
+        fn rate(desc: &mut EffectDesc) -> Option<&mut LfoRate> {
+            match desc {
+                EffectDesc::Tremolo(TremoloSettings { rate, .. }) | EffectDesc::AutoPan(AutoPanSettings { rate, .. }) => Some(rate),
+                _ => None,
+            }
+        }
+        self.ensure_effect(default.clone(), self.effects.find("delay"));
+        let index = self.effects.find(default.name())?;
+        let mut desc = self.effects.slots()[index].effect.desc();
+        let current = rate(&mut desc)?;
+        let next = match self.effects.is_bypassed(index) {
+            true => Some(*current),
+            false => current.next(),
+        };
+        // once off, it comes back on at the free rate.
+        *current = next.unwrap_or(*rate(&mut { default })?);
+        self.effects.update(index, desc);
+        self.effects.set_bypass(index, next.is_none());
+        next
+    }
+
+    fn convolution(&self) -> ConvolutionSettings {
+        match self.effect_desc("convolution") {
+            Some(EffectDesc::Convolution(settings)) => settings,
+            _ => ConvolutionSettings::default(),
+        }
+    }
+
+    fn set_convolution(&mut self, change: impl FnOnce(&mut ConvolutionSettings)) {
+        self.set_effect("convolution", |desc| {
+            if let EffectDesc::Convolution(settings) = desc { change(settings) }
+        });
+    }
+
+    /// Loads a wav file as the impulse response of the convolution reverb,
+    /// and turns it on. keeps the current one if it fails to load. the
+    /// convolution reverb goes after the algorithmic one.
+    pub fn load_impulse_response<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), hound::Error> {
+        let mut settings = ConvolutionSettings { ir: path.as_ref().to_path_buf(), ..self.convolution() };
+        settings.read()?;
+        self.ensure_effect(EffectDesc::Convolution(ConvolutionSettings::default()), self.effects.find("reverb").map(|i| i + 1));
+        if let Some(index) = self.effects.find("convolution") {
+            self.effects.replace(index, EffectDesc::Convolution(settings));
+            self.effects.set_bypass(index, false);
+        }
+        Ok(())
+    }
+
+    // toggles the bypass of the first effect with the given name.
+    pub fn toggle_effect(&mut self, name: &str) -> Option<bool> {
+        let index = self.effects.find(name)?;
+        let bypass = !self.effects.is_bypassed(index);
+        self.effects.set_bypass(index, bypass);
+        Some(!bypass)
+    }
+
+    // master effects, applied to the left and right buffers of `gen_block`
+    // output once their dc offset is removed, then limited and soft clipped
+    // so stacked notes don't hard clip at the dac.
+    pub fn process_master(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let [dl, dr] = &mut self.dc_blockers;
+        left.iter_mut().for_each(|x| *x = dl.process(*x));
+        right.iter_mut().for_each(|x| *x = dr.process(*x));
+        self.effects.set_tempo(self.transport.bpm());
+        self.effects.process(left, right, &self.input_block);
+        self.limiter.process(left, right);
+        // metered before the soft clip, so the clip indicator means something.
+        self.meter.update(left, right, self.sr.0 as f32);
+        left.iter_mut().chain(right.iter_mut()).for_each(|x| *x = soft_clip(*x));
+    }
+
+    // seconds of the linear fade in at the start of a voice, and the shortest
+    // release, so voices never start or stop on a step.
+    pub const DECLICK: f32 = 0.003;
+    fn release_time(&self) -> f32 { self.envelope.3.max(Instrument::DECLICK) }
+
+    pub fn param(&self, id: ParamId) -> f32 {
+        match id {
+            ParamId::Attack => self.envelope.0,
+            ParamId::Decay => self.envelope.1,
+            ParamId::Sustain => self.envelope.2,
+            ParamId::Release => self.envelope.3,
+            ParamId::Cutoff => self.filter.cutoff,
+            ParamId::Resonance => self.filter.resonance,
+            ParamId::FilterDrive => self.filter.drive,
+            ParamId::KeyTrack => self.filter.keytrack,
+            ParamId::FilterAttack => self.filter_envelope.envelope.0,
+            ParamId::FilterDecay => self.filter_envelope.envelope.1,
+            ParamId::FilterSustain => self.filter_envelope.envelope.2,
+            ParamId::FilterRelease => self.filter_envelope.envelope.3,
+            ParamId::FilterEnvAmount => self.filter_envelope.amount,
+            ParamId::Volume => self.master_volume,
+            ParamId::Glide => self.glide.time,
+            ParamId::UnisonDetune => self.unison.detune,
+            ParamId::UnisonSpread => self.unison.spread,
+            ParamId::NoiseLevel => self.noise.level,
+            ParamId::NoiseDecay => self.noise.decay,
+            ParamId::SubLevel => self.sub.level,
+            ParamId::PulseWidth => self.oscillator.pulse_width().unwrap_or(0.5),
+            ParamId::FmFeedback => self.fm.feedback,
+            ParamId::OperatorLevel(i) => self.fm.operators.get(i as usize).map_or(0.0, |o| o.level),
+            ParamId::OscMix => self.oscillators.mix,
+            ParamId::RingMod => self.oscillators.ring,
+            ParamId::FoldAmount => self.voice_fold.fold,
+            ParamId::FoldSymmetry => self.voice_fold.symmetry,
+            ParamId::FoldMix => self.voice_fold.mix,
+            ParamId::DriveCurve => SaturationCurve::ALL.iter().position(|c| *c == self.voice_drive.curve).unwrap_or(0) as f32,
+            ParamId::DriveAmount => self.voice_drive.drive,
+            ParamId::DriveOutput => self.voice_drive.output,
+            ParamId::DriveMix => self.voice_drive.mix,
+            ParamId::OscCoarse(i) => self.oscillators.extra.get(i as usize).map_or(0.0, |o| o.coarse),
+            ParamId::OscFine(i) => self.oscillators.extra.get(i as usize).map_or(0.0, |o| o.fine),
+            ParamId::OscLevel(i) => self.oscillators.extra.get(i as usize).map_or(0.0, |o| o.level),
+            ParamId::Bpm => self.transport.bpm(),
+            ParamId::Swing => self.transport.swing(),
+            ParamId::ArpGate => self.arpeggiator.settings.gate,
+            ParamId::BendRange => self.bend_range,
+            ParamId::InputGain => self.input_gain,
+            ParamId::LimiterCeiling => self.limiter.settings.ceiling,
+            ParamId::EqFreq(band) => self.eq_band(band).freq,
+            ParamId::EqGain(band) => self.eq_band(band).gain,
+            ParamId::EqQ(band) => self.eq_band(band).q,
+            ParamId::CompThreshold => self.compressor().threshold,
+            ParamId::CompRatio => self.compressor().ratio,
+            ParamId::CompAttack => self.compressor().attack,
+            ParamId::CompRelease => self.compressor().release,
+            ParamId::CompMakeup => self.compressor().makeup,
+            ParamId::ConvMix => self.convolution().mix,
+            ParamId::ConvPredelay => self.convolution().predelay,
+            ParamId::TremoloRate => self.tremolo().rate.hz(self.transport.bpm()),
+            ParamId::TremoloDepth => self.tremolo().depth,
+            ParamId::PanRate => self.auto_pan().rate.hz(self.transport.bpm()),
+            ParamId::PanDepth => self.auto_pan().depth,
+            ParamId::LimiterRelease => self.limiter.settings.release,
+            ParamId::Polyphony => self.polyphony.max_voices as f32,
+            ParamId::Macro(i) => self.macros.get(i as usize).map_or(0.0, |m| m.value),
+        }
+    }
+
+    // values outside the range of the parameter are clamped to it.
+    pub fn set_param(&mut self, id: ParamId, value: f32) {
+        let value = id.param().clamp(value);
+        match id {
+            ParamId::Attack => self.envelope.0 = value,
+            ParamId::Decay => self.envelope.1 = value,
+            ParamId::Sustain => self.envelope.2 = value,
+            ParamId::Release => self.envelope.3 = value,
+            ParamId::Cutoff => self.set_cutoff(value),
+            ParamId::Resonance => self.set_resonance(value),
+            ParamId::FilterDrive => self.filter.drive = value,
+            ParamId::KeyTrack => self.filter.keytrack = value,
+            ParamId::FilterAttack => self.filter_envelope.envelope.0 = value,
+            ParamId::FilterDecay => self.filter_envelope.envelope.1 = value,
+            ParamId::FilterSustain => self.filter_envelope.envelope.2 = value,
+            ParamId::FilterRelease => self.filter_envelope.envelope.3 = value,
+            ParamId::FilterEnvAmount => self.filter_envelope.amount = value,
+            ParamId::Volume => self.set_master_volume(value),
+            ParamId::Glide => self.glide.time = value,
+            ParamId::UnisonDetune => self.unison.detune = value,
+            ParamId::UnisonSpread => self.unison.spread = value,
+            ParamId::NoiseLevel => self.noise.level = value,
+            ParamId::NoiseDecay => self.noise.decay = value,
+            ParamId::SubLevel => self.sub.level = value,
+            // only the pulse wave has a width, the others are left alone.
+            ParamId::PulseWidth => if self.oscillator.pulse_width().is_some() { self.oscillator.set_waveform(BlepShape::Pulse(value)) },
+            ParamId::FmFeedback => self.fm.feedback = value,
+            ParamId::OperatorLevel(i) => if let Some(o) = self.fm.operators.get_mut(i as usize) { o.level = value },
+            ParamId::OscMix => self.oscillators.mix = value,
+            ParamId::RingMod => self.oscillators.ring = value,
+            ParamId::FoldAmount => self.voice_fold.fold = value,
+            ParamId::FoldSymmetry => self.voice_fold.symmetry = value,
+            ParamId::FoldMix => self.voice_fold.mix = value,
+            ParamId::DriveCurve => self.voice_drive.curve = SaturationCurve::ALL[(value.round().max(0.0) as usize).min(SaturationCurve::ALL.len() - 1)],
+            ParamId::DriveAmount => self.voice_drive.drive = value,
+            ParamId::DriveOutput => self.voice_drive.output = value,
+            ParamId::DriveMix => self.voice_drive.mix = value,
+            ParamId::OscCoarse(i) => if let Some(o) = self.oscillators.extra.get_mut(i as usize) { o.coarse = value },
+            ParamId::OscFine(i) => if let Some(o) = self.oscillators.extra.get_mut(i as usize) { o.fine = value },
+            ParamId::OscLevel(i) => if let Some(o) = self.oscillators.extra.get_mut(i as usize) { o.level = value },
+            ParamId::Bpm => self.transport.set_bpm(value),
+            ParamId::Swing => self.transport.set_swing(value),
+            ParamId::ArpGate => self.arpeggiator.settings.gate = value,
+            ParamId::BendRange => self.set_bend_range(value),
+            ParamId::InputGain => self.set_input_gain(value),
+            ParamId::LimiterCeiling => self.limiter.settings.ceiling = value,
+            ParamId::EqFreq(band) => self.set_eq_band(band, |b| b.freq = value),
+            ParamId::EqGain(band) => self.set_eq_band(band, |b| b.gain = value),
+            ParamId::EqQ(band) => self.set_eq_band(band, |b| b.q = value),
+            ParamId::CompThreshold => self.set_compressor(|c| c.threshold = value),
+            ParamId::CompRatio => self.set_compressor(|c| c.ratio = value),
+            ParamId::CompAttack => self.set_compressor(|c| c.attack = value),
+            ParamId::CompRelease => self.set_compressor(|c| c.release = value),
+            ParamId::CompMakeup => self.set_compressor(|c| c.makeup = value),
+            ParamId::ConvMix => self.set_convolution(|c| c.mix = value),
+            ParamId::ConvPredelay => self.set_convolution(|c| c.predelay = value),
+            // setting a rate in Hz unsyncs it.
+            ParamId::TremoloRate => {
+                let rate = self.lfo_rate(id, self.tremolo().rate, value);
+                self.set_tremolo(|t| t.rate = rate);
+            },
+            ParamId::TremoloDepth => self.set_tremolo(|t| t.depth = value),
+            ParamId::PanRate => {
+                let rate = self.lfo_rate(id, self.auto_pan().rate, value);
+                self.set_auto_pan(|p| p.rate = rate);
+            },
+            ParamId::PanDepth => self.set_auto_pan(|p| p.depth = value),
+            ParamId::LimiterRelease => self.limiter.set_release(value, self.sr.0.max(1) as f32),
+            ParamId::Polyphony => self.set_polyphony(Polyphony { max_voices: value.round() as usize, ..self.polyphony }),
+            ParamId::Macro(i) => self.set_macro(i as usize, value),
+        }
+    }
+
+    pub fn selected_param(&self) -> ParamId { self.selected_param }
+    pub fn select_param(&mut self, id: ParamId) { self.selected_param = id }
+
+    /// Binds the next control change received to the selected parameter,
+    /// replacing what that controller was bound to.
+    pub fn learn(&mut self, mode: CcMode) { self.learning = Some(mode) }
+    pub fn learning(&self) -> Option<CcMode> { self.learning }
+    pub fn cancel_learn(&mut self) { self.learning = None }
+    pub fn cc_mappings(&self) -> &[CcMapping] { &self.cc_mappings }
+
+    // a mapped controller drives its parameters, the mod wheel only keeps
+    // cc 1 while nothing else is bound to it.
+    fn control_change(&mut self, controller: u8, value: u8) {
+        // all sound off and all notes off, channel mode messages that can't
+        // be learned.
+        if controller == 120 || controller == 123 { return self.all_notes_off(); }
+        if let Some(mode) = self.learning.take() {
+            let param = self.selected_param;
+            self.cc_mappings.retain(|m| m.controller != controller);
+            self.cc_mappings.push(CcMapping { controller, param, mode });
+            self.status = format!("cc {} controls {} ({:?})", controller, param.param().name, mode);
+            return;
+        }
+        let mut mapped = false;
+        for i in 0..self.cc_mappings.len() {
+            let mapping = self.cc_mappings[i];
+            if mapping.controller != controller { continue; }
+            mapped = true;
+            self.set_param_normalized(mapping.param, mapping.apply(value, self.param_normalized(mapping.param)));
+        }
+        if !mapped && controller == 1 { self.set_mod_wheel(value as f32 / 127.0); }
+        if !mapped && controller == 64 { self.set_sustain(value >= 64); }
+    }
+
+    // the same in 0..1 over the range of the parameter.
+    pub fn param_normalized(&self, id: ParamId) -> f32 { id.param().normalize(self.param(id)) }
+    pub fn set_param_normalized(&mut self, id: ParamId, x: f32) { self.set_param(id, id.param().denormalize(x)) }
+
+    pub fn macros(&self) -> &[Macro] { &self.macros }
+    pub fn macros_mut(&mut self) -> &mut [Macro] { &mut self.macros }
+
+    // turns a macro and the parameters it moves. a macro can't turn another.
+    fn set_macro(&mut self, index: usize, value: f32) {
+        let Some(knob) = self.macros.get_mut(index) else { return };
+        let value = value.clamp(0.0, 1.0);
+        knob.value = value;
+        let targets = std::mem::take(&mut knob.targets);
+        for target in targets.iter().filter(|t| !matches!(t.param, ParamId::Macro(_))) {
+            self.set_param_normalized(target.param, target.at(value));
+        }
+        self.macros[index].targets = targets;
+    }
+
+    fn edit_state(&self) -> EditState {
+        EditState {
+            params: ParamId::ALL.iter().map(|id| self.param(*id)).collect(),
+            oscillator: self.oscillator.desc(),
+            pattern: (self.drums.current(), self.drums.kit().pattern(self.drums.current()).cloned().unwrap_or_default()),
+        }
+    }
+
+    // runs a key or a control, recording what it changed as an edit to
+    // undo. an undo or a redo isn't one, nor a load clearing the history.
+    fn record_edits(&mut self, f: impl FnOnce(&mut Self)) {
+        let (before, version) = (self.edit_state(), self.history.version());
+        f(self);
+        if self.history.version() != version { return; }
+        let changes = before.changes(&self.edit_state());
+        let now = self.now();
+        self.history.push(changes, now);
+    }
+
+    fn apply_changes(&mut self, changes: &[Change]) {
+        for change in changes {
+            match change {
+                Change::Param(id, _, to) => self.set_param(*id, *to),
+                Change::Oscillator(_, to) => self.oscillator.apply_desc(to),
+                Change::Pattern(index, _, to) => if let Some(pattern) = self.drums.pattern_mut_at(*index) { pattern.clone_from(to) },
+            }
+        }
+    }
+
+    /// Takes back the last edit of the patch or of a drum pattern. false
+    /// if there is none.
+    pub fn undo(&mut self) -> bool {
+        let Some(changes) = self.history.undo() else { return false };
+        self.apply_changes(&changes);
+        self.status = format!("undid {}", history::describe(&changes));
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let Some(changes) = self.history.redo() else { return false };
+        self.apply_changes(&changes);
+        self.status = format!("redid {}", history::describe(&changes));
+        true
+    }
+
+    pub const MAX_VOLUME: f32 = 2.0;
+    pub fn set_master_volume(&mut self, v: f32) { self.master_volume = v.clamp(0.0, Instrument::MAX_VOLUME) }
+    pub fn master_volume(&self) -> f32 { self.master_volume }
+    pub fn set_status(&mut self, status: String) { self.status = status }
+
+    pub fn snapshot_handle(&self) -> Arc<Mutex<InstrumentSnapshot>> { Arc::clone(&self.snapshot) }
+    pub fn tap_handle(&self) -> Arc<OutputTap> { Arc::clone(&self.tap) }
+    pub fn meter_handle(&self) -> Arc<Meter> { Arc::clone(&self.meter) }
+    // the master output, after effects, for the ui.
+    pub fn tap_output(&self, left: &[f32], right: &[f32]) { self.tap.push(left, right) }
+
+    pub fn publish_snapshot(&self) {
+        let now = self.now();
+        let mut notes: Vec<NoteState> = self.voices.event_buffer.values()
+            .map(|e| NoteState {
+                key: e.key,
+                freq: self.note_freq(&e.key),
+                velocity: e.velocity,
+                level: self.envelope.sample(now, e.time_press, e.time_release),
+                released: e.time_release.is_some(),
+            }).collect();
+        notes.sort_by(|a, b| a.freq.total_cmp(&b.freq));
+
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.notes = notes;
+        snapshot.envelope = self.envelope.clone();
+        snapshot.engine = self.engine;
+        snapshot.oscillator = self.oscillator.desc();
+        snapshot.oscillators = self.oscillators.desc();
+        snapshot.fm_algorithm = self.fm.algorithm;
+        snapshot.sample_zones = self.sampler.zones();
+        snapshot.drum_mode = self.drum_mode;
+        snapshot.drum_step = self.transport.playing().then(|| self.drums.step());
+        (snapshot.drum_pattern, snapshot.song_mode) = (self.drums.current(), self.drums.song_mode);
+        (snapshot.bpm, snapshot.playing, snapshot.position) = (self.transport.bpm(), self.transport.playing(), self.transport.bar_beat());
+        snapshot.following = self.transport.following();
+        (snapshot.chord, snapshot.scale) = (self.chord.chord, self.scale);
+        snapshot.generator = self.generator.enabled.then_some(self.generator.mode);
+        snapshot.sustain = self.voices.sustain();
+        snapshot.capturing = self.performance.recording();
+        snapshot.parts = self.parts.iter().map(|p| (p.desc.name(), p.instrument.voices.event_buffer.len())).collect();
+        snapshot.split = self.split_point();
+        snapshot.patch_seed = self.patch_seed;
+        snapshot.prompt.clone_from(&self.prompt);
+        (snapshot.looper, snapshot.loop_bars, snapshot.loop_layers) = (self.looper.state(), self.looper.bars, self.looper.layers());
+        snapshot.swing = self.transport.swing();
+        snapshot.filter = self.filter;
+        snapshot.filter_envelope.clone_from(&self.filter_envelope);
+        snapshot.master_volume = self.master_volume;
+        snapshot.compressor_reduction = self.effects.find("compressor")
+            .filter(|i| !self.effects.is_bypassed(*i))
+            .map_or(0.0, |i| self.effects.slots()[i].effect.reduction());
+        snapshot.limiter_reduction = self.limiter.reduction();
+        snapshot.pitch_bend = self.pitch_bend;
+        snapshot.mod_wheel = self.mod_wheel;
+        snapshot.octave = self.octave;
+        snapshot.transpose = self.transpose;
+        snapshot.play_mode = self.play_mode;
+        snapshot.voices = self.voices.event_buffer.keys().filter(|k| !self.stolen.contains_key(k) && self.note_freq(k) > 0.0).count();
+        snapshot.polyphony = self.polyphony;
+        snapshot.recording = self.is_recording();
+        snapshot.preset_name.clone_from(&self.preset_name);
+        snapshot.project_name.clone_from(&self.project_name);
+        snapshot.status.clone_from(&self.status);
+        snapshot.buffer_frames = self.buffer_frames;
+        snapshot.sample_rate = self.sr.0;
+        snapshot.keys = self.key_to_note.iter()
+            .filter_map(|(k, n)| match k { KeyCode::Char(c) => Some((*c, (n + self.key_shift()).round() as i32)), _ => None })
+            .collect();
+        snapshot.keys.sort_by_key(|(c, n)| (*n, *c));
+        snapshot.held = self.keyboard_buffer.held()
+            .filter(|k| self.note_freq(k) > 0.0)
+            .map(|k| freq_to_note(self.note_freq(k)).round() as i32)
+            .collect();
+        snapshot.latency = self.latency;
+    }
+
+    pub fn is_recording(&self) -> bool { self.recorder.is_some() }
+    pub fn record(&self, data: &[f32]) {
+        if let Some(recorder) = &self.recorder { recorder.push(data); }
+    }
+
+    // points the smoothed parameters at the current settings.
+    fn smooth_params(&mut self) {
+        let smoothed = &mut self.smoothed;
+        smoothed.volume.set_target(self.master_volume);
+        smoothed.cutoff.set_target(self.filter.cutoff);
+        smoothed.resonance.set_target(self.filter.resonance);
+        smoothed.bend.set_target(self.pitch_bend * self.bend_range);
+        smoothed.mod_wheel.set_target(self.mod_wheel);
+        smoothed.input_gain.set_target(self.input_gain);
+    }
+
+    fn t(&self, i: u128) -> f32 { ((self.cursor+i) as f32)/(self.sample_rate() as f32) }
+
+    // with scheduling on, the key and midi events that play notes are held
+    // for `play_block` instead of applied as they come. turning it off
+    // applies the ones waiting.
+    pub fn set_scheduling(&mut self, on: bool) {
+        self.scheduling = on;
+        self.last_callback = None;
+        for (_, event) in std::mem::take(&mut self.scheduled) { self.apply_live_event(event); }
+    }
+    pub fn scheduling(&self) -> bool { self.scheduling }
+
+    fn apply_live_event(&mut self, event: LiveEvent) {
+        match event {
+            // a note pressed as the prompt opened is let go with the others.
+            LiveEvent::Key(_) if self.prompt.is_some() => (),
+            LiveEvent::Key(key) => self.apply_key_event(key, self.now()),
+            LiveEvent::Midi(message) => self.apply_midi_message(message),
+        }
+    }
+
+    // keys that play, timed by the callback: notes, the sustain key and
+    // drum pads. with control or alt held, or the prompt open, a key is a
+    // command, and so is a pad written into the pattern.
+    fn plays_key(&self, event: &KeyEvent) -> bool {
+        if self.prompt.is_some() || event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) { return false; }
+        match self.drum_pad(NoteKey::Key(event.code)) {
+            Some(_) => !self.drums.writing,
+            None => Some(event.code) == self.sustain_key || self.key_to_note.contains_key(&event.code),
+        }
+    }
+
+    // the midi that plays, timed by the callback: notes, the bend and the
+    // sustain pedal while it isn't mapped or learned.
+    fn plays_midi(&self, message: &MidiMessage) -> bool {
+        match message {
+            MidiMessage::NoteOn { .. } => !self.drums.writing,
+            MidiMessage::NoteOff { .. } | MidiMessage::PitchBend { .. } => true,
+            MidiMessage::ControlChange { controller: 64, .. } =>
+                self.learning.is_none() && !self.cc_mappings.iter().any(|m| m.controller == 64),
+            _ => false,
+        }
+    }
+
+    /// Generates the next `left.len()` samples like `gen_block`, playing
+    /// the scheduled events on their sample and moving the cursor past
+    /// the block.
+    ///
+    /// an event is placed as far into the block as it came after the
+    /// previous call, so events are late by a callback but keep their
+    /// spacing instead of all landing on the start of a buffer.
+    pub fn play_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let n = left.len().min(right.len());
+        let sr = self.sample_rate() as f32;
+        let clock = self.clock;
+        let start = self.now();
+        let previous = self.last_callback.replace(start).unwrap_or(start);
+        let mut events = std::mem::take(&mut self.scheduled);
+        // to the nearest sample, the wall clock rounds both ways.
+        let offset = |time: f32| (((time - previous) * sr).round().max(0.0) as usize).min(n.saturating_sub(1));
+        let mut pending = events.drain(..).peekable();
+        let mut pos = 0;
+        while pos < n {
+            // the instrument is timed by the sample while the block plays.
+            self.clock = Clock::Offline(start + pos as f32 / sr);
+            while let Some((_, event)) = pending.next_if(|(time, _)| offset(*time) <= pos) {
+                self.apply_live_event(event);
+            }
+            let end = pending.peek().map_or(n, |(time, _)| offset(*time));
+            self.gen_block(&mut left[pos..end], &mut right[pos..end]);
+            self.mix_parts(&mut left[pos..end], &mut right[pos..end]);
+            self.advance_cursor((end - pos) as u128);
+            self.shift_input(end - pos);
+            pos = end;
+        }
+        pending.for_each(|(_, event)| self.apply_live_event(event));
+        self.clock = clock;
+        self.scheduled = events;
+    }
+
+    /// Generates the next `left.len()` samples of each channel, from the
+    /// cursor on.
+    ///
+    /// each voice is rendered over the whole block before the next one, so
+    /// its state is looked up once per block rather than once per sample.
+    /// the wave position modulation is read once per block.
+    pub fn gen_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let n = left.len().min(right.len());
+        let sr = self.sample_rate() as f32;
+        let dt = 1.0 / sr;
+        let start = self.now();
+        let mut block = std::mem::take(&mut self.block);
+        block.resize(n);
+
+        self.smooth_params();
+        for i in 0..n {
+            let t = self.t(i as u128);
+            let smoothed = &mut self.smoothed;
+            let (volume, cutoff, bend) = (smoothed.volume.tick(), smoothed.cutoff.tick(), smoothed.bend.tick());
+            (block.resonance[i], block.mod_wheel[i], block.input_gain[i]) =
+                (smoothed.resonance.tick(), smoothed.mod_wheel.tick(), smoothed.input_gain.tick());
+            block.t[i] = t;
+            block.now[i] = start + i as f32 * dt;
+            block.pitch[i] = 2f32.powf((self.lfo_modulation(t, LfoDestination::Pitch) + bend) / 12.0);
+            block.gain[i] = 1.0 + self.lfo_modulation(t, LfoDestination::Amplitude);
+            block.cutoff[i] = cutoff * 2f32.powf(self.lfo_modulation(t, LfoDestination::Cutoff));
+            // volume is global, only sources that aren't tied to a note reach it.
+            let global = self.mod_matrix.evaluate(&ModInputs {
+                t, lfos: &self.lfos, envelope: 0.0, velocity: 0.0, note: 60.0, mod_wheel: block.mod_wheel[i]
+            });
+            block.volume[i] = (volume + global.get(ModDestination::Volume)).max(0.0);
+        }
+        left[..n].fill(0.0);
+        right[..n].fill(0.0);
+
+        let mut envelope = self.envelope.clone();
+        envelope.3 = self.release_time();
+        let filter_envelope = self.filter_envelope.clone();
+        let shift = self.key_shift();
+        for (key, event) in self.voices.event_buffer.iter() {
+            let base = match key {
+                NoteKey::Key(k) => self.key_to_note.get(k).map_or(0.0, |n| note_to_freq(n + shift)),
+                NoteKey::Midi(n) | NoteKey::Loop(n) | NoteKey::Generated(n) => note_to_freq(*n as f32),
+                NoteKey::Chord(n, i) => note_to_freq(*n as f32 + *i as f32),
+            };
+            let target = self.scale.lock(freq_to_note(base)) + self.detune / 100.0;
+            let stolen = self.stolen.get(key).copied();
+            let last_note = &mut self.last_note;
+            let glide = self.glides.entry(*key).or_insert_with(|| {
+                Glide::new(last_note.replace(target).unwrap_or(target))
+            });
+
+            let (mut position, mut width, mut ring) = (0.0, 0.0, 0.0);
+            for i in 0..n {
+                let (t, now) = (block.t[i], block.now[i]);
+                let note = glide.tick(&self.glide, target, dt);
+                let mut env = envelope.sample(now, event.time_press, event.time_release);
+                let mods = self.mod_matrix.evaluate(&ModInputs {
+                    t, lfos: &self.lfos, envelope: env, velocity: event.velocity, note,
+                    mod_wheel: block.mod_wheel[i],
+                });
+                let attack = mods.get(ModDestination::Attack);
+                if attack != 0.0 {
+                    let mut scaled = envelope.clone();
+                    scaled.0 *= 2f32.powf(attack);
+                    env = scaled.sample(now, event.time_press, event.time_release);
+                }
+                if i == 0 {
+                    (position, width, ring) =
+                        (mods.get(ModDestination::WavePosition), mods.get(ModDestination::PulseWidth), mods.get(ModDestination::RingMod));
+                }
+                block.freq[i] = note_to_freq(note) * block.pitch[i] * 2f32.powf(mods.get(ModDestination::Pitch) / 12.0);
+                let mut settings = self.filter;
+                let sweep = filter_envelope.octaves(now, event.time_press, event.time_release);
+                settings.cutoff = block.cutoff[i] * 2f32.powf(mods.get(ModDestination::Cutoff) + sweep + settings.tracking(note));
+                settings.resonance = block.resonance[i] + mods.get(ModDestination::Resonance);
+                block.settings[i] = settings;
+                let mut declick = ((now - event.time_press) / Instrument::DECLICK).clamp(0.0, 1.0);
+                if let Some(stolen) = stolen { declick *= (1.0 - (now - stolen) / Instrument::DECLICK).clamp(0.0, 1.0); }
+                block.amp[i] = env * declick * event.velocity * (1.0 + mods.get(ModDestination::Amplitude)).max(0.0) * self.unison.gain();
+            }
+
+            let sampler = &self.sampler;
+            let mut sampler_voice = match self.engine {
+                Engine::Sampler => Some(self.sampler_voices.entry(*key).or_insert_with(|| {
+                    sampler.voice(target).unwrap_or(SamplerVoice { zone: usize::MAX, pos: Default::default() })
+                })),
+                _ => None,
+            };
+            let phases = self.phases.entry(*key).or_default();
+            block.left[..n].fill(0.0);
+            block.right[..n].fill(0.0);
+            self.oscillator.otf.modulate_position(position);
+            self.oscillator.otf.modulate_width(width);
+            self.oscillators.modulate(position, width, ring);
+            for k in 0..self.unison.voices() {
+                let (ratio, pan) = self.unison.voice(k);
+                let (gl, gr) = pan_gains(pan);
+                for i in 0..n { block.detuned[i] = block.freq[i] * ratio; }
+                match self.engine {
+                    Engine::Oscillator => {
+                        self.oscillator.gen_block(&mut phases.osc[k], &block.t[..n], &block.detuned[..n], &mut block.out[..n]);
+                        self.oscillators.mix_block(&mut phases.extra[k], &block.t[..n], &block.detuned[..n], &mut block.out[..n],
+                            &mut block.extra_freq[..n], &mut block.extra_out[..n]);
+                    },
+                    Engine::Fm => for i in 0..n {
+                        block.out[i] = self.fm.sample(&phases.fm[k], block.now[i], event.time_press, event.time_release);
+                        self.fm.advance(&mut phases.fm[k], block.detuned[i], sr);
+                    },
+                    Engine::Sampler => for i in 0..n {
+                        block.out[i] = sampler_voice.as_mut().map_or(0.0, |v| sampler.tick(v, k, block.detuned[i], sr));
+                    },
+                }
+                for i in 0..n {
+                    block.left[i] += block.out[i] * gl;
+                    block.right[i] += block.out[i] * gr;
+                }
+            }
+
+            if self.voice_fold.mix > 0.0 {
+                for i in 0..n {
+                    block.left[i] = self.voice_fold.fold(block.left[i]);
+                    block.right[i] = self.voice_fold.fold(block.right[i]);
+                }
+            }
+            if self.voice_drive.mix > 0.0 {
+                for i in 0..n {
+                    block.left[i] = self.voice_drive.saturate(block.left[i]);
+                    block.right[i] = self.voice_drive.saturate(block.right[i]);
+                }
+            }
+
+            for i in 0..n {
+                let noise = self.noise.gain(block.now[i] - event.time_press);
+                if noise > 0.0 {
+                    let x = self.noise_source.gen(block.t[i]) * noise;
+                    block.left[i] += x;
+                    block.right[i] += x;
+                }
+            }
+
+            if self.sub.level > 0.0 {
+                for i in 0..n {
+                    let x = self.sub.tick(&mut phases.sub, block.freq[i], sr);
+                    block.left[i] += x;
+                    block.right[i] += x;
+                }
+            }
+
+            let [fl, fr] = self.filters.entry(*key)
+                .or_insert_with(|| [VoiceFilter::new(block.settings[0], sr), VoiceFilter::new(block.settings[0], sr)]);
+            for i in 0..n {
+                fl.set_settings(block.settings[i]);
+                fr.set_settings(block.settings[i]);
+                left[i] += fl.process(block.left[i]) * block.amp[i];
+                right[i] += fr.process(block.right[i]) * block.amp[i];
+            }
+        }
+
+        for i in 0..n {
+            let drums = self.drums.gen(sr);
+            let input = if self.monitor_input { self.input_sample(i as u128) * block.input_gain[i] } else { 0.0 };
+            left[i] = (left[i] * block.gain[i] + drums) * block.volume[i] + input;
+            right[i] = (right[i] * block.gain[i] + drums) * block.volume[i] + input;
+        }
+        self.block = block;
+    }
+}
+
+
+impl Default for Instrument { fn default() -> Self { Self::new() } }
+
+impl KeyboardHandler for Instrument {
+    fn handle_key_event(&mut self, event: KeyEvent, timestamp: f32) {
+        if self.scheduling && self.plays_key(&event) { return self.scheduled.push((self.now(), LiveEvent::Key(event))); }
+        self.record_edits(|s| s.apply_key_event(event, timestamp));
+    }
+
+    // drum pads are struck, a quick second hit is another hit.
+    fn holds_key(&self, event: &KeyEvent) -> bool {
+        self.plays_key(event) && self.drum_pad(NoteKey::Key(event.code)).is_none()
+    }
+
+    fn cleanup_events(&mut self) {
+        let now = self.now();
+        self.keyboard_buffer.clean_stale_events(now, Some(self.release_time()));
+        self.voices.clean_stale_events(now, Some(self.release_time()));
+        let held = &self.voices.event_buffer;
+        self.filters.retain(|k, _| held.contains_key(k));
+        self.glides.retain(|k, _| held.contains_key(k));
+        self.sampler_voices.retain(|k, _| held.contains_key(k));
+        self.phases.retain(|k, _| held.contains_key(k));
+        // stolen voices are gone once faded out.
+        let voices = &mut self.voices.event_buffer;
+        self.stolen.retain(|k, t| {
+            let faded = now - *t > Instrument::DECLICK;
+            if faded { voices.remove(k); }
+            !faded && voices.contains_key(k)
+        });
+        self.publish_snapshot();
+    }
+}
+
+impl Instrument {
+    fn apply_key_event(&mut self, event: KeyEvent, timestamp: f32) {
+        if self.prompt.is_some() {
+            self.prompt_key(event);
+            return self.publish_snapshot();
+        }
+        match event {
+            KeyEvent { kind: KeyEventKind::Press | KeyEventKind::Repeat, code: KeyCode::Char(c @ ('z' | 'Z')), modifiers, .. }
+                if modifiers.contains(KeyModifiers::ALT) => {
+                let redo = c == 'Z' || modifiers.contains(KeyModifiers::SHIFT);
+                let done = if redo { self.redo() } else { self.undo() };
+                if !done { self.status = format!("nothing to {}", if redo { "redo" } else { "undo" }); }
+            },
+            // rolls the last seed again, undoing the tweaks made since.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(4), modifiers: KeyModifiers::SHIFT, .. } => {
+                self.status = match self.patch_seed {
+                    Some(seed) => { self.randomize(seed); format!("rolled seed {} again", seed) },
+                    None => String::from("no random patch to roll again"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press | KeyEventKind::Repeat, code: KeyCode::F(4), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.mutate(rand::random());
+                self.status = format!("mutated by up to {:.0}%, {} locked", self.mutation * 100.0, self.locks.name());
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(4), .. } => {
+                let seed = rand::random();
+                self.randomize(seed);
+                self.status = format!("random patch, seed {}", seed);
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(5), modifiers, .. } => {
+                if modifiers.contains(KeyModifiers::SHIFT) {
+                    self.set_resonance(self.filter.resonance / 1.25);
+                } else {
+                    self.set_cutoff(self.filter.cutoff / 2f32.powf(1.0/6.0));
+                }
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(6), modifiers, .. } => {
+                if modifiers.contains(KeyModifiers::SHIFT) {
+                    self.set_resonance(self.filter.resonance * 1.25);
+                } else {
+                    self.set_cutoff(self.filter.cutoff * 2f32.powf(1.0/6.0));
+                }
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(7), modifiers, .. } => {
+                if modifiers.contains(KeyModifiers::CONTROL) {
+                    self.filter.kind = self.filter.kind.next();
+                    self.status = format!("{:?} filter", self.filter.kind);
+                } else if modifiers.contains(KeyModifiers::SHIFT) {
+                    // steps an octave up to the top, then over to the bottom.
+                    let amount = self.filter_envelope.amount.round() + 1.0;
+                    self.filter_envelope.amount = if amount > FilterEnvelope::MAX_AMOUNT / 2.0 { -FilterEnvelope::MAX_AMOUNT / 2.0 } else { amount };
+                    self.status = format!("filter envelope {:+.0} octaves", self.filter_envelope.amount);
+                } else {
+                    self.filter.mode = self.filter.mode.next();
+                }
+            },
+            // added on first use, ahead of the delay.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(10), modifiers: KeyModifiers::SHIFT, .. } => {
+                self.ensure_effect(EffectDesc::Flanger(FlangerSettings::default()), self.effects.find("delay"));
+                self.status = match self.toggle_effect("flanger") {
+                    Some(true) => String::from("flanger on"),
+                    _ => String::from("flanger off"),
+                };
+            },
+            // turns the ping pong delay on, steps through the divisions,
+            // then turns it off.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(10), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.ensure_effect(EffectDesc::PingPong(PingPongSettings::default()), self.effects.find("delay"));
+                let found = self.effects.find("ping pong").map(|i| (i, self.effects.slots()[i].effect.desc()));
+                if let Some((index, EffectDesc::PingPong(settings))) = found {
+                    let division = match self.effects.is_bypassed(index) {
+                        true => Some(settings.division),
+                        false => settings.division.next(),
+                    };
+                    let division_or_first = division.unwrap_or(NoteDivision::STEPS[0]);
+                    self.effects.update(index, EffectDesc::PingPong(PingPongSettings { division: division_or_first, ..settings }));
+                    self.effects.set_bypass(index, division.is_none());
+                    self.status = match division {
+                        Some(division) => format!("ping pong delay {} at {} bpm", division, self.transport.bpm()),
+                        None => String::from("ping pong delay off"),
+                    };
+                }
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(11), modifiers, .. } if modifiers.intersects(KeyModifiers::SHIFT | KeyModifiers::CONTROL) => {
+                let (name, default) = match modifiers.contains(KeyModifiers::CONTROL) {
+                    true => ("auto pan", EffectDesc::AutoPan(AutoPanSettings::default())),
+                    false => ("tremolo", EffectDesc::Tremolo(TremoloSettings::default())),
+                };
+                self.status = match self.step_lfo_effect(default) {
+                    Some(rate @ LfoRate::Synced(_)) => format!("{} {} at {} bpm", name, rate, self.transport.bpm()),
+                    Some(rate) => format!("{} {}", name, rate),
+                    None => format!("{} off", name),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(11), modifiers: KeyModifiers::ALT, .. } => {
+                let ir = self.convolution().ir;
+                self.status = match self.toggle_effect("convolution") {
+                    Some(true) if ir.as_os_str().is_empty() => String::from("convolution reverb on, with no impulse response loaded"),
+                    Some(true) => format!("convolution reverb on: {}", ir.display()),
+                    Some(false) => String::from("convolution reverb off"),
+                    None => String::from("no impulse response loaded, see --impulse-response"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(n @ (10 | 11)), .. } => {
+                let name = if n == 10 { "delay" } else { "reverb" };
+                self.status = match self.toggle_effect(name) {
+                    Some(on) => format!("{} {}", name, if on { "on" } else { "off" }),
+                    None => format!("no {} in the effect chain", name),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(9), modifiers: KeyModifiers::SHIFT, .. } => {
+                if !self.performance.recording() {
+                    self.start_performance();
+                    self.status = String::from("capturing notes to midi");
+                } else {
+                    let path = default_performance_path();
+                    self.status = match self.stop_performance(&path) {
+                        Ok(notes) => format!("{} notes saved to {}", notes, path),
+                        Err(e) => format!("could not save {}: {}", path, e),
+                    };
+                }
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(9), .. } => {
+                let result = if self.is_recording() {
+                    self.stop_recording()
+                } else {
+                    self.start_recording(default_recording_path())
+                };
+                self.status = match result {
+                    Err(e) => format!("recording failed: {}", e),
+                    Ok(()) if self.is_recording() => String::from("recording"),
+                    Ok(()) => String::from("recording saved"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(2), modifiers: KeyModifiers::SHIFT, .. } => {
+                self.request(disk::Request::SaveProject(self.project_name.clone(), Box::new(self.project())));
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(3), modifiers: KeyModifiers::SHIFT, .. } => {
+                self.request(disk::Request::LoadProject(self.project_name.clone()));
+            },
+            // the patch as it is under a new name, leaving the preset F2
+            // saves to alone, so a lucky roll is kept before the next one.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(2), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.request(disk::Request::SaveSnapshot(self.patch(), self.patch_seed));
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(2), .. } => {
+                self.request(disk::Request::SavePreset(self.preset_name.clone(), self.patch()));
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(3), .. } => {
+                self.request(disk::Request::LoadPreset(self.preset_name.clone()));
+            },
+            // bends while held, for terminals that report key releases.
+            KeyEvent { kind, code: code @ (KeyCode::Up | KeyCode::Down), .. } => {
+                let bend = if code == KeyCode::Up { 1.0 } else { -1.0 };
+                self.set_pitch_bend(if kind == KeyEventKind::Release { 0.0 } else { bend });
+            },
+            // splits the keyboard at middle c, the patch of this instrument
+            // playing below it too until another preset is loaded there.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('k'), modifiers: KeyModifiers::ALT, .. } => {
+                let point = if self.split.is_some() { None } else { Some(60) };
+                self.status = match self.set_split(point, "") {
+                    Err(e) => format!("could not split: {}", e),
+                    Ok(()) => match self.split_point() {
+                        Some(point) => format!("split at {}, {} below", note_name(point as i32), self.parts[self.split.unwrap_or_default()].desc.name()),
+                        None => String::from("keyboard not split"),
+                    },
+                };
+            },
+            // stacks the patch on itself, detuned and spread apart, or takes
+            // the layers away.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('j'), modifiers: KeyModifiers::ALT, .. } => {
+                let layers: Vec<usize> = (0..self.parts.len()).filter(|i| self.parts[*i].desc.layer).collect();
+                self.status = if layers.is_empty() {
+                    match self.add_layer("", 10.0, 0.5) {
+                        Err(e) => format!("could not layer: {}", e),
+                        Ok(()) => String::from("layered, 10 cents up"),
+                    }
+                } else {
+                    layers.into_iter().rev().for_each(|i| { self.remove_part(i); });
+                    String::from("layers off")
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press | KeyEventKind::Repeat, code: code @ (KeyCode::Left | KeyCode::Right), modifiers: KeyModifiers::ALT, .. } => {
+                if let Some(point) = self.split_point() {
+                    let point = if code == KeyCode::Right { point.saturating_add(1).min(127) } else { point.saturating_sub(1).max(1) };
+                    let _ = self.set_split(Some(point), "");
+                    self.status = format!("split at {}", note_name(point as i32));
+                }
+            },
+            KeyEvent { kind: KeyEventKind::Press | KeyEventKind::Repeat, code: code @ (KeyCode::Left | KeyCode::Right), .. } => {
+                let step = if code == KeyCode::Right { 0.1 } else { -0.1 };
+                self.set_mod_wheel(self.mod_wheel + step);
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: code @ KeyCode::Char('[' | ']'), .. } => {
+                self.set_octave(self.octave + if code == KeyCode::Char(']') { 1 } else { -1 });
+                self.status = format!("octave {:+}", self.octave);
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: code @ KeyCode::Char('{' | '}'), .. } => {
+                self.set_transpose(self.transpose + if code == KeyCode::Char('}') { 1 } else { -1 });
+                self.status = format!("transpose {:+}", self.transpose);
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(12), modifiers, .. } => {
+                let mut glide = self.glide;
+                if modifiers.contains(KeyModifiers::SHIFT) {
+                    glide.curve = match glide.curve { GlideCurve::Linear => GlideCurve::Exponential, GlideCurve::Exponential => GlideCurve::Linear };
+                } else {
+                    const TIMES: [f32; 5] = [0.0, 0.05, 0.15, 0.4, 1.0];
+                    glide.time = TIMES.iter().copied().find(|t| *t > glide.time).unwrap_or(0.0);
+                }
+                self.set_glide(glide);
+                self.status = format!("glide {:.2}s {:?}", self.glide.time, self.glide.curve);
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(8), modifiers, .. } => {
+                if modifiers.contains(KeyModifiers::SHIFT) {
+                    self.arpeggiator.settings.mode = self.arpeggiator.settings.mode.next();
+                } else {
+                    self.set_arpeggiator_enabled(!self.arpeggiator.enabled);
+                }
+                self.status = match self.arpeggiator.enabled {
+                    true => format!("arpeggiator {:?} @ {} bpm", self.arpeggiator.settings.mode, self.transport.bpm()),
+                    false => String::from("arpeggiator off"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('u'), modifiers: KeyModifiers::CONTROL, .. } => {
+                let voices = self.unison.voices() as u8 % UnisonSettings::MAX_VOICES + 1;
+                self.set_unison(UnisonSettings { voices, ..self.unison });
+                self.status = format!("unison {} voices, {:.0} cents", voices, self.unison.detune);
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: code @ (KeyCode::PageUp | KeyCode::PageDown), modifiers: KeyModifiers::CONTROL, .. } => {
+                let step = if code == KeyCode::PageUp { -1 } else { 1 };
+                self.request(disk::Request::StepPreset(self.preset_name.clone(), step));
+            },
+            KeyEvent { kind: KeyEventKind::Press | KeyEventKind::Repeat, code: code @ (KeyCode::PageUp | KeyCode::PageDown), .. } => {
+                let step = if code == KeyCode::PageUp { 0.05 } else { -0.05 };
+                self.set_master_volume(self.master_volume + step);
+                self.status = format!("volume {:.0}%", self.master_volume * 100.0);
+            },
+            // switches to the built-in wavetable, then steps through it.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('w'), modifiers: KeyModifiers::CONTROL, .. } => {
+                // the table playing only moves, it isn't built again.
+                let position = match self.oscillator.otf.desc() {
+                    WaveDesc::Wavetable(d) if d.position < 1.0 => (d.position + 0.125).min(1.0),
+                    WaveDesc::Wavetable(_) => 0.0,
+                    _ => {
+                        self.oscillator.set_wavetable(WavetableOscillator::basic());
+                        0.0
+                    },
+                };
+                self.oscillator.otf.set_position(position);
+                self.status = format!("wavetable position {:.3}", position);
+            },
+            // switches to the additive oscillator, then steps its brightness
+            // (a) or odd/even balance (b).
+            KeyEvent { kind: KeyEventKind::Press, code: code @ KeyCode::Char('a' | 'b'), modifiers: KeyModifiers::CONTROL, .. } => {
+                let desc = match self.oscillator.otf.desc() {
+                    WaveDesc::Additive(d) if code == KeyCode::Char('a') => AdditiveDesc {
+                        brightness: if d.brightness >= 1.0 { 0.0 } else { (d.brightness + 0.25).min(1.0) }, ..d
+                    },
+                    WaveDesc::Additive(d) => AdditiveDesc {
+                        odd_even: if d.odd_even >= 1.0 { -1.0 } else { (d.odd_even + 0.5).min(1.0) }, ..d
+                    },
+                    _ => AdditiveDesc::default(),
+                };
+                self.status = format!("additive brightness {:.2}, odd/even {:+.2}", desc.brightness, desc.odd_even);
+                self.oscillator.otf = WaveDesc::Additive(desc).build();
+            },
+            // off, then each color of noise layer.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('n'), modifiers: KeyModifiers::CONTROL, .. } => {
+                let noise = match (self.noise.level > 0.0, self.noise.color) {
+                    (false, _) => NoiseLayer { color: NoiseColor::White, level: 0.3, ..self.noise },
+                    (true, NoiseColor::White) => NoiseLayer { color: NoiseColor::Pink, ..self.noise },
+                    (true, NoiseColor::Pink) => NoiseLayer { color: NoiseColor::Brown, ..self.noise },
+                    (true, NoiseColor::Brown) => NoiseLayer { level: 0.0, ..self.noise },
+                };
+                self.set_noise(noise);
+                self.status = match noise.level > 0.0 {
+                    true => format!("{:?} noise layer, {:.0} ms decay", noise.color, noise.decay * 1000.0),
+                    false => String::from("noise layer off"),
+                };
+            },
+            // off, then a sine and a square, one and two octaves down.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('s'), modifiers: KeyModifiers::CONTROL, .. } => {
+                let sub = match (self.sub.level > 0.0, self.sub.shape, self.sub.octaves) {
+                    (false, _, _) => SubOscillator { shape: SubShape::Sine, octaves: 1, level: 0.5 },
+                    (true, _, 1) => SubOscillator { octaves: 2, ..self.sub },
+                    (true, SubShape::Sine, _) => SubOscillator { shape: SubShape::Square, octaves: 1, ..self.sub },
+                    (true, SubShape::Square, _) => SubOscillator { level: 0.0, ..self.sub },
+                };
+                self.set_sub(sub);
+                self.status = match sub.level > 0.0 {
+                    true => format!("{:?} sub -{} oct, level {:.2}", sub.shape, sub.octaves, sub.level),
+                    false => String::from("sub oscillator off"),
+                };
+            },
+            // mixes in the second oscillator, then steps through its
+            // waveforms and back to the main oscillator alone.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('y'), modifiers: KeyModifiers::CONTROL, .. } => {
+                let second = &mut self.oscillators.extra[0].oscillator;
+                let next = match (self.oscillators.mix > 0.0, second.otf.desc()) {
+                    (false, _) => Some(BlepShape::Saw),
+                    (true, WaveDesc::PolyBlep(BlepShape::Saw)) => Some(BlepShape::Pulse(0.5)),
+                    (true, WaveDesc::PolyBlep(BlepShape::Pulse(_))) => Some(BlepShape::Triangle),
+                    (true, _) => None,
+                };
+                match next {
+                    Some(shape) => {
+                        second.set_waveform(shape);
+                        if self.oscillators.mix == 0.0 { self.oscillators.mix = 0.5; }
+                        self.status = format!("osc 2 {}, mix {:.0}%", second.otf.desc(), self.oscillators.mix * 100.0);
+                    },
+                    None => {
+                        self.oscillators.mix = 0.0;
+                        self.status = String::from("osc 2 and 3 off");
+                    },
+                }
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('e'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.set_engine(self.engine.next());
+                self.status = match self.engine {
+                    Engine::Sampler if self.sampler.zones() == 0 => String::from("Sampler engine, no samples in the patch"),
+                    engine => format!("{:?} engine", engine),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('g'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.fm.algorithm = self.fm.algorithm.next();
+                self.status = format!("fm algorithm {:?}", self.fm.algorithm);
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('v'), modifiers: KeyModifiers::CONTROL, .. } => {
+                // the vocoder isn't in the default chain, it goes first when added.
+                self.ensure_effect(EffectDesc::Vocoder(VocoderSettings::default()), Some(0));
+                self.status = match self.toggle_effect("vocoder") {
+                    Some(true) => String::from("vocoder on, the audio input is the modulator"),
+                    _ => String::from("vocoder off"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('f'), modifiers: KeyModifiers::CONTROL, .. } => {
+                // like the vocoder, added on first use. it goes first so the
+                // delay and reverb get the folded sound.
+                self.ensure_effect(EffectDesc::Wavefolder(WavefolderSettings::default()), Some(0));
+                self.status = match self.toggle_effect("wavefolder") {
+                    Some(true) => String::from("master wavefolder on"),
+                    _ => String::from("master wavefolder off"),
+                };
+            },
+            // turns the master drive on, steps through the curves, then
+            // turns it off. it goes first, next to the wavefolder.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('j'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.ensure_effect(EffectDesc::Saturation(SaturationSettings::default()), Some(0));
+                let found = self.effects.find("saturation").map(|i| (i, self.effects.slots()[i].effect.desc()));
+                if let Some((index, EffectDesc::Saturation(settings))) = found {
+                    let curve = match self.effects.is_bypassed(index) {
+                        true => Some(SaturationCurve::ALL[0]),
+                        false => Some(settings.curve.next()).filter(|c| *c != SaturationCurve::ALL[0]),
+                    };
+                    self.effects.update(index, EffectDesc::Saturation(SaturationSettings { curve: curve.unwrap_or(SaturationCurve::ALL[0]), ..settings }));
+                    self.effects.set_bypass(index, curve.is_none());
+                    self.status = match curve {
+                        Some(curve) => format!("master drive {:?}", curve).to_lowercase(),
+                        None => String::from("master drive off"),
+                    };
+                }
+            },
+            // the compressor goes ahead of the delay, so the echoes aren't
+            // pumped.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('z'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.ensure_effect(EffectDesc::Compressor(CompressorSettings::default()), self.effects.find("delay"));
+                self.status = match self.toggle_effect("compressor") {
+                    Some(true) => {
+                        let c = self.compressor();
+                        format!("compressor on: {:.0} dB, {:.1}:1, {:.0}/{:.0} ms, {:+.1} dB makeup",
+                            c.threshold, c.ratio, c.attack * 1000.0, c.release * 1000.0, c.makeup)
+                    },
+                    _ => String::from("compressor off"),
+                };
+            },
+            // flat until its bands are moved, through the parameters. it
+            // goes last.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('q'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.ensure_effect(EffectDesc::Eq(EqSettings::default()), None);
+                self.status = match self.toggle_effect("eq") {
+                    Some(true) => {
+                        let [low, mid, high] = [0, 1, 2].map(|b| self.eq_band(b));
+                        format!("eq on: low {:+.1} dB @ {:.0} Hz, mid {:+.1} dB @ {:.0} Hz, high {:+.1} dB @ {:.0} Hz",
+                            low.gain, low.freq, mid.gain, mid.freq, high.gain, high.freq)
+                    },
+                    _ => String::from("eq off"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('d'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.set_drum_mode(!self.drum_mode);
+                self.status = match (self.drum_mode, self.drums.pads()) {
+                    (true, 0) => String::from("drum mode, no pads in the patch"),
+                    (true, n) => format!("drum mode, {} pads on {}", n, &DRUM_KEYS[..n.min(DRUM_KEYS.len())]),
+                    (false, _) => String::from("drum mode off"),
+                };
+            },
+            // the drum pattern plays along with the transport.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('p'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.status = match (self.toggle_transport(), self.transport.playing()) {
+                    (false, _) => String::from("following midi clock, started and stopped by it"),
+                    (true, true) => format!("playing at {:.1} bpm", self.transport.bpm()),
+                    (true, false) => String::from("stopped"),
+                };
+            },
+            // these keys are left to a keymap that plays notes on them.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Tab, .. } if !self.key_to_note.contains_key(&KeyCode::Tab) => {
+                self.status = match self.transport.tap(self.now()) {
+                    Some(bpm) => format!("tap tempo {:.1} bpm", bpm),
+                    None => String::from("tap tempo, keep tapping"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press | KeyEventKind::Repeat, code: code @ KeyCode::Char('_' | '+'), .. }
+                if !self.key_to_note.contains_key(&code) => {
+                let step = if code == KeyCode::Char('+') { 0.05 } else { -0.05 };
+                self.transport.set_swing(self.transport.swing() + step);
+                self.status = format!("swing {:.0}%", self.transport.swing() * 100.0);
+            },
+            KeyEvent { kind: KeyEventKind::Press | KeyEventKind::Repeat, code: code @ KeyCode::Char('-' | '='), .. }
+                if !self.key_to_note.contains_key(&code) => {
+                self.transport.nudge(if code == KeyCode::Char('=') { 1.0 } else { -1.0 });
+                self.status = format!("{:.1} bpm", self.transport.bpm());
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('r'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.drums.writing = !self.drums.writing;
+                self.status = format!("drum pattern writing {}", if self.drums.writing { "on" } else { "off" });
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('x'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.drums.pattern_mut().clear();
+                self.status = format!("drum pattern {} cleared", self.drums.current() + 1);
+            },
+            // the generator, how it picks notes, how often it plays, and a
+            // new seed for it.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char(c @ ('g' | 'w' | 'd' | 'y')), modifiers: KeyModifiers::ALT, .. } => {
+                match c {
+                    'g' => self.set_generator_enabled(!self.generator.enabled),
+                    'w' => self.generator.mode = match self.generator.mode {
+                        GeneratorMode::Walk => GeneratorMode::Markov,
+                        GeneratorMode::Markov => GeneratorMode::Walk,
+                    },
+                    'd' => {
+                        let index = Generator::DENSITIES.iter().position(|d| *d == self.generator.density).map_or(0, |i| i + 1);
+                        self.generator.density = Generator::DENSITIES[index % Generator::DENSITIES.len()];
+                    },
+                    _ => self.generator.reseed(rand::random()),
+                }
+                self.status = match self.generator.enabled {
+                    true => format!(
+                        "generator {:?} in {}, density {:.0}%, seed {}", self.generator.mode,
+                        if self.scale.enabled() { self.scale.name() } else { String::from("chromatic") },
+                        self.generator.density * 100.0, self.generator.seed(),
+                    ),
+                    false => String::from("generator off"),
+                };
+            },
+            // the scale, its root, and whether notes out of it are moved
+            // into it or left out.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char(c @ ('m' | 'n' | 'f')), modifiers: KeyModifiers::ALT, .. } => {
+                match c {
+                    'm' => self.scale.kind = self.scale.kind.cycle(),
+                    'n' => self.scale.root = (self.scale.root + 1) % 12,
+                    _ => self.scale.lock = match self.scale.lock { ScaleLock::Snap => ScaleLock::Filter, ScaleLock::Filter => ScaleLock::Snap },
+                }
+                self.status = match (self.scale.enabled(), self.scale.lock) {
+                    (false, _) => String::from("scale lock off"),
+                    (true, ScaleLock::Snap) => format!("{} scale, notes snap to it", self.scale.name()),
+                    (true, ScaleLock::Filter) => format!("{} scale, notes out of it are left out", self.scale.name()),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('h'), modifiers: KeyModifiers::ALT, .. } => {
+                let chord = self.chord.cycle();
+                self.status = match chord {
+                    Chord::Off => String::from("chord mode off"),
+                    _ => format!("chord {} +{:?}", chord.name(), self.chord.intervals()),
+                };
+            },
+            // records the first layer of the loop, starting the transport if
+            // it isn't playing, then overdubs layers on it.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('l'), modifiers: KeyModifiers::ALT, .. } => {
+                if !self.transport.playing() { self.toggle_transport(); }
+                let state = self.looper.record();
+                self.status = match (state, self.transport.playing()) {
+                    (_, false) => String::from("looper waits for the midi clock to start"),
+                    (LooperState::Recording, _) => format!("looper recording {} bars", self.looper.bars),
+                    (LooperState::Overdubbing, _) => format!("looper overdubbing layer {}", self.looper.layers() + 1),
+                    (_, true) => format!("looper playing {} layers", self.looper.layers()),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('o'), modifiers: KeyModifiers::ALT, .. } => {
+                match self.looper.state() {
+                    LooperState::Stopped => self.looper.play(),
+                    _ => self.looper.stop(),
+                }
+                self.release_loop_notes();
+                self.status = match self.looper.state() {
+                    LooperState::Stopped => String::from("looper stopped"),
+                    _ => format!("looper playing {} layers", self.looper.layers()),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('u'), modifiers: KeyModifiers::ALT, .. } => {
+                let undone = self.looper.undo();
+                self.release_loop_notes();
+                self.status = match undone {
+                    true => format!("looper layer removed, {} left", self.looper.layers()),
+                    false => String::from("looper empty"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('e'), modifiers: KeyModifiers::ALT, .. } => {
+                self.looper.clear();
+                self.release_loop_notes();
+                self.status = String::from("looper cleared");
+            },
+            // the length applies to what is recorded next, the loop only
+            // makes sense cleared.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('b'), modifiers: KeyModifiers::ALT, .. } => {
+                let index = Looper::BARS.iter().position(|b| *b == self.looper.bars).map_or(0, |i| i + 1);
+                self.looper.bars = Looper::BARS[index % Looper::BARS.len()];
+                self.status = match self.looper.is_empty() {
+                    true => format!("loop length {} bars", self.looper.bars),
+                    false => format!("loop length {} bars, clear the loop to record at it", self.looper.bars),
+                };
+            },
+            // the chance and the ratchets of the last hit written.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('p'), modifiers: KeyModifiers::ALT, .. } => {
+                self.status = match self.drums.cycle_probability() {
+                    Some(percent) => format!("last hit plays {}% of the time", percent),
+                    None => String::from("no hit written yet"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('r'), modifiers: KeyModifiers::ALT, .. } => {
+                self.status = match self.drums.cycle_ratchet() {
+                    Some(count) => format!("last hit ratchets x{}", count),
+                    None => String::from("no hit written yet"),
+                };
+            },
+            // the pattern edited, and looped outside song mode.
+            KeyEvent { kind: KeyEventKind::Press, code: code @ KeyCode::Char(',' | '.'), modifiers: KeyModifiers::ALT, .. } => {
+                let current = self.drums.current();
+                self.drums.select_pattern(if code == KeyCode::Char('.') { current + 1 } else { current.saturating_sub(1) });
+                self.status = format!("drum pattern {} of {}", self.drums.current() + 1, self.drums.kit().pattern_count());
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('c'), modifiers: KeyModifiers::ALT, .. } => {
+                let entry = self.drums.chain_pattern();
+                self.status = format!("song: {} patterns, pattern {} x{} last", self.drums.kit().song.len(), entry.pattern + 1, entry.repeats);
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('x'), modifiers: KeyModifiers::ALT, .. } => {
+                self.drums.clear_song();
+                self.drums.song_mode = false;
+                self.status = String::from("song cleared");
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('s'), modifiers: KeyModifiers::ALT, .. } => {
+                self.drums.song_mode = !self.drums.song_mode && !self.drums.kit().song.is_empty();
+                self.status = match (self.drums.song_mode, self.drums.kit().song.is_empty()) {
+                    (_, true) => String::from("no song, chain patterns with alt+c"),
+                    (true, false) => format!("song mode, {} patterns", self.drums.kit().song.len()),
+                    (false, false) => format!("looping drum pattern {}", self.drums.current() + 1),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('l'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.meter.reset_clip();
+            },
+            // types a waveform as an expression, starting from the one playing.
+            // the notes held are let go, their releases would go to the
+            // prompt.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('i'), modifiers: KeyModifiers::ALT, .. } => {
+                self.all_notes_off();
+                self.prompt = Some(match self.oscillator.otf.desc() {
+                    WaveDesc::Expr(source) => source,
+                    _ => String::from("sin(2*pi*f*t)"),
+                });
+                self.status = String::from("waveform of t and f, enter to play it, esc to leave it");
+            },
+            // the sections random patches and mutations keep, and how far a
+            // mutation goes.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('v'), modifiers: KeyModifiers::ALT, .. } => {
+                self.locks = self.locks.cycle();
+                self.status = format!("{} locked", self.locks.name());
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('a'), modifiers: KeyModifiers::ALT, .. } => {
+                let index = mutate::AMOUNTS.iter().position(|a| *a == self.mutation).map_or(0, |i| i + 1);
+                self.mutation = mutate::AMOUNTS[index % mutate::AMOUNTS.len()];
+                self.status = format!("mutations move up to {:.0}%", self.mutation * 100.0);
+            },
+            // turns a macro up, down with shift, and selects it for midi
+            // learn. with ctrl the selected parameter is made a target of it,
+            // or stops being one.
+            KeyEvent { kind: KeyEventKind::Press | KeyEventKind::Repeat, code: KeyCode::Char(c @ ('1'..='4' | '!' | '@' | '#' | '$')), modifiers, .. }
+                if modifiers.contains(KeyModifiers::ALT) => {
+                let index = "1234".find(c).or_else(|| "!@#$".find(c)).unwrap_or_default();
+                let id = ParamId::Macro(index as u8);
+                let param = self.selected_param;
+                self.status = match (modifiers.contains(KeyModifiers::CONTROL), param) {
+                    (true, ParamId::Macro(_)) => String::from("select a parameter other than a macro with ctrl+t first"),
+                    (true, _) => {
+                        let current = self.param_normalized(param);
+                        match self.macros[index].toggle_target(param, current) {
+                            true => format!("{} moves {}", id.param().name, param.param().name),
+                            false => format!("{} no longer moves {}", id.param().name, param.param().name),
+                        }
+                    },
+                    (false, _) => {
+                        let down = modifiers.contains(KeyModifiers::SHIFT) || "!@#$".contains(c);
+                        self.set_param(id, self.param(id) + if down { -0.05 } else { 0.05 });
+                        self.select_param(id);
+                        format!("{} {:.0}%, {} targets", id.param().name, self.param(id) * 100.0, self.macros[index].targets.len())
+                    },
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('t'), modifiers: KeyModifiers::CONTROL, .. } => {
+                let index = ParamId::ALL.iter().position(|p| *p == self.selected_param).map_or(0, |i| i + 1);
+                self.select_param(ParamId::ALL[index % ParamId::ALL.len()]);
+                let id = self.selected_param;
+                self.status = format!("{} {:.3}", id.param().name, self.param(id));
+            },
+            // learns an absolute control, then a relative one, then stops.
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('k'), modifiers: KeyModifiers::CONTROL, .. } => {
+                match self.learning {
+                    None => self.learn(CcMode::Absolute),
+                    Some(CcMode::Absolute) => self.learn(CcMode::Relative),
+                    Some(CcMode::Relative) => self.cancel_learn(),
+                }
+                self.status = match self.learning {
+                    Some(mode) => format!("move a control for {} ({:?})", self.selected_param.param().name, mode),
+                    None => String::from("learn cancelled"),
+                };
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Char('o'), modifiers: KeyModifiers::CONTROL, .. } => {
+                self.set_input_monitoring(!self.monitor_input);
+                self.status = format!("input monitoring {}", if self.monitor_input { "on" } else { "off" });
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::Esc, .. } => {
+                self.all_notes_off();
+                self.status = String::from("all notes off");
+            },
+            KeyEvent { kind: KeyEventKind::Press, code: KeyCode::F(1), modifiers, .. } => {
+                if modifiers.contains(KeyModifiers::CONTROL) {
+                    self.set_polyphony(Polyphony { stealing: self.polyphony.stealing.next(), ..self.polyphony });
+                    self.status = format!("{} voices, {:?} voice stealing", self.polyphony.max_voices, self.polyphony.stealing);
+                    return self.publish_snapshot();
+                }
+                if modifiers.contains(KeyModifiers::SHIFT) {
+                    self.set_note_priority(self.note_priority.next());
+                } else {
+                    self.set_play_mode(self.play_mode.next());
+                }
+                self.status = format!("{:?} mode, {:?} note priority", self.play_mode, self.note_priority);
+            },
+            KeyEvent { code, kind, .. } if Some(code) == self.sustain_key => match kind {
+                KeyEventKind::Press => self.set_sustain(true),
+                KeyEventKind::Release => self.set_sustain(false),
+                KeyEventKind::Repeat => (),
+            },
+            KeyEvent { code, kind, .. } if self.drum_pad(NoteKey::Key(code)).is_some() => {
+                if kind == KeyEventKind::Press {
+                    let pad = self.drum_pad(NoteKey::Key(code)).unwrap_or_default();
+                    self.hit_pad(pad, 1.0);
+                }
+            },
+            KeyEvent { code, kind: KeyEventKind::Press, .. } if self.out_of_key(NoteKey::Key(code)) => (),
+            _ if self.route_key_to_parts(event) => (),
+            _ => {
+                let key = NoteKey::Key(event.code);
+                let pressed = event.kind == KeyEventKind::Press && !self.keyboard_buffer.event_buffer.contains_key(&key);
+                self.keyboard_buffer.handle_key_event(event, timestamp);
+                let velocity = self.keyboard_buffer.event_buffer.get(&key).map_or(1.0, |e| e.velocity);
+                match event.kind {
+                    KeyEventKind::Press if pressed => {
+                        self.loop_note(key, velocity, true);
+                        self.capture_note(key, velocity, true);
+                    },
+                    KeyEventKind::Release => {
+                        self.loop_note(key, 0.0, false);
+                        self.capture_note(key, 0.0, false);
+                    },
+                    _ => (),
+                }
+                if !self.arpeggiator.enabled {
+                    match self.play_mode {
+                        PlayMode::Poly => {
+                            self.voices.handle_key_event(event, timestamp);
+                            if event.kind == KeyEventKind::Press { self.voice_pressed(key, timestamp); }
+                        },
+                        _ => self.update_mono_voice(),
+                    }
+                    match event.kind {
+                        KeyEventKind::Press if pressed => self.layer_note(key, velocity, true),
+                        KeyEventKind::Release => self.layer_note(key, 0.0, false),
+                        _ => (),
+                    }
+                }
+                match event.kind {
+                    KeyEventKind::Press if pressed => self.chord_on(key, velocity),
+                    KeyEventKind::Release => self.chord_off(key),
+                    _ => (),
+                }
+            }
+        }
+        self.publish_snapshot();
+    }
+}
+
+
+impl MidiHandler for Instrument {
+    fn handle_midi_message(&mut self, message: MidiMessage) {
+        if self.scheduling && self.plays_midi(&message) { return self.scheduled.push((self.now(), LiveEvent::Midi(message))); }
+        self.apply_midi_message(message);
+    }
+}
+
+impl Instrument {
+    /// Plays a midi message `frame` samples into the next block, for
+    /// backends that time their midi to the sample, like jack. what doesn't
+    /// play is taken at once, as it comes.
+    pub fn handle_midi_message_at(&mut self, message: MidiMessage, frame: u32) {
+        if !(self.scheduling && self.plays_midi(&message)) { return self.apply_midi_message(message); }
+        // where `play_block` places it.
+        let time = self.last_callback.unwrap_or(self.now()) + frame as f32 / self.sample_rate() as f32;
+        let at = self.scheduled.partition_point(|(t, _)| *t <= time);
+        self.scheduled.insert(at, (time, LiveEvent::Midi(message)));
+    }
+
+    fn apply_midi_message(&mut self, message: MidiMessage) {
+        if self.route_to_parts(message) { return; }
+        match message {
+            // a hit written into the drum pattern is an edit.
+            MidiMessage::NoteOn { note, velocity, .. } if self.drums.writing => {
+                self.record_edits(|s| s.note_on(NoteKey::Midi(note), velocity as f32 / 127.0));
+            },
+            MidiMessage::NoteOn { note, velocity, .. } => self.note_on(NoteKey::Midi(note), velocity as f32 / 127.0),
+            MidiMessage::NoteOff { note, .. } => self.note_off(NoteKey::Midi(note)),
+            MidiMessage::PitchBend { value, .. } => self.set_pitch_bend(value as f32 / 8192.0),
+            // the pedal changes nothing to undo.
+            MidiMessage::ControlChange { controller: 64, value, .. } if self.plays_midi(&message) => self.control_change(64, value),
+            MidiMessage::ControlChange { controller, value, .. } => self.record_edits(|s| s.control_change(controller, value)),
+            MidiMessage::Clock => self.transport.clock(self.now()),
+            MidiMessage::Start if self.transport.following() => self.transport.start(self.now()),
+            MidiMessage::Continue if self.transport.following() => self.transport.resume(self.now()),
+            MidiMessage::Stop if self.transport.following() => self.transport.stop(),
+            _ => ()
+        }
+    }
+}
+
+impl std::fmt::Debug for Instrument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // self.sr.fmt(f);
+        // self.freq.fmt(f);
+        let _=self.cursor.fmt(f);
+        // self.keyboard_buffer.fmt(f);
+        // self.envelope.fmt(f);
+        std::fmt::Result::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod instrument_tests {
+    use super::*;
+
+    #[test]
+    fn test_render_is_deterministic() {
+        let events = [NoteEvent::on(0.0, 60, 100), NoteEvent::on(0.1, 64, 80), NoteEvent::off(0.2, 60), NoteEvent::off(0.3, 64)];
+        let render = || {
+            let mut instrument = Instrument::new();
+            instrument.set_unison(UnisonSettings { voices: 3, ..UnisonSettings::default() });
+            instrument.render(&events, 22050, 22050)
+        };
+        let first = render();
+        assert!(first.iter().any(|x| x.abs() > 1e-3));
+        assert_eq!(first, render());
+    }
+
+    #[test]
+    fn test_pitch_holds_after_hours() {
+        // sign changes of the first second played from `cursor` on.
+        let crossings = |cursor: u128| {
+            let mut instrument = Instrument::new();
+            instrument.set_sample_rate(cpal::SampleRate(48000));
+            instrument.note_on(NoteKey::Midi(69), 1.0);
+            instrument.advance_cursor(cursor);
+            let (mut left, mut right) = (vec![0.0; 48000], vec![0.0; 48000]);
+            instrument.gen_block(&mut left, &mut right);
+            left.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count()
+        };
+        assert_eq!(crossings(0), crossings(48000 * 3600 * 5));
+    }
+
+    #[test]
+    fn test_params_by_id() {
+        let mut instrument = Instrument::new();
+        // parameters of an effect not in the chain don't add it.
+        instrument.set_param(ParamId::EqGain(1), 6.0);
+        assert!(instrument.effects.find("eq").is_none());
+        instrument.ensure_effect(EffectDesc::Eq(EqSettings::default()), None);
+        instrument.set_param(ParamId::EqGain(1), 6.0);
+        assert_eq!(instrument.param(ParamId::EqGain(1)), 6.0);
+        assert!(instrument.effects.is_bypassed(instrument.effects.find("eq").unwrap()));
+        instrument.set_param(ParamId::CompRatio, 8.0);
+        assert!(instrument.effects.find("compressor").is_none());
+        instrument.ensure_effect(EffectDesc::Compressor(CompressorSettings::default()), None);
+        instrument.set_param(ParamId::TremoloDepth, 1.0);
+        assert!(instrument.effects.find("tremolo").is_none());
+        // a synced rate written back as it is stays synced, and off.
+        instrument.step_lfo_effect(EffectDesc::Tremolo(TremoloSettings::default()));
+        instrument.step_lfo_effect(EffectDesc::Tremolo(TremoloSettings::default()));
+        instrument.toggle_effect("tremolo");
+        let rate = instrument.tremolo().rate;
+        assert!(matches!(rate, LfoRate::Synced(_)));
+        instrument.set_param_normalized(ParamId::TremoloRate, instrument.param_normalized(ParamId::TremoloRate));
+        assert_eq!(instrument.tremolo().rate, rate);
+        assert!(instrument.effects.is_bypassed(instrument.effects.find("tremolo").unwrap()));
+        instrument.ensure_effect(EffectDesc::AutoPan(AutoPanSettings::default()), None);
+        instrument.set_param(ParamId::ConvMix, 1.0);
+        assert!(instrument.effects.find("convolution").is_none());
+        instrument.ensure_effect(EffectDesc::Convolution(ConvolutionSettings::default()), None);
+        // a width doesn't turn another wave into a pulse.
+        instrument.oscillator.set_waveform(BlepShape::Saw);
+        instrument.set_param(ParamId::PulseWidth, 0.2);
+        assert_eq!(instrument.oscillator.desc().otf, WaveDesc::PolyBlep(BlepShape::Saw));
+        instrument.oscillator.set_waveform(BlepShape::Pulse(0.5));
+        for id in ParamId::ALL {
+            let param = id.param();
+            instrument.set_param(id, param.max * 2.0);
+            assert_eq!(instrument.param(id), param.max, "{}", param.name);
+            instrument.set_param_normalized(id, 0.0);
+            assert_eq!(instrument.param(id), param.min, "{}", param.name);
+        }
+        instrument.set_param_normalized(ParamId::Sustain, 0.5);
+        assert_eq!(instrument.envelope().2, 0.5);
+    }
+
+    #[test]
+    fn test_impulse_response_read_once() {
+        let path = std::env::temp_dir().join(format!("rsynth-instrument-ir-{}.wav", std::process::id()));
+        let spec = hound::WavSpec { channels: 1, sample_rate: 1000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        [16384i16, 8192].into_iter().for_each(|s| writer.write_sample(s).unwrap());
+        writer.finalize().unwrap();
+        let mut instrument = Instrument::new();
+        assert!(instrument.load_impulse_response(path.with_extension("missing")).is_err());
+        assert!(instrument.effects.find("convolution").is_none());
+        instrument.load_impulse_response(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // rebuilt for another rate with the file gone, it still convolves.
+        instrument.set_sample_rate(cpal::SampleRate(1000));
+        instrument.set_param(ParamId::ConvMix, 1.0);
+        let index = instrument.effects.find("convolution").unwrap();
+        let (mut left, mut right) = (vec![0.0; 2048], vec![0.0; 2048]);
+        left[0] = 1.0;
+        instrument.effects.slots_mut()[index].effect.process_stereo(&mut left, &mut right);
+        assert!(left.iter().any(|x| x.abs() > 0.1));
+    }
+
+    #[test]
+    fn test_keymap_keys_play() {
+        // `-` nudges the tempo, unless the keymap plays a note on it.
+        let mut instrument = Instrument::new();
+        let bpm = instrument.transport().bpm();
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('-'), KeyModifiers::NONE), 0.0);
+        assert!(instrument.transport().bpm() < bpm);
+        let mut instrument = Instrument::new();
+        instrument.key_to_note.insert(KeyCode::Char('-'), 68.0);
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('-'), KeyModifiers::NONE), 0.0);
+        assert_eq!((instrument.transport().bpm(), voice_keys(&instrument).len()), (bpm, 1));
+    }
+
+    #[test]
+    fn test_midi_learn() {
+        let mut instrument = Instrument::new();
+        instrument.select_param(ParamId::Cutoff);
+        instrument.learn(CcMode::Absolute);
+        instrument.handle_midi_message(MidiMessage::ControlChange { channel: 0, controller: 74, value: 10 });
+        assert_eq!(instrument.learning(), None);
+        instrument.handle_midi_message(MidiMessage::ControlChange { channel: 0, controller: 74, value: 0 });
+        assert_eq!(instrument.filter_settings().cutoff, 20.0);
+
+        // rebinding the mod wheel's controller takes it over.
+        instrument.select_param(ParamId::Sustain);
+        instrument.learn(CcMode::Relative);
+        instrument.handle_midi_message(MidiMessage::ControlChange { channel: 0, controller: 1, value: 64 });
+        instrument.handle_midi_message(MidiMessage::ControlChange { channel: 0, controller: 1, value: 70 });
+        assert!((instrument.envelope().2 - (0.2 + 6.0 * CcMapping::STEP)).abs() < 1e-6);
+        assert_eq!(instrument.mod_wheel(), 0.0);
+        assert_eq!(instrument.patch().cc_mappings.len(), 2);
+    }
+
+    #[test]
+    fn test_all_notes_off() {
+        let mut instrument = Instrument::new();
+        instrument.note_on(NoteKey::Midi(60), 1.0);
+        instrument.note_on(NoteKey::Key(KeyCode::Char('q')), 1.0);
+        instrument.set_pitch_bend(0.5);
+        instrument.handle_midi_message(MidiMessage::ControlChange { channel: 0, controller: 123, value: 0 });
+        assert!(voice_keys(&instrument).is_empty());
+        assert_eq!(instrument.keyboard_buffer.held().count(), 0);
+        assert_eq!(instrument.pitch_bend(), 0.0);
+    }
+
+    #[test]
+    fn test_scheduled_events() {
+        let mut instrument = Instrument::new();
+        instrument.set_sample_rate(cpal::SampleRate(48000));
+        instrument.envelope = Envelope(0.0, 0.0, 1.0, 0.0);
+        instrument.oscillator.set_waveform(BlepShape::Saw);
+        instrument.set_scheduling(true);
+        let (mut left, mut right) = (vec![0.0; 512], vec![0.0; 512]);
+        instrument.play_block(&mut left, &mut right);
+        instrument.handle_midi_message(MidiMessage::NoteOn { channel: 0, note: 69, velocity: 127 });
+        assert!(voice_keys(&instrument).is_empty());
+        // as if the note came 100 samples after the previous callback.
+        instrument.scheduled[0].0 = instrument.last_callback.unwrap() + 100.0 / 48000.0;
+        instrument.play_block(&mut left, &mut right);
+        assert!(left[..=100].iter().all(|x| *x == 0.0));
+        assert!(left[100..512].iter().any(|x| x.abs() > 0.1));
+        assert_eq!((instrument.cursor(), voice_keys(&instrument).len()), (1024, 1));
+
+        // commands aren't held for the callback, the notes typed are.
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('i'), KeyModifiers::ALT), 0.0);
+        assert!(instrument.prompt.is_some() && instrument.scheduled.is_empty());
+        instrument.prompt = None;
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE), 0.0);
+        assert_eq!(instrument.scheduled.len(), 1);
+
+        // midi timed to the sample goes where it falls in the block.
+        instrument.scheduled.clear();
+        instrument.handle_midi_message_at(MidiMessage::NoteOn { channel: 0, note: 60, velocity: 100 }, 300);
+        instrument.handle_midi_message_at(MidiMessage::NoteOn { channel: 0, note: 62, velocity: 100 }, 100);
+        instrument.handle_midi_message_at(MidiMessage::ControlChange { channel: 0, controller: 1, value: 64 }, 200);
+        let times: Vec<f32> = instrument.scheduled.iter().map(|(t, _)| (t - instrument.last_callback.unwrap()) * 48000.0).collect();
+        assert!(times.len() == 2 && (times[0] - 100.0).abs() < 0.5 && (times[1] - 300.0).abs() < 0.5, "{:?}", times);
+    }
+
+    #[test]
+    fn test_midi_clock() {
+        // following, the tempo and position come from the clock.
+        let mut instrument = Instrument::new();
+        instrument.set_midi_clock(MidiClock::In);
+        let tick = 60.0 / 90.0 / Transport::PPQN as f32;
+        let mut events = vec![NoteEvent { time: 0.0, message: MidiMessage::Start }];
+        events.extend((0..=48).map(|i| NoteEvent { time: i as f32 * tick, message: MidiMessage::Clock }));
+        instrument.render(&events, 48000, (48.5 * tick * 48000.0) as usize);
+        assert!((instrument.transport().bpm() - 90.0).abs() < 0.1);
+        // past the last tick, but not as far as the next.
+        let position = instrument.transport().position();
+        assert!((2.0 - 1e-6..=2.0 + 1.0 / 24.0 + 1e-6).contains(&position), "{}", position);
+        assert!(!instrument.toggle_transport() && instrument.transport().playing());
+
+        // sending, a start then 24 clocks a beat, one on each beat.
+        struct Sent(Arc<Mutex<Vec<MidiMessage>>>, Arc<Mutex<Vec<f32>>>);
+        impl MidiSink for Sent {
+            fn send(&mut self, message: MidiMessage) { self.0.lock().unwrap().push(message); }
+            fn send_after(&mut self, message: MidiMessage, delay: f32) {
+                self.send(message);
+                self.1.lock().unwrap().push(delay);
+            }
+        }
+        let (sent, delays) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+        let mut instrument = Instrument::new();
+        instrument.set_midi_clock(MidiClock::Out);
+        instrument.set_midi_output(Box::new(Sent(sent.clone(), delays.clone())));
+        instrument.clock = Clock::Offline(0.0);
+        assert!(instrument.toggle_transport());
+        instrument.render(&[], 48000, 48000 + 256);
+        let position = instrument.transport().position();
+        assert!(position > 2.0);
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent[0], MidiMessage::Start);
+        assert_eq!(sent[1..].iter().filter(|m| **m == MidiMessage::Clock).count() as f64, (position * 24.0).ceil());
+        // each tick goes out as far into the block as it fell in the last.
+        let delays = delays.lock().unwrap();
+        assert!(delays.iter().all(|d| (0.0..=256.0 / 48000.0 + 1e-6).contains(d)));
+        assert!(delays.iter().filter(|d| **d > 0.0).count() > delays.len() / 2);
+    }
+
+    #[test]
+    fn test_voice_stealing() {
+        let mut instrument = Instrument::new();
+        instrument.set_polyphony(Polyphony { max_voices: 2, stealing: VoiceStealing::Oldest });
+        let play = |instrument: &mut Instrument, t: f32, key: NoteKey| {
+            instrument.clock = Clock::Offline(t);
+            instrument.note_on(key, 1.0);
+            let mut stolen: Vec<NoteKey> = instrument.stolen.keys().copied().collect();
+            stolen.sort_by_key(|k| k.to_string());
+            stolen
+        };
+        play(&mut instrument, 0.0, NoteKey::Midi(60));
+        play(&mut instrument, 0.1, NoteKey::Midi(64));
+        assert_eq!(play(&mut instrument, 0.2, NoteKey::Midi(67)), [NoteKey::Midi(60)]);
+        // released voices go before held ones.
+        instrument.note_off(NoteKey::Midi(67));
+        assert_eq!(play(&mut instrument, 0.3, NoteKey::Midi(72)), [NoteKey::Midi(60), NoteKey::Midi(67)]);
+        assert_eq!(voice_keys(&instrument).len(), 2);
+
+        // a key playing a pitch already sounding takes that voice.
+        instrument.set_polyphony(Polyphony { max_voices: 2, stealing: VoiceStealing::SameNote });
+        instrument.stolen.clear();
+        instrument.voices.event_buffer.clear();
+        let q = NoteKey::Key(KeyCode::Char('q'));
+        let note = freq_to_note(instrument.note_freq(&q)).round() as u8;
+        play(&mut instrument, 1.0, NoteKey::Midi(note));
+        play(&mut instrument, 1.1, NoteKey::Midi(note + 4));
+        assert_eq!(play(&mut instrument, 1.2, q), [NoteKey::Midi(note)]);
+    }
+
+    #[test]
+    fn test_filter_envelope() {
+        // a pluck: the cutoff starts 6 octaves up and closes within 50 ms.
+        let mut instrument = Instrument::new();
+        instrument.envelope = Envelope(0.0, 0.0, 1.0, 0.0);
+        instrument.oscillator.set_waveform(BlepShape::Saw);
+        instrument.filter.cutoff = 200.0;
+        instrument.filter_envelope = FilterEnvelope { envelope: Envelope(0.0, 0.05, 0.0, 0.0), amount: 6.0 };
+        let left: Vec<f32> = instrument.render(&[NoteEvent::on(0.0, 69, 127)], 48000, 24000).into_iter().step_by(2).collect();
+        // the difference between samples grows with the high end.
+        let brightness = |x: &[f32]| x.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f32>();
+        assert!(brightness(&left[480..1440]) > 4.0 * brightness(&left[12000..12960]));
+    }
+
+    #[test]
+    fn test_key_tracking() {
+        let cutoff = |keytrack: f32, note: u8| {
+            let mut instrument = Instrument::new();
+            instrument.filter.cutoff = 1000.0;
+            instrument.filter.keytrack = keytrack;
+            instrument.set_sample_rate(cpal::SampleRate(48000));
+            instrument.note_on(NoteKey::Midi(note), 1.0);
+            let (mut left, mut right) = (vec![0.0; 16], vec![0.0; 16]);
+            instrument.gen_block(&mut left, &mut right);
+            instrument.block.settings[0].cutoff
+        };
+        assert!((cutoff(1.0, 72) - 2000.0).abs() < 1.0);
+        assert!((cutoff(0.5, 36) - 500.0).abs() < 1.0);
+        assert!((cutoff(0.0, 84) - 1000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_declick_ramps() {
+        let mut instrument = Instrument::new();
+        instrument.envelope = Envelope(0.0, 0.0, 1.0, 0.0);
+        instrument.oscillator.set_waveform(BlepShape::Saw);
+        let events = [NoteEvent::on(0.1, 69, 127), NoteEvent::off(0.2, 69)];
+        let left: Vec<f32> = instrument.render(&events, 48000, 12000).into_iter().step_by(2).collect();
+        let ramp = (Instrument::DECLICK * 48000.0) as usize;
+        // the saw starts at -1, but the voice fades in from silence.
+        assert!(left[4800..4800 + ramp / 8].iter().all(|x| x.abs() < 0.2));
+        assert!(left[4800 + ramp..9600].iter().any(|x| x.abs() > 0.5));
+        // and out again over the shortest release.
+        assert!(left[9600..9600 + ramp / 8].iter().any(|x| x.abs() > 0.5));
+        assert!(left[9600 + ramp + 8..].iter().all(|x| x.abs() < 0.01));
+    }
+
+    fn voice_keys(instr: &Instrument) -> Vec<NoteKey> {
+        instr.voices.event_buffer.values().filter(|e| e.time_release.is_none()).map(|e| e.key).collect()
+    }
+
+    #[test]
+    fn test_mono_note_priority() {
+        let (c, e, g) = (NoteKey::Midi(60), NoteKey::Midi(64), NoteKey::Midi(67));
+        let mut instr = Instrument::new();
+        instr.set_play_mode(PlayMode::Mono);
+        instr.note_on(e, 1.0);
+        instr.note_on(g, 1.0);
+        instr.note_on(c, 1.0);
+        assert_eq!(voice_keys(&instr), vec![c]);
+
+        instr.set_note_priority(NotePriority::High);
+        assert_eq!(voice_keys(&instr), vec![g]);
+        instr.note_off(g);
+        assert_eq!(voice_keys(&instr), vec![e]);
+        instr.note_off(e);
+        instr.note_off(c);
+        assert!(voice_keys(&instr).is_empty());
+    }
+
+    #[test]
+    fn test_legato_keeps_envelope() {
+        let (c, e) = (NoteKey::Midi(60), NoteKey::Midi(64));
+        let mut instr = Instrument::new();
+        instr.clock = Clock::Offline(0.0);
+        instr.set_play_mode(PlayMode::Legato);
+        instr.note_on(c, 1.0);
+        let pressed = instr.voices.event_buffer[&c].time_press;
+        instr.clock = Clock::Offline(0.005);
+        instr.note_on(e, 1.0);
+        assert_eq!(instr.voices.event_buffer[&e].time_press, pressed);
+
+        instr.set_play_mode(PlayMode::Mono);
+        instr.note_off(e);
+        instr.note_on(e, 1.0);
+        assert!(instr.voices.event_buffer[&e].time_press > pressed);
+    }
+
+    #[test]
+    fn test_input_is_padded_with_silence() {
+        let mut instr = Instrument::new();
+        instr.input_queue().lock().unwrap().extend([0.5, -0.5]);
+        instr.pull_input(3);
+        assert_eq!((instr.input_sample(0), instr.input_sample(1), instr.input_sample(2)), (0.5, -0.5, 0.0));
+        assert!(instr.input_queue().lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_looper_plays_back_notes() {
+        let mut instrument = Instrument::new();
+        instrument.clock = Clock::Offline(0.0);
+        instrument.looper.bars = 1;
+        instrument.looper.record();
+        instrument.toggle_transport();
+        // a beat in, half a beat long at 120 bpm, then again a bar on.
+        let events = [NoteEvent::on(0.5, 60, 100), NoteEvent::off(0.75, 60)];
+        instrument.render(&events, 8000, 8000 * 5 / 2 + 400);
+        assert_eq!(instrument.looper.state(), LooperState::Playing);
+        assert!(instrument.keyboard_buffer.held().any(|k| *k == NoteKey::Loop(60)));
+        instrument.transport.stop();
+        instrument.tick_transport();
+        assert_eq!(instrument.keyboard_buffer.held().count(), 0);
+    }
+
+    #[test]
+    fn test_chord_mode() {
+        let mut instrument = Instrument::new();
+        instrument.set_chord("min".parse().unwrap());
+        instrument.note_on(NoteKey::Midi(60), 1.0);
+        let mut keys = voice_keys(&instrument);
+        keys.sort_by_key(|k| k.to_string());
+        assert_eq!(keys, [NoteKey::Midi(60), NoteKey::Chord(60, 3), NoteKey::Chord(60, 7)]);
+        // released with its note, even once the chord changed.
+        instrument.set_chord(ChordSettings::default());
+        instrument.note_off(NoteKey::Midi(60));
+        assert_eq!(instrument.keyboard_buffer.held().count(), 0);
+
+        // the arpeggiator plays the notes of the chord.
+        instrument.set_chord("7".parse().unwrap());
+        instrument.set_arpeggiator_enabled(true);
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE), 0.0);
+        instrument.tick_arpeggiator();
+        assert_eq!(instrument.arp_held.len(), 4);
+    }
+
+    #[test]
+    fn test_scale_lock() {
+        let mut instrument = Instrument::new();
+        instrument.set_scale("c major".parse().unwrap());
+        instrument.note_on(NoteKey::Midi(61), 1.0);
+        assert_eq!(instrument.note_freq(&NoteKey::Midi(61)), note_to_freq(60.0));
+        assert_eq!(voice_keys(&instrument), [NoteKey::Midi(61)]);
+
+        // the chord is kept in key too, out of it its notes are left out.
+        instrument.set_scale("c major filter".parse().unwrap());
+        instrument.set_chord("maj".parse().unwrap());
+        instrument.note_on(NoteKey::Midi(63), 1.0);
+        instrument.note_on(NoteKey::Midi(62), 1.0);
+        let mut keys = voice_keys(&instrument);
+        keys.sort_by_key(|k| k.to_string());
+        assert_eq!(keys, [NoteKey::Midi(61), NoteKey::Midi(62), NoteKey::Chord(62, 7)]);
+    }
+
+    #[test]
+    fn test_generator_plays_in_key() {
+        let mut instrument = Instrument::new();
+        instrument.clock = Clock::Offline(0.0);
+        instrument.set_scale("a minor-pentatonic".parse().unwrap());
+        instrument.generator.reseed(3);
+        instrument.generator.density = 1.0;
+        instrument.set_generator_enabled(true);
+        assert!(instrument.transport.playing());
+        let mut played = Vec::new();
+        for block in 0..200 {
+            instrument.clock = Clock::Offline(block as f32 * 0.01);
+            instrument.tick_transport();
+            played.extend(voice_keys(&instrument));
+        }
+        assert!(played.len() > 100);
+        assert!(played.iter().all(|k| matches!(k, NoteKey::Generated(n) if instrument.scale.contains(*n as f32))));
+        instrument.set_generator_enabled(false);
+        assert!(voice_keys(&instrument).is_empty());
+    }
+
+    #[test]
+    fn test_sustain_pedal() {
+        let mut instrument = Instrument::new();
+        let sustain = |instrument: &mut Instrument, value| {
+            instrument.handle_midi_message(MidiMessage::ControlChange { channel: 0, controller: 64, value })
+        };
+        instrument.note_on(NoteKey::Midi(60), 1.0);
+        sustain(&mut instrument, 127);
+        instrument.note_off(NoteKey::Midi(60));
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE), 0.0);
+        instrument.handle_key_event(KeyEvent::new_with_kind(KeyCode::Char('q'), KeyModifiers::NONE, KeyEventKind::Release), 0.0);
+        assert_eq!(voice_keys(&instrument).len(), 2);
+        assert_eq!(instrument.keyboard_buffer.held().count(), 0);
+        sustain(&mut instrument, 0);
+        assert!(voice_keys(&instrument).is_empty());
+
+        // the space bar holds it too, in mono mode the last note.
+        instrument.play_mode = PlayMode::Mono;
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE), 0.0);
+        instrument.note_on(NoteKey::Midi(62), 1.0);
+        instrument.note_off(NoteKey::Midi(62));
+        assert_eq!(voice_keys(&instrument), [NoteKey::Midi(62)]);
+        instrument.handle_key_event(KeyEvent::new_with_kind(KeyCode::Char(' '), KeyModifiers::NONE, KeyEventKind::Release), 0.0);
+        assert!(voice_keys(&instrument).is_empty());
+    }
+
+    #[test]
+    fn test_performance_capture() {
+        let mut instrument = Instrument::new();
+        instrument.clock = Clock::Offline(0.0);
+        instrument.set_chord("maj".parse().unwrap());
+        instrument.start_performance();
+        instrument.clock = Clock::Offline(0.5);
+        instrument.note_on(NoteKey::Midi(60), 1.0);
+        instrument.clock = Clock::Offline(1.0);
+        instrument.note_off(NoteKey::Midi(60));
+        let path = std::env::temp_dir().join(format!("rsynth-performance-{}.mid", std::process::id()));
+        assert_eq!(instrument.stop_performance(&path).unwrap(), 3);
+        let events = crate::render::midi_events(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(events.len(), 6);
+        assert!(events[..3].iter().all(|e| e.time == 0.5 && matches!(e.message, MidiMessage::NoteOn { .. })));
+        assert!(events[3..].iter().all(|e| e.time == 1.0 && matches!(e.message, MidiMessage::NoteOff { .. })));
+
+        // the key is a command, taken as it comes rather than in the
+        // callback, so the file is never written there.
+        let mut instrument = Instrument::new();
+        instrument.set_scheduling(true);
+        instrument.handle_key_event(KeyEvent::new(KeyCode::F(9), KeyModifiers::SHIFT), 0.0);
+        assert!(instrument.performance().recording() && instrument.scheduled.is_empty());
+    }
+
+    #[test]
+    fn test_parts_by_channel_and_zone() {
+        let mut instrument = Instrument::new();
+        instrument.set_sample_rate(cpal::SampleRate(44100));
+        instrument.add_part("".parse().unwrap()).unwrap();
+        instrument.add_part(",ch=2,zone=C2-B3,pan=1".parse().unwrap()).unwrap();
+        instrument.parts[0].desc.zone = Some((72, 127));
+        let voices = |instrument: &Instrument| -> Vec<usize> {
+            std::iter::once(&instrument.voices).chain(instrument.parts.iter().map(|p| &p.instrument.voices))
+                .map(|v| v.event_buffer.values().filter(|e| e.time_release.is_none()).count()).collect()
+        };
+        let on = |channel, note| MidiMessage::NoteOn { channel, note, velocity: 100 };
+        instrument.handle_midi_message(on(0, 60));
+        instrument.handle_midi_message(on(0, 80));
+        instrument.handle_midi_message(on(1, 40));
+        instrument.handle_midi_message(on(1, 65));
+        assert_eq!(voices(&instrument), [2, 1, 1]);
+        instrument.handle_midi_message(MidiMessage::NoteOff { channel: 0, note: 80 });
+        assert_eq!(voices(&instrument), [2, 0, 1]);
+        // the bend of a channel reaches the parts taking its notes. the part
+        // of a zone alone takes it from every channel, sharing it with this
+        // instrument.
+        let bends = |instrument: &Instrument| -> Vec<f32> {
+            std::iter::once(instrument.pitch_bend()).chain(instrument.parts.iter().map(|p| p.instrument.pitch_bend())).collect()
+        };
+        instrument.handle_midi_message(MidiMessage::PitchBend { channel: 0, value: 4096 });
+        assert_eq!(bends(&instrument), [0.5, 0.5, 0.0]);
+        instrument.handle_midi_message(MidiMessage::PitchBend { channel: 1, value: -8192 });
+        assert_eq!(bends(&instrument), [0.5, -1.0, -1.0]);
+        instrument.handle_midi_message(MidiMessage::PitchBend { channel: 0, value: 0 });
+
+        // the computer keyboard plays the zones, the release follows the press
+        // even if the octave changed in between.
+        let z = KeyCode::Char('z');
+        instrument.set_octave(-1);
+        instrument.handle_key_event(KeyEvent::new(z, KeyModifiers::NONE), 0.0);
+        instrument.set_octave(0);
+        assert_eq!(voices(&instrument), [2, 0, 2]);
+        instrument.handle_key_event(KeyEvent::new_with_kind(z, KeyModifiers::NONE, KeyEventKind::Release), 0.0);
+        assert_eq!(voices(&instrument), [2, 0, 1]);
+
+        // the second part plays on the right only, panned hard there.
+        assert!(instrument.remove_part(0).is_some());
+        instrument.all_notes_off();
+        instrument.handle_midi_message(on(1, 40));
+        let (mut left, mut right) = (vec![0.0; 256], vec![0.0; 256]);
+        instrument.mix_parts(&mut left, &mut right);
+        assert!(left.iter().all(|x| x.abs() < 1e-6) && right.iter().any(|x| x.abs() > 1e-3));
+    }
+
+    #[test]
+    fn test_keyboard_split() {
+        let mut instrument = Instrument::new();
+        instrument.add_part(",ch=3".parse().unwrap()).unwrap();
+        instrument.set_split(Some(60), "").unwrap();
+        assert_eq!((instrument.split, instrument.split_point()), (Some(1), Some(60)));
+        instrument.handle_midi_message(MidiMessage::NoteOn { channel: 0, note: 59, velocity: 100 });
+        instrument.handle_midi_message(MidiMessage::NoteOn { channel: 0, note: 60, velocity: 100 });
+        assert_eq!(voice_keys(&instrument), [NoteKey::Midi(60)]);
+        assert_eq!(voice_keys(&instrument.parts[1].instrument), [NoteKey::Midi(59)]);
+
+        // moved at runtime, and kept track of as parts come and go.
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Right, KeyModifiers::ALT), 0.0);
+        assert_eq!(instrument.split_point(), Some(61));
+        instrument.remove_part(0);
+        assert_eq!((instrument.split, instrument.split_point()), (Some(0), Some(61)));
+        instrument.set_split(None, "").unwrap();
+        assert!(instrument.parts.is_empty() && instrument.split.is_none());
+    }
+
+    #[test]
+    fn test_undo_randomize_and_pattern_clear() {
+        let mut instrument = Instrument::new();
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        let patch = instrument.patch();
+        instrument.handle_key_event(key(KeyCode::F(4), KeyModifiers::NONE), 0.0);
+        let random = instrument.patch();
+        assert_ne!(random, patch);
+        instrument.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::ALT), 0.0);
+        assert_eq!(instrument.patch(), patch);
+        instrument.handle_key_event(key(KeyCode::Char('Z'), KeyModifiers::ALT | KeyModifiers::SHIFT), 0.0);
+        assert_eq!(instrument.patch(), random);
+
+        instrument.drums_mut().pattern_mut().set(0, 0, true);
+        instrument.handle_key_event(key(KeyCode::Char('x'), KeyModifiers::CONTROL), 0.0);
+        assert!(!instrument.drums().kit().pattern.get(0, 0));
+        // notes played aren't edits, the clear is still the last one.
+        instrument.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::NONE), 0.0);
+        instrument.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::ALT), 0.0);
+        assert!(instrument.drums().kit().pattern.get(0, 0));
+        instrument.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::ALT), 0.0);
+        assert_eq!(instrument.patch().oscillator, patch.oscillator);
+        instrument.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::ALT), 0.0);
+        assert_eq!(instrument.status, "nothing to undo");
+
+        // a preset loaded in a fresh session isn't an edit either.
+        let mut instrument = Instrument::new();
+        instrument.handle_key_event(key(KeyCode::PageDown, KeyModifiers::CONTROL), 0.0);
+        instrument.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::ALT), 0.0);
+        assert_eq!(instrument.status, "nothing to undo");
+    }
+
+    #[test]
+    fn test_undo_keeps_the_wavetable() {
+        use crate::audio::waves::{WaveDesc, WavetableDesc, WavetableSource};
+        let path = std::env::temp_dir().join(format!("rsynth-instrument-wavetable-{}.wav", std::process::id()));
+        let spec = hound::WavSpec { channels: 1, sample_rate: 1000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        (0..8).for_each(|i| writer.write_sample(i as i16 * 4096).unwrap());
+        writer.finalize().unwrap();
+        let mut instrument = Instrument::new();
+        instrument.oscillator.load_wavetable(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let sample = instrument.oscillator.otf.gen(0.3);
+        assert_ne!(sample, 0.0);
+
+        // the frames are kept with the edit, the file isn't read again.
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        instrument.handle_key_event(key(KeyCode::F(4), KeyModifiers::NONE), 0.0);
+        instrument.handle_key_event(key(KeyCode::Char('z'), KeyModifiers::ALT), 0.0);
+        assert_eq!(instrument.oscillator.otf.desc(), WaveDesc::Wavetable(WavetableDesc::new(WavetableSource::File(path), 0.0)));
+        assert_eq!(instrument.oscillator.otf.gen(0.3), sample);
+    }
+
+    #[test]
+    fn test_seeded_randomize() {
+        let (mut a, mut b) = (Instrument::new(), Instrument::new());
+        a.randomize(42);
+        b.randomize(42);
+        assert_eq!((a.patch(), a.patch_seed()), (b.patch(), Some(42)));
+        b.randomize(43);
+        assert_ne!(a.patch(), b.patch());
+
+        // rolled again by key, whatever was tweaked in between.
+        a.envelope = Envelope(0.5, 0.5, 0.5, 0.5);
+        a.handle_key_event(KeyEvent::new(KeyCode::F(4), KeyModifiers::SHIFT), 0.0);
+        b.randomize(42);
+        assert_eq!(a.patch(), b.patch());
+    }
+
+    #[test]
+    fn test_reload_preset() {
+        // the factory pad, whatever pad is saved in the presets dir.
+        let patch = preset::factory_patch("pad").unwrap().unwrap();
+        let mut instrument = Instrument::new();
+        instrument.apply_patch(&patch);
+        assert!(!instrument.reload_preset(&patch));
+        let cutoff = instrument.filter_settings().cutoff;
+        instrument.set_cutoff(cutoff / 2.0);
+        assert!(instrument.reload_preset(&patch));
+        assert_eq!(instrument.filter_settings().cutoff, cutoff);
+    }
+
+    #[test]
+    fn test_expression_prompt() {
+        let mut instrument = Instrument::new();
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        // a note held as the prompt opens isn't left stuck.
+        instrument.handle_key_event(key(KeyCode::Char('q')), 0.0);
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('i'), KeyModifiers::ALT), 0.0);
+        instrument.handle_key_event(KeyEvent::new_with_kind(KeyCode::Char('q'), KeyModifiers::NONE, KeyEventKind::Release), 0.0);
+        assert!(instrument.keyboard_buffer.event_buffer.is_empty() && voice_keys(&instrument).is_empty());
+        assert_eq!(instrument.prompt.as_deref(), Some("sin(2*pi*f*t)"));
+        // typed keys edit the expression rather than play.
+        for code in [KeyCode::Backspace, KeyCode::Char('/'), KeyCode::Char('3'), KeyCode::Char(')'), KeyCode::Char(')'), KeyCode::Enter] {
+            instrument.handle_key_event(key(code), 0.0);
+        }
+        assert!(instrument.keyboard_buffer.event_buffer.is_empty());
+        assert!(instrument.prompt.is_some() && instrument.status.starts_with("invalid expression"));
+        instrument.handle_key_event(key(KeyCode::Backspace), 0.0);
+        instrument.handle_key_event(key(KeyCode::Enter), 0.0);
+        assert_eq!((instrument.prompt.as_ref(), instrument.patch().oscillator.otf), (None, WaveDesc::Expr(String::from("sin(2*pi*f*t/3)"))));
+
+        // kept in the patch, and undone like any other change of waveform.
+        let mut loaded = Instrument::new();
+        loaded.apply_patch(&instrument.patch());
+        assert_eq!(loaded.patch().oscillator, instrument.patch().oscillator);
+        instrument.undo();
+        assert_eq!(instrument.patch().oscillator.otf, Instrument::new().patch().oscillator.otf);
+    }
+
+    #[test]
+    fn test_mutate_and_locks() {
+        let mut instrument = Instrument::new();
+        instrument.randomize(7);
+        let (patch, cutoff) = (instrument.patch(), instrument.param_normalized(ParamId::Cutoff));
+        instrument.set_locks("envelope".parse().unwrap());
+        instrument.mutate(1);
+        let mutated = instrument.patch();
+        assert_eq!(mutated.envelope, patch.envelope);
+        assert_ne!(mutated.filter, patch.filter);
+        assert!((instrument.param_normalized(ParamId::Cutoff) - cutoff).abs() <= instrument.mutation() + 1e-4);
+
+        // a locked section keeps its roll, the others roll as they would.
+        let mut other = Instrument::new();
+        other.set_locks("oscillator".parse().unwrap());
+        other.randomize(7);
+        assert_eq!((other.patch().envelope, other.patch().filter), (patch.envelope, patch.filter));
+        assert_ne!(other.patch().oscillator, patch.oscillator);
+
+        // mutating keeps the kind of waveform, a pulse keeps being one.
+        let mut instrument = Instrument::new();
+        instrument.set_mutation(1.0);
+        instrument.oscillator.set_waveform(BlepShape::Saw);
+        (0..8).for_each(|seed| instrument.mutate(seed));
+        assert_eq!(instrument.patch().oscillator.otf, WaveDesc::PolyBlep(BlepShape::Saw));
+        instrument.oscillator.set_waveform(BlepShape::Pulse(0.5));
+        instrument.mutate(3);
+        assert!(instrument.oscillator.pulse_width().is_some_and(|w| w != 0.5));
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut instrument = Instrument::new();
+        instrument.select_param(ParamId::Cutoff);
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('2'), KeyModifiers::ALT | KeyModifiers::CONTROL), 0.0);
+        instrument.select_param(ParamId::Sustain);
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('2'), KeyModifiers::ALT | KeyModifiers::CONTROL), 0.0);
+        assert_eq!(instrument.macros()[1].targets.len(), 2);
+        assert_eq!(instrument.param(ParamId::Cutoff), 20000.0);
+        // the cutoff comes down from the top while the sustain goes up.
+        instrument.macros_mut()[1].targets[1].curve = crate::audio::macros::MacroCurve::Exponential;
+        let sustain = instrument.param(ParamId::Sustain);
+        instrument.set_param(ParamId::Macro(1), 0.5);
+        assert!((instrument.param_normalized(ParamId::Cutoff) - 0.5).abs() < 1e-4);
+        assert!((instrument.param(ParamId::Sustain) - (sustain + (1.0 - sustain) * 0.25)).abs() < 1e-4);
+
+        // learned like any parameter, and kept in the patch.
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('2'), KeyModifiers::ALT), 0.0);
+        assert_eq!(instrument.selected_param(), ParamId::Macro(1));
+        instrument.learn(CcMode::Absolute);
+        instrument.handle_midi_message(MidiMessage::ControlChange { channel: 0, controller: 20, value: 0 });
+        instrument.handle_midi_message(MidiMessage::ControlChange { channel: 0, controller: 20, value: 127 });
+        assert_eq!(instrument.param(ParamId::Cutoff), 20.0);
+        let mut other = Instrument::new();
+        other.apply_patch(&instrument.patch());
+        assert_eq!(other.macros(), instrument.macros());
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut instrument = Instrument::new();
+        instrument.add_layer("", 50.0, 0.0).unwrap();
+        instrument.add_part(",layer,zone=C5-C8".parse().unwrap()).unwrap();
+        instrument.handle_midi_message(MidiMessage::NoteOn { channel: 0, note: 60, velocity: 100 });
+        instrument.handle_midi_message(MidiMessage::NoteOn { channel: 0, note: 72, velocity: 100 });
+        assert_eq!(voice_keys(&instrument).len(), 2);
+        assert_eq!(voice_keys(&instrument.parts[0].instrument).len(), 2);
+        assert_eq!(voice_keys(&instrument.parts[1].instrument), [NoteKey::Midi(72)]);
+        // the first layer sounds a quarter tone up.
+        let layer = &instrument.parts[0].instrument;
+        assert!((freq_to_note(layer.note_freq(&NoteKey::Midi(60))) - 60.5).abs() < 1e-3);
+
+        // a computer key is let go on the note it pressed there.
+        let z = KeyCode::Char('z');
+        instrument.handle_key_event(KeyEvent::new(z, KeyModifiers::NONE), 0.0);
+        instrument.set_octave(1);
+        instrument.handle_key_event(KeyEvent::new_with_kind(z, KeyModifiers::NONE, KeyEventKind::Release), 0.0);
+        let held = |i: &Instrument| i.voices.event_buffer.values().filter(|e| e.time_release.is_none()).count();
+        assert_eq!((held(&instrument), held(&instrument.parts[0].instrument)), (2, 2));
+        instrument.handle_midi_message(MidiMessage::NoteOff { channel: 0, note: 72 });
+        assert_eq!(held(&instrument.parts[1].instrument), 0);
+
+        instrument.handle_key_event(KeyEvent::new(KeyCode::Char('j'), KeyModifiers::ALT), 0.0);
+        assert!(instrument.parts.is_empty());
     }
 }
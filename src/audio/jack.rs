@@ -0,0 +1,110 @@
+//! Jack module.
+//!
+//! plays through a jack client of its own, rather than through cpal's jack
+//! host, which names its client and ports itself. the client has the
+//! ports `out_left`, `out_right` and `midi_in`, so the audio and the midi
+//! of the synth are routed in the same session graph. the outputs are
+//! connected to the ports asked for, or to the system playback ports.
+//!
+
+use std::sync::{Arc, Mutex};
+use jack::{AudioOut, Client, ClientOptions, Control, MidiIn, Port, PortFlags, ProcessHandler, ProcessScope};
+
+use crate::audio::instrument::{Instrument, render_callback};
+use crate::midi::MidiMessage;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JackConfig {
+    // the name of the client, jack adds a number if it's taken.
+    pub name: String,
+    // full names of the ports `out_left` and `out_right` go to, like
+    // `system:playback_1`. empty to connect to the first physical ones.
+    pub connect: Vec<String>,
+}
+
+impl Default for JackConfig {
+    fn default() -> Self { JackConfig { name: String::from("rsynth"), connect: Vec::new() } }
+}
+
+/// The running client, it plays until stopped or dropped.
+pub struct JackClient(jack::AsyncClient<(), Process>);
+
+impl JackClient {
+    pub fn stop(self) { let _ = self.0.deactivate(); }
+}
+
+struct Process {
+    instrument: Arc<Mutex<Instrument>>,
+    left_out: Port<AudioOut>,
+    right_out: Port<AudioOut>,
+    midi_in: Port<MidiIn>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+    frames: Vec<f32>,
+    sample_rate: f32,
+}
+
+impl ProcessHandler for Process {
+    fn process(&mut self, _: &Client, ps: &ProcessScope) -> Control {
+        let mut instrument = self.instrument.lock().unwrap();
+        // each message on its frame of the period.
+        for raw in self.midi_in.iter(ps) {
+            if let Some(message) = MidiMessage::parse(raw.bytes) { instrument.handle_midi_message_at(message, raw.time); }
+        }
+        let n = ps.n_frames() as usize;
+        self.left.resize(n, 0.0);
+        self.right.resize(n, 0.0);
+        // a period is played while the next one is rendered.
+        render_callback(&mut instrument, &mut self.left, &mut self.right, n as f32 / self.sample_rate);
+        self.left_out.as_mut_slice(ps).copy_from_slice(&self.left);
+        self.right_out.as_mut_slice(ps).copy_from_slice(&self.right);
+        self.frames.clear();
+        self.frames.extend(self.left.iter().zip(&self.right).flat_map(|(l, r)| [*l, *r]));
+        instrument.record(&self.frames);
+        Control::Continue
+    }
+}
+
+/// Opens the client and starts playing the instrument on it. the jack
+/// server has to be running already.
+pub fn start_jack(mtx_instrmnt: &Arc<Mutex<Instrument>>, config: &JackConfig) -> Result<JackClient, String> {
+    let jack_error = |e: jack::Error| format!("jack: {}", e);
+    let (client, _) = Client::new(&config.name, ClientOptions::NO_START_SERVER).map_err(jack_error)?;
+    let left_out = client.register_port("out_left", AudioOut).map_err(jack_error)?;
+    let right_out = client.register_port("out_right", AudioOut).map_err(jack_error)?;
+    let midi_in = client.register_port("midi_in", MidiIn).map_err(jack_error)?;
+    let outputs = [left_out.name().map_err(jack_error)?, right_out.name().map_err(jack_error)?];
+
+    let sample_rate = client.sample_rate() as u32;
+    let mut status = format!("jack {} @ {} Hz", client.name(), sample_rate);
+    {
+        let mut instrument = mtx_instrmnt.lock().unwrap();
+        instrument.set_sample_rate(cpal::SampleRate(sample_rate));
+        instrument.set_channels(2);
+        instrument.set_status(status.clone());
+    }
+    let targets = match config.connect.is_empty() {
+        true => client.ports(None, Some(jack::jack_sys::FLOAT_MONO_AUDIO), PortFlags::IS_INPUT | PortFlags::IS_PHYSICAL),
+        false => config.connect.clone(),
+    };
+
+    let process = Process {
+        instrument: Arc::clone(mtx_instrmnt),
+        left_out, right_out, midi_in,
+        left: Vec::new(), right: Vec::new(), frames: Vec::new(),
+        sample_rate: sample_rate as f32,
+    };
+    let active = client.activate_async((), process).map_err(jack_error)?;
+    // ports that can't be connected leave the output to be routed by hand.
+    let failed: Vec<&str> = outputs.iter().zip(&targets)
+        .filter(|(output, target)| active.as_client().connect_ports_by_name(output, target).is_err())
+        .map(|(_, target)| target.as_str())
+        .collect();
+    let mut instrument = mtx_instrmnt.lock().unwrap();
+    if !failed.is_empty() {
+        status.push_str(&format!(" (could not connect to {})", failed.join(", ")));
+        instrument.set_status(status);
+    }
+    instrument.set_scheduling(true);
+    Ok(JackClient(active))
+}
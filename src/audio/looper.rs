@@ -0,0 +1,184 @@
+//! Looper module.
+//!
+//! records the notes played against the transport and plays them back
+//! over a loop of a few bars, while more are played on top. each pass of
+//! recording adds a layer, layers can be taken off again from the last.
+//!
+//! what is recorded during a pass joins the loop when the pass ends, so
+//! the notes just played don't play a second time right away.
+//!
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LoopNote { pub beat: f64, pub note: u8, pub velocity: f32, pub on: bool }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum LooperState { #[default] Stopped, Recording, Playing, Overdubbing }
+
+pub struct Looper {
+    pub bars: u32,
+    state: LooperState,
+    layers: Vec<Vec<LoopNote>>,
+    // the notes of the pass being recorded.
+    pending: Vec<LoopNote>,
+}
+
+impl Default for Looper { fn default() -> Self { Self::new() } }
+
+impl Looper {
+    pub const BARS: [u32; 4] = [1, 2, 4, 8];
+
+    pub fn new() -> Looper {
+        Looper { bars: 2, state: LooperState::Stopped, layers: Vec::new(), pending: Vec::with_capacity(256) }
+    }
+
+    pub fn state(&self) -> LooperState { self.state }
+    pub fn layers(&self) -> usize { self.layers.len() }
+    pub fn is_empty(&self) -> bool { self.layers.is_empty() && self.pending.is_empty() }
+
+    // length of the loop in beats.
+    pub fn length(&self, beats_per_bar: u32) -> f64 { (self.bars.max(1) * beats_per_bar) as f64 }
+
+    /// Records the first layer, or overdubs once there is a loop. pressed
+    /// again, it goes on playing what was recorded.
+    pub fn record(&mut self) -> LooperState {
+        self.state = match (self.state, self.layers.is_empty()) {
+            (LooperState::Recording | LooperState::Overdubbing, _) => LooperState::Playing,
+            (_, true) => LooperState::Recording,
+            (_, false) => LooperState::Overdubbing,
+        };
+        self.state
+    }
+
+    pub fn play(&mut self) { self.state = LooperState::Playing }
+    pub fn stop(&mut self) { self.state = LooperState::Stopped }
+
+    // takes off the last layer, or the pass being recorded.
+    pub fn undo(&mut self) -> bool {
+        if !self.pending.is_empty() { self.pending.clear(); return true; }
+        self.layers.pop().is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.layers.clear();
+        self.pending.clear();
+        self.state = LooperState::Stopped;
+    }
+
+    pub fn recording(&self) -> bool { matches!(self.state, LooperState::Recording | LooperState::Overdubbing) }
+
+    // a note pressed or released at `beat` of the transport, while recording.
+    pub fn note(&mut self, beat: f64, length: f64, note: u8, velocity: f32, on: bool) {
+        if !self.recording() { return; }
+        self.pending.push(LoopNote { beat: beat.rem_euclid(length), note, velocity, on });
+    }
+
+    /// Plays the notes of the loop between the beats `from` and `to` of
+    /// the transport, the loop being `length` beats from the top.
+    pub fn tick(&mut self, from: f64, to: f64, length: f64, mut play: impl FnMut(LoopNote)) {
+        if length <= 0.0 || to <= from { return; }
+        let start = from.rem_euclid(length);
+        let end = start + (to - from).min(length);
+        if self.state != LooperState::Stopped {
+            // up to the end of the loop, then on from its top.
+            let notes = || self.layers.iter().flatten();
+            notes().filter(|n| start <= n.beat && n.beat < end).for_each(|n| play(*n));
+            notes().filter(|n| n.beat + length < end).for_each(|n| play(*n));
+        }
+        // the pass joins the loop once over, or once no longer recording.
+        if !self.pending.is_empty() && (end >= length || !self.recording()) {
+            let mut layer = std::mem::take(&mut self.pending);
+            self.pending = Vec::with_capacity(layer.capacity());
+            // the first pass sets the loop going, overdubs go on to the next.
+            if self.state == LooperState::Recording { self.state = LooperState::Playing; }
+            // notes still held end with the loop, ahead of what starts at
+            // its top, and go on being held into the next pass.
+            let mut open = [None; 128];
+            layer.iter().for_each(|n| if let Some(v) = open.get_mut(n.note as usize) { *v = n.on.then_some(n.velocity) });
+            let held = (0..128u8).filter_map(|note| open[note as usize].map(|velocity| (note, velocity)));
+            let ends: Vec<LoopNote> = held.clone().map(|(note, _)| LoopNote { beat: 0.0, note, velocity: 0.0, on: false }).collect();
+            if self.recording() {
+                self.pending.extend(held.map(|(note, velocity)| LoopNote { beat: 0.0, note, velocity, on: true }));
+            }
+            layer.splice(0..0, ends);
+            layer.sort_by(|a, b| a.beat.total_cmp(&b.beat));
+            self.layers.push(layer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod looper_tests {
+    use super::*;
+
+    fn played(looper: &mut Looper, from: f64, to: f64) -> Vec<u8> {
+        let mut notes = Vec::new();
+        looper.tick(from, to, 4.0, |n| notes.push(n.note));
+        notes
+    }
+
+    #[test]
+    fn test_record_and_overdub() {
+        let mut looper = Looper::new();
+        assert_eq!(looper.record(), LooperState::Recording);
+        looper.note(0.5, 4.0, 60, 1.0, true);
+        looper.note(1.0, 4.0, 60, 0.0, false);
+        // not played back during the pass it was played in.
+        assert!(played(&mut looper, 0.4, 1.5).is_empty());
+        assert!(played(&mut looper, 1.5, 4.1).is_empty());
+        assert_eq!((looper.layers(), looper.state()), (1, LooperState::Playing));
+        assert_eq!(played(&mut looper, 4.1, 4.6), vec![60]);
+
+        // overdubbing keeps the loop playing and adds a layer.
+        assert_eq!(looper.record(), LooperState::Overdubbing);
+        looper.note(6.0, 4.0, 64, 1.0, true);
+        assert_eq!(played(&mut looper, 4.6, 6.5), vec![60]);
+        assert_eq!(looper.record(), LooperState::Playing);
+        assert!(played(&mut looper, 6.5, 7.0).is_empty());
+        // the 64 left held is let go at the top.
+        assert_eq!(played(&mut looper, 7.0, 10.5), vec![60, 60, 64, 64]);
+        assert_eq!(looper.layers(), 2);
+
+        assert!(looper.undo());
+        assert_eq!(played(&mut looper, 10.5, 14.5), vec![60, 60]);
+        looper.stop();
+        assert!(played(&mut looper, 14.0, 18.0).is_empty());
+    }
+
+    #[test]
+    fn test_notes_across_the_top() {
+        let mut looper = Looper::new();
+        looper.record();
+        looper.note(3.9, 4.0, 60, 1.0, true);
+        looper.note(4.2, 4.0, 60, 0.0, false);
+        looper.record();
+        played(&mut looper, 3.95, 4.3);
+        let mut notes = Vec::new();
+        looper.tick(7.8, 8.25, 4.0, |n| notes.push(n.on));
+        assert_eq!(notes, vec![true, false]);
+    }
+
+    #[test]
+    fn test_held_across_the_end() {
+        let mut looper = Looper::new();
+        looper.record();
+        looper.note(3.5, 4.0, 60, 1.0, true);
+        played(&mut looper, 3.4, 4.1);
+        assert_eq!(looper.state(), LooperState::Playing);
+        // the note held over the end is released at the top.
+        let mut notes = Vec::new();
+        looper.tick(4.1, 8.1, 4.0, |n| notes.push((n.beat, n.on)));
+        assert_eq!(notes, vec![(3.5, true), (0.0, false)]);
+
+        // overdubbing, it goes on into the next pass until let go.
+        looper.record();
+        looper.note(9.0, 4.0, 64, 1.0, true);
+        played(&mut looper, 8.1, 12.1);
+        looper.note(12.5, 4.0, 64, 0.0, false);
+        looper.record();
+        played(&mut looper, 12.5, 16.1);
+        let mut notes = Vec::new();
+        looper.tick(16.1, 20.1, 4.0, |n| notes.push((n.note, n.beat, n.on)));
+        assert!(notes.contains(&(64, 0.0, false)) && notes.contains(&(64, 0.0, true)) && notes.contains(&(64, 0.5, false)));
+        assert_eq!(looper.layers(), 3);
+    }
+}
@@ -0,0 +1,96 @@
+//! Macros module.
+//!
+//! a few knobs that each turn several parameters at once, every one over a
+//! range of its own and along a curve. a macro is a parameter itself, so
+//! keys, midi learn and undo reach it like any other.
+//!
+
+use serde::{Serialize, Deserialize};
+
+use crate::audio::params::ParamId;
+
+pub const MACROS: usize = 4;
+
+// how the travel of the macro is spread over the range of a target: evenly,
+// slow then fast, or fast then slow.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum MacroCurve { #[default] Linear, Exponential, Logarithmic }
+
+impl MacroCurve {
+    pub fn apply(self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        match self {
+            MacroCurve::Linear => x,
+            MacroCurve::Exponential => x * x,
+            MacroCurve::Logarithmic => x.sqrt(),
+        }
+    }
+}
+
+// a parameter moved by a macro, from and to normalized values of it. `to`
+// may be below `from` to turn the parameter down.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct MacroTarget {
+    pub param: ParamId,
+    #[serde(default)]
+    pub from: f32,
+    #[serde(default = "default_to")]
+    pub to: f32,
+    #[serde(default)]
+    pub curve: MacroCurve,
+}
+
+fn default_to() -> f32 { 1.0 }
+
+impl MacroTarget {
+    /// Normalized value of the parameter with the macro at `value`.
+    pub fn at(&self, value: f32) -> f32 { self.from + (self.to - self.from) * self.curve.apply(value) }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Macro {
+    #[serde(default)]
+    pub value: f32,
+    #[serde(default)]
+    pub targets: Vec<MacroTarget>,
+}
+
+impl Macro {
+    /// Adds `param` as a target, moving from where it is now to the far end
+    /// of its range, or takes it away if it's one already. true if added.
+    pub fn toggle_target(&mut self, param: ParamId, current: f32) -> bool {
+        if let Some(i) = self.targets.iter().position(|t| t.param == param) {
+            self.targets.remove(i);
+            return false;
+        }
+        let to = if current < 0.5 { 1.0 } else { 0.0 };
+        // the parameter stays put until the macro is turned.
+        let from = current - (to - current) * self.value / (1.0 - self.value).max(1e-3);
+        self.targets.push(MacroTarget { param, from: from.clamp(-1.0, 2.0), to, curve: MacroCurve::Linear });
+        true
+    }
+}
+
+pub fn default_macros() -> Vec<Macro> { vec![Macro::default(); MACROS] }
+
+#[cfg(test)]
+mod macros_tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_and_curves() {
+        let target = MacroTarget { param: ParamId::Cutoff, from: 0.8, to: 0.2, curve: MacroCurve::Exponential };
+        let expected = [0.8, 0.8 - 0.6 * 0.25, 0.2];
+        assert!([0.0, 0.5, 1.0].iter().zip(expected).all(|(x, y)| (target.at(*x) - y).abs() < 1e-6));
+        assert_eq!(MacroCurve::Logarithmic.apply(0.25), 0.5);
+
+        let mut knob = Macro::default();
+        assert!(knob.toggle_target(ParamId::Cutoff, 0.7));
+        assert_eq!((knob.targets[0].from, knob.targets[0].to), (0.7, 0.0));
+        assert!(!knob.toggle_target(ParamId::Cutoff, 0.7) && knob.targets.is_empty());
+        // assigned halfway up, the parameter is where it was.
+        knob.value = 0.5;
+        knob.toggle_target(ParamId::Sustain, 0.2);
+        assert!((knob.targets[0].at(0.5) - 0.2).abs() < 1e-6);
+    }
+}
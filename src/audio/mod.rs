@@ -1,4 +1,38 @@
+pub mod arpeggiator;
 
 
+pub mod capture;
+pub mod chord;
+pub mod convolution;
+pub mod device;
+pub mod drums;
+pub mod effects;
+pub mod expr;
+pub mod filter;
+pub mod fm;
+#[cfg(test)]
+mod golden_tests;
+pub mod generator;
+pub mod glide;
+pub mod history;
 pub mod instrument;
+#[cfg(feature = "jack")]
+pub mod jack;
+pub mod looper;
+pub mod macros;
+pub mod modulation;
+pub mod mutate;
+pub mod oscillators;
+pub mod params;
+pub mod parts;
+pub mod performance;
+pub mod recorder;
+pub mod sampler;
+pub mod scale;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod smooth;
+pub mod tempo;
+pub mod transport;
+pub mod unison;
 pub mod waves;
\ No newline at end of file
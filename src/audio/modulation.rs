@@ -0,0 +1,121 @@
+//! Modulation module.
+//!
+//! a modulation matrix is a list of routes from a source (lfo, envelope,
+//! velocity, note) to an instrument parameter. routes are summed per
+//! destination, in the unit of that destination.
+//!
+
+use serde::{Serialize, Deserialize};
+
+use crate::audio::waves::Lfo;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum ModSource { Lfo(usize), Envelope, Velocity, Note, ModWheel }
+
+// pitch in semitones, cutoff in octaves, resonance in q, amplitude and
+// volume as a gain offset, attack in octaves of the attack time, wave
+// position as an offset of the wavetable position, pulse width as an offset
+// of the share of the cycle the pulse is high, ring modulation as an offset
+// of its amount.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum ModDestination { Pitch, Amplitude, Cutoff, Resonance, Volume, Attack, WavePosition, PulseWidth, RingMod }
+
+impl ModDestination {
+    pub const COUNT: usize = 9;
+    fn index(self) -> usize { self as usize }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct ModRoute { pub source: ModSource, pub destination: ModDestination, pub amount: f32 }
+
+impl ModRoute {
+    pub fn new(source: ModSource, destination: ModDestination, amount: f32) -> ModRoute {
+        ModRoute { source, destination, amount }
+    }
+}
+
+// values of the per-note sources at the sample being generated.
+pub struct ModInputs<'a> {
+    pub t: f32,
+    pub lfos: &'a [Lfo],
+    pub envelope: f32,
+    pub velocity: f32,
+    // midi note number.
+    pub note: f32,
+    pub mod_wheel: f32,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ModValues([f32; ModDestination::COUNT]);
+
+impl ModValues {
+    pub fn get(&self, destination: ModDestination) -> f32 { self.0[destination.index()] }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ModMatrix { routes: Vec<ModRoute> }
+
+impl ModMatrix {
+    pub fn new() -> ModMatrix { ModMatrix { routes: Vec::new() } }
+
+    pub fn add_route(&mut self, route: ModRoute) -> usize { self.routes.push(route); self.routes.len() - 1 }
+
+    pub fn remove_route(&mut self, index: usize) -> Option<ModRoute> {
+        if index < self.routes.len() { Some(self.routes.remove(index)) } else { None }
+    }
+
+    pub fn routes(&self) -> &[ModRoute] { &self.routes }
+    pub fn set_routes(&mut self, routes: &[ModRoute]) { self.routes = routes.to_vec() }
+    pub fn is_empty(&self) -> bool { self.routes.is_empty() }
+
+    pub fn evaluate(&self, inputs: &ModInputs) -> ModValues {
+        let mut values = ModValues::default();
+        for route in &self.routes {
+            let source = match route.source {
+                ModSource::Lfo(i) => inputs.lfos.get(i).map_or(0.0, |l| l.value(inputs.t)),
+                ModSource::Envelope => inputs.envelope,
+                ModSource::Velocity => inputs.velocity,
+                // centered on middle C, one unit per octave.
+                ModSource::Note => (inputs.note - 60.0) / 12.0,
+                ModSource::ModWheel => inputs.mod_wheel,
+            };
+            values.0[route.destination.index()] += source * route.amount;
+        }
+        values
+    }
+}
+
+pub fn freq_to_note(freq: f32) -> f32 { 69.0 + 12.0 * (freq.max(1e-3) / 440.0).log2() }
+pub fn note_to_freq(note: f32) -> f32 { 440.0 * 2f32.powf((note - 69.0) / 12.0) }
+
+#[cfg(test)]
+mod modulation_tests {
+    use super::*;
+    use crate::audio::waves::{LfoShape, LfoDestination};
+
+    #[test]
+    fn test_routes_sum_per_destination() {
+        let mut m = ModMatrix::new();
+        m.add_route(ModRoute::new(ModSource::Envelope, ModDestination::Cutoff, 2.0));
+        m.add_route(ModRoute::new(ModSource::Note, ModDestination::Cutoff, 1.0));
+        m.add_route(ModRoute::new(ModSource::Lfo(0), ModDestination::Pitch, 0.5));
+        m.add_route(ModRoute::new(ModSource::Lfo(3), ModDestination::Pitch, 1.0));
+
+        let lfos = [Lfo::new(1.0, 1.0, LfoShape::Square, LfoDestination::Pitch)];
+        let inputs = ModInputs { t: 0.0, lfos: &lfos, envelope: 0.5, velocity: 1.0, note: 72.0, mod_wheel: 0.0 };
+        let v = m.evaluate(&inputs);
+        assert_eq!(v.get(ModDestination::Cutoff), 2.0);
+        assert_eq!(v.get(ModDestination::Pitch), 0.5);
+        assert_eq!(v.get(ModDestination::Volume), 0.0);
+
+        m.remove_route(0);
+        assert_eq!(m.evaluate(&inputs).get(ModDestination::Cutoff), 1.0);
+    }
+
+    #[test]
+    fn test_freq_to_note() {
+        assert!((freq_to_note(440.0) - 69.0).abs() < 1e-4);
+        assert!((freq_to_note(261.63) - 60.0).abs() < 1e-2);
+        assert!((note_to_freq(freq_to_note(130.81)) - 130.81).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,106 @@
+//! Mutate module.
+//!
+//! the sections of the patch a random roll or a mutation may touch. a
+//! locked section is kept as it is, and a mutation moves the parameters
+//! of the others a little from where they are rather than anywhere.
+//!
+
+use rand::Rng;
+
+use crate::audio::params::ParamId;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Section { Oscillator, Envelope, Filter }
+
+impl Section {
+    pub const ALL: [Section; 3] = [Section::Oscillator, Section::Envelope, Section::Filter];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Oscillator => "oscillator",
+            Section::Envelope => "envelope",
+            Section::Filter => "filter",
+        }
+    }
+
+    // the parameters of the section a mutation moves.
+    pub fn params(self) -> &'static [ParamId] {
+        match self {
+            Section::Oscillator => &[ParamId::PulseWidth, ParamId::OscMix, ParamId::RingMod, ParamId::SubLevel, ParamId::NoiseLevel],
+            Section::Envelope => &[ParamId::Attack, ParamId::Decay, ParamId::Sustain, ParamId::Release],
+            Section::Filter => &[
+                ParamId::Cutoff, ParamId::Resonance, ParamId::FilterDrive, ParamId::KeyTrack, ParamId::FilterAttack,
+                ParamId::FilterDecay, ParamId::FilterSustain, ParamId::FilterRelease, ParamId::FilterEnvAmount,
+            ],
+        }
+    }
+
+    fn bit(self) -> u8 { 1 << Section::ALL.iter().position(|s| *s == self).unwrap_or_default() }
+}
+
+// the sections kept as they are, one bit each.
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub struct Locks(u8);
+
+impl Locks {
+    pub fn locked(self, section: Section) -> bool { self.0 & section.bit() != 0 }
+
+    pub fn set(&mut self, section: Section, locked: bool) {
+        self.0 = if locked { self.0 | section.bit() } else { self.0 & !section.bit() };
+    }
+
+    // every combination in turn, from none locked to all of them.
+    pub fn cycle(self) -> Locks { Locks((self.0 + 1) % (1 << Section::ALL.len())) }
+
+    pub fn name(self) -> String {
+        let locked: Vec<&str> = Section::ALL.into_iter().filter(|s| self.locked(*s)).map(Section::name).collect();
+        if locked.is_empty() { String::from("nothing") } else { locked.join(", ") }
+    }
+}
+
+// sections by name, like `envelope,filter`.
+impl std::str::FromStr for Locks {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Locks, String> {
+        let mut locks = Locks::default();
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let section = Section::ALL.into_iter().find(|s| s.name() == name)
+                .ok_or_else(|| format!("invalid section '{}', expected oscillator, envelope or filter", name))?;
+            locks.set(section, true);
+        }
+        Ok(locks)
+    }
+}
+
+// how far a mutation moves a parameter at most, as a share of its range.
+pub const AMOUNTS: [f32; 4] = [0.05, 0.1, 0.2, 0.4];
+
+/// A normalized value moved by up to `amount` either way.
+pub fn mutate<R: Rng + ?Sized>(rng: &mut R, x: f32, amount: f32) -> f32 {
+    let amount = amount.clamp(0.0, 1.0);
+    if amount == 0.0 { return x; }
+    (x + rng.gen_range(-amount..=amount)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod mutate_tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_locks_and_mutate() {
+        let locks: Locks = "envelope, filter".parse().unwrap();
+        assert!(!locks.locked(Section::Oscillator) && locks.locked(Section::Envelope) && locks.locked(Section::Filter));
+        assert_eq!(locks.name(), "envelope, filter");
+        assert!("amp".parse::<Locks>().is_err());
+        let names: Vec<String> = std::iter::successors(Some(Locks::default()), |l| Some(l.cycle())).take(9).map(|l| l.name()).collect();
+        assert_eq!(names[1..4], ["oscillator", "envelope", "oscillator, envelope"]);
+        assert_eq!((names[7].as_str(), names[8].as_str()), ("oscillator, envelope, filter", "nothing"));
+
+        let mut rng = StdRng::seed_from_u64(1);
+        assert!((0..100).all(|_| (mutate(&mut rng, 0.5, 0.1) - 0.5).abs() <= 0.1));
+        assert!((0..100).all(|_| mutate(&mut rng, 0.98, 0.4) <= 1.0));
+        assert_eq!(mutate(&mut rng, 0.3, 0.0), 0.3);
+    }
+}
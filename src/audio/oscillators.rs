@@ -0,0 +1,158 @@
+//! Oscillators module.
+//!
+//! besides the main oscillator, each voice has two more, with their own
+//! waveform, tuning and level. `mix` crossfades from the main oscillator
+//! alone (0) to the others alone (1), at zero they are not generated.
+//! `ring` crossfades that mix to the main oscillator times the second one,
+//! for metallic and bell tones.
+//!
+
+use serde::{Serialize, Deserialize};
+
+use crate::audio::waves::{BlepShape, Oscillator, OscillatorDesc, PolyBlepWave};
+
+// oscillators after the main one, numbered 2 and 3 in the ui.
+pub const EXTRA: usize = 2;
+
+/// One of the extra oscillators, `coarse` semitones and `fine` cents off
+/// the note.
+pub struct ExtraOscillator { pub oscillator: Oscillator, pub coarse: f32, pub fine: f32, pub level: f32 }
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ExtraOscillatorDesc { pub oscillator: OscillatorDesc, pub coarse: f32, pub fine: f32, pub level: f32 }
+
+impl ExtraOscillator {
+    fn new(level: f32) -> ExtraOscillator {
+        ExtraOscillator { oscillator: Oscillator::new(Box::new(PolyBlepWave::new(BlepShape::Saw))), coarse: 0.0, fine: 0.0, level }
+    }
+
+    // coarse tuning moves in whole semitones.
+    pub fn ratio(&self) -> f32 { 2f32.powf((self.coarse.round() + self.fine / 100.0) / 12.0) }
+
+    pub fn desc(&self) -> ExtraOscillatorDesc {
+        ExtraOscillatorDesc { oscillator: self.oscillator.desc(), coarse: self.coarse, fine: self.fine, level: self.level }
+    }
+
+    pub fn apply_desc(&mut self, d: &ExtraOscillatorDesc) {
+        self.oscillator.apply_desc(&d.oscillator);
+        (self.coarse, self.fine, self.level) = (d.coarse, d.fine, d.level);
+    }
+}
+
+pub struct VoiceOscillators { pub mix: f32, pub ring: f32, pub extra: [ExtraOscillator; EXTRA], ring_offset: f32 }
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct VoiceOscillatorsDesc {
+    pub mix: f32,
+    #[serde(default)]
+    pub ring: f32,
+    pub extra: [ExtraOscillatorDesc; EXTRA],
+}
+
+impl Default for VoiceOscillators {
+    // the third oscillator starts silent, so turning up the mix gives two.
+    fn default() -> Self {
+        VoiceOscillators { mix: 0.0, ring: 0.0, extra: [ExtraOscillator::new(1.0), ExtraOscillator::new(0.0)], ring_offset: 0.0 }
+    }
+}
+
+impl Default for VoiceOscillatorsDesc {
+    fn default() -> Self { VoiceOscillators::default().desc() }
+}
+
+impl VoiceOscillators {
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.extra.iter_mut().for_each(|e| e.oscillator.sample_rate = sample_rate);
+    }
+
+    // the same modulation as the main oscillator gets, and an offset of
+    // the ring modulation.
+    pub fn modulate(&mut self, position: f32, width: f32, ring: f32) {
+        self.ring_offset = ring;
+        for e in self.extra.iter_mut() {
+            e.oscillator.otf.modulate_position(position);
+            e.oscillator.otf.modulate_width(width);
+        }
+    }
+
+    /// Mixes the extra oscillators into `out`, which holds the main one,
+    /// for a voice at frequencies `freq`. `detuned` and `scratch` are
+    /// overwritten.
+    pub fn mix_block(&mut self, phases: &mut [f64; EXTRA], t: &[f32], freq: &[f32], out: &mut [f32], detuned: &mut [f32], scratch: &mut [f32]) {
+        let mix = self.mix.clamp(0.0, 1.0);
+        let ring = (self.ring + self.ring_offset).clamp(0.0, 1.0);
+        if mix == 0.0 && ring == 0.0 { return; }
+        for (k, (e, phase)) in self.extra.iter_mut().zip(phases.iter_mut()).enumerate() {
+            let gain = (1.0 - ring) * mix * e.level;
+            // the second oscillator is always needed for the ring modulation.
+            if gain == 0.0 && (k > 0 || ring == 0.0) { continue; }
+            let ratio = e.ratio();
+            detuned.iter_mut().zip(freq).for_each(|(d, f)| *d = f * ratio);
+            e.oscillator.gen_block(phase, t, detuned, scratch);
+            match k {
+                0 => {
+                    let dry = (1.0 - ring) * (1.0 - mix);
+                    out.iter_mut().zip(scratch.iter()).for_each(|(x, y)| *x = dry * *x + gain * y + ring * *x * y);
+                },
+                _ => out.iter_mut().zip(scratch.iter()).for_each(|(x, y)| *x += gain * y),
+            }
+        }
+    }
+
+    pub fn desc(&self) -> VoiceOscillatorsDesc {
+        VoiceOscillatorsDesc { mix: self.mix, ring: self.ring, extra: [self.extra[0].desc(), self.extra[1].desc()] }
+    }
+
+    pub fn apply_desc(&mut self, d: &VoiceOscillatorsDesc) {
+        (self.mix, self.ring) = (d.mix, d.ring);
+        self.extra.iter_mut().zip(&d.extra).for_each(|(e, d)| e.apply_desc(d));
+    }
+}
+
+#[cfg(test)]
+mod oscillators_tests {
+    use super::*;
+
+    #[test]
+    fn test_mix_block() {
+        // a second of a 100 Hz square at 8 kHz, against one an octave up.
+        let n = 8000;
+        let mut oscillators = VoiceOscillators::default();
+        oscillators.set_sample_rate(8000.0);
+        oscillators.extra[0].oscillator.set_waveform(BlepShape::Pulse(0.5));
+        oscillators.extra[0].coarse = 12.0;
+        let (t, freq) = (vec![0.0; n], vec![100.0; n]);
+        let (mut detuned, mut scratch) = (vec![0.0; n], vec![0.0; n]);
+        let mut mixed = |mix: f32| {
+            oscillators.mix = mix;
+            let mut out = vec![1.0; n];
+            oscillators.mix_block(&mut [0.0; EXTRA], &t, &freq, &mut out, &mut detuned, &mut scratch);
+            out
+        };
+        assert!(mixed(0.0).iter().all(|x| *x == 1.0));
+        let crossings = mixed(1.0).windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count();
+        assert!((399..=401).contains(&crossings), "{}", crossings);
+        // halfway, half the main oscillator and half the square.
+        let half = mixed(0.5);
+        assert!((half[10] - 1.0).abs() < 1e-6 && half[30].abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_ring_modulation() {
+        let n = 800;
+        let mut oscillators = VoiceOscillators { ring: 1.0, ..VoiceOscillators::default() };
+        oscillators.set_sample_rate(8000.0);
+        oscillators.extra[0].oscillator.set_waveform(BlepShape::Pulse(0.5));
+        let (t, freq) = (vec![0.0; n], vec![100.0; n]);
+        let (mut detuned, mut scratch) = (vec![0.0; n], vec![0.0; n]);
+        // the main oscillator times the second, with no mix needed.
+        let mut out = vec![0.5; n];
+        oscillators.mix_block(&mut [0.0; EXTRA], &t, &freq, &mut out, &mut detuned, &mut scratch);
+        assert!((out[20] - 0.5).abs() < 1e-6 && (out[60] + 0.5).abs() < 1e-6);
+        // halfway, modulated down from full.
+        oscillators.modulate(0.0, 0.0, -0.5);
+        let mut out = vec![0.5; n];
+        oscillators.mix_block(&mut [0.0; EXTRA], &t, &freq, &mut out, &mut detuned, &mut scratch);
+        assert!((out[20] - 0.5).abs() < 1e-6 && out[60].abs() < 1e-6);
+    }
+}
@@ -0,0 +1,267 @@
+//! Params module.
+//!
+//! the values of the instrument that can be tweaked while it plays, each
+//! under an id along with the range it moves in. controllers (midi, the
+//! ui, automation) can work in normalized 0..1 values and leave the
+//! scaling to the parameter.
+//!
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Copy, Clone)]
+pub enum ParamId {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Cutoff,
+    Resonance,
+    FilterDrive,
+    KeyTrack,
+    // the filter envelope, and how many octaves it moves the cutoff.
+    FilterAttack,
+    FilterDecay,
+    FilterSustain,
+    FilterRelease,
+    FilterEnvAmount,
+    Volume,
+    Glide,
+    UnisonDetune,
+    UnisonSpread,
+    NoiseLevel,
+    NoiseDecay,
+    SubLevel,
+    PulseWidth,
+    FmFeedback,
+    // level of fm operator 1 to 4, numbered from 0.
+    OperatorLevel(u8),
+    // mix of the main oscillator against the others, and the tuning and
+    // level of oscillator 2 and 3, numbered from 0.
+    OscMix,
+    // amount of oscillator 1 times oscillator 2.
+    RingMod,
+    // the wavefolder of each voice.
+    FoldAmount,
+    FoldSymmetry,
+    FoldMix,
+    // the overdrive of each voice, its curve stepped through by number.
+    DriveCurve,
+    DriveAmount,
+    DriveOutput,
+    DriveMix,
+    OscCoarse(u8),
+    OscFine(u8),
+    OscLevel(u8),
+    // the tempo of the transport.
+    #[serde(alias = "ArpBpm")]
+    Bpm,
+    // how late the off-beat steps come, 1 being half a step.
+    Swing,
+    ArpGate,
+    BendRange,
+    InputGain,
+    // the bands of the master eq, low, mid and high numbered from 0.
+    EqFreq(u8),
+    EqGain(u8),
+    EqQ(u8),
+    // the master compressor.
+    CompThreshold,
+    CompRatio,
+    CompAttack,
+    CompRelease,
+    CompMakeup,
+    // the convolution reverb, its pre-delay in seconds.
+    ConvMix,
+    ConvPredelay,
+    // free running rates in Hz of the tremolo and the auto pan.
+    TremoloRate,
+    TremoloDepth,
+    PanRate,
+    PanDepth,
+    // ceiling of the output limiter in dB, and its release.
+    LimiterCeiling,
+    LimiterRelease,
+    Polyphony,
+    // macro knob 1 to 4, numbered from 0.
+    Macro(u8),
+}
+
+// how normalized values map onto the range. exponential ranges give each
+// octave (or each doubling of a time) the same share of the travel, and
+// need a minimum above zero.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum ParamCurve { Linear, Exponential }
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Param {
+    pub id: ParamId,
+    pub name: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    pub curve: ParamCurve,
+}
+
+impl Param {
+    pub fn clamp(&self, value: f32) -> f32 { value.clamp(self.min, self.max) }
+
+    /// Position of `value` in the range, 0 at the minimum and 1 at the
+    /// maximum.
+    pub fn normalize(&self, value: f32) -> f32 {
+        let value = self.clamp(value);
+        let x = match self.curve {
+            ParamCurve::Linear => (value - self.min) / (self.max - self.min),
+            ParamCurve::Exponential => (value / self.min).ln() / (self.max / self.min).ln(),
+        };
+        x.clamp(0.0, 1.0)
+    }
+
+    pub fn denormalize(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        let value = match self.curve {
+            ParamCurve::Linear => self.min + (self.max - self.min) * x,
+            ParamCurve::Exponential => self.min * (self.max / self.min).powf(x),
+        };
+        self.clamp(value)
+    }
+}
+
+impl ParamId {
+    pub const ALL: [ParamId; 73] = [
+        ParamId::Attack, ParamId::Decay, ParamId::Sustain, ParamId::Release,
+        ParamId::Cutoff, ParamId::Resonance, ParamId::FilterDrive, ParamId::KeyTrack, ParamId::FilterAttack, ParamId::FilterDecay,
+        ParamId::FilterSustain, ParamId::FilterRelease, ParamId::FilterEnvAmount, ParamId::Volume, ParamId::Glide,
+        ParamId::UnisonDetune, ParamId::UnisonSpread, ParamId::NoiseLevel, ParamId::NoiseDecay, ParamId::SubLevel, ParamId::PulseWidth,
+        ParamId::FmFeedback, ParamId::OperatorLevel(0), ParamId::OperatorLevel(1), ParamId::OperatorLevel(2),
+        ParamId::OperatorLevel(3), ParamId::OscMix, ParamId::RingMod, ParamId::FoldAmount, ParamId::FoldSymmetry, ParamId::FoldMix,
+        ParamId::DriveCurve, ParamId::DriveAmount, ParamId::DriveOutput, ParamId::DriveMix, ParamId::OscCoarse(0), ParamId::OscFine(0), ParamId::OscLevel(0),
+        ParamId::OscCoarse(1), ParamId::OscFine(1), ParamId::OscLevel(1), ParamId::Bpm, ParamId::Swing, ParamId::ArpGate, ParamId::BendRange, ParamId::InputGain,
+        ParamId::EqFreq(0), ParamId::EqGain(0), ParamId::EqQ(0), ParamId::EqFreq(1), ParamId::EqGain(1), ParamId::EqQ(1),
+        ParamId::EqFreq(2), ParamId::EqGain(2), ParamId::EqQ(2), ParamId::CompThreshold, ParamId::CompRatio,
+        ParamId::CompAttack, ParamId::CompRelease, ParamId::CompMakeup, ParamId::ConvMix, ParamId::ConvPredelay, ParamId::TremoloRate, ParamId::TremoloDepth,
+        ParamId::PanRate, ParamId::PanDepth, ParamId::LimiterCeiling, ParamId::LimiterRelease,
+        ParamId::Polyphony, ParamId::Macro(0), ParamId::Macro(1), ParamId::Macro(2), ParamId::Macro(3),
+    ];
+
+    pub fn param(self) -> Param {
+        use ParamCurve::*;
+        let (name, min, max, default, curve) = match self {
+            ParamId::Attack => ("attack", 0.001, 10.0, 1.0, Exponential),
+            ParamId::Decay => ("decay", 0.001, 10.0, 1.0, Exponential),
+            ParamId::Sustain => ("sustain", 0.0, 1.0, 0.2, Linear),
+            ParamId::Release => ("release", 0.001, 10.0, 1.0, Exponential),
+            ParamId::Cutoff => ("cutoff", 20.0, 20000.0, 20000.0, Exponential),
+            ParamId::Resonance => ("resonance", 0.1, 20.0, std::f32::consts::FRAC_1_SQRT_2, Exponential),
+            ParamId::FilterDrive => ("filter drive", 0.1, 10.0, 1.0, Exponential),
+            ParamId::KeyTrack => ("key tracking", 0.0, 1.0, 0.0, Linear),
+            ParamId::FilterAttack => ("filter attack", 0.001, 10.0, 0.005, Exponential),
+            ParamId::FilterDecay => ("filter decay", 0.001, 10.0, 0.3, Exponential),
+            ParamId::FilterSustain => ("filter sustain", 0.0, 1.0, 0.0, Linear),
+            ParamId::FilterRelease => ("filter release", 0.001, 10.0, 0.3, Exponential),
+            ParamId::FilterEnvAmount => ("filter env amount", -8.0, 8.0, 0.0, Linear),
+            ParamId::Volume => ("volume", 0.0, 2.0, 1.0, Linear),
+            ParamId::Glide => ("glide", 0.0, 2.0, 0.0, Linear),
+            ParamId::UnisonDetune => ("detune", 0.0, 100.0, 20.0, Linear),
+            ParamId::UnisonSpread => ("spread", 0.0, 1.0, 0.5, Linear),
+            ParamId::NoiseLevel => ("noise level", 0.0, 1.0, 0.0, Linear),
+            ParamId::NoiseDecay => ("noise decay", 0.0, 2.0, 0.05, Linear),
+            ParamId::SubLevel => ("sub level", 0.0, 1.0, 0.0, Linear),
+            ParamId::PulseWidth => ("pulse width", 0.05, 0.95, 0.5, Linear),
+            ParamId::FmFeedback => ("fm feedback", 0.0, 1.0, 0.0, Linear),
+            ParamId::OperatorLevel(i) => (["op 1 level", "op 2 level", "op 3 level", "op 4 level"][i.min(3) as usize], 0.0, 1.0, 1.0, Linear),
+            ParamId::OscMix => ("osc mix", 0.0, 1.0, 0.0, Linear),
+            ParamId::RingMod => ("ring mod", 0.0, 1.0, 0.0, Linear),
+            ParamId::FoldAmount => ("fold", 1.0, 10.0, 3.0, Linear),
+            ParamId::FoldSymmetry => ("fold symmetry", -1.0, 1.0, 0.0, Linear),
+            ParamId::FoldMix => ("fold mix", 0.0, 1.0, 0.0, Linear),
+            ParamId::DriveCurve => ("drive curve", 0.0, 2.0, 0.0, Linear),
+            ParamId::DriveAmount => ("drive", 0.0, 36.0, 12.0, Linear),
+            ParamId::DriveOutput => ("drive output", -24.0, 12.0, -6.0, Linear),
+            ParamId::DriveMix => ("drive mix", 0.0, 1.0, 0.0, Linear),
+            ParamId::OscCoarse(i) => (["osc 2 coarse", "osc 3 coarse"][i.min(1) as usize], -24.0, 24.0, 0.0, Linear),
+            ParamId::OscFine(i) => (["osc 2 fine", "osc 3 fine"][i.min(1) as usize], -100.0, 100.0, 0.0, Linear),
+            ParamId::OscLevel(i) => (["osc 2 level", "osc 3 level"][i.min(1) as usize], 0.0, 1.0, 1.0, Linear),
+            ParamId::Bpm => ("bpm", 20.0, 300.0, 120.0, Linear),
+            ParamId::Swing => ("swing", 0.0, 1.0, 0.0, Linear),
+            ParamId::ArpGate => ("arp gate", 0.05, 1.0, 0.5, Linear),
+            ParamId::BendRange => ("bend range", 0.0, 24.0, 2.0, Linear),
+            ParamId::InputGain => ("input gain", 0.0, 4.0, 1.0, Linear),
+            ParamId::EqFreq(i) => (["eq low freq", "eq mid freq", "eq high freq"][i.min(2) as usize], 20.0, 20000.0, [120.0, 1000.0, 8000.0][i.min(2) as usize], Exponential),
+            ParamId::EqGain(i) => (["eq low gain", "eq mid gain", "eq high gain"][i.min(2) as usize], -18.0, 18.0, 0.0, Linear),
+            ParamId::EqQ(i) => (["eq low q", "eq mid q", "eq high q"][i.min(2) as usize], 0.1, 10.0, [std::f32::consts::FRAC_1_SQRT_2, 1.0, std::f32::consts::FRAC_1_SQRT_2][i.min(2) as usize], Exponential),
+            ParamId::CompThreshold => ("comp threshold", -60.0, 0.0, -18.0, Linear),
+            ParamId::CompRatio => ("comp ratio", 1.0, 20.0, 4.0, Exponential),
+            ParamId::CompAttack => ("comp attack", 0.0001, 0.5, 0.01, Exponential),
+            ParamId::CompRelease => ("comp release", 0.01, 2.0, 0.15, Exponential),
+            ParamId::CompMakeup => ("comp makeup", 0.0, 24.0, 0.0, Linear),
+            ParamId::ConvMix => ("convolution mix", 0.0, 1.0, 0.3, Linear),
+            ParamId::ConvPredelay => ("convolution predelay", 0.0, 0.5, 0.02, Linear),
+            ParamId::TremoloRate => ("tremolo rate", 0.05, 20.0, 4.0, Exponential),
+            ParamId::TremoloDepth => ("tremolo depth", 0.0, 1.0, 0.5, Linear),
+            ParamId::PanRate => ("pan rate", 0.05, 20.0, 0.25, Exponential),
+            ParamId::PanDepth => ("pan depth", 0.0, 1.0, 0.8, Linear),
+            ParamId::LimiterCeiling => ("limiter ceiling", -24.0, 0.0, -2.0, Linear),
+            ParamId::LimiterRelease => ("limiter release", 0.01, 2.0, 0.1, Exponential),
+            ParamId::Polyphony => ("polyphony", 1.0, 32.0, 16.0, Linear),
+            ParamId::Macro(i) => (["macro 1", "macro 2", "macro 3", "macro 4"][i.min(3) as usize], 0.0, 1.0, 0.0, Linear),
+        };
+        Param { id: self, name, min, max, default, curve }
+    }
+}
+
+// absolute controls send where the knob is, relative ones (endless
+// encoders) how far it turned, as an offset from 64.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum CcMode { #[default] Absolute, Relative }
+
+/// A midi control change bound to a parameter.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct CcMapping {
+    pub controller: u8,
+    pub param: ParamId,
+    #[serde(default)]
+    pub mode: CcMode,
+}
+
+impl CcMapping {
+    // share of the range a relative control moves per step.
+    pub const STEP: f32 = 1.0 / 128.0;
+
+    /// Normalized value of the parameter after a control change of
+    /// `value`, from `current`.
+    pub fn apply(&self, value: u8, current: f32) -> f32 {
+        match self.mode {
+            CcMode::Absolute => value as f32 / 127.0,
+            CcMode::Relative => (current + (value as f32 - 64.0) * CcMapping::STEP).clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod params_tests {
+    use super::*;
+
+    #[test]
+    fn test_normalized_roundtrip() {
+        for id in ParamId::ALL {
+            let p = id.param();
+            assert!(p.min < p.max && p.clamp(p.default) == p.default, "{}", p.name);
+            for x in [0.0, 0.25, 0.5, 1.0] {
+                assert!((p.normalize(p.denormalize(x)) - x).abs() < 1e-4, "{} at {}", p.name, x);
+            }
+        }
+        // the middle of the cutoff range is the geometric mean, 632 Hz.
+        let cutoff = ParamId::Cutoff.param();
+        assert!((cutoff.denormalize(0.5) - 632.5).abs() < 0.5);
+        assert_eq!(cutoff.denormalize(2.0), 20000.0);
+    }
+
+    #[test]
+    fn test_cc_modes() {
+        let absolute = CcMapping { controller: 74, param: ParamId::Cutoff, mode: CcMode::Absolute };
+        assert_eq!(absolute.apply(127, 0.2), 1.0);
+        let relative = CcMapping { mode: CcMode::Relative, ..absolute };
+        assert_eq!(relative.apply(66, 0.5), 0.5 + 2.0 * CcMapping::STEP);
+        assert_eq!(relative.apply(0, 0.1), 0.0);
+    }
+}
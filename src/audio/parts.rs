@@ -0,0 +1,168 @@
+//! Parts module.
+//!
+//! more instruments played next to the main one, each on a midi channel, a
+//! zone of the keyboard, or both. their voices are mixed into the main
+//! instrument before its master effects, which the parts share. a layer
+//! part plays every note of the main instrument instead, stacked on it.
+//!
+
+use serde::{Serialize, Deserialize};
+
+use crate::audio::instrument::Instrument;
+use crate::keymap::{note_name, parse_note_name};
+use crate::preset::{Patch, PresetError};
+
+// how a part is set up: the preset it plays, empty for the default patch,
+// the midi channel (from 0) and the notes it answers, its level and its
+// place in the stereo field from -1 (left) to 1. a layer plays the notes
+// of the main instrument, in its zone if it has one, detuned in cents.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PartDesc {
+    #[serde(default)]
+    pub preset: String,
+    #[serde(default)]
+    pub channel: Option<u8>,
+    #[serde(default)]
+    pub zone: Option<(u8, u8)>,
+    #[serde(default = "default_volume")]
+    pub volume: f32,
+    #[serde(default)]
+    pub pan: f32,
+    #[serde(default)]
+    pub layer: bool,
+    #[serde(default)]
+    pub detune: f32,
+}
+
+fn default_volume() -> f32 { 1.0 }
+
+impl Default for PartDesc {
+    fn default() -> Self { PartDesc { preset: String::new(), channel: None, zone: None, volume: 1.0, pan: 0.0, layer: false, detune: 0.0 } }
+}
+
+impl PartDesc {
+    pub fn name(&self) -> String {
+        let mut name = if self.preset.is_empty() { String::from("default") } else { self.preset.clone() };
+        if let Some(channel) = self.channel { name.push_str(&format!(" ch{}", channel + 1)); }
+        if let Some((low, high)) = self.zone {
+            name.push_str(&format!(" {}-{}", note_name(low as i32), note_name(high as i32)));
+        }
+        if self.layer { name.push_str(" layer"); }
+        if self.detune != 0.0 { name.push_str(&format!(" {:+}c", self.detune)); }
+        name
+    }
+
+    /// Whether the part plays a note from the midi channel, or from the
+    /// computer keyboard without one. a part answers nothing until given
+    /// a channel or a zone, a layer only the notes the main instrument
+    /// plays.
+    pub fn accepts(&self, channel: Option<u8>, note: u8) -> bool {
+        if self.layer { return false; }
+        let in_zone = self.zone.is_none_or(|(low, high)| (low..=high).contains(&note));
+        match (channel, self.channel) {
+            (Some(c), Some(part)) => c == part && in_zone,
+            (Some(_), None) | (None, _) => self.zone.is_some() && in_zone,
+        }
+    }
+
+    /// Whether the part takes the controllers and bend of the midi channel,
+    /// as it takes notes from it: the channel is its own, or it has none
+    /// but a zone.
+    pub fn listens(&self, channel: u8) -> bool {
+        !self.layer && match self.channel { Some(part) => part == channel, None => self.zone.is_some() }
+    }
+
+    // whether a layer plays a note of the main instrument.
+    pub fn layers(&self, note: u8) -> bool {
+        self.layer && self.zone.is_none_or(|(low, high)| (low..=high).contains(&note))
+    }
+
+    // left and right gains, at equal power.
+    pub fn gains(&self) -> (f32, f32) {
+        let angle = (self.pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+        let volume = self.volume.max(0.0) * std::f32::consts::SQRT_2;
+        (volume * angle.cos(), volume * angle.sin())
+    }
+}
+
+// a preset name followed by settings, like `bass,ch=2,zone=C1-B2,vol=0.8,pan=-0.3`
+// or `pad,layer,detune=7`.
+impl std::str::FromStr for PartDesc {
+    type Err = String;
+    fn from_str(s: &str) -> Result<PartDesc, String> {
+        let mut fields = s.split(',').map(str::trim);
+        let mut desc = PartDesc { preset: fields.next().unwrap_or_default().to_string(), ..PartDesc::default() };
+        for field in fields {
+            let invalid = || format!("invalid part setting '{}', expected ch=1..16, zone=C1-B2, vol=0..2, pan=-1..1, layer or detune=-100..100", field);
+            if field == "layer" { desc.layer = true; continue; }
+            let (key, value) = field.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "ch" => desc.channel = Some(value.parse::<u8>().ok().filter(|c| (1..=16).contains(c)).ok_or_else(invalid)? - 1),
+                "zone" => {
+                    let (low, high) = value.split_once('-').ok_or_else(invalid)?;
+                    let note = |n: &str| parse_note_name(n).filter(|n| (0..128).contains(n)).map(|n| n as u8);
+                    let (low, high) = note(low).zip(note(high)).ok_or_else(invalid)?;
+                    desc.zone = Some((low.min(high), low.max(high)));
+                },
+                "vol" => desc.volume = value.parse::<f32>().ok().filter(|v| (0.0..=2.0).contains(v)).ok_or_else(invalid)?,
+                "pan" => desc.pan = value.parse::<f32>().ok().filter(|p| (-1.0..=1.0).contains(p)).ok_or_else(invalid)?,
+                "detune" => desc.detune = value.parse::<f32>().ok().filter(|d| (-100.0..=100.0).contains(d)).ok_or_else(invalid)?,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(desc)
+    }
+}
+
+pub struct Part {
+    pub desc: PartDesc,
+    pub instrument: Instrument,
+}
+
+impl Part {
+    /// A part playing its preset, loaded from the presets dir.
+    pub fn new(desc: PartDesc) -> Result<Part, PresetError> {
+        let mut instrument = Instrument::new();
+        if !desc.preset.is_empty() { instrument.load_preset(&desc.preset)?; }
+        instrument.set_detune(desc.detune);
+        Ok(Part { desc, instrument })
+    }
+
+    // a part playing a patch it was saved with.
+    pub fn from_patch(desc: PartDesc, patch: &Patch) -> Part {
+        let mut instrument = Instrument::new();
+        instrument.apply_patch(patch);
+        instrument.set_detune(desc.detune);
+        Part { desc, instrument }
+    }
+}
+
+#[cfg(test)]
+mod parts_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_route() {
+        let desc: PartDesc = "bass, ch=2, zone=B2-C1, vol=0.5, pan=-1".parse().unwrap();
+        assert_eq!((desc.preset.as_str(), desc.channel, desc.zone), ("bass", Some(1), Some((24, 47))));
+        assert_eq!(desc.name(), "bass ch2 C1-B2");
+        assert!(desc.accepts(Some(1), 30) && !desc.accepts(Some(0), 30) && !desc.accepts(Some(1), 60));
+        assert!(desc.accepts(None, 30) && !desc.accepts(None, 60));
+        let (left, right) = desc.gains();
+        assert!((left - 0.5 * std::f32::consts::SQRT_2).abs() < 1e-6 && right.abs() < 1e-6);
+
+        let lead: PartDesc = "lead,ch=3".parse().unwrap();
+        assert!(lead.accepts(Some(2), 90) && !lead.accepts(None, 90));
+        assert!(!PartDesc::default().accepts(Some(0), 60));
+        let (left, right) = PartDesc::default().gains();
+        assert!((left - 1.0).abs() < 1e-6 && (right - 1.0).abs() < 1e-6);
+        assert!("x,ch=17".parse::<PartDesc>().is_err());
+        assert!("x,zone=C1".parse::<PartDesc>().is_err());
+        assert!("x,mute".parse::<PartDesc>().is_err());
+
+        let pad: PartDesc = "pad, layer, zone=C4-C5, detune=-7".parse().unwrap();
+        assert_eq!((pad.layer, pad.detune, pad.name()), (true, -7.0, String::from("pad C4-C5 layer -7c")));
+        assert!(!pad.accepts(None, 60) && pad.layers(60) && !pad.layers(50));
+        assert!(!lead.layers(90) && "x,detune=120".parse::<PartDesc>().is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Performance module.
+//!
+//! captures the notes played against the transport, whoever plays them,
+//! and writes them to a standard midi file to carry a jam over to a daw.
+//!
+
+use std::path::Path;
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+use crate::input::NoteKey;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PerformanceNote { pub beat: f64, pub note: u8, pub velocity: u8, pub on: bool }
+
+#[derive(Default)]
+pub struct Performance {
+    // the beat the capture starts from, the top of the bar it began in.
+    start: Option<f64>,
+    notes: Vec<PerformanceNote>,
+    // the note each key held plays, so it's let go even if the octave
+    // changed in between.
+    held: Vec<(NoteKey, u8)>,
+}
+
+impl Performance {
+    pub const TICKS_PER_BEAT: u16 = 480;
+
+    pub fn recording(&self) -> bool { self.start.is_some() }
+    pub fn notes(&self) -> &[PerformanceNote] { &self.notes }
+
+    /// Starts a capture over, at the bar the transport is in.
+    pub fn start(&mut self, beat: f64, beats_per_bar: u32) {
+        let bar = beats_per_bar.max(1) as f64;
+        self.start = Some((beat / bar).floor() * bar);
+        self.notes.clear();
+        self.held.clear();
+    }
+
+    // stops capturing, letting go of the notes still held.
+    pub fn stop(&mut self, beat: f64) {
+        for (_, note) in std::mem::take(&mut self.held) {
+            self.push(beat, note, 0, false);
+        }
+        self.start = None;
+    }
+
+    pub fn note_on(&mut self, key: NoteKey, beat: f64, note: u8, velocity: f32) {
+        if !self.recording() { return; }
+        if let Some(i) = self.held.iter().position(|(k, _)| *k == key) {
+            let (_, previous) = self.held.swap_remove(i);
+            self.push(beat, previous, 0, false);
+        }
+        self.held.push((key, note));
+        self.push(beat, note, (velocity * 127.0).round().clamp(1.0, 127.0) as u8, true);
+    }
+
+    pub fn note_off(&mut self, key: NoteKey, beat: f64) {
+        let Some(i) = self.held.iter().position(|(k, _)| *k == key) else { return };
+        let (_, note) = self.held.swap_remove(i);
+        self.push(beat, note, 0, false);
+    }
+
+    fn push(&mut self, beat: f64, note: u8, velocity: u8, on: bool) {
+        let Some(start) = self.start else { return };
+        self.notes.push(PerformanceNote { beat: (beat - start).max(0.0), note, velocity, on });
+    }
+
+    /// The notes as a single track midi file, at `bpm`.
+    pub fn to_smf(&self, bpm: f32) -> Smf<'static> {
+        let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(Self::TICKS_PER_BEAT.into())));
+        let micros = (60_000_000.0 / bpm.max(1.0) as f64).round() as u32;
+        let mut track = vec![TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::Tempo(micros.into())) }];
+        let mut notes = self.notes.clone();
+        // releases first on a tick, so a note struck again isn't cut short.
+        notes.sort_by(|a, b| a.beat.total_cmp(&b.beat).then(a.on.cmp(&b.on)));
+        let mut last = 0u32;
+        for n in notes {
+            let tick = (n.beat * Self::TICKS_PER_BEAT as f64).round() as u32;
+            let message = match n.on {
+                true => MidiMessage::NoteOn { key: n.note.into(), vel: n.velocity.into() },
+                false => MidiMessage::NoteOff { key: n.note.into(), vel: 0.into() },
+            };
+            track.push(TrackEvent { delta: (tick - last).into(), kind: TrackEventKind::Midi { channel: 0.into(), message } });
+            last = tick;
+        }
+        track.push(TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) });
+        smf.tracks.push(track);
+        smf
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, bpm: f32) -> std::io::Result<()> { self.to_smf(bpm).save(path) }
+}
+
+// a clock set before 1970 names it 0 rather than panicking.
+pub fn default_performance_path() -> String {
+    format!("rsynth-{}.mid", std::time::UNIX_EPOCH.elapsed().map_or(0, |d| d.as_secs()))
+}
+
+#[cfg(test)]
+mod performance_tests {
+    use super::*;
+    use crate::render::midi_events;
+    use crate::midi::MidiMessage as Message;
+
+    #[test]
+    fn test_export_reads_back() {
+        let mut performance = Performance::default();
+        let (a, b) = (NoteKey::Midi(1), NoteKey::Midi(2));
+        performance.note_on(a, 0.0, 60, 1.0);
+        assert!(performance.notes().is_empty());
+        performance.start(5.5, 4);
+        performance.note_on(a, 5.5, 60, 1.0);
+        performance.note_on(b, 6.0, 64, 0.5);
+        performance.note_off(a, 6.0);
+        performance.stop(7.0);
+        assert_eq!(performance.notes().len(), 4);
+
+        let mut bytes = Vec::new();
+        performance.to_smf(120.0).write_std(&mut bytes).unwrap();
+        let events = midi_events(&bytes).unwrap();
+        let times: Vec<f32> = events.iter().map(|e| e.time).collect();
+        // the capture starts at the top of the second bar, at 120 bpm.
+        assert_eq!(times, [0.75, 1.0, 1.0, 1.5]);
+        assert!(matches!(events[0].message, Message::NoteOn { note: 60, velocity: 127, .. }));
+        assert!(matches!(events[1].message, Message::NoteOff { note: 60, .. }));
+        assert!(matches!(events[2].message, Message::NoteOn { note: 64, velocity: 64, .. }));
+    }
+}
@@ -0,0 +1,80 @@
+//! Recorder module.
+//!
+//! writes the generated samples to a WAV file. samples are handed over
+//! to a writer thread so the audio callback never touches the disk, in
+//! buffers the writer hands back to be filled again rather than freed.
+//!
+
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+
+// buffers made up front, of samples each. one grows on the first
+// callbacks if they're larger, and keeps its size from then on.
+const BUFFERS: usize = 16;
+const BUFFER_SIZE: usize = 8192;
+
+pub struct Recorder {
+    tx: Sender<Vec<f32>>,
+    free: Receiver<Vec<f32>>,
+    writer: JoinHandle<Result<(), hound::Error>>,
+}
+
+impl Recorder {
+    pub fn start<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16) -> Result<Recorder, hound::Error> {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut wav = hound::WavWriter::create(path, spec)?;
+        let (tx, rx) = channel::<Vec<f32>>();
+        let (give_back, free) = channel::<Vec<f32>>();
+        (0..BUFFERS).for_each(|_| { let _ = give_back.send(Vec::with_capacity(BUFFER_SIZE)); });
+        let writer = std::thread::spawn(move || {
+            // the loop ends once the recorder (and its sender) is dropped.
+            for mut chunk in rx {
+                for sample in chunk.drain(..) { wav.write_sample(sample)?; }
+                let _ = give_back.send(chunk);
+            }
+            wav.finalize()
+        });
+        Ok(Recorder { tx, free, writer })
+    }
+
+    // interleaved samples, exactly as they were written to the cpal buffer.
+    // a new buffer is only made if the writer has all of them.
+    pub fn push(&self, data: &[f32]) {
+        let mut chunk = self.free.try_recv().unwrap_or_default();
+        chunk.extend_from_slice(data);
+        let _ = self.tx.send(chunk);
+    }
+
+    pub fn stop(self) -> Result<(), hound::Error> {
+        drop(self.tx);
+        self.writer.join().unwrap_or(Ok(()))
+    }
+}
+
+pub fn default_recording_path() -> String {
+    format!("rsynth-{}.wav", std::time::UNIX_EPOCH.elapsed().map_or(0, |d| d.as_secs()))
+}
+
+#[cfg(test)]
+mod recorder_tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_reused_in_order() {
+        let path = std::env::temp_dir().join(format!("rsynth-recorder-{}.wav", std::process::id()));
+        let recorder = Recorder::start(&path, 1000, 2).unwrap();
+        // more pushes than buffers, the ones handed back are filled again.
+        let chunks: Vec<Vec<f32>> = (0..BUFFERS * 3).map(|i| vec![i as f32 / 100.0; 4]).collect();
+        chunks.iter().for_each(|c| recorder.push(c));
+        recorder.stop().unwrap();
+        let samples: Vec<f32> = hound::WavReader::open(&path).unwrap().samples().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples, chunks.concat());
+    }
+}
@@ -0,0 +1,157 @@
+//! Sampler module.
+//!
+//! plays wav files mapped over ranges of midi notes. a sample is pitched
+//! by reading it faster or slower than its root note, the envelope, filter
+//! and effects apply the same as for the other engines.
+//!
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+
+use crate::audio::modulation::note_to_freq;
+use crate::audio::unison::UnisonSettings;
+use crate::audio::waves::{read_wav, Loaded};
+
+fn lowest_note() -> u8 { 0 }
+fn highest_note() -> u8 { 127 }
+
+// `root` is the midi note the file was recorded at, `low` and `high` the
+// range of notes it plays.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SampleZone {
+    pub path: PathBuf,
+    pub root: u8,
+    #[serde(default = "lowest_note")]
+    pub low: u8,
+    #[serde(default = "highest_note")]
+    pub high: u8,
+    #[serde(skip)]
+    pub sample: Loaded<Sample>,
+}
+
+impl SampleZone {
+    pub fn new(path: PathBuf, root: u8) -> SampleZone { SampleZone { path, root, low: 0, high: 127, sample: Loaded::default() } }
+    pub fn contains(&self, note: u8) -> bool { (self.low..=self.high).contains(&note) }
+
+    // decodes the wav file of `path`. keeps the last one decoded if it fails.
+    pub fn read(&mut self) -> Result<(), hound::Error> {
+        self.sample = Loaded(Some(Arc::new(Sample::load(&self.path)?)));
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct SamplerDesc { pub zones: Vec<SampleZone> }
+
+pub struct Sample { pub data: Vec<f32>, pub sample_rate: f32 }
+
+impl Sample {
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Sample, hound::Error> {
+        let (data, sample_rate) = read_wav(path)?;
+        Ok(Sample { data, sample_rate: sample_rate as f32 })
+    }
+
+    // linear interpolation, silence past the end.
+    pub fn read(&self, pos: f32) -> f32 {
+        let i = pos as usize;
+        let frac = pos.fract();
+        let at = |i: usize| self.data.get(i).copied().unwrap_or(0.0);
+        at(i) * (1.0 - frac) + at(i + 1) * frac
+    }
+
+    pub fn len(&self) -> usize { self.data.len() }
+    pub fn is_empty(&self) -> bool { self.data.is_empty() }
+}
+
+// playback position of a voice in its zone's sample, one per unison voice.
+#[derive(Debug, Clone, Copy)]
+pub struct SamplerVoice { pub zone: usize, pub pos: [f32; UnisonSettings::MAX_VOICES as usize] }
+
+#[derive(Default)]
+pub struct Sampler {
+    desc: SamplerDesc,
+    samples: Vec<Arc<Sample>>,
+}
+
+impl Sampler {
+    pub fn new() -> Sampler { Sampler::default() }
+
+    pub fn load(desc: &SamplerDesc) -> Result<Sampler, hound::Error> {
+        let mut sampler = Sampler::new();
+        for zone in &desc.zones { sampler.add_zone(zone.clone())?; }
+        Ok(sampler)
+    }
+
+    // a zone not read is decoded here.
+    pub fn add_zone(&mut self, zone: SampleZone) -> Result<(), hound::Error> {
+        self.samples.push(match &zone.sample.0 { Some(sample) => sample.clone(), None => Arc::new(Sample::load(&zone.path)?) });
+        self.desc.zones.push(zone);
+        Ok(())
+    }
+
+    pub fn desc(&self) -> &SamplerDesc { &self.desc }
+    pub fn zones(&self) -> usize { self.desc.zones.len() }
+
+    /// Zone that plays `note`, the first one whose range contains it or
+    /// else the one with the closest root.
+    pub fn zone(&self, note: f32) -> Option<usize> {
+        let n = note.round().clamp(0.0, 127.0) as u8;
+        let zones = &self.desc.zones;
+        zones.iter().position(|z| z.contains(n)).or_else(|| {
+            (0..zones.len()).min_by_key(|&i| (zones[i].root as i32 - n as i32).abs())
+        })
+    }
+
+    pub fn voice(&self, note: f32) -> Option<SamplerVoice> {
+        self.zone(note).map(|zone| SamplerVoice { zone, pos: [0.0; UnisonSettings::MAX_VOICES as usize] })
+    }
+
+    /// Reads the next sample of unison voice `k` playing at `freq`, `sr`
+    /// being the output sample rate.
+    pub fn tick(&self, voice: &mut SamplerVoice, k: usize, freq: f32, sr: f32) -> f32 {
+        let (Some(zone), Some(sample)) = (self.desc.zones.get(voice.zone), self.samples.get(voice.zone)) else { return 0.0 };
+        let pos = &mut voice.pos[k];
+        let x = sample.read(*pos);
+        *pos += freq / note_to_freq(zone.root as f32) * sample.sample_rate / sr;
+        x
+    }
+}
+
+#[cfg(test)]
+mod sampler_tests {
+    use super::*;
+
+    fn zone(root: u8, low: u8, high: u8) -> SampleZone { SampleZone { low, high, ..SampleZone::new(PathBuf::new(), root) } }
+
+    #[test]
+    fn test_zones_and_pitch() {
+        let mut sampler = Sampler::new();
+        for (z, data) in [(zone(48, 0, 59), vec![0.0, 1.0, 2.0, 3.0]), (zone(72, 60, 80), vec![5.0; 4])] {
+            sampler.desc.zones.push(z);
+            sampler.samples.push(Arc::new(Sample { data, sample_rate: 100.0 }));
+        }
+        assert_eq!(sampler.zone(40.0), Some(0));
+        assert_eq!(sampler.zone(60.0), Some(1));
+        assert_eq!(sampler.zone(100.0), Some(1));
+
+        // an octave above the root reads twice as fast.
+        let mut voice = sampler.voice(50.0).unwrap();
+        let freq = note_to_freq(48.0) * 2.0;
+        let read: Vec<f32> = (0..3).map(|_| sampler.tick(&mut voice, 0, freq, 100.0)).collect();
+        assert_eq!(read[1], 2.0);
+        assert_eq!(read[2], 0.0);
+    }
+
+    #[test]
+    fn test_read_zone_not_reloaded() {
+        // a zone read beforehand isn't decoded again, its file is gone.
+        let mut read = SampleZone::new(PathBuf::from("/nonexistent/piano.wav"), 60);
+        assert!(read.read().is_err() && read.sample.0.is_none());
+        read.sample = Loaded(Some(Arc::new(Sample { data: vec![1.0; 4], sample_rate: 100.0 })));
+        let sampler = Sampler::load(&SamplerDesc { zones: vec![read.clone()] }).unwrap();
+        assert_eq!(sampler.zones(), 1);
+        read.sample = Loaded::default();
+        assert!(Sampler::load(&SamplerDesc { zones: vec![read] }).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Scale module.
+//!
+//! keeps the notes played in key: a note out of the scale is moved to the
+//! nearest note of it, or not played at all.
+//!
+
+use crate::keymap::{note_name, parse_note_name};
+
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub enum ScaleKind {
+    #[default]
+    Off,
+    Major,
+    Minor,
+    Dorian,
+    Mixolydian,
+    HarmonicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+}
+
+impl ScaleKind {
+    pub const ALL: [ScaleKind; 9] = [
+        ScaleKind::Off, ScaleKind::Major, ScaleKind::Minor, ScaleKind::Dorian, ScaleKind::Mixolydian,
+        ScaleKind::HarmonicMinor, ScaleKind::MajorPentatonic, ScaleKind::MinorPentatonic, ScaleKind::Blues,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScaleKind::Off => "off",
+            ScaleKind::Major => "major",
+            ScaleKind::Minor => "minor",
+            ScaleKind::Dorian => "dorian",
+            ScaleKind::Mixolydian => "mixolydian",
+            ScaleKind::HarmonicMinor => "harmonic-minor",
+            ScaleKind::MajorPentatonic => "pentatonic",
+            ScaleKind::MinorPentatonic => "minor-pentatonic",
+            ScaleKind::Blues => "blues",
+        }
+    }
+
+    // semitones above the root of the notes in an octave of the scale.
+    pub fn steps(self) -> &'static [u8] {
+        match self {
+            ScaleKind::Off => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            ScaleKind::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleKind::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleKind::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            ScaleKind::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            ScaleKind::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            ScaleKind::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleKind::MinorPentatonic => &[0, 3, 5, 7, 10],
+            ScaleKind::Blues => &[0, 3, 5, 6, 7, 10],
+        }
+    }
+
+    pub fn cycle(self) -> ScaleKind {
+        let index = ScaleKind::ALL.iter().position(|k| *k == self).unwrap_or_default();
+        ScaleKind::ALL[(index + 1) % ScaleKind::ALL.len()]
+    }
+}
+
+// what happens to a note out of the scale.
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub enum ScaleLock { #[default] Snap, Filter }
+
+// `root` is the pitch class of the first note of the scale, 0 for c.
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub struct Scale { pub kind: ScaleKind, pub root: u8, pub lock: ScaleLock }
+
+impl Scale {
+    pub fn enabled(&self) -> bool { self.kind != ScaleKind::Off }
+
+    pub fn name(&self) -> String {
+        let root = note_name(self.root as i32 + 12);
+        format!("{} {}", root.trim_end_matches('0'), self.kind.name())
+    }
+
+    /// Whether the midi note, rounded to a semitone, is in the scale.
+    pub fn contains(&self, note: f32) -> bool {
+        let class = (note.round() as i32 - self.root as i32).rem_euclid(12) as u8;
+        self.kind.steps().contains(&class)
+    }
+
+    // the note of the scale nearest to a midi note, the lower one of two
+    // as near.
+    pub fn snap(&self, note: f32) -> f32 {
+        let rounded = note.round();
+        (0..=6).flat_map(|d| [-d, d]).map(|d| rounded + d as f32).find(|n| self.contains(*n)).unwrap_or(note)
+    }
+
+    /// The note played for a midi note: moved into the scale when snapping,
+    /// left alone otherwise.
+    pub fn lock(&self, note: f32) -> f32 {
+        match (self.enabled(), self.lock) {
+            (true, ScaleLock::Snap) => self.snap(note),
+            _ => note,
+        }
+    }
+
+    // whether a midi note isn't played at all.
+    pub fn filters(&self, note: f32) -> bool {
+        self.enabled() && self.lock == ScaleLock::Filter && !self.contains(note)
+    }
+}
+
+// a root and a scale like `d dorian`, followed by `filter` to leave out the
+// notes out of the scale rather than move them.
+impl std::str::FromStr for Scale {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Scale, String> {
+        let error = || format!(
+            "invalid scale '{}', expected a root and one of {}, like 'd dorian'",
+            s, ScaleKind::ALL.map(|k| k.name()).join(", "),
+        );
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (root, kind, lock) = match words[..] {
+            ["off"] => return Ok(Scale::default()),
+            [root, kind] => (root, kind, ScaleLock::Snap),
+            [root, kind, "filter"] => (root, kind, ScaleLock::Filter),
+            _ => return Err(error()),
+        };
+        let root = parse_note_name(&format!("{}0", root)).ok_or_else(error)?.rem_euclid(12) as u8;
+        let kind = ScaleKind::ALL.into_iter().find(|k| k.name() == kind).ok_or_else(error)?;
+        Ok(Scale { kind, root, lock })
+    }
+}
+
+#[cfg(test)]
+mod scale_tests {
+    use super::*;
+
+    #[test]
+    fn test_snap_and_filter() {
+        let scale: Scale = "d dorian".parse().unwrap();
+        assert_eq!((scale.root, scale.kind, scale.name()), (2, ScaleKind::Dorian, String::from("D dorian")));
+        // d e f g a b c: c# goes down to c, d# down to d.
+        assert_eq!([61.0, 63.0, 64.2, 66.0].map(|n| scale.lock(n)), [60.0, 62.0, 64.0, 65.0]);
+        assert!(!scale.filters(61.0));
+
+        let scale: Scale = "Bb pentatonic filter".parse().unwrap();
+        assert_eq!(scale.root, 10);
+        assert!(scale.filters(71.0) && !scale.filters(70.0));
+        assert_eq!(scale.lock(71.0), 71.0);
+        assert!("h major".parse::<Scale>().is_err());
+        assert!("c lydian".parse::<Scale>().is_err());
+        assert_eq!(ScaleKind::Blues.cycle(), ScaleKind::Off);
+    }
+}
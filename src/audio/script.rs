@@ -0,0 +1,131 @@
+//! Script module.
+//!
+//! oscillators written as rhai scripts under `$XDG_CONFIG_HOME/rsynth/scripts`.
+//! a script defines `fn f(t, freq)`, giving the sample `t` seconds into a
+//! cycle of a note at `freq` hz, like `sin(2.0 * PI() * freq * t)`. it's
+//! run when the script is loaded rather than per sample: one cycle is
+//! rendered for each octave, the script being free to leave out what would
+//! alias there, and played back as a table.
+//!
+
+use std::path::{Path, PathBuf};
+use rhai::{Dynamic, Engine, Scope};
+
+use crate::audio::waves::{OctaveTables, WaveDesc, WaveGenerator};
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(std::io::Error),
+    Parse(String),
+    Eval(String),
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptError::Io(e) => write!(f, "script io error: {}", e),
+            ScriptError::Parse(e) => write!(f, "invalid script: {}", e),
+            ScriptError::Eval(e) => write!(f, "script failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+impl From<std::io::Error> for ScriptError { fn from(e: std::io::Error) -> Self { ScriptError::Io(e) } }
+
+pub fn scripts_dir() -> PathBuf {
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("rsynth").join("scripts")
+}
+
+// relative paths are taken from the scripts dir.
+pub fn script_path<P: AsRef<Path>>(path: P) -> PathBuf { scripts_dir().join(path) }
+
+pub struct ScriptWave {
+    path: PathBuf,
+    tables: OctaveTables,
+}
+
+impl ScriptWave {
+    pub fn new(path: PathBuf, tables: OctaveTables) -> ScriptWave { ScriptWave { path, tables } }
+
+    /// Compiles the script at `path` and renders its tables.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ScriptWave, ScriptError> {
+        Ok(ScriptWave::new(path.as_ref().to_path_buf(), ScriptWave::read(path.as_ref())?))
+    }
+
+    // the tables of the script at `path`, a thousand or so calls into it,
+    // so never under the instrument lock where it can be helped.
+    pub fn read(path: &Path) -> Result<OctaveTables, ScriptError> {
+        ScriptWave::render(&std::fs::read_to_string(script_path(path))?)
+    }
+
+    pub fn render(source: &str) -> Result<OctaveTables, ScriptError> {
+        let engine = Engine::new();
+        let ast = engine.compile(source).map_err(|e| ScriptError::Parse(e.to_string()))?;
+        let mut scope = Scope::new();
+        OctaveTables::render(|t, freq| {
+            let y: Dynamic = engine.call_fn(&mut scope, &ast, "f", (t, freq)).map_err(|e| ScriptError::Eval(e.to_string()))?;
+            // whole numbers are fine as samples too.
+            y.as_float().or_else(|_| y.as_int().map(|i| i as f64))
+                .map_err(|t| ScriptError::Eval(format!("f returned {}, expected a number", t)))
+        })
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+}
+
+impl WaveGenerator for ScriptWave {
+    fn gen(&mut self, t: f32) -> f32 { self.tables.gen(t) }
+    fn desc(&self) -> WaveDesc { WaveDesc::Script(self.path.clone()) }
+    fn set_increment(&mut self, dt: f32) { self.tables.set_increment(dt) }
+    fn tables(&self) -> Option<OctaveTables> { Some(self.tables.clone()) }
+}
+
+#[cfg(test)]
+mod script_tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut wave = ScriptWave { path: PathBuf::from("x.rhai"), tables: ScriptWave::render("fn f(t, freq) { sin(2.0 * PI() * freq * t) }").unwrap() };
+        assert!([440.0, 8000.0].iter().all(|f| { wave.set_increment(f / OctaveTables::RATE); (wave.gen(0.25) - 1.0).abs() < 1e-4 }));
+        assert_eq!(wave.desc(), WaveDesc::Script(PathBuf::from("x.rhai")));
+
+        // a script can drop a partial as it nears nyquist.
+        let source = "fn f(t, freq) { let y = sin(2.0 * PI() * freq * t); if freq < 5000.0 { y += 0.5 * sin(6.0 * PI() * freq * t) } y }";
+        let mut tables = ScriptWave::render(source).unwrap();
+        tables.set_increment(440.0 / OctaveTables::RATE);
+        let low = tables.gen(0.25);
+        tables.set_increment(8000.0 / OctaveTables::RATE);
+        assert!((low - 0.5).abs() < 1e-3 && (tables.gen(0.25) - 1.0).abs() < 1e-3);
+
+        assert!(matches!(ScriptWave::render("fn f(t, freq) {"), Err(ScriptError::Parse(_))));
+        assert!(matches!(ScriptWave::render("fn f(t, freq) { \"loud\" }"), Err(ScriptError::Eval(_))));
+        assert!(matches!(ScriptWave::render("fn g(t) { t }"), Err(ScriptError::Eval(_))));
+    }
+
+    #[test]
+    fn test_read_before_building() {
+        use crate::audio::waves::Oscillator;
+        let path = std::env::temp_dir().join(format!("rsynth-script-{}.rhai", std::process::id()));
+        std::fs::write(&path, "fn f(t, freq) { sin(2.0 * PI() * freq * t) }").unwrap();
+        let mut oscillator = Oscillator::new(Box::new(crate::audio::waves::NullWave));
+        let mut desc = oscillator.desc();
+        desc.otf = WaveDesc::Script(path.clone());
+        desc.read().unwrap();
+        assert!(desc.tables.0.is_some());
+        // the tables read are played, not those of the file as it is now.
+        std::fs::write(&path, "fn f(t, freq) {").unwrap();
+        oscillator.apply_desc(&desc);
+        assert_eq!(oscillator.desc().otf, WaveDesc::Script(path.clone()));
+        assert!(desc.read().is_err_and(|e| e.contains("invalid script")));
+        std::fs::remove_file(&path).unwrap();
+
+        // the desc of the oscillator carries its tables, an undo plays them again.
+        let edit = oscillator.desc();
+        let mut other = Oscillator::new(Box::new(crate::audio::waves::NullWave));
+        other.apply_desc(&edit);
+        other.otf.set_increment(440.0 / OctaveTables::RATE);
+        assert!((other.otf.gen(0.25) - 1.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,85 @@
+//! Smoothing module.
+//!
+//! parameters that can be changed while notes play glide to their new
+//! value over a few milliseconds instead of jumping, which would be heard
+//! as zipper noise or clicks.
+//!
+
+/// A value that ramps linearly to its target, one step per sample.
+///
+/// a new target restarts the ramp from wherever the value is, so it always
+/// takes `TIME` to get there.
+#[derive(Debug, Clone, Copy)]
+pub struct SmoothedParam {
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: usize,
+    ramp: usize,
+}
+
+impl SmoothedParam {
+    // seconds a ramp lasts.
+    pub const TIME: f32 = 0.005;
+
+    pub fn new(value: f32, sample_rate: f32) -> SmoothedParam {
+        let mut param = SmoothedParam { current: value, target: value, step: 0.0, remaining: 0, ramp: 1 };
+        param.set_sample_rate(sample_rate);
+        param
+    }
+
+    // also ends any ramp going on.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.ramp = ((SmoothedParam::TIME * sample_rate) as usize).max(1);
+        self.snap();
+    }
+
+    pub fn set_target(&mut self, target: f32) {
+        if target == self.target { return; }
+        self.target = target;
+        self.remaining = self.ramp;
+        self.step = (target - self.current) / self.ramp as f32;
+    }
+
+    // jumps straight to the target.
+    pub fn snap(&mut self) {
+        self.current = self.target;
+        self.remaining = 0;
+    }
+
+    pub fn value(&self) -> f32 { self.current }
+    pub fn target(&self) -> f32 { self.target }
+
+    // the value for the next sample.
+    pub fn tick(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 { self.target } else { self.current + self.step };
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod smooth_tests {
+    use super::*;
+
+    #[test]
+    fn test_ramps_to_target() {
+        let mut param = SmoothedParam::new(1.0, 1000.0);
+        assert_eq!(param.tick(), 1.0);
+        // 5 ms at 1 kHz, five steps.
+        param.set_target(2.0);
+        let ramp: Vec<f32> = (0..6).map(|_| param.tick()).collect();
+        assert!((ramp[1] - 1.4).abs() < 1e-6);
+        assert_eq!(&ramp[4..], &[2.0, 2.0]);
+
+        // retargeting halfway starts over from the current value.
+        param.set_target(0.0);
+        (0..2).for_each(|_| { param.tick(); });
+        param.set_target(1.0);
+        assert!((param.tick() - (1.2 + (1.0 - 1.2) / 5.0)).abs() < 1e-6);
+        param.snap();
+        assert_eq!(param.value(), 1.0);
+    }
+}
@@ -0,0 +1,89 @@
+//! Tempo module.
+//!
+//! note lengths for anything that follows the tempo (delays, lfos) instead
+//! of a time in seconds.
+//!
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub enum Feel { #[default] Straight, Dotted, Triplet }
+
+/// A note length: 1/`fraction` of a whole note, dotted (one and a half
+/// times as long) or a triplet (two thirds).
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+pub struct NoteDivision { pub fraction: u8, #[serde(default)] pub feel: Feel }
+
+impl Default for NoteDivision {
+    fn default() -> Self { NoteDivision::new(8, Feel::Dotted) }
+}
+
+impl NoteDivision {
+    // the lengths offered when stepping through them, shortest first.
+    pub const STEPS: [NoteDivision; 9] = [
+        NoteDivision::new(16, Feel::Straight), NoteDivision::new(8, Feel::Triplet), NoteDivision::new(8, Feel::Straight),
+        NoteDivision::new(8, Feel::Dotted), NoteDivision::new(4, Feel::Triplet), NoteDivision::new(4, Feel::Straight),
+        NoteDivision::new(4, Feel::Dotted), NoteDivision::new(2, Feel::Straight), NoteDivision::new(1, Feel::Straight),
+    ];
+
+    pub const fn new(fraction: u8, feel: Feel) -> NoteDivision { NoteDivision { fraction, feel } }
+
+    // length in beats, a beat being a quarter note.
+    pub fn beats(&self) -> f32 {
+        let straight = 4.0 / self.fraction.max(1) as f32;
+        match self.feel {
+            Feel::Straight => straight,
+            Feel::Dotted => straight * 1.5,
+            Feel::Triplet => straight * 2.0 / 3.0,
+        }
+    }
+
+    pub fn seconds(&self, bpm: f32) -> f32 { self.beats() * 60.0 / bpm.max(1.0) }
+
+    // the next of `STEPS`, none after the longest.
+    pub fn next(&self) -> Option<NoteDivision> {
+        let i = NoteDivision::STEPS.iter().position(|d| d == self)?;
+        NoteDivision::STEPS.get(i + 1).copied()
+    }
+}
+
+/// Rate of an lfo, in Hz or one cycle per note division.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum LfoRate { Free(f32), Synced(NoteDivision) }
+
+impl LfoRate {
+    pub fn hz(&self, bpm: f32) -> f32 {
+        match self {
+            LfoRate::Free(hz) => *hz,
+            LfoRate::Synced(division) => 1.0 / division.seconds(bpm),
+        }
+    }
+
+    // free, then each of `NoteDivision::STEPS`, none after the longest.
+    pub fn next(&self) -> Option<LfoRate> {
+        match self {
+            LfoRate::Free(_) => Some(LfoRate::Synced(NoteDivision::STEPS[0])),
+            LfoRate::Synced(division) => division.next().map(LfoRate::Synced),
+        }
+    }
+}
+
+impl std::fmt::Display for LfoRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LfoRate::Free(hz) => write!(f, "{:.2} Hz", hz),
+            LfoRate::Synced(division) => write!(f, "{}", division),
+        }
+    }
+}
+
+impl std::fmt::Display for NoteDivision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "1/{}", self.fraction)?;
+        match self.feel {
+            Feel::Straight => Ok(()),
+            Feel::Dotted => write!(f, " dotted"),
+            Feel::Triplet => write!(f, " triplet"),
+        }
+    }
+}
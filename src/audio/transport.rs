@@ -0,0 +1,206 @@
+//! Transport module.
+//!
+//! the tempo, and a position in beats that moves while playing. the drum
+//! pattern steps on the position, the arpeggiator and the tempo synced
+//! effects follow the tempo whether playing or not. the swing delays the
+//! off-beat steps of both the drum pattern and the arpeggiator.
+//!
+//! following a midi clock, both come from its ticks instead: the tempo
+//! from how far apart they are, the position from how many there were.
+//!
+
+use std::collections::VecDeque;
+
+/// Position of `step`, in steps, with the off-beat ones (every second
+/// step) delayed by `swing`: from 0, straight, to 1, half a step late.
+pub fn swung_step(step: usize, swing: f32) -> f64 {
+    step as f64 + if step % 2 == 1 { swing.clamp(0.0, 1.0) as f64 / 2.0 } else { 0.0 }
+}
+
+pub struct Transport {
+    bpm: f32,
+    swing: f32,
+    playing: bool,
+    beat: f64,
+    // time of the last advance, in seconds.
+    last: f32,
+    taps: Vec<f32>,
+    following: bool,
+    // position of the last clock tick, the times of the recent ones, and
+    // whether the next one is the first after a start.
+    clock_beat: f64,
+    ticks: VecDeque<f32>,
+    downbeat: bool,
+}
+
+impl Default for Transport { fn default() -> Self { Self::new() } }
+
+impl Transport {
+    pub const MIN_BPM: f32 = 20.0;
+    pub const MAX_BPM: f32 = 300.0;
+    pub const BEATS_PER_BAR: u32 = 4;
+    // taps further apart than this start over, and the tempo comes from
+    // at most the last `TAPS`.
+    const TAP_TIMEOUT: f32 = 2.0;
+    const TAPS: usize = 4;
+    // midi clock ticks to a beat. the tempo is measured over a beat of
+    // them, and starts over after a gap this long.
+    pub const PPQN: u32 = 24;
+    const CLOCK_TIMEOUT: f32 = 1.0;
+
+    pub fn new() -> Transport {
+        Transport {
+            bpm: 120.0, swing: 0.0, playing: false, beat: 0.0, last: 0.0, taps: Vec::with_capacity(Transport::TAPS),
+            following: false, clock_beat: 0.0, ticks: VecDeque::with_capacity(Transport::PPQN as usize + 1), downbeat: false,
+        }
+    }
+
+    pub fn bpm(&self) -> f32 { self.bpm }
+    pub fn set_bpm(&mut self, bpm: f32) { self.bpm = bpm.clamp(Transport::MIN_BPM, Transport::MAX_BPM) }
+    pub fn nudge(&mut self, delta: f32) { self.set_bpm(self.bpm + delta) }
+
+    pub fn swing(&self) -> f32 { self.swing }
+    pub fn set_swing(&mut self, swing: f32) { self.swing = swing.clamp(0.0, 1.0) }
+
+    pub fn playing(&self) -> bool { self.playing }
+
+    // plays from the top.
+    pub fn start(&mut self, now: f32) {
+        (self.playing, self.beat, self.last) = (true, 0.0, now);
+        (self.clock_beat, self.downbeat) = (0.0, true);
+    }
+
+    // plays on from where it stopped.
+    pub fn resume(&mut self, now: f32) {
+        (self.playing, self.last) = (true, now);
+        (self.clock_beat, self.downbeat) = (self.beat, true);
+    }
+
+    pub fn stop(&mut self) { self.playing = false }
+
+    // position in beats as of the last advance.
+    pub fn position(&self) -> f64 { self.beat }
+
+    // position in beats at `now`, between advances. following a clock it
+    // doesn't go past the next tick, so it never has to go back.
+    pub fn position_at(&self, now: f32) -> f64 {
+        if !self.playing { return self.beat; }
+        let beat = self.beat + ((now - self.last).max(0.0) * self.bpm / 60.0) as f64;
+        match self.following {
+            true => beat.min(self.clock_beat + 1.0 / Transport::PPQN as f64),
+            false => beat,
+        }
+    }
+
+    pub fn following(&self) -> bool { self.following }
+    pub fn set_following(&mut self, on: bool) {
+        self.following = on;
+        self.ticks.clear();
+    }
+
+    /// A midi clock tick at `now`: the tempo is the rate of the recent
+    /// ticks, and while playing the position moves a tick on (but for the
+    /// first tick after a start, which is the start).
+    pub fn clock(&mut self, now: f32) {
+        if !self.following { return; }
+        if self.ticks.back().is_some_and(|t| now - t > Transport::CLOCK_TIMEOUT || now < *t) { self.ticks.clear(); }
+        if self.ticks.len() > Transport::PPQN as usize { self.ticks.pop_front(); }
+        self.ticks.push_back(now);
+        if let (Some(first), Some(last)) = (self.ticks.front(), self.ticks.back()) {
+            let beats = (self.ticks.len() - 1) as f32 / Transport::PPQN as f32;
+            if last > first { self.set_bpm(60.0 * beats / (last - first)); }
+        }
+        if !self.playing { return; }
+        if !std::mem::take(&mut self.downbeat) { self.clock_beat += 1.0 / Transport::PPQN as f64; }
+        (self.beat, self.last) = (self.clock_beat, now);
+    }
+
+    // the clock ticks between the beats `from` inclusive and `to` exclusive.
+    pub fn ticks_between(from: f64, to: f64) -> u64 {
+        let ppqn = Transport::PPQN as f64;
+        ((to * ppqn).ceil() - (from * ppqn).ceil()).max(0.0) as u64
+    }
+
+    // bar and beat in the bar, both counted from one.
+    pub fn bar_beat(&self) -> (u64, u32) {
+        let beats = self.beat.max(0.0) as u64;
+        let per_bar = Transport::BEATS_PER_BAR as u64;
+        (beats / per_bar + 1, (beats % per_bar) as u32 + 1)
+    }
+
+    /// Moves the position on to `now`. returns the beats it moved over,
+    /// from inclusive to exclusive, none while stopped.
+    pub fn advance(&mut self, now: f32) -> Option<(f64, f64)> {
+        let from = self.beat;
+        self.beat = self.position_at(now);
+        self.last = now;
+        self.playing.then_some((from, self.beat))
+    }
+
+    /// Tap tempo: the tempo of the taps so far, once there are two.
+    pub fn tap(&mut self, now: f32) -> Option<f32> {
+        if self.taps.last().is_some_and(|t| now - t > Transport::TAP_TIMEOUT || now < *t) { self.taps.clear(); }
+        if self.taps.len() == Transport::TAPS { self.taps.remove(0); }
+        self.taps.push(now);
+        let (first, last) = (self.taps.first()?, self.taps.last()?);
+        if self.taps.len() < 2 || last <= first { return None; }
+        self.set_bpm(60.0 * (self.taps.len() - 1) as f32 / (last - first));
+        Some(self.bpm)
+    }
+}
+
+#[cfg(test)]
+mod transport_tests {
+    use super::*;
+
+    #[test]
+    fn test_transport() {
+        let mut transport = Transport::new();
+        assert_eq!(transport.advance(1.0), None);
+        transport.start(1.0);
+        // 120 bpm, two beats a second.
+        assert_eq!(transport.advance(1.5), Some((0.0, 1.0)));
+        assert_eq!(transport.position_at(2.0), 2.0);
+        assert_eq!(transport.advance(3.5), Some((1.0, 5.0)));
+        assert_eq!(transport.bar_beat(), (2, 2));
+        transport.stop();
+        assert_eq!(transport.advance(4.0), None);
+        assert_eq!(transport.position(), 5.0);
+
+        // taps half a second apart, then a pause starting over.
+        assert_eq!(transport.tap(10.0), None);
+        assert_eq!(transport.tap(10.5), Some(120.0));
+        assert_eq!(transport.tap(11.0), Some(120.0));
+        assert_eq!(transport.tap(20.0), None);
+        assert_eq!(transport.tap(20.25), Some(240.0));
+        transport.nudge(100.0);
+        assert_eq!(transport.bpm(), Transport::MAX_BPM);
+        assert_eq!(Transport::ticks_between(0.0, 0.5), 12);
+        assert_eq!(Transport::ticks_between(0.5, 0.5), 0);
+
+        transport.set_swing(2.0);
+        assert_eq!(transport.swing(), 1.0);
+        assert_eq!((swung_step(2, 0.5), swung_step(3, 0.5)), (2.0, 3.25));
+    }
+
+    #[test]
+    fn test_follow_midi_clock() {
+        let mut transport = Transport::new();
+        transport.clock(0.0);
+        assert_eq!(transport.bpm(), 120.0);
+        transport.set_following(true);
+        // a beat of ticks at 100 bpm, before and after a start.
+        let tick = 0.6 / Transport::PPQN as f32;
+        (0..12).for_each(|i| transport.clock(i as f32 * tick));
+        assert!((transport.bpm() - 100.0).abs() < 0.01);
+        transport.start(12.0 * tick);
+        (12..=24).for_each(|i| transport.clock(i as f32 * tick));
+        assert!((transport.position() - 0.5).abs() < 1e-6);
+        // between ticks it moves on, but not past the next one.
+        assert!(transport.position_at(24.5 * tick) > 0.5);
+        assert!((transport.position_at(30.0 * tick) - 0.5 - 1.0 / 24.0).abs() < 1e-6);
+        transport.stop();
+        transport.clock(25.0 * tick);
+        assert!((transport.position() - 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,58 @@
+//! Unison module.
+//!
+//! stacks detuned copies of each voice, spread evenly in pitch and across
+//! the stereo field.
+//!
+
+use serde::{Serialize, Deserialize};
+
+// `detune` is the distance in cents between the lowest and highest voice,
+// `spread` how far apart they are panned, 1 being hard left to hard right.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct UnisonSettings { pub voices: u8, pub detune: f32, pub spread: f32 }
+
+impl Default for UnisonSettings {
+    fn default() -> Self { UnisonSettings { voices: 1, detune: 20.0, spread: 0.5 } }
+}
+
+impl UnisonSettings {
+    pub const MAX_VOICES: u8 = 8;
+
+    pub fn voices(&self) -> usize { self.voices.clamp(1, UnisonSettings::MAX_VOICES) as usize }
+
+    // frequency ratio and pan (-1 left, 1 right) of the `k`th voice.
+    pub fn voice(&self, k: usize) -> (f32, f32) {
+        let n = self.voices();
+        if n == 1 { return (1.0, 0.0); }
+        let x = 2.0 * k as f32 / (n - 1) as f32 - 1.0;
+        let ratio = 2f32.powf(x * self.detune * 0.5 / 1200.0);
+        (ratio, x * self.spread.clamp(0.0, 1.0))
+    }
+
+    // keeps the loudness about the same whatever the number of voices.
+    pub fn gain(&self) -> f32 { 1.0 / (self.voices() as f32).sqrt() }
+}
+
+// left and right gains for a pan position, both are 1 at the center.
+pub fn pan_gains(pan: f32) -> (f32, f32) { ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0)) }
+
+#[cfg(test)]
+mod unison_tests {
+    use super::*;
+
+    #[test]
+    fn test_unison_voices() {
+        let single = UnisonSettings { voices: 1, detune: 50.0, spread: 1.0 };
+        assert_eq!(single.voice(0), (1.0, 0.0));
+
+        let u = UnisonSettings { voices: 3, detune: 100.0, spread: 0.5 };
+        let (low, left) = u.voice(0);
+        let (mid, center) = u.voice(1);
+        let (high, right) = u.voice(2);
+        assert!((low - 2f32.powf(-50.0 / 1200.0)).abs() < 1e-6);
+        assert!((high * low - 1.0).abs() < 1e-6);
+        assert_eq!((mid, center), (1.0, 0.0));
+        assert_eq!((left, right), (-0.5, 0.5));
+        assert_eq!(pan_gains(-0.5), (1.0, 0.5));
+    }
+}
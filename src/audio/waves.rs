@@ -1,39 +1,423 @@
+use std::sync::Arc;
 
-
-pub trait WaveGenerator { fn gen(&mut self, t: f32) -> f32; }
-pub trait Randomize { fn randomize(&mut self); }
-
-unsafe impl Send for Oscillator {}
-unsafe impl Send for LinearTransform {}
-unsafe impl Send for ConstantWave {}
-unsafe impl Send for NullWave {}
-unsafe impl Send for RandomWave {}
+pub trait WaveGenerator {
+    fn gen(&mut self, t: f32) -> f32;
+    fn desc(&self) -> WaveDesc;
+    // phase advanced per sample, for generators that need to know it
+    // (e.g. band-limited ones).
+    fn set_increment(&mut self, _dt: f32) {}
+    // offset added to the morph position, for generators that have one
+    // (e.g. wavetables). set every sample by the modulation matrix.
+    fn modulate_position(&mut self, _offset: f32) {}
+    // offset added to the pulse width, for generators that have one.
+    fn modulate_width(&mut self, _offset: f32) {}
+    // the morph position itself, for generators that have one.
+    fn set_position(&mut self, _position: f32) {}
+    // the tables rendered from a file, for generators made of them (e.g.
+    // scripts), so a description can carry them instead of the file.
+    fn tables(&self) -> Option<OctaveTables> { None }
+    // replaces each phase of `buf` with its sample. one virtual call per
+    // block instead of one per sample.
+    fn gen_block(&mut self, buf: &mut [f32]) {
+        for x in buf.iter_mut() { *x = self.gen(*x) }
+    }
+}
+// draws from the rng given, so a seeded one rolls the same patch again.
+pub trait Randomize { fn randomize<R: Rng + ?Sized>(&mut self, rng: &mut R); }
 
 pub struct NullWave;
-impl WaveGenerator for NullWave { fn gen(&mut self, _: f32) -> f32 { 0.0 }}
+impl WaveGenerator for NullWave { fn gen(&mut self, _: f32) -> f32 { 0.0 } fn desc(&self) -> WaveDesc { WaveDesc::Null } }
 
 pub struct IdentityWave;
-impl WaveGenerator for IdentityWave { fn gen(&mut self, t: f32) -> f32 { 1.0 }}
+impl WaveGenerator for IdentityWave { fn gen(&mut self, _: f32) -> f32 { 1.0 } fn desc(&self) -> WaveDesc { WaveDesc::Identity } }
 
 pub struct ConstantWave;
-impl WaveGenerator for ConstantWave { fn gen(&mut self, t: f32) -> f32 { t }}
+impl WaveGenerator for ConstantWave { fn gen(&mut self, t: f32) -> f32 { t } fn desc(&self) -> WaveDesc { WaveDesc::Constant } }
 
 pub struct SinWave;
-impl WaveGenerator for SinWave { fn gen(&mut self, t: f32) -> f32 { (t*std::f32::consts::FRAC_PI_2).sin() }}
+impl WaveGenerator for SinWave { fn gen(&mut self, t: f32) -> f32 { (t*std::f32::consts::FRAC_PI_2).sin() } fn desc(&self) -> WaveDesc { WaveDesc::Sin } }
 
 pub struct SquareWave;
-impl WaveGenerator for SquareWave { fn gen(&mut self, t: f32) -> f32 {  if (t as i32) % 2 == 0 { 1.0 } else { -1.0 } }}
+impl WaveGenerator for SquareWave { fn gen(&mut self, t: f32) -> f32 {  if (t as i32) % 2 == 0 { 1.0 } else { -1.0 } } fn desc(&self) -> WaveDesc { WaveDesc::Square } }
 
 pub struct TriWave;
-impl WaveGenerator for TriWave { fn gen(&mut self, t: f32) -> f32 { (t % 2.0)-1.0 }}
+impl WaveGenerator for TriWave { fn gen(&mut self, t: f32) -> f32 { (t % 2.0)-1.0 } fn desc(&self) -> WaveDesc { WaveDesc::Tri } }
+
+// polynomial band-limited step, correction for a unit discontinuity at
+// phase 0. `t` is the phase in [0, 1) and `dt` the phase increment per sample.
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else { 0.0 }
+}
+
+// integrated poly_blep, rounds the corners of slope discontinuities.
+fn poly_blamp(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt - 1.0;
+        -t * t * t / 3.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt + 1.0;
+        t * t * t / 3.0
+    } else { 0.0 }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum BlepShape { Saw, Pulse(f32), Triangle }
+
+/// Band-limited saw, pulse and triangle.
+///
+/// unlike the naive waves above, `t` is read as a phase in cycles (period
+/// of 1.0). the increment must be kept up to date through `set_increment`,
+/// with a zero increment the output is the same as the naive shape.
+pub struct PolyBlepWave { pub shape: BlepShape, dt: f32, width_offset: f32 }
+impl PolyBlepWave { pub fn new(shape: BlepShape) -> PolyBlepWave { PolyBlepWave { shape, dt: 0.0, width_offset: 0.0 } } }
+impl WaveGenerator for PolyBlepWave {
+    fn gen(&mut self, t: f32) -> f32 {
+        let p = t.rem_euclid(1.0);
+        let dt = self.dt.clamp(0.0, 0.5);
+        match self.shape {
+            BlepShape::Saw => 2.0*p - 1.0 - poly_blep(p, dt),
+            BlepShape::Pulse(width) => {
+                let w = (width + self.width_offset).clamp(0.05, 0.95);
+                let naive = if p < w { 1.0 } else { -1.0 };
+                naive + poly_blep(p, dt) - poly_blep((p - w).rem_euclid(1.0), dt)
+            },
+            BlepShape::Triangle => {
+                4.0*(p - 0.5).abs() - 1.0
+                    - 8.0*dt*poly_blamp(p, dt)
+                    + 8.0*dt*poly_blamp((p + 0.5).rem_euclid(1.0), dt)
+            }
+        }
+    }
+
+    fn set_increment(&mut self, dt: f32) { self.dt = dt }
+    fn modulate_width(&mut self, offset: f32) { self.width_offset = offset }
+    fn desc(&self) -> WaveDesc { WaveDesc::PolyBlep(self.shape) }
+}
+
+// where the frames of a wavetable come from.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum WavetableSource { Basic, File(std::path::PathBuf) }
+
+// `frames` are those of a `File` source, see `read`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct WavetableDesc {
+    pub source: WavetableSource,
+    pub position: f32,
+    #[serde(skip)]
+    pub frames: Loaded<Vec<Vec<f32>>>,
+}
+
+impl WavetableDesc {
+    pub fn new(source: WavetableSource, position: f32) -> WavetableDesc {
+        WavetableDesc { source, position, frames: Loaded::default() }
+    }
+
+    // reads and resamples the wav file of a `File` source. keeps the last
+    // frames read if it fails.
+    pub fn read(&mut self) -> Result<(), hound::Error> {
+        if let WavetableSource::File(path) = &self.source {
+            self.frames = Loaded(Some(WavetableOscillator::load(path, WavetableOscillator::TABLE_SIZE)?.frames));
+        }
+        Ok(())
+    }
+}
+
+/// Wavetable oscillator: a set of single cycle frames, morphed through by
+/// `position` (0 is the first frame, 1 the last). the phase is in cycles.
+pub struct WavetableOscillator {
+    source: WavetableSource,
+    // shared with the descriptions taken of it.
+    frames: Arc<Vec<Vec<f32>>>,
+    pub position: f32,
+    offset: f32,
+}
+
+impl WavetableOscillator {
+    // samples per frame, tables are resampled to it when loaded.
+    pub const TABLE_SIZE: usize = 2048;
+
+    pub fn new(source: WavetableSource, frames: Vec<Vec<f32>>) -> WavetableOscillator {
+        let frames = match frames.is_empty() {
+            true => vec![vec![0.0; WavetableOscillator::TABLE_SIZE]],
+            false => frames.iter().map(|f| resample(f, WavetableOscillator::TABLE_SIZE)).collect(),
+        };
+        WavetableOscillator { source, frames: Arc::new(frames), position: 0.0, offset: 0.0 }
+    }
+
+    // sine, triangle, saw and square, in that order.
+    pub fn basic() -> WavetableOscillator {
+        let n = WavetableOscillator::TABLE_SIZE;
+        let frame = |f: &dyn Fn(f32) -> f32| (0..n).map(|i| f(i as f32 / n as f32)).collect::<Vec<f32>>();
+        let frames = vec![
+            frame(&|p| (std::f32::consts::TAU * p).sin()),
+            frame(&|p| 1.0 - 4.0 * ((p + 0.25).rem_euclid(1.0) - 0.5).abs()),
+            frame(&|p| 2.0 * (p + 0.5).rem_euclid(1.0) - 1.0),
+            frame(&|p| if p < 0.5 { 1.0 } else { -1.0 }),
+        ];
+        WavetableOscillator::new(WavetableSource::Basic, frames)
+    }
+
+    /// Loads a wav file of back to back single cycle frames of `frame_len`
+    /// samples each (2048 in most tables). a file shorter than that is one
+    /// frame. multichannel files only use their first channel.
+    pub fn load<P: AsRef<std::path::Path>>(path: P, frame_len: usize) -> Result<WavetableOscillator, hound::Error> {
+        let (samples, _) = read_wav(path.as_ref())?;
+        let frames = samples.chunks(frame_len.max(1)).filter(|f| f.len() > 1).map(|f| f.to_vec()).collect();
+        Ok(WavetableOscillator::new(WavetableSource::File(path.as_ref().to_path_buf()), frames))
+    }
+
+    pub fn frames(&self) -> usize { self.frames.len() }
+
+    fn read(frame: &[f32], p: f32) -> f32 {
+        let x = p * frame.len() as f32;
+        let i = x as usize % frame.len();
+        let frac = x.fract();
+        frame[i] * (1.0 - frac) + frame[(i + 1) % frame.len()] * frac
+    }
+}
+
+/// Reads the first channel of a wav file as floats in -1..1, along with
+/// its sample rate.
+pub fn read_wav<P: AsRef<std::path::Path>>(path: P) -> Result<(Vec<f32>, u32), hound::Error> {
+    let (mut channels, sample_rate) = read_wav_channels(path)?;
+    Ok((channels.swap_remove(0), sample_rate))
+}
+
+/// The contents of a file a description names, read with it (see
+/// `preset::read_files`) and carried along so that building from the
+/// description never touches the disk. they follow from the path, so they are neither saved
+/// nor compared.
+pub struct Loaded<T>(pub Option<Arc<T>>);
+
+impl<T> Default for Loaded<T> { fn default() -> Self { Loaded(None) } }
+impl<T> Clone for Loaded<T> { fn clone(&self) -> Self { Loaded(self.0.clone()) } }
+impl<T> PartialEq for Loaded<T> { fn eq(&self, _: &Self) -> bool { true } }
+
+impl<T> std::fmt::Debug for Loaded<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(if self.0.is_some() { "Loaded" } else { "Unloaded" })
+    }
+}
+
+/// Reads every channel of a wav file, at least one.
+pub fn read_wav_channels<P: AsRef<std::path::Path>>(path: P) -> Result<(Vec<Vec<f32>>, u32), hound::Error> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let channels = spec.channels.max(1) as usize;
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|s| s.map(|s| s as f32 * scale)).collect::<Result<_, _>>()?
+        },
+    };
+    let split = (0..channels).map(|c| samples.iter().skip(c).step_by(channels).copied().collect()).collect();
+    Ok((split, spec.sample_rate))
+}
+
+// linear resampling of a single cycle to `len` samples.
+fn resample(frame: &[f32], len: usize) -> Vec<f32> {
+    (0..len).map(|i| WavetableOscillator::read(frame, i as f32 / len as f32)).collect()
+}
 
-fn random_wave_generator() -> Box<dyn WaveGenerator> {
-    let mut rng = rand::thread_rng();
-    let index = rng.gen_range(1..7);
+/// A wave given as a function of the time into a cycle, in seconds, and of
+/// the frequency, rendered once for each octave and played back from the
+/// octave of the note. the function is free to leave out what would alias
+/// at a frequency.
+#[derive(Clone)]
+pub struct OctaveTables { tables: Arc<Vec<Vec<f32>>>, dt: f32 }
+
+impl OctaveTables {
+    pub const OCTAVES: usize = 10;
+    const LOWEST: f32 = 27.5;
+    // the increment is all a generator is told of the note, it's taken as
+    // per sample at this rate to pick the octave.
+    pub const RATE: f32 = 48000.0;
+    const TABLE_SIZE: usize = 1024;
+
+    /// Renders each octave at its top, so nothing `f` leaves in aliases
+    /// further up it. samples are clipped to -1..1, and silent where `f`
+    /// isn't a number.
+    pub fn render<E>(mut f: impl FnMut(f64, f64) -> Result<f64, E>) -> Result<OctaveTables, E> {
+        let tables = (0..OctaveTables::OCTAVES).map(|octave| {
+            let freq = (OctaveTables::LOWEST * 2f32.powi(octave as i32 + 1)) as f64;
+            (0..OctaveTables::TABLE_SIZE).map(|i| {
+                let y = f(i as f64 / OctaveTables::TABLE_SIZE as f64 / freq, freq)?;
+                Ok(if y.is_finite() { (y as f32).clamp(-1.0, 1.0) } else { 0.0 })
+            }).collect()
+        }).collect::<Result<_, E>>()?;
+        Ok(OctaveTables { tables: Arc::new(tables), dt: 0.0 })
+    }
+
+    pub fn gen(&self, t: f32) -> f32 {
+        let octave = (self.dt * OctaveTables::RATE / OctaveTables::LOWEST).log2().floor();
+        let table = &self.tables[octave.clamp(0.0, (OctaveTables::OCTAVES - 1) as f32) as usize];
+        WavetableOscillator::read(table, t.rem_euclid(1.0))
+    }
+
+    pub fn set_increment(&mut self, dt: f32) { self.dt = dt }
+}
+
+impl WaveGenerator for WavetableOscillator {
+    fn gen(&mut self, t: f32) -> f32 {
+        let p = t.rem_euclid(1.0);
+        let last = (self.frames.len() - 1) as f32;
+        let x = (self.position + self.offset).clamp(0.0, 1.0) * last;
+        let (i, frac) = (x as usize, x.fract());
+        let a = WavetableOscillator::read(&self.frames[i], p);
+        if frac == 0.0 { return a; }
+        a * (1.0 - frac) + WavetableOscillator::read(&self.frames[(i + 1).min(self.frames.len() - 1)], p) * frac
+    }
+    fn desc(&self) -> WaveDesc {
+        WaveDesc::Wavetable(WavetableDesc { source: self.source.clone(), position: self.position, frames: Loaded(Some(self.frames.clone())) })
+    }
+    fn modulate_position(&mut self, offset: f32) { self.offset = offset }
+    fn set_position(&mut self, position: f32) { self.position = position }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AdditiveDesc { pub amplitudes: Vec<f32>, pub brightness: f32, pub odd_even: f32 }
+
+impl Default for AdditiveDesc {
+    // a saw, one over k amplitudes.
+    fn default() -> Self {
+        AdditiveDesc { amplitudes: (1..=16).map(|k| 1.0 / k as f32).collect(), brightness: 1.0, odd_even: 0.0 }
+    }
+}
+
+/// Sum of sine partials at whole multiples of the frequency, `amplitudes[k]`
+/// being the level of partial k + 1. partials above nyquist are skipped.
+///
+/// `brightness` (0..1) rolls the upper partials off, 1 leaving them as
+/// they are. `odd_even` (-1..1) leans towards odd partials (hollow,
+/// clarinet like) when negative and even ones when positive.
+pub struct AdditiveOscillator { pub desc: AdditiveDesc, dt: f32 }
+
+impl AdditiveOscillator {
+    pub fn new(desc: AdditiveDesc) -> AdditiveOscillator { AdditiveOscillator { desc, dt: 0.0 } }
+
+    // level of partial `k` (1 is the fundamental) after the macros.
+    pub fn partial_level(&self, k: usize) -> f32 {
+        let d = &self.desc;
+        let rolloff = (k as f32).powf(-2.0 * (1.0 - d.brightness.clamp(0.0, 1.0)));
+        let balance = d.odd_even.clamp(-1.0, 1.0);
+        let parity = if k % 2 == 1 { (1.0 - balance).min(1.0) } else { (1.0 + balance).min(1.0) };
+        d.amplitudes.get(k - 1).copied().unwrap_or(0.0) * rolloff * parity
+    }
+}
+
+impl WaveGenerator for AdditiveOscillator {
+    fn gen(&mut self, t: f32) -> f32 {
+        let p = t.rem_euclid(1.0);
+        let (mut sum, mut norm) = (0.0, 0.0);
+        for k in 1..=self.desc.amplitudes.len() {
+            if self.dt > 0.0 && k as f32 * self.dt >= 0.5 { break; }
+            let level = self.partial_level(k);
+            sum += level * (std::f32::consts::TAU * k as f32 * p).sin();
+            norm += level.abs();
+        }
+        // keeps the sum within -1..1 however many partials there are.
+        sum / norm.max(1.0)
+    }
+    fn set_increment(&mut self, dt: f32) { self.dt = dt }
+    fn desc(&self) -> WaveDesc { WaveDesc::Additive(self.desc.clone()) }
+}
+
+/// Serializable description of a wave generator tree, used to store
+/// patches and rebuild the generators from them. a script is kept by path
+/// whether or not scripts are built in, so a patch using one still loads,
+/// and an expression as it was typed.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum WaveDesc { Null, Identity, Constant, Sin, Square, Tri, Random, PolyBlep(BlepShape), Wavetable(WavetableDesc), Additive(AdditiveDesc), Noise(NoiseColor), Linear(Box<LinearDesc>), Script(std::path::PathBuf), Expr(String) }
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct LinearDesc { pub alpha: WaveDesc, pub beta: WaveDesc }
+
+impl std::fmt::Display for WaveDesc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WaveDesc::Null => write!(f, "0"),
+            WaveDesc::Identity => write!(f, "1"),
+            WaveDesc::Constant => write!(f, "t"),
+            WaveDesc::Sin => write!(f, "sin"),
+            WaveDesc::Square => write!(f, "square"),
+            WaveDesc::Tri => write!(f, "tri"),
+            WaveDesc::Random => write!(f, "rand"),
+            WaveDesc::PolyBlep(BlepShape::Saw) => write!(f, "saw~"),
+            WaveDesc::PolyBlep(BlepShape::Pulse(w)) => write!(f, "pulse~{:.2}", w),
+            WaveDesc::PolyBlep(BlepShape::Triangle) => write!(f, "tri~"),
+            WaveDesc::Wavetable(d) => match &d.source {
+                WavetableSource::Basic => write!(f, "table@{:.2}", d.position),
+                WavetableSource::File(path) => write!(f, "{}@{:.2}", path.display(), d.position),
+            },
+            WaveDesc::Additive(d) => write!(f, "additive×{} b{:.2} oe{:+.2}", d.amplitudes.len(), d.brightness, d.odd_even),
+            WaveDesc::Noise(c) => write!(f, "{:?} noise", c),
+            WaveDesc::Linear(d) => write!(f, "({})", d),
+            WaveDesc::Script(path) => write!(f, "script {}", path.display()),
+            WaveDesc::Expr(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl std::fmt::Display for LinearDesc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}*t + {}", self.alpha, self.beta)
+    }
+}
+
+impl WaveDesc {
+    pub fn build(&self) -> Box<dyn WaveGenerator + Send> {
+        match self {
+            WaveDesc::Null => Box::new(NullWave),
+            WaveDesc::Identity => Box::new(IdentityWave),
+            WaveDesc::Constant => Box::new(ConstantWave),
+            WaveDesc::Sin => Box::new(SinWave),
+            WaveDesc::Square => Box::new(SquareWave),
+            WaveDesc::Tri => Box::new(TriWave),
+            WaveDesc::Random => Box::new(RandomWave::new()),
+            WaveDesc::PolyBlep(shape) => Box::new(PolyBlepWave::new(*shape)),
+            WaveDesc::Wavetable(d) => {
+                // a file not read plays silence.
+                let mut table = match (&d.source, &d.frames.0) {
+                    (WavetableSource::Basic, _) => WavetableOscillator::basic(),
+                    (WavetableSource::File(_), Some(frames)) =>
+                        WavetableOscillator { source: d.source.clone(), frames: frames.clone(), position: 0.0, offset: 0.0 },
+                    (WavetableSource::File(_), None) => WavetableOscillator::new(d.source.clone(), Vec::new()),
+                };
+                table.position = d.position;
+                Box::new(table)
+            },
+            WaveDesc::Additive(d) => Box::new(AdditiveOscillator::new(d.clone())),
+            WaveDesc::Noise(c) => c.build(),
+            WaveDesc::Linear(d) => Box::new(LinearTransform::from_desc(d)),
+            // like a table, a script that can't be run plays silence.
+            #[cfg(feature = "script")]
+            WaveDesc::Script(path) => match crate::audio::script::ScriptWave::load(path) {
+                Ok(wave) => Box::new(wave),
+                Err(_) => Box::new(NullWave),
+            },
+            #[cfg(not(feature = "script"))]
+            WaveDesc::Script(_) => Box::new(NullWave),
+            WaveDesc::Expr(source) => match crate::audio::expr::ExprWave::parse(source) {
+                Ok(wave) => Box::new(wave),
+                Err(_) => Box::new(NullWave),
+            },
+        }
+    }
+}
+
+fn random_wave_generator<R: Rng + ?Sized>(rng: &mut R) -> Box<dyn WaveGenerator + Send> {
+    let index = rng.gen_range(1..10);
 
     if index == 0 {
         let mut lt = LinearTransform::default();
-        lt.randomize();
+        lt.randomize(rng);
         return Box::new(lt);
     }
     if index == 1 {
@@ -46,116 +430,355 @@ fn random_wave_generator() -> Box<dyn WaveGenerator> {
         Box::new(SquareWave)
     } else if index == 5 {
         Box::new(TriWave)
+    } else if index == 6 {
+        Box::new(PolyBlepWave::new(BlepShape::Saw))
+    } else if index == 7 {
+        Box::new(PolyBlepWave::new(BlepShape::Pulse(0.5)))
+    } else if index == 8 {
+        Box::new(PolyBlepWave::new(BlepShape::Triangle))
     } else {
         Box::new(RandomWave::new())
     }
 }
 
-pub struct RandomWave { rng: ThreadRng  }
-impl RandomWave { pub fn new() -> RandomWave { RandomWave { rng: thread_rng() }} }
-impl WaveGenerator for RandomWave {  fn gen(&mut self, _: f32) -> f32 { self.rng.gen() } }
+pub struct RandomWave { rng: StdRng }
+impl RandomWave { pub fn new() -> RandomWave { RandomWave { rng: StdRng::from_entropy() }} }
+impl Default for RandomWave { fn default() -> Self { Self::new() } }
+impl WaveGenerator for RandomWave {  fn gen(&mut self, _: f32) -> f32 { self.rng.gen() } fn desc(&self) -> WaveDesc { WaveDesc::Random } }
 
-pub struct LinearTransform { pub alpha: Box<dyn WaveGenerator>, pub beta: Box<dyn WaveGenerator> }
-impl LinearTransform {  fn default() -> LinearTransform { LinearTransform { alpha: Box::new(IdentityWave), beta: Box::new(NullWave) } } }
-impl WaveGenerator for LinearTransform { fn gen(&mut self, t: f32) -> f32 { self.alpha.gen(t)*t + self.beta.gen(t) } }
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum NoiseColor { White, Pink, Brown }
 
-impl Randomize for LinearTransform {
-    fn randomize(&mut self) {
-        self.alpha = random_wave_generator();
-        self.beta = random_wave_generator();
+impl NoiseColor {
+    pub fn build(self) -> Box<dyn WaveGenerator + Send> {
+        match self {
+            NoiseColor::White => Box::new(WhiteNoise::new()),
+            NoiseColor::Pink => Box::new(PinkNoise::new()),
+            NoiseColor::Brown => Box::new(BrownNoise::new()),
+        }
     }
 }
 
-pub trait Voicing { fn gen(&self, osc: &mut Vec<&mut Oscillator>, t: f32, freq: f32) -> f32; }
+// xorshift, cheap enough to run per sample and Send, unlike ThreadRng.
+#[derive(Debug, Clone)]
+struct NoiseRng(u32);
+impl NoiseRng {
+    fn new() -> NoiseRng { NoiseRng(thread_rng().gen::<u32>() | 1) }
+    // uniform in [-1, 1).
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+}
 
-pub struct MeanVoicing;
-impl Voicing for MeanVoicing { fn gen(&self, oscs: &mut Vec<&mut Oscillator>, t: f32, freq: f32) -> f32 { 
-    oscs.iter_mut().map(|o| o.gen(t, freq)).sum::<f32>() / (oscs.len() as f32)
-}}
+/// Flat spectrum noise, uniform in -1..1. `t` is ignored.
+pub struct WhiteNoise { rng: NoiseRng }
+impl WhiteNoise { pub fn new() -> WhiteNoise { WhiteNoise { rng: NoiseRng::new() } } }
+impl Default for WhiteNoise { fn default() -> Self { Self::new() } }
+impl WaveGenerator for WhiteNoise { fn gen(&mut self, _: f32) -> f32 { self.rng.next() } fn desc(&self) -> WaveDesc { WaveDesc::Noise(NoiseColor::White) } }
 
-pub struct RepeatedVoicing(pub f32, pub f32, pub u16);
-impl Voicing for RepeatedVoicing { 
-     fn gen(&self, oscs: &mut Vec<&mut Oscillator>, t: f32, freq: f32) -> f32 { 
-        let mut total = 0.0;
-        for osc in oscs {
-            total += osc.gen(t, freq);
-            for i in 1..self.2 {
-                let i_f = i as f32;
-                total += osc.gen(t-(self.0*i_f), freq*(self.1*i_f));
-                total += osc.gen(t+(self.0*i_f), freq*(1.0/(self.1*i_f)));
-            }
-        }
-        total / (self.2 as f32) / 2.0
+/// -3dB per octave noise, white noise through Paul Kellet's filter.
+pub struct PinkNoise { rng: NoiseRng, b: [f32; 7] }
+impl PinkNoise { pub fn new() -> PinkNoise { PinkNoise { rng: NoiseRng::new(), b: [0.0; 7] } } }
+impl Default for PinkNoise { fn default() -> Self { Self::new() } }
+impl WaveGenerator for PinkNoise {
+    fn gen(&mut self, _: f32) -> f32 {
+        let w = self.rng.next();
+        let b = &mut self.b;
+        b[0] = 0.99886 * b[0] + w * 0.0555179;
+        b[1] = 0.99332 * b[1] + w * 0.0750759;
+        b[2] = 0.96900 * b[2] + w * 0.153_852;
+        b[3] = 0.86650 * b[3] + w * 0.3104856;
+        b[4] = 0.55000 * b[4] + w * 0.5329522;
+        b[5] = -0.7616 * b[5] - w * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + w * 0.5362;
+        b[6] = w * 0.115926;
+        (pink * 0.11).clamp(-1.0, 1.0)
     }
+    fn desc(&self) -> WaveDesc { WaveDesc::Noise(NoiseColor::Pink) }
 }
 
-unsafe impl Send for MeanVoicing {}
-unsafe impl Send for RepeatedVoicing {}
+/// -6dB per octave noise, integrated white noise with a leak so it
+/// doesn't drift away from zero.
+pub struct BrownNoise { rng: NoiseRng, level: f32 }
+impl BrownNoise { pub fn new() -> BrownNoise { BrownNoise { rng: NoiseRng::new(), level: 0.0 } } }
+impl Default for BrownNoise { fn default() -> Self { Self::new() } }
+impl WaveGenerator for BrownNoise {
+    fn gen(&mut self, _: f32) -> f32 {
+        self.level = (self.level + 0.02 * self.rng.next()) / 1.02;
+        (self.level * 3.5).clamp(-1.0, 1.0)
+    }
+    fn desc(&self) -> WaveDesc { WaveDesc::Noise(NoiseColor::Brown) }
+}
 
+/// Noise mixed into every voice, on top of the oscillator. `decay` (in
+/// seconds) fades it out after the note starts for percussive attacks,
+/// zero keeps it for the whole note.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct NoiseLayer { pub color: NoiseColor, pub level: f32, pub decay: f32 }
 
-pub struct Test {
-    pub osc: Oscillator,
-    pub voicing: Box<dyn Voicing>
+impl Default for NoiseLayer {
+    fn default() -> Self { NoiseLayer { color: NoiseColor::White, level: 0.0, decay: 0.05 } }
 }
 
-impl Test {
-    fn gen(&mut self, t: f32, freq: f32) -> f32 { 
-        self.voicing.gen(&mut vec![&mut self.osc], t, freq)
+impl NoiseLayer {
+    // gain of the layer `dt` seconds after the note started.
+    pub fn gain(&self, dt: f32) -> f32 {
+        if self.decay > 0.0 { self.level * (-dt.max(0.0) / self.decay).exp() } else { self.level }
     }
 }
 
-unsafe impl Send for Test {}
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum SubShape { #[default] Sine, Square }
+
+/// An oscillator one or two octaves under the note, mixed into every voice
+/// before the filter. a level of zero turns it off.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct SubOscillator { pub shape: SubShape, pub octaves: u8, pub level: f32 }
+
+impl Default for SubOscillator {
+    fn default() -> Self { SubOscillator { shape: SubShape::Sine, octaves: 1, level: 0.0 } }
+}
+
+impl SubOscillator {
+    pub fn ratio(&self) -> f32 { 0.5f32.powi(self.octaves.clamp(1, 2) as i32) }
+
+    // the next sample of a voice playing `freq`, from its phase in cycles.
+    pub fn tick(&self, phase: &mut f64, freq: f32, sample_rate: f32) -> f32 {
+        let dt = (freq * self.ratio() / sample_rate).clamp(0.0, 0.5);
+        let p = *phase as f32;
+        *phase = (*phase + dt as f64).fract();
+        let x = match self.shape {
+            SubShape::Sine => (std::f32::consts::TAU * p).sin(),
+            SubShape::Square => {
+                let naive = if p < 0.5 { 1.0 } else { -1.0 };
+                naive + poly_blep(p, dt) - poly_blep((p - 0.5).rem_euclid(1.0), dt)
+            },
+        };
+        x * self.level
+    }
+}
+
+pub struct LinearTransform { pub alpha: Box<dyn WaveGenerator + Send>, pub beta: Box<dyn WaveGenerator + Send> }
+impl LinearTransform {
+    fn default() -> LinearTransform { LinearTransform { alpha: Box::new(IdentityWave), beta: Box::new(NullWave) } }
+    pub fn linear_desc(&self) -> LinearDesc { LinearDesc { alpha: self.alpha.desc(), beta: self.beta.desc() } }
+    pub fn from_desc(d: &LinearDesc) -> LinearTransform { LinearTransform { alpha: d.alpha.build(), beta: d.beta.build() } }
+}
+impl WaveGenerator for LinearTransform {
+    fn gen(&mut self, t: f32) -> f32 { self.alpha.gen(t)*t + self.beta.gen(t) }
+    fn desc(&self) -> WaveDesc { WaveDesc::Linear(Box::new(self.linear_desc())) }
+}
+
+impl Randomize for LinearTransform {
+    fn randomize<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        self.alpha = random_wave_generator(rng);
+        self.beta = random_wave_generator(rng);
+    }
+}
 
 // wave generation on steroids
 pub struct Oscillator {
     pub ttf : LinearTransform,
     pub wtf : LinearTransform,
-    pub otf : Box<dyn WaveGenerator>,
+    pub otf : Box<dyn WaveGenerator + Send>,
+    pub sample_rate: f32,
 }
 
 impl Oscillator { 
+    // phases wrap at a whole number of periods of every wave: the naive sine
+    // repeats every 4, the naive square and triangle every 2, the rest every 1.
+    pub const PERIOD: f64 = 4.0;
+
+    pub fn new(otf: Box<dyn WaveGenerator + Send>) -> Oscillator {
+        Oscillator {
+            ttf: LinearTransform::default(),
+            wtf: LinearTransform::default(),
+            otf,
+            sample_rate: 44100.0,
+        }
+    }
+
+    // stateless, the phase is worked out from the time. it drifts once `t`
+    // gets large, voices use `gen_block` and keep their own phase.
     pub fn gen(&mut self, t: f32, freq: f32) -> f32 {  
-        self.otf.gen(self.ttf.gen(t)*self.wtf.gen(freq)) 
+        let f = self.wtf.gen(freq);
+        // assumes the time transform doesn't stray far from identity.
+        self.otf.set_increment(f / self.sample_rate);
+        self.otf.gen(self.ttf.gen(t)*f) 
     } 
+
+    /// Samples at frequencies `freq` into `out`, from `phase` on, advancing
+    /// it by each frequency over the sample rate. `t` only feeds the time
+    /// transform. the phase increment is taken from the first frequency of
+    /// the block.
+    pub fn gen_block(&mut self, phase: &mut f64, t: &[f32], freq: &[f32], out: &mut [f32]) {
+        let Some(first) = freq.first() else { return };
+        self.otf.set_increment(self.wtf.gen(*first) / self.sample_rate);
+        for ((x, t), f) in out.iter_mut().zip(t).zip(freq) {
+            let f = self.wtf.gen(*f);
+            // with the identity transform the warp is exactly zero, and the
+            // phase only comes from the accumulator.
+            let warp = (self.ttf.gen(*t) - t) * f;
+            *x = (*phase + warp as f64).rem_euclid(Oscillator::PERIOD) as f32;
+            *phase = (*phase + (f / self.sample_rate) as f64).rem_euclid(Oscillator::PERIOD);
+        }
+        self.otf.gen_block(out);
+    }
+
+    pub fn set_waveform(&mut self, shape: BlepShape) { self.otf = Box::new(PolyBlepWave::new(shape)) }
+
+    // width of the pulse wave, when that is the waveform.
+    pub fn pulse_width(&self) -> Option<f32> {
+        match self.otf.desc() { WaveDesc::PolyBlep(BlepShape::Pulse(w)) => Some(w), _ => None }
+    }
+    pub fn set_wavetable(&mut self, table: WavetableOscillator) { self.otf = Box::new(table) }
+
+    pub fn load_wavetable<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), hound::Error> {
+        self.set_wavetable(WavetableOscillator::load(path, WavetableOscillator::TABLE_SIZE)?);
+        Ok(())
+    }
+
+    pub fn desc(&self) -> OscillatorDesc {
+        OscillatorDesc { ttf: self.ttf.linear_desc(), wtf: self.wtf.linear_desc(), otf: self.otf.desc(), tables: Loaded(self.otf.tables().map(Arc::new)) }
+    }
+
+    pub fn apply_desc(&mut self, d: &OscillatorDesc) {
+        self.ttf = LinearTransform::from_desc(&d.ttf);
+        self.wtf = LinearTransform::from_desc(&d.wtf);
+        // an unchanged wave is kept as it is, not built again.
+        if self.otf.desc() == d.otf { return; }
+        self.otf = match (&d.otf, &d.tables.0) {
+            #[cfg(feature = "script")]
+            (WaveDesc::Script(path), Some(tables)) => Box::new(crate::audio::script::ScriptWave::new(path.clone(), OctaveTables::clone(tables))),
+            _ => d.otf.build(),
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct OscillatorDesc {
+    pub ttf: LinearDesc,
+    pub wtf: LinearDesc,
+    pub otf: WaveDesc,
+    // the tables of a script `otf`, see `read`.
+    #[serde(skip)]
+    pub tables: Loaded<OctaveTables>,
+}
+
+impl OscillatorDesc {
+    /// Reads the wavetable `otf` names, or runs its script, so the
+    /// oscillator is built without touching the disk.
+    pub fn read(&mut self) -> Result<(), String> {
+        match &mut self.otf {
+            WaveDesc::Wavetable(d) => d.read().map_err(|e| match &d.source {
+                WavetableSource::File(path) => format!("{}: {}", path.display(), e),
+                WavetableSource::Basic => e.to_string(),
+            }),
+            #[cfg(feature = "script")]
+            WaveDesc::Script(path) => {
+                let tables = crate::audio::script::ScriptWave::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                self.tables = Loaded(Some(Arc::new(tables)));
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Randomize for Oscillator {
-    fn randomize(&mut self) {
-        self.ttf.randomize();
-        self.wtf.randomize();
-        self.otf = random_wave_generator();
+    fn randomize<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        self.ttf.randomize(rng);
+        self.wtf.randomize(rng);
+        self.otf = random_wave_generator(rng);
     }
 }
 
 use rand::{thread_rng, Rng};
-use rand::rngs::ThreadRng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize};
+
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum LfoShape { Sine, Triangle, Saw, Square }
 
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum LfoDestination { Pitch, Amplitude, Cutoff }
+
+/// Low frequency oscillator.
+///
+/// `depth` is read in the unit of the destination: semitones for pitch,
+/// octaves for cutoff and a 0..1 gain reduction for amplitude.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct Lfo { pub rate: f32, pub depth: f32, pub shape: LfoShape, pub destination: LfoDestination }
+
+impl Lfo {
+    pub fn new(rate: f32, depth: f32, shape: LfoShape, destination: LfoDestination) -> Lfo {
+        Lfo { rate, depth, shape, destination }
+    }
+
+    // bipolar value in [-1, 1], not scaled by depth.
+    pub fn value(&self, t: f32) -> f32 {
+        let p = (t * self.rate).rem_euclid(1.0);
+        match self.shape {
+            LfoShape::Sine => (p * std::f32::consts::TAU).sin(),
+            LfoShape::Triangle => 1.0 - 4.0 * (p - 0.5).abs(),
+            LfoShape::Saw => 2.0 * p - 1.0,
+            LfoShape::Square => if p < 0.5 { 1.0 } else { -1.0 },
+        }
+    }
+
+    pub fn sample(&self, t: f32) -> f32 { self.value(t) * self.depth }
+}
 
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct EnvTimeAmp { time: f32, min: f32, max: f32 } 
 impl EnvTimeAmp { pub fn new(time: f32, min: f32, max: f32) -> Self { Self { time, min, max } } }
 
-#[derive(Debug)]
+/// ADSR envelope: attack time, decay time, sustain level and release time.
+///
+/// the sustain level holds for as long as the key is held. once released,
+/// the level ramps down to zero from wherever it was at the release.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Envelope(pub f32, pub f32, pub f32, pub f32);
 
 impl Envelope {
-    pub fn new() -> Envelope {  return Envelope(1.0, 1.0, 0.2, 1.0); }
+    pub fn new() -> Envelope { Envelope(1.0, 1.0, 0.2, 1.0) }
+
+    // level while the key is held, `dt` seconds after it was pressed.
+    fn held_level(&self, dt: f32) -> f32 {
+        let (attack, decay, sustain) = (self.0, self.1, self.2);
+        if dt <= 0.0 {
+            0.0
+        } else if dt < attack {
+            dt / attack
+        } else if dt < attack + decay {
+            let x = (dt - attack) / decay;
+            1.0 + (sustain - 1.0) * x
+        } else {
+            sustain
+        }
+    }
 
     pub fn sample(&self, t: f32, t0: f32, t1: Option<f32>) -> f32 {
-        macro_rules! lerp { ($t:expr, $a:expr, $b:expr) => ($a*(1.0-$t) + $b*$t) }
-        macro_rules! lt { ($a:expr, $b:expr) => ( ($a.clamp(0.0, $b)/$b) ) }
         match t1 {
-            Some(t1_) => { lerp!(lt!(t-t1_, self.3), self.2, 0.0) },
-            None => { 
-                lerp!(lt!(t-t0-self.0, self.1), lerp!(lt!(t-t0, self.0), 0.0, 1.0), self.2) 
-            }
+            Some(t1_) if t >= t1_ => {
+                let from = self.held_level(t1_ - t0);
+                let x = if self.3 > 0.0 { ((t - t1_) / self.3).min(1.0) } else { 1.0 };
+                from * (1.0 - x)
+            },
+            _ => self.held_level(t - t0)
         }
     }
 }
 
+impl Default for Envelope { fn default() -> Self { Self::new() } }
+
 impl Randomize for Envelope {
-    fn randomize(&mut self) {
-        let mut rng = thread_rng();
+    fn randomize<R: Rng + ?Sized>(&mut self, rng: &mut R) {
         self.0 = rng.gen();
         self.1 = rng.gen();
         self.2 = rng.gen();
@@ -164,10 +787,11 @@ impl Randomize for Envelope {
 }
 
 
+#[cfg(test)]
 mod wave_tests {
     use rand::Rng;
 
-    use crate::audio::waves::{EnvTimeAmp, Envelope, Oscillator, LinearTransform, ConstantWave, NullWave, SinWave, WaveGenerator};
+    use crate::audio::waves::{Envelope, Oscillator, LinearTransform, ConstantWave, NullWave, SinWave, WaveGenerator, PolyBlepWave, BlepShape, Lfo, LfoShape, LfoDestination, WavetableOscillator, WavetableDesc, WavetableSource, WaveDesc, Loaded, AdditiveOscillator, AdditiveDesc, NoiseColor, NoiseLayer, SubOscillator, SubShape};
 
     use super::IdentityWave;
 
@@ -192,6 +816,25 @@ mod wave_tests {
 
     }
 
+    #[test]
+    fn test_envelope_sustain_and_release() {
+        let e = Envelope(1.0, 1.0, 0.5, 1.0);
+
+        // sustain holds until the release, however long the key is held.
+        assert_approx_eq!(e.sample(1000.0, 0.0, None), 0.5);
+        assert_approx_eq!(e.sample(1000.0, 0.0, Some(2000.0)), 0.5);
+        assert_approx_eq!(e.sample(1000.5, 0.0, Some(1000.0)), 0.25);
+
+        // released during the attack: the release starts from that level.
+        assert_approx_eq!(e.sample(0.4, 0.0, Some(0.4)), 0.4);
+        assert_approx_eq!(e.sample(0.9, 0.0, Some(0.4)), 0.2);
+        assert_approx_eq!(e.sample(1.4, 0.0, Some(0.4)), 0.0);
+
+        let instant = Envelope(0.0, 0.0, 0.8, 0.0);
+        assert_approx_eq!(instant.sample(0.1, 0.0, None), 0.8);
+        assert_approx_eq!(instant.sample(0.2, 0.0, Some(0.1)), 0.0);
+    }
+
     #[test]
     fn test_constant_wave() {
         let mut g = ConstantWave;
@@ -214,11 +857,7 @@ mod wave_tests {
 
     #[test]
     fn test_simple_sin_wave() {
-        let mut test_generator = Oscillator { 
-            ttf: LinearTransform { alpha: Box::new(IdentityWave), beta: Box::new(NullWave) },
-            wtf: LinearTransform { alpha: Box::new(IdentityWave), beta: Box::new(NullWave) },
-            otf: Box::new(SinWave)
-        };
+        let mut test_generator = Oscillator::new(Box::new(SinWave));
 
         let mut control_generator = SinWave;
 
@@ -228,4 +867,158 @@ mod wave_tests {
         }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_poly_blep_matches_naive_away_from_edges() {
+        let mut saw = PolyBlepWave::new(BlepShape::Saw);
+        let mut pulse = PolyBlepWave::new(BlepShape::Pulse(0.5));
+        let mut tri = PolyBlepWave::new(BlepShape::Triangle);
+        for w in [&mut saw, &mut pulse, &mut tri] { w.set_increment(0.01); }
+
+        assert_approx_eq!(saw.gen(0.25), -0.5);
+        assert_approx_eq!(saw.gen(3.75), 0.5);
+        assert_approx_eq!(pulse.gen(0.25), 1.0);
+        assert_approx_eq!(pulse.gen(0.75), -1.0);
+        assert_approx_eq!(tri.gen(0.25), 0.0);
+        assert_approx_eq!(tri.gen(0.5), -1.0 + 8.0*0.01/3.0);
+    }
+
+    #[test]
+    fn test_poly_blep_smooths_discontinuities() {
+        let mut saw = PolyBlepWave::new(BlepShape::Saw);
+        saw.set_increment(0.01);
+        assert_approx_eq!(saw.gen(0.0), 0.0);
+        assert!(saw.gen(0.995) < 0.75);
+        assert!(saw.gen(1.005) > -0.75);
+
+        let mut pulse = PolyBlepWave::new(BlepShape::Pulse(0.5));
+        pulse.set_increment(0.01);
+        assert_approx_eq!(pulse.gen(0.5), 0.0);
+    }
+
+    #[test]
+    fn test_lfo_shapes() {
+        let sine = Lfo::new(2.0, 0.5, LfoShape::Sine, LfoDestination::Pitch);
+        assert_approx_eq!(sine.value(0.0), 0.0);
+        assert_approx_eq!(sine.value(0.125), 1.0);
+        assert_approx_eq!(sine.sample(0.125), 0.5);
+
+        let tri = Lfo::new(1.0, 1.0, LfoShape::Triangle, LfoDestination::Cutoff);
+        assert_approx_eq!(tri.value(0.0), -1.0);
+        assert_approx_eq!(tri.value(0.5), 1.0);
+        assert_approx_eq!(tri.value(1.25), 0.0);
+    }
+
+
+    #[test]
+    fn test_wavetable_morph() {
+        let mut table = WavetableOscillator::basic();
+        assert_eq!(table.frames(), 4);
+        assert_approx_eq!(table.gen(0.25), 1.0);
+        assert_approx_eq!(table.gen(1.25), 1.0);
+        // halfway between the saw and the square.
+        table.position = 2.5 / 3.0;
+        assert_approx_eq!(table.gen(0.25), (0.5 + 1.0) / 2.0);
+        table.modulate_position(1.0);
+        assert_approx_eq!(table.gen(0.75), -1.0);
+    }
+
+    #[test]
+    fn test_wavetable_from_wav() {
+        let path = std::env::temp_dir().join(format!("rsynth-table-{}.wav", std::process::id()));
+        let spec = hound::WavSpec { channels: 1, sample_rate: 44100, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        // two frames of 4 samples, a ramp and its negation.
+        for s in [0, 8192, 16384, 24576, 0, -8192, -16384, -24576] { writer.write_sample(s as i16).unwrap(); }
+        writer.finalize().unwrap();
+
+        let mut table = WavetableOscillator::load(&path, 4).unwrap();
+        let mut read = WavetableDesc::new(WavetableSource::File(path.clone()), 0.0);
+        read.read().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(table.frames(), 2);
+        assert_approx_eq!(table.gen(0.5), 0.5);
+        table.set_position(1.0);
+        assert_approx_eq!(table.gen(0.5), -0.5);
+
+        // built from a description, the frames read are played, the file
+        // is gone. one not read plays silence.
+        let mut built = table.desc().build();
+        assert_approx_eq!(built.gen(0.5), -0.5);
+        assert!(WaveDesc::Wavetable(read.clone()).build().gen(0.3).abs() > 0.1);
+        read.frames = Loaded::default();
+        assert!(read.read().is_err());
+        assert_eq!(WaveDesc::Wavetable(read).build().gen(0.3), 0.0);
+    }
+
+    #[test]
+    fn test_additive_partials() {
+        let mut sine = AdditiveOscillator::new(AdditiveDesc { amplitudes: vec![1.0], brightness: 1.0, odd_even: 0.0 });
+        assert_approx_eq!(sine.gen(0.25), 1.0);
+
+        let mut organ = AdditiveOscillator::new(AdditiveDesc { amplitudes: vec![1.0, 1.0, 1.0], brightness: 1.0, odd_even: -1.0 });
+        assert_eq!(organ.partial_level(2), 0.0);
+        assert_eq!(organ.partial_level(3), 1.0);
+        organ.desc.brightness = 0.5;
+        assert_approx_eq!(organ.partial_level(3), 1.0 / 3.0);
+
+        // the third partial is past nyquist and left out.
+        organ.set_increment(0.2);
+        organ.desc.odd_even = 0.0;
+        assert_approx_eq!(organ.gen(0.25), 0.5 * (std::f32::consts::TAU * 0.5).sin() / 1.5 + 1.0 / 1.5);
+    }
+
+    // power in the low and high halves of the spectrum, from the energy of
+    // the signal and of its first difference.
+    fn spectral_tilt(color: NoiseColor) -> f32 {
+        let mut noise = color.build();
+        let x: Vec<f32> = (0..50000).map(|_| noise.gen(0.0)).collect();
+        assert!(x.iter().all(|v| (-1.0..=1.0).contains(v)));
+        let power: f32 = x.iter().map(|v| v * v).sum();
+        let diff: f32 = x.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum();
+        diff / power
+    }
+
+    #[test]
+    fn test_noise_colors() {
+        // white noise differences have twice its power, darker noises less.
+        let (white, pink, brown) = (spectral_tilt(NoiseColor::White), spectral_tilt(NoiseColor::Pink), spectral_tilt(NoiseColor::Brown));
+        assert!((white - 2.0).abs() < 0.1);
+        assert!(pink < white && brown < pink);
+
+        let layer = NoiseLayer { color: NoiseColor::Pink, level: 0.5, decay: 0.1 };
+        assert_approx_eq!(layer.gain(0.0), 0.5);
+        assert!(layer.gain(0.5) < 0.01);
+        assert_eq!(NoiseLayer { decay: 0.0, ..layer }.gain(10.0), 0.5);
+    }
+
+    #[test]
+    fn test_pulse_width_modulation() {
+        // with no increment the pulse is the naive one, high for `width` of
+        // the cycle.
+        let duty = |wave: &mut PolyBlepWave| (0..100).filter(|i| wave.gen(*i as f32 / 100.0 + 0.005) > 0.0).count();
+        let mut wave = PolyBlepWave::new(BlepShape::Pulse(0.5));
+        assert_eq!(duty(&mut wave), 50);
+        wave.modulate_width(0.25);
+        assert_eq!(duty(&mut wave), 75);
+        wave.modulate_width(-1.0);
+        assert_eq!(duty(&mut wave), 5);
+    }
+
+    #[test]
+    fn test_sub_oscillator() {
+        // two octaves under 400 Hz at 8 kHz, a cycle takes 80 samples.
+        let sub = SubOscillator { shape: SubShape::Square, octaves: 2, level: 0.5 };
+        let mut phase = 0.0;
+        let samples: Vec<f32> = (0..160).map(|_| sub.tick(&mut phase, 400.0, 8000.0)).collect();
+        let rising = samples.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert_eq!(rising, 1);
+        assert!(samples.iter().all(|x| x.abs() <= 0.5 + 1e-6));
+        assert_approx_eq!(samples[20], 0.5);
+        assert_approx_eq!(samples[60], -0.5);
+
+        let sine = SubOscillator { shape: SubShape::Sine, octaves: 1, level: 1.0 };
+        let mut phase = 0.0;
+        let peak = (0..40).map(|_| sine.tick(&mut phase, 400.0, 8000.0)).fold(0.0f32, f32::max);
+        assert_approx_eq!(peak, 1.0);
+    }
+}
@@ -0,0 +1,192 @@
+//! Command line module.
+//!
+//! startup options. anything not given keeps its default: the default
+//! audio devices, the keymap in the config dir and the default patch.
+//!
+
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+
+use rsynth::audio::chord::ChordSettings;
+use rsynth::audio::device::AudioConfig;
+use rsynth::audio::parts::PartDesc;
+use rsynth::audio::mutate::Locks;
+use rsynth::audio::scale::Scale;
+use rsynth::input::KeyRelease;
+use rsynth::midi::MidiClock;
+
+#[derive(Parser, Debug, Default)]
+#[command(name = "rsynth", version, about = "a terminal synthesizer")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Output device name, as listed by --list-devices.
+    #[arg(long)]
+    pub device: Option<String>,
+    /// Output sample rate in Hz.
+    #[arg(long, global = true)]
+    pub sample_rate: Option<u32>,
+    /// Frames per audio callback.
+    #[arg(long)]
+    pub buffer_size: Option<u32>,
+    /// Capture the default input device.
+    #[arg(long)]
+    pub input: bool,
+    /// Capture this input device.
+    #[arg(long)]
+    pub input_device: Option<String>,
+    /// Print the output devices and the configs they support, then exit.
+    #[arg(long)]
+    pub list_devices: bool,
+    /// Preset to load at startup, by name: a saved one or one of the
+    /// factory keys, bass, lead, pad and fx. keys if not given.
+    #[arg(long, global = true)]
+    pub preset: Option<String>,
+    /// Wav file to load as the impulse response of the convolution reverb.
+    #[arg(long)]
+    pub impulse_response: Option<PathBuf>,
+    /// Midi clock: off, in (the transport follows the clock of the midi
+    /// input) or out (sends clock, start and stop to the first midi output).
+    #[arg(long, default_value = "off")]
+    pub midi_clock: MidiClock,
+    /// Chord added to every note: maj, min, 7, m7, sus4, or semitones
+    /// above the note like 0,4,7,11 for a chord of your own.
+    #[arg(long)]
+    pub chord: Option<ChordSettings>,
+    /// Scale the notes played are kept in, like 'd dorian'. followed by
+    /// 'filter' the notes out of it are left out instead of moved into it.
+    #[arg(long)]
+    pub scale: Option<Scale>,
+    /// Seed of the note generator, to play a line heard before again.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Rolls a random patch from this seed, the one shown after F4, in
+    /// place of the oscillator and envelope of the preset.
+    #[arg(long)]
+    pub patch_seed: Option<u64>,
+    /// Rhai script to play as the waveform of the oscillator, relative to
+    /// the scripts dir, reloaded as it's saved.
+    #[cfg(feature = "script")]
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+    /// Plays through jack as a client of this name, rsynth if none is
+    /// given, with the ports out_left, out_right and midi_in.
+    #[cfg(feature = "jack")]
+    #[arg(long, num_args = 0..=1, default_missing_value = "rsynth")]
+    pub jack: Option<String>,
+    /// Jack ports out_left and out_right are connected to, like
+    /// 'system:playback_1,system:playback_2'. the first physical outputs if
+    /// not given.
+    #[cfg(feature = "jack")]
+    #[arg(long, value_delimiter = ',')]
+    pub jack_connect: Vec<String>,
+    /// Sections random patches and mutations leave alone, like
+    /// 'envelope,filter'. of oscillator, envelope and filter.
+    #[arg(long)]
+    pub lock: Option<Locks>,
+    /// Another instrument played next to the preset, on a midi channel
+    /// and/or a zone of the keyboard: a preset name followed by settings,
+    /// like 'bass,ch=2,zone=C1-B2,vol=0.8,pan=-0.3', or layered on every
+    /// note, like 'pad,layer,detune=7,pan=0.5'. can be repeated.
+    #[arg(long)]
+    pub part: Vec<PartDesc>,
+    /// Splits the keyboard at this note, like C4: the keys below it play
+    /// another instrument, the split preset or the same patch.
+    #[arg(long)]
+    pub split: Option<String>,
+    /// Preset played below the split point.
+    #[arg(long)]
+    pub split_preset: Option<String>,
+    /// Project to pick up, by name, in place of the preset and parts. one
+    /// not saved yet is saved under this name by shift+F2.
+    #[arg(long)]
+    pub project: Option<String>,
+    /// Saves the project every minute and on quit.
+    #[arg(long)]
+    pub autosave: bool,
+    /// Doesn't reload the preset and keymap in use when their files change.
+    #[arg(long)]
+    pub no_watch: bool,
+    /// Keymap file to use instead of the one in the config dir.
+    #[arg(long)]
+    pub keymap: Option<PathBuf>,
+    /// How key releases are detected: auto, events (reported by the
+    /// terminal) or timeout (inferred from key repeat).
+    #[arg(long, default_value = "auto")]
+    pub key_release: KeyRelease,
+    /// Read the keyboard from /dev/input instead of the terminal.
+    #[cfg(all(feature = "evdev", target_os = "linux"))]
+    #[arg(long)]
+    pub evdev: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render a midi file to a wav file, without audio devices or the ui.
+    Render(RenderArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct RenderArgs {
+    /// Midi file to play.
+    #[arg(long)]
+    pub midi: PathBuf,
+    /// Wav file to write.
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Seconds rendered after the last note is released.
+    #[arg(long, default_value_t = 2.0)]
+    pub tail: f32,
+}
+
+impl Cli {
+    pub fn audio_config(&self) -> AudioConfig {
+        AudioConfig {
+            device: self.device.clone(),
+            sample_rate: self.sample_rate,
+            buffer_size: self.buffer_size,
+            input: self.input || self.input_device.is_some(),
+            input_device: self.input_device.clone(),
+            #[cfg(feature = "jack")]
+            jack: self.jack.clone().map(|name| rsynth::audio::jack::JackConfig { name, connect: self.jack_connect.clone() }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flags() {
+        let cli = Cli::try_parse_from([
+            "rsynth", "--device", "USB Audio", "--sample-rate", "48000", "--preset", "pad1",
+            "--keymap", "my.toml", "--input-device", "mic",
+        ]).unwrap();
+        assert_eq!(cli.preset.as_deref(), Some("pad1"));
+        assert_eq!(cli.keymap, Some(PathBuf::from("my.toml")));
+        let config = cli.audio_config();
+        assert_eq!(config.device.as_deref(), Some("USB Audio"));
+        assert_eq!(config.sample_rate, Some(48000));
+        assert!(config.input);
+        assert_eq!(cli.key_release, KeyRelease::Auto);
+        let cli = Cli::try_parse_from(["rsynth", "--key-release", "timeout"]).unwrap();
+        assert_eq!(cli.key_release, KeyRelease::Timeout);
+        let cli = Cli::try_parse_from(["rsynth", "--midi-clock", "out"]).unwrap();
+        assert_eq!(cli.midi_clock, MidiClock::Out);
+        assert!(Cli::try_parse_from(["rsynth", "--midi-clock", "both"]).is_err());
+        let cli = Cli::try_parse_from(["rsynth", "--chord", "0,3,7,10"]).unwrap();
+        assert_eq!(cli.chord.map(|c| c.user), Some(vec![3, 7, 10]));
+        let cli = Cli::try_parse_from(["rsynth", "--part", "bass,ch=2", "--part", "lead,zone=C5-C7"]).unwrap();
+        assert_eq!(cli.part.iter().map(|p| p.preset.as_str()).collect::<Vec<_>>(), ["bass", "lead"]);
+        let cli = Cli::try_parse_from(["rsynth", "--scale", "f# minor filter"]).unwrap();
+        assert_eq!(cli.scale.map(|s| s.root), Some(6));
+
+        assert!(Cli::try_parse_from(["rsynth", "--sample-rate", "fast"]).is_err());
+
+        let cli = Cli::try_parse_from(["rsynth", "render", "--midi", "song.mid", "--preset", "x", "-o", "out.wav"]).unwrap();
+        let Some(Command::Render(args)) = cli.command else { panic!("expected the render command") };
+        assert_eq!(cli.preset.as_deref(), Some("x"));
+        assert_eq!((args.midi, args.output, args.tail), (PathBuf::from("song.mid"), PathBuf::from("out.wav"), 2.0));
+    }
+}
@@ -0,0 +1,162 @@
+//! Disk module.
+//!
+//! loads and saves the presets and projects asked for from the keyboard. keys are
+//! handled under the lock of the instrument, so a key only asks: the files
+//! are read or written on a thread of their own, out of the lock, which is
+//! taken again only to apply what was read, as the watcher does.
+//!
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::audio::instrument::Instrument;
+use crate::preset::{self, Patch, PresetError};
+use crate::project::{self, Project};
+use crate::shutdown::Shutdown;
+
+/// Disk work asked for by a key.
+pub enum Request {
+    /// Loads the preset of that name.
+    LoadPreset(String),
+    /// Loads the preset that many places on from the one named, through
+    /// the factory presets and then the saved ones.
+    StepPreset(String, isize),
+    /// Saves the patch as the preset of that name.
+    SavePreset(String, Patch),
+    /// Saves the patch under a new snapshot name, with the seed it was
+    /// rolled from, if any, for the status.
+    SaveSnapshot(Patch, Option<u64>),
+    /// Loads the project of that name.
+    LoadProject(String),
+    /// Saves the session, taken as it was, as the project of that name.
+    SaveProject(String, Box<Project>),
+}
+
+/// What a request did, to be applied to the instrument.
+pub enum Done {
+    // the preset of that name, and whether the files it names were read.
+    Preset(String, Result<(Box<Patch>, Result<(), String>), PresetError>),
+    Project(String, Result<(Box<Project>, Result<(), String>), PresetError>),
+    Status(String),
+}
+
+impl Request {
+    /// Reads or writes what the request names, without the instrument.
+    pub fn run(self) -> Done {
+        match self {
+            Request::LoadPreset(name) => {
+                let loaded = preset::load_patch(&name).map(|mut patch| {
+                    let read = preset::read_files(&mut patch);
+                    (Box::new(patch), read)
+                });
+                Done::Preset(name, loaded)
+            },
+            Request::StepPreset(current, step) => {
+                let names = preset::preset_names(&preset::presets_dir());
+                let index = names.iter().position(|n| *n == current)
+                    .map_or(0, |i| (i as isize + step).rem_euclid(names.len() as isize) as usize);
+                Request::LoadPreset(names[index].clone()).run()
+            },
+            Request::SavePreset(name, patch) => Done::Status(match preset::save_patch(&name, &patch) {
+                Ok(_) => format!("saved preset {}", name),
+                Err(e) => e.to_string(),
+            }),
+            Request::SaveSnapshot(patch, seed) => {
+                let name = preset::snapshot_name(&preset::presets_dir());
+                Done::Status(match (preset::save_patch(&name, &patch), seed) {
+                    (Ok(_), Some(seed)) => format!("snapshot saved as preset {} (seed {})", name, seed),
+                    (Ok(_), None) => format!("snapshot saved as preset {}", name),
+                    (Err(e), _) => e.to_string(),
+                })
+            },
+            Request::LoadProject(name) => {
+                let loaded = project::load_project(&name).map(|mut project| {
+                    let read = project::read_files(&mut project);
+                    (Box::new(project), read)
+                });
+                Done::Project(name, loaded)
+            },
+            Request::SaveProject(name, project) => Done::Status(match project::save_project(&name, &project) {
+                Ok(path) => format!("saved project {} to {}", name, path.display()),
+                Err(e) => e.to_string(),
+            }),
+        }
+    }
+}
+
+impl Done {
+    pub fn apply(self, instrument: &mut Instrument) {
+        match self {
+            Done::Preset(name, Ok((patch, read))) => {
+                instrument.use_preset(&name, &patch);
+                instrument.set_status(match read {
+                    Ok(()) => format!("loaded preset {}", name),
+                    Err(e) => format!("loaded preset {}, could not read {}", name, e),
+                });
+            },
+            Done::Project(name, Ok((project, read))) => {
+                instrument.apply_project(&project);
+                instrument.set_project_name(&name);
+                instrument.set_status(match read {
+                    Ok(()) => format!("loaded project {}", name),
+                    Err(e) => format!("loaded project {}, could not read {}", name, e),
+                });
+            },
+            Done::Preset(_, Err(e)) | Done::Project(_, Err(e)) => instrument.set_status(e.to_string()),
+            Done::Status(status) => instrument.set_status(status),
+        }
+    }
+}
+
+/// Does the requests sent until shutdown, the ones already sent then
+/// included.
+pub fn thread_disk(instrument: Arc<Mutex<Instrument>>, requests: Receiver<Request>, shutdown: Shutdown) {
+    loop {
+        let request = match requests.recv_timeout(Duration::from_millis(100)) {
+            Ok(request) => request,
+            Err(RecvTimeoutError::Timeout) if !shutdown.is_requested() => continue,
+            Err(_) => return,
+        };
+        // stepped from the preset loaded by now, as presses can come
+        // quicker than the loads.
+        let request = match request {
+            Request::StepPreset(_, step) => Request::StepPreset(instrument.lock().unwrap().preset_name().to_string(), step),
+            request => request,
+        };
+        let done = request.run();
+        done.apply(&mut instrument.lock().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod disk_tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use crate::input::KeyboardHandler;
+
+    #[test]
+    fn test_load_left_to_the_thread() {
+        let mut instrument = Instrument::new();
+        instrument.use_preset("pad", &preset::factory_patch("pad").unwrap().unwrap());
+        instrument.randomize(7);
+        let (sender, requests) = std::sync::mpsc::channel();
+        instrument.set_disk(sender);
+
+        // the key only asks, the preset is loaded once the request is done.
+        instrument.handle_key_event(KeyEvent::new(KeyCode::F(3), KeyModifiers::NONE), 0.0);
+        assert_eq!(instrument.patch_seed(), Some(7));
+        let request = requests.try_recv().unwrap();
+        assert!(matches!(&request, Request::LoadPreset(name) if name == "pad"));
+        request.run().apply(&mut instrument);
+        assert_eq!(instrument.patch_seed(), None);
+        assert_eq!(instrument.preset_name(), "pad");
+        instrument.handle_key_event(KeyEvent::new(KeyCode::PageDown, KeyModifiers::CONTROL), 0.0);
+        assert_eq!(instrument.preset_name(), "pad");
+        assert!(matches!(requests.try_recv(), Ok(Request::StepPreset(name, 1)) if name == "pad"));
+
+        // the project is taken as it is when the key is pressed.
+        instrument.handle_key_event(KeyEvent::new(KeyCode::F(2), KeyModifiers::SHIFT), 0.0);
+        assert!(matches!(requests.try_recv(), Ok(Request::SaveProject(name, project)) if name == "session" && project.preset == "pad"));
+    }
+}
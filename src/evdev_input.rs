@@ -0,0 +1,172 @@
+//! Evdev input module.
+//!
+//! reads the keyboards under /dev/input directly. most terminals only pass
+//! on characters, with no releases and few keys at once, while the kernel
+//! reports every key going down and up. keys are read wherever the focus
+//! is, and reading them needs access to the devices (usually membership of
+//! the input group). characters follow the us layout.
+//!
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers};
+use evdev::{Device, EventSummary, KeyCode as Key};
+
+use crate::input::KeyboardHandler;
+use crate::shutdown::Shutdown;
+
+// the devices that look like keyboards: the ones with letters and a space bar.
+pub fn open_keyboards() -> Result<Vec<Device>, Error> {
+    let keyboards: Vec<Device> = evdev::enumerate()
+        .map(|(_, device)| device)
+        .filter(|device| device.supported_keys().is_some_and(|keys| keys.contains(Key::KEY_A) && keys.contains(Key::KEY_SPACE)))
+        .collect();
+    if keyboards.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, "no readable keyboard in /dev/input (is the user in the input group?)"));
+    }
+    keyboards.iter().try_for_each(|device| device.set_nonblocking(true))?;
+    Ok(keyboards)
+}
+
+// the same job as `thread_input`, for keys read from the devices. quitting
+// is still up to the terminal, which sees ctrl+c too.
+pub fn thread_evdev(mut devices: Vec<Device>, mut handlers: Vec<Arc<Mutex<dyn KeyboardHandler + Send>>>, shutdown: Shutdown) -> Result<(), Error> {
+    let mut keys = EvdevKeys::default();
+    let now = Instant::now();
+    let mut cleanup = Instant::now();
+    while !shutdown.is_requested() {
+        for device in devices.iter_mut() {
+            let events = match device.fetch_events() {
+                Ok(events) => events,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            };
+            for event in events {
+                let EventSummary::Key(_, key, value) = event.destructure() else { continue };
+                if let Some(event) = keys.translate(key, value) {
+                    handlers.iter_mut().for_each(|h| h.lock().unwrap().handle_key_event(event, now.elapsed().as_secs_f32()));
+                }
+            }
+        }
+        if cleanup.elapsed() > Duration::from_millis(25) {
+            handlers.iter_mut().for_each(|h| h.lock().unwrap().cleanup_events());
+            cleanup = Instant::now();
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+// turns device keys into the key events the terminal would send, keeping
+// track of the modifiers. a key is released as the code it was pressed
+// as, so letting go of shift before the key does not leave a note held.
+#[derive(Debug)]
+struct EvdevKeys {
+    modifiers: KeyModifiers,
+    pressed: HashMap<Key, KeyEvent>,
+}
+
+impl Default for EvdevKeys {
+    fn default() -> Self { EvdevKeys { modifiers: KeyModifiers::NONE, pressed: HashMap::new() } }
+}
+
+impl EvdevKeys {
+    // value is 1 for a press, 0 for a release and 2 for auto repeat, which
+    // comes through as a repeat rather than another press.
+    fn translate(&mut self, key: Key, value: i32) -> Option<KeyEvent> {
+        if let Some(modifier) = modifier(key) {
+            self.modifiers.set(modifier, value != 0);
+            return None;
+        }
+        match value {
+            1 => {
+                let shift = self.modifiers.contains(KeyModifiers::SHIFT);
+                let event = KeyEvent { code: key_code(key, shift)?, modifiers: self.modifiers, kind: KeyEventKind::Press, state: KeyEventState::NONE };
+                self.pressed.insert(key, event);
+                Some(event)
+            },
+            2 => self.pressed.get(&key).map(|event| KeyEvent { kind: KeyEventKind::Repeat, ..*event }),
+            _ => self.pressed.remove(&key).map(|event| KeyEvent { kind: KeyEventKind::Release, ..event }),
+        }
+    }
+}
+
+fn modifier(key: Key) -> Option<KeyModifiers> {
+    match key {
+        Key::KEY_LEFTSHIFT | Key::KEY_RIGHTSHIFT => Some(KeyModifiers::SHIFT),
+        Key::KEY_LEFTCTRL | Key::KEY_RIGHTCTRL => Some(KeyModifiers::CONTROL),
+        Key::KEY_LEFTALT | Key::KEY_RIGHTALT => Some(KeyModifiers::ALT),
+        _ => None,
+    }
+}
+
+fn key_code(key: Key, shift: bool) -> Option<KeyCode> {
+    const LETTERS: [Key; 26] = [
+        Key::KEY_A, Key::KEY_B, Key::KEY_C, Key::KEY_D, Key::KEY_E, Key::KEY_F, Key::KEY_G, Key::KEY_H, Key::KEY_I,
+        Key::KEY_J, Key::KEY_K, Key::KEY_L, Key::KEY_M, Key::KEY_N, Key::KEY_O, Key::KEY_P, Key::KEY_Q, Key::KEY_R,
+        Key::KEY_S, Key::KEY_T, Key::KEY_U, Key::KEY_V, Key::KEY_W, Key::KEY_X, Key::KEY_Y, Key::KEY_Z,
+    ];
+    const FUNCTION: [Key; 12] = [
+        Key::KEY_F1, Key::KEY_F2, Key::KEY_F3, Key::KEY_F4, Key::KEY_F5, Key::KEY_F6,
+        Key::KEY_F7, Key::KEY_F8, Key::KEY_F9, Key::KEY_F10, Key::KEY_F11, Key::KEY_F12,
+    ];
+    // the rest of the printable keys, unshifted and shifted.
+    const CHARS: [(Key, char, char); 21] = [
+        (Key::KEY_1, '1', '!'), (Key::KEY_2, '2', '@'), (Key::KEY_3, '3', '#'), (Key::KEY_4, '4', '$'),
+        (Key::KEY_5, '5', '%'), (Key::KEY_6, '6', '^'), (Key::KEY_7, '7', '&'), (Key::KEY_8, '8', '*'),
+        (Key::KEY_9, '9', '('), (Key::KEY_0, '0', ')'), (Key::KEY_MINUS, '-', '_'), (Key::KEY_EQUAL, '=', '+'),
+        (Key::KEY_LEFTBRACE, '[', '{'), (Key::KEY_RIGHTBRACE, ']', '}'), (Key::KEY_SEMICOLON, ';', ':'),
+        (Key::KEY_APOSTROPHE, '\'', '"'), (Key::KEY_GRAVE, '`', '~'), (Key::KEY_BACKSLASH, '\\', '|'),
+        (Key::KEY_COMMA, ',', '<'), (Key::KEY_DOT, '.', '>'), (Key::KEY_SLASH, '/', '?'),
+    ];
+    if let Some(i) = LETTERS.iter().position(|&k| k == key) {
+        let c = (b'a' + i as u8) as char;
+        return Some(KeyCode::Char(if shift { c.to_ascii_uppercase() } else { c }));
+    }
+    if let Some(i) = FUNCTION.iter().position(|&k| k == key) {
+        return Some(KeyCode::F(i as u8 + 1));
+    }
+    if let Some(&(_, c, shifted)) = CHARS.iter().find(|(k, _, _)| *k == key) {
+        return Some(KeyCode::Char(if shift { shifted } else { c }));
+    }
+    match key {
+        Key::KEY_SPACE => Some(KeyCode::Char(' ')),
+        Key::KEY_ENTER => Some(KeyCode::Enter),
+        Key::KEY_TAB => Some(KeyCode::Tab),
+        Key::KEY_BACKSPACE => Some(KeyCode::Backspace),
+        Key::KEY_ESC => Some(KeyCode::Esc),
+        Key::KEY_LEFT => Some(KeyCode::Left),
+        Key::KEY_RIGHT => Some(KeyCode::Right),
+        Key::KEY_UP => Some(KeyCode::Up),
+        Key::KEY_DOWN => Some(KeyCode::Down),
+        Key::KEY_PAGEUP => Some(KeyCode::PageUp),
+        Key::KEY_PAGEDOWN => Some(KeyCode::PageDown),
+        Key::KEY_HOME => Some(KeyCode::Home),
+        Key::KEY_END => Some(KeyCode::End),
+        Key::KEY_INSERT => Some(KeyCode::Insert),
+        Key::KEY_DELETE => Some(KeyCode::Delete),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod evdev_input_tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_keys() {
+        let mut keys = EvdevKeys::default();
+        assert_eq!(keys.translate(Key::KEY_LEFTSHIFT, 1), None);
+        let press = keys.translate(Key::KEY_LEFTBRACE, 1).unwrap();
+        assert_eq!((press.code, press.modifiers), (KeyCode::Char('{'), KeyModifiers::SHIFT));
+        assert_eq!(keys.translate(Key::KEY_LEFTBRACE, 2).unwrap().kind, KeyEventKind::Repeat);
+        // shift goes up first, the release still matches the press.
+        keys.translate(Key::KEY_LEFTSHIFT, 0);
+        let release = keys.translate(Key::KEY_LEFTBRACE, 0).unwrap();
+        assert_eq!((release.code, release.kind), (KeyCode::Char('{'), KeyEventKind::Release));
+        assert_eq!(keys.translate(Key::KEY_Q, 1).unwrap().code, KeyCode::Char('q'));
+    }
+}
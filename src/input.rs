@@ -1,7 +1,11 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use crossterm::event::{read, Event, KeyCode, KeyEventKind, KeyEvent, poll};
+use crossterm::event::{read, Event, KeyCode, KeyEventKind, KeyEvent, KeyModifiers, poll};
+use crossterm::event::{KeyboardEnhancementFlags, PushKeyboardEnhancementFlags, PopKeyboardEnhancementFlags};
+use rand::Rng;
+
+use crate::shutdown::Shutdown;
 
 #[macro_export]
 macro_rules! secs_now {
@@ -10,17 +14,82 @@ macro_rules! secs_now {
     };
 }
 
-pub fn thread_input(mut handlers: Vec<Arc<Mutex<dyn KeyboardHandler + Send>>>) -> Result<(), std::io::Error>  {
-    // let mut stdout = std::io::stdout();
+// how the end of a key press is found out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum KeyRelease {
+    // the terminal reports releases if it speaks the kitty keyboard
+    // protocol (or is a windows console), otherwise they are inferred.
+    #[default]
+    Auto,
+    // trust the terminal to report releases.
+    Events,
+    // a key counts as released once its auto repeat stops.
+    Timeout,
+}
+
+impl std::str::FromStr for KeyRelease {
+    type Err = String;
+    fn from_str(s: &str) -> Result<KeyRelease, String> {
+        match s {
+            "auto" => Ok(KeyRelease::Auto),
+            "events" => Ok(KeyRelease::Events),
+            "timeout" => Ok(KeyRelease::Timeout),
+            _ => Err(format!("unknown key release mode '{}', expected auto, events or timeout", s)),
+        }
+    }
+}
+
+// asks the terminal for release events while alive.
+struct EnhancedKeyboard;
+
+impl EnhancedKeyboard {
+    fn push() -> Result<EnhancedKeyboard, std::io::Error> {
+        let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES | KeyboardEnhancementFlags::REPORT_EVENT_TYPES;
+        crossterm::execute!(std::io::stdout(), PushKeyboardEnhancementFlags(flags))?;
+        Ok(EnhancedKeyboard)
+    }
+}
+
+impl Drop for EnhancedKeyboard {
+    fn drop(&mut self) { let _ = crossterm::execute!(std::io::stdout(), PopKeyboardEnhancementFlags); }
+}
+
+// reads keys until ctrl+c or a shutdown requested elsewhere. quitting
+// requests the shutdown for the other threads.
+pub fn thread_input(mut handlers: Vec<Arc<Mutex<dyn KeyboardHandler + Send>>>, shutdown: Shutdown, release: KeyRelease) -> Result<(), std::io::Error>  {
+    // windows consoles report releases without being asked.
+    let enhanced = !cfg!(windows) && release != KeyRelease::Timeout
+        && crossterm::terminal::supports_keyboard_enhancement().unwrap_or(false);
+    let _enhanced = if enhanced { Some(EnhancedKeyboard::push()?) } else { None };
+    let mut inference = match release {
+        KeyRelease::Events => None,
+        KeyRelease::Timeout => Some(ReleaseInference::default()),
+        KeyRelease::Auto => (!enhanced && !cfg!(windows)).then(ReleaseInference::default),
+    };
+
     let now = std::time::Instant::now();
-    loop {
+    while !shutdown.is_requested() {
+        if let Some(inference) = &mut inference {
+            for event in inference.expired(now.elapsed().as_secs_f32()) {
+                handlers.iter_mut().for_each(|h| h.lock().unwrap().handle_key_event(event, now.elapsed().as_secs_f32()));
+            }
+        }
         if poll(Duration::from_millis(25))? {
-            match read()? {
+            let event = match (read()?, &mut inference) {
+                (Event::Key(event), Some(inference)) => {
+                    let held = handlers.iter().any(|h| h.lock().unwrap().holds_key(&event));
+                    Event::Key(inference.observe(event, held, now.elapsed().as_secs_f32()))
+                },
+                (event, _) => event,
+            };
+            match event {
+                // q plays a note, quitting is on ctrl+c.
                 Event::Key(crossterm::event::KeyEvent {
-                    code: KeyCode::Char('q'),
-                    kind: KeyEventKind::Release,
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                    kind: KeyEventKind::Press,
                     ..
-                }) => break,
+                }) => shutdown.request(),
                 crossterm::event::Event::Key(event) => { 
                     handlers.iter_mut().for_each(
                         |handler| handler.lock().unwrap().handle_key_event(event, now.elapsed().as_secs_f32())
@@ -38,22 +107,123 @@ pub fn thread_input(mut handlers: Vec<Arc<Mutex<dyn KeyboardHandler + Send>>>) -
 pub trait KeyboardHandler {
     fn handle_key_event(&mut self, event: crossterm::event::KeyEvent, timestamp: f32);
     fn cleanup_events(&mut self) {}
+    // keys held down to play, like notes, rather than struck. only their
+    // releases are inferred.
+    fn holds_key(&self, _event: &KeyEvent) -> bool { false }
+}
+
+// what triggered a note: a computer key, a midi note number, the looper
+// or the generator playing a midi note number, or chord mode adding an
+// interval to the midi note of a note played.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NoteKey { Key(KeyCode), Midi(u8), Loop(u8), Generated(u8), Chord(u8, u8) }
+
+impl std::fmt::Display for NoteKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoteKey::Key(KeyCode::Char(c)) => write!(f, "{}", c),
+            NoteKey::Key(k) => write!(f, "{}", k),
+            NoteKey::Midi(n) => write!(f, "#{}", n),
+            NoteKey::Loop(n) => write!(f, "~{}", n),
+            NoteKey::Generated(n) => write!(f, "*{}", n),
+            NoteKey::Chord(n, i) => write!(f, "#{}+{}", n, i),
+        }
+    }
+}
+
+// velocity given to notes played from the computer keyboard, which has
+// no notion of it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum KeyboardVelocity { Fixed(f32), Random { min: f32, max: f32 } }
+
+impl KeyboardVelocity {
+    pub fn sample(&self) -> f32 {
+        match *self {
+            KeyboardVelocity::Fixed(v) => v,
+            KeyboardVelocity::Random { min, max } if max > min => rand::thread_rng().gen_range(min..max),
+            KeyboardVelocity::Random { min, .. } => min,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct KeyboardBufferEvent {
-    pub key: KeyCode,
+    pub key: NoteKey,
+    pub velocity: f32,
     pub time_press: f32,
     pub time_release: Option<f32>,
 }
 
 #[derive(Debug)]
 pub struct KeyboardBuffer {
-    pub event_buffer : std::collections::HashMap<KeyCode, KeyboardBufferEvent>
+    pub event_buffer : std::collections::HashMap<NoteKey, KeyboardBufferEvent>,
+    pub velocity: KeyboardVelocity,
+    // while the sustain is down, the keys let go meanwhile. they are
+    // released once it's up.
+    sustained: Option<Vec<NoteKey>>,
+}
+
+// infers releases on terminals that only report presses. a held key
+// repeats its press, first after the auto repeat delay and then at the
+// repeat rate, so a key not seen again for longer than that was let go.
+// releases come late by the timeout, and a key struck again within it
+// counts as held. keys with other modifiers are other keys.
+#[derive(Debug)]
+pub struct ReleaseInference {
+    // seconds to wait for the first repeat, and for the following ones.
+    pub delay: f32,
+    pub interval: f32,
+    held: HashMap<(KeyCode, KeyModifiers), HeldKey>,
+}
+
+#[derive(Debug)]
+struct HeldKey { event: KeyEvent, last_seen: f32, repeating: bool }
+
+impl Default for ReleaseInference {
+    fn default() -> Self { ReleaseInference { delay: 0.6, interval: 0.1, held: HashMap::new() } }
+}
+
+impl ReleaseInference {
+    // the event as it would have been reported with releases on: presses
+    // of a key already held are repeats. keys that aren't `held` down to
+    // play are left as they are, each press a press.
+    pub fn observe(&mut self, event: KeyEvent, held: bool, timestamp: f32) -> KeyEvent {
+        let key = (event.code, event.modifiers);
+        if !held && !self.held.contains_key(&key) { return event; }
+        match event.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => match self.held.get_mut(&key) {
+                Some(held) => {
+                    held.last_seen = timestamp;
+                    held.repeating = true;
+                    KeyEvent { kind: KeyEventKind::Repeat, ..event }
+                },
+                None => {
+                    self.held.insert(key, HeldKey { event, last_seen: timestamp, repeating: false });
+                    KeyEvent { kind: KeyEventKind::Press, ..event }
+                },
+            },
+            KeyEventKind::Release => {
+                self.held.remove(&key);
+                event
+            },
+        }
+    }
+
+    // release events for the keys that stopped repeating.
+    pub fn expired(&mut self, timestamp: f32) -> Vec<KeyEvent> {
+        let mut released = Vec::new();
+        self.held.retain(|_, held| {
+            let timeout = if held.repeating { self.interval } else { self.delay };
+            let alive = timestamp - held.last_seen < timeout;
+            if !alive { released.push(KeyEvent { kind: KeyEventKind::Release, ..held.event }); }
+            alive
+        });
+        released
+    }
 }
 
 impl KeyboardBuffer {
-    pub fn new() -> KeyboardBuffer { KeyboardBuffer { event_buffer: std::collections::HashMap::<KeyCode, KeyboardBufferEvent>::default() } }
+    pub fn new() -> KeyboardBuffer { KeyboardBuffer { event_buffer: std::collections::HashMap::<NoteKey, KeyboardBufferEvent>::default(), velocity: KeyboardVelocity::Fixed(1.0), sustained: None } }
     pub fn clean_stale_events(&mut self, now: f32, stale_time_limit: Option<f32>) {
         self.event_buffer.retain(|_, v| {
             match v.time_release {
@@ -63,27 +233,125 @@ impl KeyboardBuffer {
         });
     }
 
-    pub fn event_buffer(&mut self) -> &mut HashMap<KeyCode, KeyboardBufferEvent> {
+    pub fn event_buffer(&mut self) -> &mut HashMap<NoteKey, KeyboardBufferEvent> {
         &mut self.event_buffer
     }
+
+    // unlike a key event, pressing a key that is still in the buffer
+    // starts it over.
+    pub fn press(&mut self, key: NoteKey, velocity: f32, timestamp: f32) {
+        self.event_buffer.insert(key, KeyboardBufferEvent { key, velocity, time_press: timestamp, time_release: None });
+        self.unsustain(key);
+    }
+
+    pub fn sustain(&self) -> bool { self.sustained.is_some() }
+
+    /// Puts the sustain down, or lets it up, releasing the keys let go
+    /// while it was down.
+    pub fn set_sustain(&mut self, on: bool, timestamp: f32) {
+        match (on, self.sustained.take()) {
+            (true, keys) => self.sustained = Some(keys.unwrap_or_default()),
+            (false, Some(keys)) => keys.into_iter().for_each(|k| self.release(k, timestamp)),
+            (false, None) => (),
+        }
+    }
+
+    // a key let go by the player: released, unless the sustain holds it.
+    pub fn note_off(&mut self, key: NoteKey, timestamp: f32) {
+        match &mut self.sustained {
+            Some(keys) if self.event_buffer.contains_key(&key) => { if !keys.contains(&key) { keys.push(key); } },
+            _ => self.release(key, timestamp),
+        }
+    }
+
+    // a key pressed again is held by the player rather than the sustain.
+    fn unsustain(&mut self, key: NoteKey) {
+        if let Some(keys) = &mut self.sustained { keys.retain(|k| *k != key); }
+    }
+
+    pub fn release(&mut self, key: NoteKey, timestamp: f32) {
+        if let Some(e) = self.event_buffer.get_mut(&key) {
+            e.time_release.get_or_insert(timestamp);
+        }
+    }
+
+    pub fn release_all(&mut self, timestamp: f32) {
+        self.event_buffer.values_mut().for_each(|e| { e.time_release.get_or_insert(timestamp); });
+    }
+
+    pub fn held(&self) -> impl Iterator<Item = &NoteKey> {
+        self.event_buffer.values().filter(|e| e.time_release.is_none()).map(|e| &e.key)
+    }
 }
 
+impl Default for KeyboardBuffer { fn default() -> Self { Self::new() } }
+
 impl KeyboardHandler for KeyboardBuffer {
     fn handle_key_event(&mut self, event: KeyEvent, timestamp: f32) {
         match event {
             KeyEvent {kind: KeyEventKind::Press, ..} => {
-                self.event_buffer().entry(event.code).or_insert(KeyboardBufferEvent {
-                    key: event.code,
+                let velocity = self.velocity.sample();
+                self.event_buffer().entry(NoteKey::Key(event.code)).or_insert(KeyboardBufferEvent {
+                    key: NoteKey::Key(event.code),
+                    velocity,
                     time_press: timestamp,
                     time_release: None,
                 });
+                self.unsustain(NoteKey::Key(event.code));
             },
+            KeyEvent {kind: KeyEventKind::Release, ..} if self.sustain() => self.note_off(NoteKey::Key(event.code), timestamp),
             KeyEvent {kind: KeyEventKind::Release, ..} => {
-                if let Some(buffer_event) = self.event_buffer().get_mut(&event.code) {
+                if let Some(buffer_event) = self.event_buffer().get_mut(&NoteKey::Key(event.code)) {
                     buffer_event.time_release = Some(timestamp);
                 } 
             },
             _ => ()
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod input_tests {
+    use super::*;
+
+    #[test]
+    fn test_release_inference() {
+        let mut inference = ReleaseInference::default();
+        let press = KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE);
+        assert_eq!(inference.observe(press, true, 0.0).kind, KeyEventKind::Press);
+        // still within the auto repeat delay.
+        assert!(inference.expired(0.5).is_empty());
+        assert_eq!(inference.observe(press, true, 0.55).kind, KeyEventKind::Repeat);
+        assert!(inference.expired(0.6).is_empty());
+        // once repeating, a missed repeat is enough.
+        let released = inference.expired(0.7);
+        assert_eq!(released.len(), 1);
+        assert_eq!((released[0].code, released[0].kind), (KeyCode::Char('a'), KeyEventKind::Release));
+        assert_eq!(inference.observe(press, true, 0.8).kind, KeyEventKind::Press);
+
+        // the same key with control is a command, struck twice it's two
+        // presses and never released.
+        let command = KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL);
+        assert_eq!(inference.observe(command, false, 0.85).kind, KeyEventKind::Press);
+        assert_eq!(inference.observe(command, false, 0.9).kind, KeyEventKind::Press);
+        assert_eq!(inference.expired(2.0).iter().map(|e| e.modifiers).collect::<Vec<_>>(), [KeyModifiers::NONE]);
+    }
+
+    #[test]
+    fn test_sustain_defers_releases() {
+        let mut buffer = KeyboardBuffer::new();
+        let (a, b) = (NoteKey::Midi(60), NoteKey::Midi(64));
+        buffer.press(a, 1.0, 0.0);
+        buffer.set_sustain(true, 0.1);
+        buffer.press(b, 1.0, 0.2);
+        buffer.note_off(a, 0.3);
+        buffer.note_off(b, 0.4);
+        assert_eq!(buffer.held().count(), 2);
+        // struck again while sustained, it's held by the key again.
+        buffer.press(b, 1.0, 0.5);
+        buffer.set_sustain(false, 0.6);
+        assert_eq!(buffer.held().collect::<Vec<_>>(), [&b]);
+        assert_eq!(buffer.event_buffer[&a].time_release, Some(0.6));
+        buffer.note_off(b, 0.7);
+        assert_eq!(buffer.held().count(), 0);
+    }
+}
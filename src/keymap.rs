@@ -0,0 +1,190 @@
+//! Keymap module.
+//!
+//! maps computer keys to notes. the default map is the two-row layout of
+//! trackers, it can be replaced by a toml file in the config directory.
+//! the file also names the key held as a sustain pedal, space by default.
+//!
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use crossterm::event::KeyCode;
+use serde::{Serialize, Deserialize};
+
+use crate::audio::modulation::freq_to_note;
+
+// keys in the order of the notes they play, chromatically from a C. the
+// zxcv row starts on the base note and the qwerty row an octave above,
+// black keys sit on the row above each of them.
+const LOWER_ROW: &str = "zsxdcvgbhnjm,l.;/";
+const UPPER_ROW: &str = "q2w3er5t6y7ui9o0p";
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum KeyNote { Name(String), Freq(f32) }
+
+// `sustain` is a character, `space`, or empty for no sustain key.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Keymap {
+    #[serde(default = "default_sustain")]
+    pub sustain: String,
+    pub keys: BTreeMap<String, KeyNote>,
+}
+
+fn default_sustain() -> String { String::from("space") }
+
+#[derive(Debug)]
+pub enum KeymapError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    InvalidKey(String),
+    InvalidNote(String),
+}
+
+impl std::fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeymapError::Io(e) => write!(f, "keymap io error: {}", e),
+            KeymapError::Parse(e) => write!(f, "invalid keymap: {}", e),
+            KeymapError::InvalidKey(k) => write!(f, "invalid key in keymap: {:?}", k),
+            KeymapError::InvalidNote(n) => write!(f, "invalid note in keymap: {:?}", n),
+        }
+    }
+}
+
+impl std::error::Error for KeymapError {}
+impl From<std::io::Error> for KeymapError { fn from(e: std::io::Error) -> Self { KeymapError::Io(e) } }
+impl From<toml::de::Error> for KeymapError { fn from(e: toml::de::Error) -> Self { KeymapError::Parse(e) } }
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+pub fn note_name(note: i32) -> String {
+    format!("{}{}", NOTE_NAMES[note.rem_euclid(12) as usize], note.div_euclid(12) - 1)
+}
+
+/// Parses a note name like "C4", "F#3" or "Bb-1" into a midi note number.
+pub fn parse_note_name(name: &str) -> Option<i32> {
+    let mut chars = name.trim().chars();
+    let semitone = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0, 'D' => 2, 'E' => 4, 'F' => 5, 'G' => 7, 'A' => 9, 'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.chars().next()? {
+        '#' => (1, &rest[1..]),
+        'b' => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let octave: i32 = octave.parse().ok()?;
+    Some(12 * (octave + 1) + semitone + accidental)
+}
+
+impl Keymap {
+    // two-row layout, `base` is the midi note of the z key.
+    pub fn two_row(base: i32) -> Keymap {
+        let row = |keys: &'static str, base: i32| keys.chars().enumerate()
+            .map(move |(i, c)| (c.to_string(), KeyNote::Name(note_name(base + i as i32))));
+        Keymap { sustain: default_sustain(), keys: row(LOWER_ROW, base).chain(row(UPPER_ROW, base + 12)).collect() }
+    }
+
+    pub fn parse(text: &str) -> Result<Keymap, KeymapError> {
+        let keymap: Keymap = toml::from_str(text)?;
+        keymap.notes()?;
+        keymap.sustain_key()?;
+        Ok(keymap)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Keymap, KeymapError> {
+        Keymap::parse(&std::fs::read_to_string(path)?)
+    }
+
+    // midi note number of each key, fractional for keys mapped to a frequency.
+    pub fn notes(&self) -> Result<HashMap<KeyCode, f32>, KeymapError> {
+        self.keys.iter().map(|(key, note)| {
+            let mut chars = key.chars();
+            let c = match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(KeymapError::InvalidKey(key.clone())),
+            };
+            let note = match note {
+                KeyNote::Freq(f) => freq_to_note(*f),
+                KeyNote::Name(n) => parse_note_name(n).ok_or_else(|| KeymapError::InvalidNote(n.clone()))? as f32,
+            };
+            Ok((KeyCode::Char(c), note))
+        }).collect()
+    }
+}
+
+impl Keymap {
+    pub fn sustain_key(&self) -> Result<Option<KeyCode>, KeymapError> {
+        let mut chars = self.sustain.chars();
+        match (self.sustain.as_str(), chars.next(), chars.next()) {
+            ("", ..) => Ok(None),
+            ("space", ..) => Ok(Some(KeyCode::Char(' '))),
+            (_, Some(c), None) => Ok(Some(KeyCode::Char(c))),
+            _ => Err(KeymapError::InvalidKey(self.sustain.clone())),
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self { Keymap::two_row(48) }
+}
+
+pub fn keymap_path() -> PathBuf {
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("rsynth").join("keymap.toml")
+}
+
+#[cfg(test)]
+mod keymap_tests {
+    use super::*;
+    use crate::audio::modulation::note_to_freq;
+
+    #[test]
+    fn test_note_names() {
+        assert_eq!(parse_note_name("C4"), Some(60));
+        assert_eq!(parse_note_name("A4"), Some(69));
+        assert_eq!(parse_note_name("F#3"), Some(54));
+        assert_eq!(parse_note_name("Bb2"), Some(46));
+        assert_eq!(parse_note_name("C-1"), Some(0));
+        assert_eq!(parse_note_name("H2"), None);
+        assert_eq!(parse_note_name("C"), None);
+    }
+
+    #[test]
+    fn test_note_name_round_trip() {
+        assert_eq!(note_name(60), "C4");
+        assert_eq!(note_name(-1), "B-2");
+        assert!((0..128).all(|n| parse_note_name(&note_name(n)) == Some(n)));
+    }
+
+    #[test]
+    fn test_default_keymap() {
+        let n = Keymap::default().notes().unwrap();
+        assert_eq!(n.len(), LOWER_ROW.len() + UPPER_ROW.len());
+        assert_eq!(n[&KeyCode::Char('z')], 48.0);
+        assert_eq!(n[&KeyCode::Char('m')], 59.0);
+        assert_eq!(n[&KeyCode::Char(',')], 60.0);
+        assert_eq!(n[&KeyCode::Char('q')], 60.0);
+        assert_eq!(n[&KeyCode::Char('2')], 61.0);
+        assert_eq!(n[&KeyCode::Char('p')], 76.0);
+    }
+
+    #[test]
+    fn test_azerty_keymap() {
+        let keymap = Keymap::parse(include_str!("../keymaps/azerty.toml")).unwrap();
+        assert_eq!(keymap.notes().unwrap().len(), Keymap::default().keys.len());
+    }
+
+    #[test]
+    fn test_custom_keymap() {
+        let keymap = Keymap::parse("[keys]\nw = \"C3\"\nq = 432.0\n").unwrap();
+        let n = keymap.notes().unwrap();
+        assert!((note_to_freq(n[&KeyCode::Char('q')]) - 432.0).abs() < 1e-2);
+        assert!(Keymap::parse("[keys]\nw = \"X3\"\n").is_err());
+        assert!(Keymap::parse("[keys]\nww = \"C3\"\n").is_err());
+        assert_eq!(keymap.sustain_key().unwrap(), Some(KeyCode::Char(' ')));
+        let keymap = Keymap::parse("sustain = \"<\"\n[keys]\n").unwrap();
+        assert_eq!(keymap.sustain_key().unwrap(), Some(KeyCode::Char('<')));
+        assert!(Keymap::parse("sustain = \"tab\"\n[keys]\n").is_err());
+    }
+}
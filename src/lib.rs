@@ -0,0 +1,28 @@
+//! rsynth, a terminal synthesizer.
+//!
+//! the engine: instruments, wave generators, effects, presets and midi,
+//! with no terminal attached. the binary in `main.rs` adds the command
+//! line and the ui, and wires the engine to the audio device, the keyboard
+//! and midi input.
+//!
+
+pub mod audio;
+pub mod disk;
+#[cfg(all(feature = "evdev", target_os = "linux"))]
+pub mod evdev_input;
+pub mod input;
+pub mod keymap;
+pub mod midi;
+pub mod preset;
+pub mod project;
+pub mod render;
+pub mod shutdown;
+pub mod visual;
+pub mod watch;
+
+pub use audio::waves;
+pub use audio::instrument::{Engine, Instrument, InstrumentSnapshot, NoteEvent};
+pub use audio::params::{Param, ParamId};
+pub use input::{KeyboardHandler, NoteKey};
+pub use midi::{MidiHandler, MidiMessage};
+pub use preset::{Patch, PresetError};
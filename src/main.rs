@@ -1,64 +1,198 @@
-use std::sync::{Arc, Mutex};
-use crossterm::ExecutableCommand;
-use crossterm::terminal::Clear;
-use crossterm::cursor::MoveTo;
-use audio::instrument::{Instrument, thread_audio};
-use input::{KeyboardHandler, thread_input};
-
+mod cli;
+mod tui;
 
-pub mod audio;
-pub mod input;
+use std::sync::{Arc, Mutex};
+use clap::Parser;
+use rsynth::{disk, keymap, preset, project, render, watch};
+use cli::{Cli, Command};
+use rsynth::audio::device::describe_output_devices;
+use rsynth::audio::instrument::{Instrument, thread_audio};
+use rsynth::input::{KeyboardHandler, thread_input};
+use rsynth::keymap::{Keymap, parse_note_name};
+use rsynth::midi::{MidiClock, MidiHandler, MidiSender, connect_midi_input, connect_midi_output};
+use rsynth::shutdown::Shutdown;
+use tui::thread_tui;
 
-// ====================
-//      AUDIO
+const AUTOSAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
 
-fn thread_debug(m: Arc<Mutex<Instrument>>) {
-    let mut stdout = std::io::stdout();
-    loop {
-        {
-            let i = &mut m.lock().unwrap();
-            stdout.execute(Clear(crossterm::terminal::ClearType::All)).unwrap();
-            stdout.execute(MoveTo(0, 0)).unwrap();
-            println!("{:?}", i.keyboard_buffer().event_buffer());
-            // println!("🔥");
+fn main() {
+    let cli = Cli::parse();
+    if let Some(Command::Render(args)) = &cli.command {
+        if let Err(e) = render::render_file(&args.midi, &args.output, args.tail, cli.preset.as_deref(), cli.sample_rate) {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-        std::thread::sleep(std::time::Duration::from_millis(250));
+        return;
     }
-}
-
-struct DebugKeyboardHandler;
-impl KeyboardHandler for DebugKeyboardHandler {
-    fn handle_key_event(&mut self, event: crossterm::event::KeyEvent, timestamp: f32) {
-        match event.kind {
-            crossterm::event::KeyEventKind::Press => { println!("press"); },
-            crossterm::event::KeyEventKind::Release => { println!("release"); },
-            _ => ()
+    if cli.list_devices {
+        match describe_output_devices(&cpal::default_host()) {
+            Ok(lines) => lines.iter().for_each(|l| println!("{}", l)),
+            Err(e) => { eprintln!("{}", e); std::process::exit(1); }
         }
+        return;
     }
-}
-
+    let audio_config = cli.audio_config();
 
-fn main() {
-    let instr = Instrument::new();
-    let debug = DebugKeyboardHandler {};
+    let mut instr = Instrument::new();
+    // without one given, the first factory preset rather than a bare sine.
+    let preset = cli.preset.as_deref().unwrap_or(preset::FACTORY[0].0);
+    if let Err(e) = instr.load_preset(preset) { eprintln!("preset {}: {}", preset, e); std::process::exit(1); }
+    if let Some(locks) = cli.lock { instr.set_locks(locks); }
+    if let Some(seed) = cli.patch_seed { instr.randomize(seed); }
+    #[cfg(feature = "script")]
+    if let Some(path) = &cli.script {
+        if let Err(e) = instr.load_script(path) { eprintln!("{}: {}", path.display(), e); std::process::exit(1); }
+    }
+    for part in &cli.part {
+        if let Err(e) = instr.add_part(part.clone()) { eprintln!("part {}: {}", part.name(), e); std::process::exit(1); }
+    }
+    if let Some(split) = &cli.split {
+        let Some(point) = parse_note_name(split).filter(|n| (1..128).contains(n)) else {
+            eprintln!("invalid split point {}, expected a note like C4", split);
+            std::process::exit(1);
+        };
+        if let Err(e) = instr.set_split(Some(point as u8), cli.split_preset.as_deref().unwrap_or_default()) {
+            eprintln!("split: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(name) = &cli.project {
+        if project::project_path(name).exists() {
+            if let Err(e) = instr.load_project(name) { eprintln!("project {}: {}", name, e); std::process::exit(1); }
+        }
+        instr.set_project_name(name);
+    }
+    if let Some(path) = &cli.impulse_response {
+        if let Err(e) = instr.load_impulse_response(path) { eprintln!("{}: {}", path.display(), e); std::process::exit(1); }
+    }
+    // after the preset, the keymap follows the keyboard rather than the patch.
+    // one given on the command line has to load, the default one is optional.
+    let keymap_path = cli.keymap.clone().unwrap_or_else(keymap::keymap_path);
+    let keymap_in_use = cli.keymap.is_some() || keymap_path.exists();
+    if keymap_in_use {
+        if let Err(e) = Keymap::load(&keymap_path).and_then(|k| instr.apply_keymap(&k)) {
+            if cli.keymap.is_some() { eprintln!("{}: {}", keymap_path.display(), e); std::process::exit(1); }
+            instr.set_status(e.to_string());
+        }
+    }
+    if let Some(chord) = &cli.chord { instr.set_chord(chord.clone()); }
+    if let Some(scale) = cli.scale { instr.set_scale(scale); }
+    if let Some(seed) = cli.seed { instr.generator_mut().reseed(seed); }
+    instr.set_midi_clock(cli.midi_clock);
+    if cli.midi_clock == MidiClock::Out {
+        match connect_midi_output() {
+            Ok(connection) => instr.set_midi_output(Box::new(MidiSender::spawn(connection))),
+            Err(e) => instr.set_status(format!("midi out: {}", e)),
+        }
+    }
+    let snapshot = instr.snapshot_handle();
+    let tap = instr.tap_handle();
+    let meter = instr.meter_handle();
 
     let mtx_instrmnt = Arc::new(Mutex::<Instrument>::new(instr));
-    let mtx_debug = Arc::new(Mutex::<DebugKeyboardHandler>::new(debug));
 
-    let mtx_inst_debug = mtx_instrmnt.clone();
-    std::thread::spawn(|| thread_debug(mtx_inst_debug));
+    // a panic in any thread gives the terminal back and stops the others.
+    let shutdown = Shutdown::new();
+    let hook = std::panic::take_hook();
+    let panicked = shutdown.clone();
+    std::panic::set_hook(Box::new(move |info| {
+        panicked.request();
+        ratatui::restore();
+        hook(info);
+    }));
+
+    let tui_shutdown = shutdown.clone();
+    let tui = std::thread::spawn(|| thread_tui(snapshot, tap, meter, tui_shutdown));
 
     let mtx_inst_audio= mtx_instrmnt.clone();
-    std::thread::spawn(|| thread_audio(mtx_inst_audio));
+    let audio_shutdown = shutdown.clone();
+    let audio = std::thread::spawn(|| thread_audio(mtx_inst_audio, audio_config, audio_shutdown));
+
+    let mtx_inst_midi = mtx_instrmnt.clone();
+    let midi_handlers: Vec<Arc<Mutex<dyn MidiHandler + Send>>> = vec![mtx_inst_midi];
+    // kept alive until the end of main, dropping it closes the port.
+    let _midi = match connect_midi_input(midi_handlers) {
+        Ok(connection) => Some(connection),
+        Err(e) => { mtx_instrmnt.lock().unwrap().set_status(format!("midi: {}", e)); None }
+    };
+
+    // kept alive until the end of main too, the files are watched while it is.
+    let _watcher = (!cli.no_watch).then(|| {
+        match watch::watch_files(mtx_instrmnt.clone(), keymap_in_use.then_some(keymap_path)) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => { mtx_instrmnt.lock().unwrap().set_status(format!("watch: {}", e)); None }
+        }
+    });
 
     let mtx_inst_input = mtx_instrmnt.clone();
-    let mtx_debug_input = mtx_debug.clone();
     let event_handlers: Vec<Arc<Mutex<dyn KeyboardHandler + Send>>> = vec![
         (mtx_inst_input as Arc<Mutex<dyn KeyboardHandler + Send>>).clone(),
-        (mtx_debug_input as Arc<Mutex<dyn KeyboardHandler + Send>>).clone(),
-
     ];
-    if let Err(e) = std::thread::spawn(|| thread_input(event_handlers)).join() {
-        eprintln!("Failed to join thread: {:?}", e);
+    // with the keys read from the devices, the terminal input is only
+    // drained and still quits on ctrl+c.
+    #[cfg(all(feature = "evdev", target_os = "linux"))]
+    let event_handlers = match cli.evdev.then(rsynth::evdev_input::open_keyboards) {
+        Some(Ok(devices)) => {
+            let (mtx_status, evdev_shutdown) = (mtx_instrmnt.clone(), shutdown.clone());
+            std::thread::spawn(move || {
+                if let Err(e) = rsynth::evdev_input::thread_evdev(devices, event_handlers, evdev_shutdown) {
+                    mtx_status.lock().unwrap().set_status(format!("evdev: {}", e));
+                }
+            });
+            Vec::new()
+        },
+        Some(Err(e)) => {
+            mtx_instrmnt.lock().unwrap().set_status(format!("evdev: {}, reading keys from the terminal", e));
+            event_handlers
+        },
+        None => event_handlers,
+    };
+    // the project is taken under the lock and written out of it, so the
+    // audio thread doesn't wait on the disk.
+    let autosave = cli.autosave.then(|| {
+        let (mtx_inst_save, save_shutdown) = (mtx_instrmnt.clone(), shutdown.clone());
+        std::thread::spawn(move || {
+            while !save_shutdown.wait_timeout(AUTOSAVE_INTERVAL) {
+                let (name, project) = {
+                    let instr = mtx_inst_save.lock().unwrap();
+                    (instr.project_name().to_string(), instr.project())
+                };
+                if let Err(e) = project::save_project(&name, &project) {
+                    mtx_inst_save.lock().unwrap().set_status(format!("autosave: {}", e));
+                }
+            }
+        })
+    });
+    // presets and projects loaded and saved from the keyboard are read
+    // and written out of the lock too.
+    let (disk_requests, requests) = std::sync::mpsc::channel();
+    mtx_instrmnt.lock().unwrap().set_disk(disk_requests);
+    let (mtx_inst_disk, disk_shutdown) = (mtx_instrmnt.clone(), shutdown.clone());
+    let disk = std::thread::spawn(move || disk::thread_disk(mtx_inst_disk, requests, disk_shutdown));
+    let input_shutdown = shutdown.clone();
+    let key_release = cli.key_release;
+    match std::thread::spawn(move || thread_input(event_handlers, input_shutdown, key_release)).join() {
+        Ok(Err(e)) => eprintln!("input: {}", e),
+        Err(e) => eprintln!("Failed to join thread: {:?}", e),
+        Ok(Ok(())) => (),
+    }
+    // whatever ended the input, everything else stops with it.
+    shutdown.request();
+    if let Ok(Err(e)) = tui.join() { eprintln!("ui: {}", e); }
+    let _ = audio.join();
+    let _ = disk.join();
+
+    if let Some(autosave) = autosave {
+        let _ = autosave.join();
+        let (name, project) = {
+            let instr = mtx_instrmnt.lock().unwrap();
+            (instr.project_name().to_string(), instr.project())
+        };
+        if let Err(e) = project::save_project(&name, &project) { eprintln!("autosave: {}", e); }
+    }
+
+    let recording = mtx_instrmnt.lock().unwrap().stop_recording();
+    if let Err(e) = recording {
+        eprintln!("Failed to finish recording: {}", e);
     }
 }
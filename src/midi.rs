@@ -0,0 +1,190 @@
+//! Midi module.
+//!
+//! parses incoming midi messages and hands them to the registered handlers,
+//! the same way the input module does with key events.
+//!
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::SyncSender;
+use std::time::{Duration, Instant};
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MidiMessage {
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8 },
+    ControlChange { channel: u8, controller: u8, value: u8 },
+    // centered on zero, in [-8192, 8191].
+    PitchBend { channel: u8, value: i16 },
+    Clock,
+    Start,
+    Continue,
+    Stop,
+}
+
+impl MidiMessage {
+    pub fn parse(bytes: &[u8]) -> Option<MidiMessage> {
+        let status = *bytes.first()?;
+        let channel = status & 0x0f;
+        let data = |i: usize| bytes.get(i).map(|b| b & 0x7f);
+        match status {
+            0xf8 => Some(MidiMessage::Clock),
+            0xfa => Some(MidiMessage::Start),
+            0xfb => Some(MidiMessage::Continue),
+            0xfc => Some(MidiMessage::Stop),
+            s if s & 0xf0 == 0x90 && data(2)? > 0 => Some(MidiMessage::NoteOn { channel, note: data(1)?, velocity: data(2)? }),
+            // note on with a zero velocity is a note off.
+            s if s & 0xf0 == 0x80 || s & 0xf0 == 0x90 => Some(MidiMessage::NoteOff { channel, note: data(1)? }),
+            s if s & 0xf0 == 0xb0 => Some(MidiMessage::ControlChange { channel, controller: data(1)?, value: data(2)? }),
+            s if s & 0xf0 == 0xe0 => {
+                let value = ((data(2)? as i16) << 7 | data(1)? as i16) - 8192;
+                Some(MidiMessage::PitchBend { channel, value })
+            },
+            _ => None
+        }
+    }
+
+    // the bytes of the message, returns how many of `out` it took.
+    pub fn write(&self, out: &mut [u8; 3]) -> usize {
+        let mut put = |bytes: &[u8]| { out[..bytes.len()].copy_from_slice(bytes); bytes.len() };
+        match *self {
+            MidiMessage::NoteOn { channel, note, velocity } => put(&[0x90 | channel & 0x0f, note & 0x7f, velocity & 0x7f]),
+            MidiMessage::NoteOff { channel, note } => put(&[0x80 | channel & 0x0f, note & 0x7f, 0]),
+            MidiMessage::ControlChange { channel, controller, value } => put(&[0xb0 | channel & 0x0f, controller & 0x7f, value & 0x7f]),
+            MidiMessage::PitchBend { channel, value } => {
+                let v = (value.clamp(-8192, 8191) + 8192) as u16;
+                put(&[0xe0 | channel & 0x0f, (v & 0x7f) as u8, (v >> 7) as u8])
+            },
+            MidiMessage::Clock => put(&[0xf8]),
+            MidiMessage::Start => put(&[0xfa]),
+            MidiMessage::Continue => put(&[0xfb]),
+            MidiMessage::Stop => put(&[0xfc]),
+        }
+    }
+}
+
+/// What the transport does with midi clock: nothing, follow the clock
+/// coming in, or send its own out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum MidiClock {
+    #[default]
+    Off,
+    In,
+    Out,
+}
+
+impl std::str::FromStr for MidiClock {
+    type Err = String;
+    fn from_str(s: &str) -> Result<MidiClock, String> {
+        match s {
+            "off" => Ok(MidiClock::Off),
+            "in" => Ok(MidiClock::In),
+            "out" => Ok(MidiClock::Out),
+            _ => Err(format!("unknown midi clock mode '{}', expected off, in or out", s)),
+        }
+    }
+}
+
+pub trait MidiHandler {
+    fn handle_midi_message(&mut self, message: MidiMessage);
+}
+
+// where outgoing messages go.
+pub trait MidiSink: Send {
+    fn send(&mut self, message: MidiMessage);
+    // `delay` seconds from now, for messages timed within a block. sinks
+    // that can't wait send it now.
+    fn send_after(&mut self, message: MidiMessage, _delay: f32) { self.send(message) }
+}
+
+/// Hands messages to a thread sending them to `out`, each at its time, so
+/// the audio callback never waits on the port.
+pub struct MidiSender { queue: SyncSender<(MidiMessage, Instant)> }
+
+impl MidiSender {
+    // messages waiting at most, a second of clock at 300 bpm and then some.
+    const CAPACITY: usize = 256;
+
+    pub fn spawn(mut out: impl MidiSink + 'static) -> MidiSender {
+        let (queue, messages) = std::sync::mpsc::sync_channel::<(MidiMessage, Instant)>(MidiSender::CAPACITY);
+        // ends with the sender.
+        std::thread::spawn(move || for (message, at) in messages {
+            std::thread::sleep(at.saturating_duration_since(Instant::now()));
+            out.send(message);
+        });
+        MidiSender { queue }
+    }
+}
+
+impl MidiSink for MidiSender {
+    fn send(&mut self, message: MidiMessage) { self.send_after(message, 0.0) }
+
+    // a full queue drops the message rather than wait for room.
+    fn send_after(&mut self, message: MidiMessage, delay: f32) {
+        let _ = self.queue.try_send((message, Instant::now() + Duration::from_secs_f32(delay.max(0.0))));
+    }
+}
+
+impl MidiSink for MidiOutputConnection {
+    // a message that can't be sent is dropped, there is no one to tell.
+    fn send(&mut self, message: MidiMessage) {
+        let mut bytes = [0; 3];
+        let n = message.write(&mut bytes);
+        let _ = MidiOutputConnection::send(self, &bytes[..n]);
+    }
+}
+
+/// Connects to the first midi input port found.
+///
+/// the connection closes when the returned value is dropped.
+pub fn connect_midi_input(handlers: Vec<Arc<Mutex<dyn MidiHandler + Send>>>) -> Result<MidiInputConnection<()>, String> {
+    let input = MidiInput::new("rsynth").map_err(|e| e.to_string())?;
+    let ports = input.ports();
+    let port = ports.first().ok_or("no midi input port found")?;
+    let name = input.port_name(port).unwrap_or_default();
+    input.connect(port, "rsynth-in", move |_, bytes, _| {
+        if let Some(message) = MidiMessage::parse(bytes) {
+            handlers.iter().for_each(|h| h.lock().unwrap().handle_midi_message(message));
+        }
+    }, ()).map_err(|e| format!("could not connect to {}: {}", name, e))
+}
+
+/// Connects to the first midi output port found.
+pub fn connect_midi_output() -> Result<MidiOutputConnection, String> {
+    let output = MidiOutput::new("rsynth").map_err(|e| e.to_string())?;
+    let ports = output.ports();
+    let port = ports.first().ok_or("no midi output port found")?;
+    let name = output.port_name(port).unwrap_or_default();
+    output.connect(port, "rsynth-out").map_err(|e| format!("could not connect to {}: {}", name, e))
+}
+
+#[cfg(test)]
+mod midi_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_messages() {
+        assert_eq!(MidiMessage::parse(&[0x91, 60, 100]), Some(MidiMessage::NoteOn { channel: 1, note: 60, velocity: 100 }));
+        assert_eq!(MidiMessage::parse(&[0x90, 60, 0]), Some(MidiMessage::NoteOff { channel: 0, note: 60 }));
+        assert_eq!(MidiMessage::parse(&[0x80, 60, 64]), Some(MidiMessage::NoteOff { channel: 0, note: 60 }));
+        assert_eq!(MidiMessage::parse(&[0xe0, 0x00, 0x40]), Some(MidiMessage::PitchBend { channel: 0, value: 0 }));
+        assert_eq!(MidiMessage::parse(&[0xe0, 0x7f, 0x7f]), Some(MidiMessage::PitchBend { channel: 0, value: 8191 }));
+        assert_eq!(MidiMessage::parse(&[0xb2, 1, 127]), Some(MidiMessage::ControlChange { channel: 2, controller: 1, value: 127 }));
+        assert_eq!(MidiMessage::parse(&[0xf8]), Some(MidiMessage::Clock));
+        assert_eq!(MidiMessage::parse(&[0x90, 60]), None);
+    }
+
+    #[test]
+    fn test_write_messages() {
+        let messages = [
+            MidiMessage::NoteOn { channel: 3, note: 61, velocity: 90 }, MidiMessage::NoteOff { channel: 0, note: 61 },
+            MidiMessage::ControlChange { channel: 15, controller: 74, value: 3 }, MidiMessage::PitchBend { channel: 1, value: -8192 },
+            MidiMessage::PitchBend { channel: 1, value: 100 }, MidiMessage::Clock, MidiMessage::Start, MidiMessage::Stop,
+        ];
+        for message in messages {
+            let mut bytes = [0; 3];
+            let n = message.write(&mut bytes);
+            assert_eq!(MidiMessage::parse(&bytes[..n]), Some(message));
+        }
+    }
+}
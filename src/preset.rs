@@ -0,0 +1,259 @@
+//! Preset module.
+//!
+//! patches are stored as toml files under `$XDG_CONFIG_HOME/rsynth/presets`.
+//! a few factory ones are built in, and a saved preset of the same name
+//! takes the place of one.
+//!
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+use crate::audio::waves::{Envelope, OscillatorDesc, Lfo, NoiseLayer, SubOscillator};
+use crate::audio::filter::{FilterEnvelope, FilterSettings};
+use crate::audio::modulation::ModRoute;
+use crate::audio::effects::{EffectSlotDesc, SaturationSettings, WavefolderSettings, default_effects, read_impulse_responses};
+use crate::audio::glide::GlideSettings;
+use crate::audio::unison::UnisonSettings;
+use crate::audio::oscillators::VoiceOscillatorsDesc;
+use crate::audio::instrument::{Engine, PlayMode, NotePriority, Polyphony};
+use crate::audio::fm::FmVoice;
+use crate::audio::sampler::SamplerDesc;
+use crate::audio::drums::DrumKit;
+use crate::audio::params::CcMapping;
+use crate::audio::macros::{Macro, default_macros};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Patch {
+    #[serde(default)]
+    pub engine: Engine,
+    pub oscillator: OscillatorDesc,
+    #[serde(default)]
+    pub oscillators: VoiceOscillatorsDesc,
+    #[serde(default)]
+    pub fm: FmVoice,
+    #[serde(default)]
+    pub sampler: SamplerDesc,
+    #[serde(default)]
+    pub drums: DrumKit,
+    #[serde(default)]
+    pub noise: NoiseLayer,
+    #[serde(default)]
+    pub sub: SubOscillator,
+    #[serde(default = "WavefolderSettings::off")]
+    pub voice_fold: WavefolderSettings,
+    #[serde(default = "SaturationSettings::off")]
+    pub voice_drive: SaturationSettings,
+    pub envelope: Envelope,
+    #[serde(default)]
+    pub filter: FilterSettings,
+    #[serde(default)]
+    pub filter_envelope: FilterEnvelope,
+    #[serde(default)]
+    pub lfos: Vec<Lfo>,
+    #[serde(default)]
+    pub modulation: Vec<ModRoute>,
+    #[serde(default = "default_effects")]
+    pub effects: Vec<EffectSlotDesc>,
+    #[serde(default)]
+    pub glide: GlideSettings,
+    #[serde(default)]
+    pub unison: UnisonSettings,
+    #[serde(default)]
+    pub play_mode: PlayMode,
+    #[serde(default)]
+    pub note_priority: NotePriority,
+    #[serde(default)]
+    pub polyphony: Polyphony,
+    #[serde(default)]
+    pub cc_mappings: Vec<CcMapping>,
+    #[serde(default = "default_macros")]
+    pub macros: Vec<Macro>,
+    // keyed by the character that plays the note. a patch without one
+    // keeps the keymap playing.
+    #[serde(default)]
+    pub keymap: BTreeMap<String, f32>,
+}
+
+#[derive(Debug)]
+pub enum PresetError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl std::fmt::Display for PresetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PresetError::Io(e) => write!(f, "preset io error: {}", e),
+            PresetError::Parse(e) => write!(f, "invalid preset: {}", e),
+            PresetError::Serialize(e) => write!(f, "could not serialize preset: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PresetError {}
+impl From<std::io::Error> for PresetError { fn from(e: std::io::Error) -> Self { PresetError::Io(e) } }
+impl From<toml::de::Error> for PresetError { fn from(e: toml::de::Error) -> Self { PresetError::Parse(e) } }
+impl From<toml::ser::Error> for PresetError { fn from(e: toml::ser::Error) -> Self { PresetError::Serialize(e) } }
+
+pub fn presets_dir() -> PathBuf {
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("rsynth").join("presets")
+}
+
+pub fn preset_path(name: &str) -> PathBuf { presets_dir().join(format!("{}.toml", name)) }
+
+pub fn save_patch(name: &str, patch: &Patch) -> Result<PathBuf, PresetError> {
+    let path = preset_path(name);
+    std::fs::create_dir_all(presets_dir())?;
+    std::fs::write(&path, toml::to_string(patch)?)?;
+    Ok(path)
+}
+
+/// The first `snap-N` name not taken in `dir`, for a patch saved on the
+/// spot without asking for one.
+pub fn snapshot_name(dir: &Path) -> String {
+    (1..).map(|n| format!("snap-{:03}", n))
+        .find(|name| !dir.join(format!("{}.toml", name)).exists())
+        .unwrap_or_default()
+}
+
+// built in, in the order they're browsed. the first is played at start.
+pub const FACTORY: [(&str, &str); 5] = [
+    ("keys", include_str!("../presets/keys.toml")),
+    ("bass", include_str!("../presets/bass.toml")),
+    ("lead", include_str!("../presets/lead.toml")),
+    ("pad", include_str!("../presets/pad.toml")),
+    ("fx", include_str!("../presets/fx.toml")),
+];
+
+pub fn factory_patch(name: &str) -> Option<Result<Patch, PresetError>> {
+    FACTORY.iter().find(|(n, _)| *n == name).map(|(_, text)| Ok(toml::from_str(text)?))
+}
+
+pub fn load_patch(name: &str) -> Result<Patch, PresetError> {
+    match std::fs::read_to_string(preset_path(name)) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => factory_patch(name).unwrap_or(Err(e.into())),
+        text => Ok(toml::from_str(&text?)?),
+    }
+}
+
+/// Reads the files the patch names besides itself, its wavetables,
+/// scripts, samples, drum pads and the impulse responses of its
+/// convolutions, so applying it doesn't have to. the others are still read
+/// when one fails. the files are read on the thread that calls it: the
+/// app reads a patch before it locks the instrument to apply it (see
+/// `disk` and `watch`), so neither the audio nor the lock waits on them.
+pub fn read_files(patch: &mut Patch) -> Result<(), String> {
+    read_oscillators(patch).and(read_samples(patch)).and(read_impulse_responses(&mut patch.effects))
+}
+
+fn read_samples(patch: &mut Patch) -> Result<(), String> {
+    let mut result = Ok(());
+    for zone in patch.sampler.zones.iter_mut() {
+        if let Err(e) = zone.read() { result = result.and(Err(format!("{}: {}", zone.path.display(), e))); }
+    }
+    for pad in patch.drums.pads.iter_mut() {
+        if let Err(e) = pad.read() { result = result.and(Err(format!("{}: {}", pad.path.display(), e))); }
+    }
+    result
+}
+
+fn read_oscillators(patch: &mut Patch) -> Result<(), String> {
+    let oscillators = std::iter::once(&mut patch.oscillator).chain(patch.oscillators.extra.iter_mut().map(|e| &mut e.oscillator));
+    let mut result = Ok(());
+    for oscillator in oscillators {
+        if let Err(e) = oscillator.read() { result = result.and(Err(e)); }
+    }
+    result
+}
+
+/// The factory presets, then the saved ones in `dir` by name.
+pub fn preset_names(dir: &Path) -> Vec<String> {
+    let mut saved: Vec<String> = std::fs::read_dir(dir).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "toml"))
+        .filter_map(|path| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .filter(|name| !FACTORY.iter().any(|(n, _)| n == name))
+        .collect();
+    saved.sort();
+    FACTORY.iter().map(|(n, _)| n.to_string()).chain(saved).collect()
+}
+
+#[cfg(test)]
+mod preset_tests {
+    use crate::audio::waves::{Oscillator, SinWave, BlepShape, Randomize, LfoShape, LfoDestination};
+    use crate::audio::modulation::{ModSource, ModDestination};
+    use crate::audio::sampler::SampleZone;
+    use crate::audio::drums::{DrumPad, Pattern, SongEntry};
+    use crate::audio::params::{CcMode, ParamId};
+    use crate::audio::macros::{MacroTarget, MacroCurve};
+    use super::*;
+
+    #[test]
+    fn test_patch_roundtrip() {
+        let mut osc = Oscillator::new(Box::new(SinWave));
+        osc.randomize(&mut rand::thread_rng());
+        osc.set_waveform(BlepShape::Pulse(0.3));
+        let patch = Patch {
+            engine: Engine::Fm,
+            oscillator: osc.desc(),
+            oscillators: VoiceOscillatorsDesc { mix: 0.4, ..VoiceOscillatorsDesc::default() },
+            fm: FmVoice::default(),
+            sampler: SamplerDesc { zones: vec![SampleZone::new(PathBuf::from("piano-c4.wav"), 60)] },
+            drums: DrumKit {
+                pads: vec![DrumPad { path: PathBuf::from("kick.wav"), gain: 1.0, choke: None, sample: Default::default() }],
+                pattern: Pattern {
+                    length: 16, rate: 4.0, steps: vec![vec![true, false, false, false]],
+                    probability: vec![vec![50]], ratchets: vec![vec![3]],
+                },
+                patterns: vec![Pattern { length: 8, rate: 2.0, steps: vec![vec![false, true]], ..Pattern::default() }],
+                song: vec![SongEntry { pattern: 0, repeats: 3 }, SongEntry { pattern: 1, repeats: 1 }],
+            },
+            noise: NoiseLayer { color: crate::audio::waves::NoiseColor::Brown, level: 0.2, decay: 0.0 },
+            sub: SubOscillator { shape: crate::audio::waves::SubShape::Square, octaves: 2, level: 0.4 },
+            voice_fold: WavefolderSettings { fold: 4.5, symmetry: -0.2, mix: 0.8 },
+            voice_drive: SaturationSettings { curve: crate::audio::effects::SaturationCurve::Tube, drive: 18.0, output: -9.0, mix: 1.0 },
+            envelope: Envelope(0.1, 0.2, 0.3, 0.4),
+            filter: FilterSettings::default(),
+            filter_envelope: FilterEnvelope { envelope: Envelope(0.0, 0.15, 0.1, 0.2), amount: -3.5 },
+            lfos: vec![Lfo::new(5.0, 0.3, LfoShape::Sine, LfoDestination::Pitch)],
+            modulation: vec![ModRoute::new(ModSource::Lfo(0), ModDestination::Cutoff, 1.0)],
+            effects: default_effects(),
+            glide: GlideSettings { time: 0.2, curve: crate::audio::glide::GlideCurve::Exponential },
+            unison: UnisonSettings { voices: 3, detune: 12.0, spread: 0.8 },
+            play_mode: PlayMode::Legato,
+            note_priority: NotePriority::Low,
+            polyphony: Polyphony { max_voices: 6, stealing: crate::audio::instrument::VoiceStealing::Quietest },
+            cc_mappings: vec![CcMapping { controller: 21, param: ParamId::OperatorLevel(2), mode: CcMode::Relative }],
+            macros: vec![Macro { value: 0.4, targets: vec![MacroTarget { param: ParamId::Cutoff, from: 0.9, to: 0.3, curve: MacroCurve::Logarithmic }] }],
+            keymap: BTreeMap::from([("z".to_string(), 130.81), ("s".to_string(), 138.59)]),
+        };
+
+        let text = toml::to_string(&patch).unwrap();
+        assert_eq!(toml::from_str::<Patch>(&text).unwrap(), patch);
+    }
+
+    #[test]
+    fn test_factory_presets() {
+        for (name, _) in FACTORY {
+            let patch = factory_patch(name).unwrap().unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert!(patch.keymap.is_empty(), "{} shouldn't change the keymap", name);
+        }
+        assert_eq!(factory_patch("pad").unwrap().unwrap().unison.voices, 5);
+        assert!(factory_patch("organ").is_none());
+    }
+
+    #[test]
+    fn test_snapshot_name() {
+        let dir = std::env::temp_dir().join(format!("rsynth-snapshots-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(snapshot_name(&dir), "snap-001");
+        std::fs::write(dir.join("snap-001.toml"), "").unwrap();
+        std::fs::write(dir.join("snap-003.toml"), "").unwrap();
+        assert_eq!(snapshot_name(&dir), "snap-002");
+        std::fs::write(dir.join("bass.toml"), "").unwrap();
+        assert_eq!(preset_names(&dir), ["keys", "bass", "lead", "pad", "fx", "snap-001", "snap-003"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! Project module.
+//!
+//! a session saved as one toml file under `$XDG_CONFIG_HOME/rsynth/projects`:
+//! the patch of the instrument and of each of its parts, which carry their
+//! drum patterns, midi mappings and effects, along with the tempo and how
+//! the parts are played. unlike a preset, loading one brings back the whole
+//! jam.
+//!
+
+use std::path::PathBuf;
+use serde::{Serialize, Deserialize};
+
+use crate::audio::parts::PartDesc;
+use crate::preset::{self, Patch, PresetError};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TransportSettings {
+    #[serde(default = "default_bpm")]
+    pub bpm: f32,
+    #[serde(default)]
+    pub swing: f32,
+    #[serde(default)]
+    pub song_mode: bool,
+}
+
+fn default_bpm() -> f32 { 120.0 }
+
+impl Default for TransportSettings {
+    fn default() -> Self { TransportSettings { bpm: default_bpm(), swing: 0.0, song_mode: false } }
+}
+
+// a part with the patch it played, kept even if its preset changed since.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ProjectPart {
+    pub desc: PartDesc,
+    pub patch: Patch,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Project {
+    #[serde(default = "default_volume")]
+    pub master_volume: f32,
+    // the preset the patch was loaded from, saved to by F2.
+    #[serde(default)]
+    pub preset: String,
+    // the part playing below the split point, if split.
+    #[serde(default)]
+    pub split: Option<usize>,
+    #[serde(default)]
+    pub transport: TransportSettings,
+    pub patch: Patch,
+    #[serde(default)]
+    pub parts: Vec<ProjectPart>,
+}
+
+fn default_volume() -> f32 { 1.0 }
+
+pub fn projects_dir() -> PathBuf {
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("rsynth").join("projects")
+}
+
+pub fn project_path(name: &str) -> PathBuf { projects_dir().join(format!("{}.toml", name)) }
+
+pub fn save_project(name: &str, project: &Project) -> Result<PathBuf, PresetError> {
+    let path = project_path(name);
+    std::fs::create_dir_all(projects_dir())?;
+    // written next to it first, so a save cut short leaves the last one.
+    let partial = path.with_extension("toml.part");
+    std::fs::write(&partial, toml::to_string(project)?)?;
+    std::fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+pub fn load_project(name: &str) -> Result<Project, PresetError> {
+    Ok(toml::from_str(&std::fs::read_to_string(project_path(name))?)?)
+}
+
+// the files of the patch and of each part, see `preset::read_files`.
+pub fn read_files(project: &mut Project) -> Result<(), String> {
+    let parts = project.parts.iter_mut().map(|p| preset::read_files(&mut p.patch));
+    parts.fold(preset::read_files(&mut project.patch), Result::and)
+}
+
+#[cfg(test)]
+mod project_tests {
+    use super::*;
+    use crate::audio::instrument::Instrument;
+
+    #[test]
+    fn test_project_roundtrip() {
+        let mut instrument = Instrument::new();
+        instrument.transport_mut().set_bpm(97.0);
+        instrument.set_master_volume(0.7);
+        instrument.add_part(",ch=2,pan=-0.5".parse().unwrap()).unwrap();
+        instrument.set_split(Some(48), "").unwrap();
+        instrument.parts_mut()[0].instrument.set_cutoff(300.0);
+
+        let text = toml::to_string(&instrument.project()).unwrap();
+        let project: Project = toml::from_str(&text).unwrap();
+        assert_eq!(project, instrument.project());
+
+        let mut loaded = Instrument::new();
+        loaded.apply_project(&project);
+        assert_eq!(loaded.project(), project);
+        assert_eq!((loaded.transport().bpm(), loaded.split_point()), (97.0, Some(48)));
+        assert_eq!(loaded.parts()[0].instrument.filter_settings().cutoff, 300.0);
+        assert_eq!(loaded.parts()[0].desc.channel, Some(1));
+
+        // a split part that's gone isn't split at.
+        loaded.apply_project(&Project { split: Some(5), ..project });
+        assert_eq!((loaded.parts().len(), loaded.split_point()), (2, None));
+    }
+}
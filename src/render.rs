@@ -0,0 +1,133 @@
+//! Render module.
+//!
+//! plays a midi file through the instrument and writes the result to a wav
+//! file, as fast as it can, without an audio device or a terminal.
+//!
+
+use std::path::Path;
+use midly::{MetaMessage, Smf, Timing, TrackEventKind};
+
+use crate::audio::instrument::{Instrument, NoteEvent};
+use crate::midi::MidiMessage;
+use crate::preset::PresetError;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug)]
+pub enum RenderError {
+    Io(std::io::Error),
+    Midi(midly::Error),
+    Wav(hound::Error),
+    Preset(PresetError),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::Io(e) => write!(f, "render io error: {}", e),
+            RenderError::Midi(e) => write!(f, "invalid midi file: {}", e),
+            RenderError::Wav(e) => write!(f, "could not write wav: {}", e),
+            RenderError::Preset(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+impl From<std::io::Error> for RenderError { fn from(e: std::io::Error) -> Self { RenderError::Io(e) } }
+impl From<midly::Error> for RenderError { fn from(e: midly::Error) -> Self { RenderError::Midi(e) } }
+impl From<hound::Error> for RenderError { fn from(e: hound::Error) -> Self { RenderError::Wav(e) } }
+impl From<PresetError> for RenderError { fn from(e: PresetError) -> Self { RenderError::Preset(e) } }
+
+/// Channel messages of every track of a standard midi file, merged and
+/// timed in seconds, following its tempo changes.
+pub fn midi_events(bytes: &[u8]) -> Result<Vec<NoteEvent>, RenderError> {
+    let smf = Smf::parse(bytes)?;
+    let mut merged: Vec<(u64, TrackEventKind)> = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            merged.push((tick, event.kind));
+        }
+    }
+    // stable, so events on the same tick keep their order within a track.
+    merged.sort_by_key(|(tick, _)| *tick);
+
+    // seconds per tick, which only changes with the tempo in metrical files.
+    let (per_beat, mut tick_len) = match smf.header.timing {
+        Timing::Metrical(ticks) => (Some(ticks.as_int().max(1) as f64), 0.5 / ticks.as_int().max(1) as f64),
+        Timing::Timecode(fps, sub) => (None, 1.0 / (fps.as_f32() as f64 * sub.max(1) as f64)),
+    };
+    let (mut last_tick, mut time) = (0u64, 0.0f64);
+    let mut events = Vec::new();
+    for (tick, kind) in merged {
+        time += (tick - last_tick) as f64 * tick_len;
+        last_tick = tick;
+        match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(micros)) => {
+                if let Some(ticks) = per_beat { tick_len = micros.as_int() as f64 / 1e6 / ticks; }
+            },
+            kind => {
+                let mut bytes = Vec::with_capacity(3);
+                let Some(live) = kind.as_live_event() else { continue };
+                live.write_std(&mut bytes)?;
+                if let Some(message) = MidiMessage::parse(&bytes) { events.push(NoteEvent { time: time as f32, message }); }
+            },
+        }
+    }
+    Ok(events)
+}
+
+pub fn write_wav<P: AsRef<Path>>(path: P, samples: &[f32], sample_rate: u32) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec { channels: 2, sample_rate, bits_per_sample: 32, sample_format: hound::SampleFormat::Float };
+    let mut wav = hound::WavWriter::create(path, spec)?;
+    for sample in samples { wav.write_sample(*sample)?; }
+    wav.finalize()
+}
+
+/// Renders the midi file at `midi` to the wav file at `output`. `tail`
+/// seconds after the last event leave room for the releases and the
+/// effects to ring out.
+pub fn render_file(midi: &Path, output: &Path, tail: f32, preset: Option<&str>, sample_rate: Option<u32>) -> Result<(), RenderError> {
+    let mut instrument = Instrument::new();
+    if let Some(name) = preset { instrument.load_preset(name)?; }
+    let events = midi_events(&std::fs::read(midi)?)?;
+    let sample_rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    let end = events.last().map_or(0.0, |e| e.time) + instrument.envelope().3 + tail.max(0.0);
+    let samples = instrument.render(&events, sample_rate, (end * sample_rate as f32) as usize);
+    write_wav(output, &samples, sample_rate)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod render_tests {
+    use super::*;
+
+    // format 0 file at 96 ticks per beat: a beat at 120 bpm, a tempo change
+    // to 60 bpm, then a note held for a beat.
+    const SONG: &[u8] = &[
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+        b'M', b'T', b'r', b'k', 0, 0, 0, 19,
+        0x60, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40,
+        0x00, 0x90, 60, 100,
+        0x60, 0x80, 60, 0,
+        0x00, 0xff, 0x2f, 0x00,
+    ];
+
+    #[test]
+    fn test_midi_events_follow_tempo() {
+        let events = midi_events(SONG).unwrap();
+        assert_eq!(events, vec![NoteEvent::on(0.5, 60, 100), NoteEvent::off(1.5, 60)]);
+    }
+
+    #[test]
+    fn test_render_plays_the_note() {
+        let mut instrument = Instrument::new();
+        let events = midi_events(SONG).unwrap();
+        let samples = instrument.render(&events, 8000, 8000 * 5);
+        assert_eq!(samples.len(), 8000 * 5 * 2);
+        let peak = |range: std::ops::Range<usize>| samples[range].iter().fold(0.0f32, |a, x| a.max(x.abs()));
+        assert_eq!(peak(0..8000), 0.0);
+        assert!(peak(8000..24000) > 0.01);
+    }
+}
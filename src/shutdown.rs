@@ -0,0 +1,55 @@
+//! Shutdown module.
+//!
+//! a flag shared by the threads of the app. once requested, each thread
+//! finishes what it is doing, cleans up and returns. threads with nothing
+//! else to do block on it instead of polling.
+//!
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+struct Flag { requested: Mutex<bool>, changed: Condvar }
+
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Arc<Flag>);
+
+impl Shutdown {
+    pub fn new() -> Shutdown { Shutdown::default() }
+
+    pub fn request(&self) {
+        *self.0.requested.lock().unwrap() = true;
+        self.0.changed.notify_all();
+    }
+
+    pub fn is_requested(&self) -> bool { *self.0.requested.lock().unwrap() }
+
+    // blocks until shutdown is requested.
+    pub fn wait(&self) {
+        let requested = self.0.requested.lock().unwrap();
+        let _requested = self.0.changed.wait_while(requested, |requested| !*requested).unwrap();
+    }
+
+    // like `wait`, giving up after `timeout`. true if shutdown was requested.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let requested = self.0.requested.lock().unwrap();
+        let (requested, _) = self.0.changed.wait_timeout_while(requested, timeout, |requested| !*requested).unwrap();
+        *requested
+    }
+}
+
+#[cfg(test)]
+mod shutdown_tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_wakes_on_request() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.wait_timeout(Duration::from_millis(1)));
+        let other = shutdown.clone();
+        let waiting = std::thread::spawn(move || other.wait());
+        shutdown.request();
+        waiting.join().unwrap();
+        assert!(shutdown.is_requested() && shutdown.wait_timeout(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,240 @@
+//! Terminal UI module.
+//!
+//! renders the instrument state from the snapshot published by the
+//! instrument, so drawing never holds the instrument lock.
+//!
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::symbols::Marker;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, List, ListItem, Paragraph, Sparkline};
+use ratatui::widgets::canvas::{Canvas, Points};
+
+use rsynth::audio::instrument::{Engine, Instrument, InstrumentSnapshot};
+use rsynth::audio::chord::Chord;
+use rsynth::audio::looper::LooperState;
+use rsynth::keymap::note_name;
+use rsynth::shutdown::Shutdown;
+use rsynth::visual::{Meter, MeterLevels, OutputTap, Spectrum};
+
+// samples across the oscilloscope, about 20 ms at 48 kHz.
+const SCOPE_SAMPLES: usize = 1024;
+
+// draws until shutdown, then gives the terminal back in the state it was.
+pub fn thread_tui(snapshot: Arc<Mutex<InstrumentSnapshot>>, tap: Arc<OutputTap>, meter: Arc<Meter>, shutdown: Shutdown) -> Result<(), std::io::Error> {
+    let mut terminal = ratatui::try_init()?;
+    let result = draw_until(&mut terminal, snapshot, tap, meter, &shutdown);
+    ratatui::try_restore()?;
+    result
+}
+
+fn draw_until(terminal: &mut ratatui::DefaultTerminal, snapshot: Arc<Mutex<InstrumentSnapshot>>, tap: Arc<OutputTap>, meter: Arc<Meter>, shutdown: &Shutdown) -> Result<(), std::io::Error> {
+    let mut spectrum = Spectrum::new();
+    while !shutdown.is_requested() {
+        let state = snapshot.lock().unwrap().clone();
+        let samples = tap.latest(Spectrum::SIZE);
+        let scope = &samples[samples.len() - SCOPE_SAMPLES..];
+        let levels = meter.levels();
+        terminal.draw(|frame| {
+            // one band per column of the analyzer.
+            let bands = (frame.area().width as usize * 3 / 10).saturating_sub(2);
+            let spectrum = spectrum.analyze(&samples, state.sample_rate as f32, bands);
+            draw(frame, &state, scope, &spectrum, &levels)
+        })?;
+        shutdown.wait_timeout(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+// dB bar of a meter channel, from -60 dB to full scale.
+fn meter_line(name: &str, peak: f32, rms: f32, width: usize) -> Line<'static> {
+    let db = |x: f32| 20.0 * x.max(1e-6).log10();
+    let cells = |x: f32| (((db(x) + 60.0) / 60.0).clamp(0.0, 1.0) * width as f32) as usize;
+    let (rms_cells, peak_cells) = (cells(rms), cells(peak).max(cells(rms)));
+    let color = if peak >= 1.0 { Color::Red } else if db(peak) > -6.0 { Color::Yellow } else { Color::Green };
+    Line::from(vec![
+        format!("{} ", name).into(),
+        "█".repeat(rms_cells).fg(color),
+        "▒".repeat(peak_cells - rms_cells).fg(color),
+        " ".repeat(width - peak_cells).into(),
+        format!(" {:>5.1} dB", db(peak).max(-60.0)).dark_gray(),
+    ])
+}
+
+fn is_black(note: i32) -> bool { matches!(note.rem_euclid(12), 1 | 3 | 6 | 8 | 10) }
+
+// piano of the mapped range, three columns per white key. black keys sit
+// on the line above, across the edge of the white keys around them. each
+// key shows the character that plays it, held keys are highlighted.
+fn keyboard_lines(keys: &[(char, i32)], held: &[i32], width: usize) -> Vec<Line<'static>> {
+    let (Some(low), Some(high)) = (keys.iter().map(|k| k.1).min(), keys.iter().map(|k| k.1).max()) else {
+        return vec![Line::from("no keys mapped".dark_gray())];
+    };
+    let label = |note: i32| keys.iter().find(|k| k.1 == note).map_or(' ', |k| k.0);
+    let style = |note: i32| match (held.contains(&note), is_black(note)) {
+        (true, _) => Style::default().fg(Color::Black).bg(Color::Cyan),
+        (false, true) => Style::default().fg(Color::White).bg(Color::DarkGray),
+        (false, false) => Style::default(),
+    };
+    let low = low - low.rem_euclid(12);
+    let whites: Vec<i32> = (low..=high).filter(|n| !is_black(*n)).take(width / 3).collect();
+
+    let (mut black, mut white, mut names) = (Vec::new(), Vec::new(), Vec::new());
+    for &n in &whites {
+        let left = if is_black(n - 1) && n > low { Span::styled(" ", style(n - 1)) } else { Span::raw(" ") };
+        let right = if is_black(n + 1) && n < high { Span::styled(label(n + 1).to_string(), style(n + 1)) } else { Span::raw(" ") };
+        black.extend([left, Span::raw(" "), right]);
+        white.push(Span::styled(format!("│{}", label(n)), style(n)));
+        white.push(Span::styled(" ", style(n)));
+        let name = if n.rem_euclid(12) == 0 { note_name(n) } else { String::new() };
+        names.push(Span::raw(format!("{:<3}", name)).dark_gray());
+    }
+    vec![Line::from(black), Line::from(white), Line::from(names)]
+}
+
+fn draw(frame: &mut Frame, state: &InstrumentSnapshot, scope: &[f32], spectrum: &[f32], levels: &MeterLevels) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1), Constraint::Min(0), Constraint::Length(2)
+    ]).areas(frame.area());
+    let [left, side] = Layout::horizontal([
+        Constraint::Percentage(40), Constraint::Percentage(60)
+    ]).areas(body);
+    let [notes_area, keyboard_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(5)]).areas(left);
+    let [envelope_area, oscillator_area, filter_area, master_area, meter_area, analysis] = Layout::vertical([
+        Constraint::Length(6), Constraint::Length(6), Constraint::Length(4), Constraint::Length(3), Constraint::Length(4), Constraint::Min(0)
+    ]).areas(side);
+    let [scope_area, spectrum_area] = Layout::horizontal([
+        Constraint::Percentage(50), Constraint::Percentage(50)
+    ]).areas(analysis);
+
+    let mut title = vec![
+        "rsynth".bold(),
+        format!("  preset: {}", state.preset_name).into(),
+        format!("  project: {}", state.project_name).into(),
+        format!("  {:?}", state.play_mode).into(),
+        format!("  {}/{} voices", state.voices, state.polyphony.max_voices).dark_gray(),
+        if state.drum_mode { "  drums".into() } else { "".into() },
+        format!("  oct {:+}  trn {:+}  bend {:+.2}  mod {:.2}", state.octave, state.transpose, state.pitch_bend, state.mod_wheel).dark_gray(),
+    ];
+    if state.buffer_frames > 0 {
+        title.push(format!("  {} frames {:.1} ms", state.buffer_frames, state.latency * 1000.0).dark_gray());
+    }
+    title.push(format!("  {:.1} bpm", state.bpm).into());
+    if state.swing > 0.0 { title.push(format!(" swing {:.0}%", state.swing * 100.0).dark_gray()); }
+    if state.scale.enabled() { title.push(format!("  {}", state.scale.name()).into()); }
+    if let Some(mode) = state.generator { title.push(format!("  gen {:?}", mode).magenta()); }
+    if state.sustain { title.push("  sus".yellow()); }
+    if state.chord != Chord::Off { title.push(format!("  chord {}", state.chord.name()).into()); }
+    if state.following { title.push(" midi clock".cyan()); }
+    if state.playing { title.push(format!("  ▶ {}.{}", state.position.0, state.position.1).green()); }
+    if let Some(step) = state.drum_step { title.push(format!("  step {:>2}", step + 1).into()); }
+    if state.drum_mode || state.drum_step.is_some() {
+        title.push(format!("  pat {}", state.drum_pattern + 1).into());
+        if state.song_mode { title.push(" song".cyan()); }
+    }
+    match state.looper {
+        LooperState::Stopped if state.loop_layers == 0 => (),
+        LooperState::Stopped => title.push(format!("  loop {} bars ■ {}", state.loop_bars, state.loop_layers).dark_gray()),
+        LooperState::Playing => title.push(format!("  loop {} bars ▶ {}", state.loop_bars, state.loop_layers).green()),
+        _ => title.push(format!("  loop {} bars ● {}", state.loop_bars, state.loop_layers + 1).red()),
+    }
+    if state.recording { title.push("  ● REC".red().bold()); }
+    if state.capturing { title.push("  ● MIDI".red().bold()); }
+    if let Some(point) = state.split { title.push(format!("  split {}", note_name(point as i32)).into()); }
+    if let Some(seed) = state.patch_seed { title.push(format!("  seed {}", seed).dark_gray()); }
+    for (name, voices) in &state.parts { title.push(format!("  [{} {}]", name, voices).dark_gray()); }
+    frame.render_widget(Line::from(title), header);
+
+    let notes: Vec<ListItem> = state.notes.iter().map(|n| {
+        let bar = "█".repeat((n.level.clamp(0.0, 1.0) * 20.0) as usize);
+        let style = if n.released { Style::default().fg(Color::DarkGray) } else { Style::default() };
+        ListItem::new(format!("{:>4} {:>8.2} Hz {:>3.0}% {}", n.key.to_string(), n.freq, n.velocity * 100.0, bar)).style(style)
+    }).collect();
+    frame.render_widget(List::new(notes).block(Block::bordered().title(" notes ")), notes_area);
+
+    let piano = keyboard_lines(&state.keys, &state.held, keyboard_area.width.saturating_sub(2) as usize);
+    frame.render_widget(Paragraph::new(piano).block(Block::bordered().title(" keyboard ")), keyboard_area);
+
+    let e = &state.envelope;
+    let envelope = Paragraph::new(vec![
+        Line::from(format!("attack   {:.3} s", e.0)),
+        Line::from(format!("decay    {:.3} s", e.1)),
+        Line::from(format!("sustain  {:.3}", e.2)),
+        Line::from(format!("release  {:.3} s", e.3)),
+    ]).block(Block::bordered().title(" envelope "));
+    frame.render_widget(envelope, envelope_area);
+
+    let oscillator = match state.engine {
+        Engine::Oscillator => Paragraph::new(vec![
+            Line::from(format!("wave  {}", state.oscillator.otf)),
+            Line::from(format!("time  {}", state.oscillator.ttf)),
+            Line::from(format!("freq  {}", state.oscillator.wtf)),
+            Line::from(match state.oscillators.mix > 0.0 || state.oscillators.ring > 0.0 {
+                true => format!("mix {:.0}%  ring {:.0}%  {}", state.oscillators.mix * 100.0, state.oscillators.ring * 100.0, state.oscillators.extra.iter().enumerate()
+                    .map(|(i, o)| format!("{}: {} {:+.0} {:+.0}c {:.2}", i + 2, o.oscillator.otf, o.coarse.round(), o.fine, o.level))
+                    .collect::<Vec<_>>().join("  ")),
+                false => String::from("osc 2/3 off"),
+            }),
+        ]).block(Block::bordered().title(" oscillator ")),
+        Engine::Fm => Paragraph::new(format!("algorithm  {:?}", state.fm_algorithm))
+            .block(Block::bordered().title(" fm ")),
+        Engine::Sampler => Paragraph::new(format!("{} zones", state.sample_zones))
+            .block(Block::bordered().title(" sampler ")),
+    };
+    frame.render_widget(oscillator, oscillator_area);
+
+    let f = &state.filter;
+    let e = &state.filter_envelope;
+    let filter = Paragraph::new(vec![
+        Line::from(format!("{:?} {:?}  {:.0} Hz  q {:.2}  drive {:.1}  key {:.0}%", f.kind, f.mode, f.cutoff, f.resonance, f.drive, f.keytrack * 100.0)),
+        Line::from(format!("env {:+.1} oct  a {:.2}  d {:.2}  s {:.2}  r {:.2}", e.amount, e.envelope.0, e.envelope.1, e.envelope.2, e.envelope.3)),
+    ])
+        .block(Block::bordered().title(" filter "));
+    frame.render_widget(filter, filter_area);
+
+    let master = Gauge::default()
+        .block(Block::bordered().title(" master "))
+        .ratio((state.master_volume / Instrument::MAX_VOLUME).clamp(0.0, 1.0) as f64)
+        .label(format!("{:.0}%", state.master_volume * 100.0));
+    frame.render_widget(master, master_area);
+
+    let width = (meter_area.width as usize).saturating_sub(14);
+    let mut title = vec![" meter ".into()];
+    if levels.clipped { title.push(" CLIP ".white().on_red().bold()); }
+    if state.compressor_reduction < -0.1 { title.push(format!(" comp {:.1} dB ", state.compressor_reduction).cyan()); }
+    if state.limiter_reduction < -0.1 { title.push(format!(" limit {:.1} dB ", state.limiter_reduction).yellow()); }
+    let meter = Paragraph::new(vec![
+        meter_line("L", levels.peak[0], levels.rms[0], width),
+        meter_line("R", levels.peak[1], levels.rms[1], width),
+    ]).block(Block::bordered().title(Line::from(title)));
+    frame.render_widget(meter, meter_area);
+
+    let points: Vec<(f64, f64)> = scope.iter().enumerate().map(|(i, x)| (i as f64, *x as f64)).collect();
+    let scope = Canvas::default()
+        .block(Block::bordered().title(" scope "))
+        .marker(Marker::Braille)
+        .x_bounds([0.0, SCOPE_SAMPLES as f64])
+        .y_bounds([-1.0, 1.0])
+        .paint(|ctx| ctx.draw(&Points { coords: &points, color: Color::Cyan }));
+    frame.render_widget(scope, scope_area);
+
+    // dB above the floor, as bar heights.
+    let bars: Vec<u64> = spectrum.iter().map(|db| (db - Spectrum::FLOOR_DB) as u64).collect();
+    let spectrum = Sparkline::default()
+        .block(Block::bordered().title(" spectrum "))
+        .data(&bars)
+        .max(-Spectrum::FLOOR_DB as u64)
+        .style(Style::default().fg(Color::Green));
+    frame.render_widget(spectrum, spectrum_area);
+
+    let help = "F1 play mode (+shift: priority, +ctrl: voice stealing) · F2 save (+ctrl: snapshot) · F3 load (+shift: project) · F4 randomize (+shift: same seed again, +ctrl: mutate) · Alt+V locks · Alt+A mutation amount · Alt+Z undo (+shift: redo) · F5/F6 cutoff (+shift: q) · F7 filter mode (+shift: env amount, +ctrl: type) · F8 arp (+shift: mode) · F9 record (+shift: midi) · F10 delay (+shift: flanger, +ctrl: ping pong) · F11 reverb (+shift: tremolo, +ctrl: auto pan, +alt: convolution) · F12 glide (+shift: curve) · ^W wavetable · ^A/^B additive · ^N noise · ^S sub · ^Y osc 2 · ^D drums · ^P play/stop · Tab tap tempo · -/= tempo · _/+ swing · ^R pattern write · ^X pattern clear · Alt+P/R hit probability/ratchets · Alt+,/. pattern · Alt+C chain · Alt+X clear song · Alt+S song mode · Alt+L loop record/overdub · Alt+O loop play/stop · Alt+U undo layer · Alt+E clear loop · Alt+B loop bars · Alt+H chord · Alt+M/N scale/root · Alt+F scale snap/filter · Alt+G generator · Alt+W walk/markov · Alt+D density · Alt+Y new seed · Alt+K split (+alt ←→: split point) · Alt+J layer · Alt+I waveform expression · ^E engine · ^G fm algorithm · ^U unison · ^O monitor input · ^V vocoder · ^F wavefolder · ^J drive · ^Q eq · ^Z compressor · [ ] octave · { } transpose · PgUp/PgDn volume (+ctrl: preset) · ↑↓ bend · ←→ mod · ^L reset clip · ^T select param · Alt+1-4 macros (+shift: down, +ctrl: assign param) · ^K midi learn · Space sustain · Esc all notes off · ^C quit";
+    let status = match &state.prompt {
+        Some(text) => Line::from(vec![Span::raw(format!("{} · ", state.status)), format!("wave> {}_", text).bold()]),
+        None => Line::from(state.status.clone()),
+    };
+    frame.render_widget(Paragraph::new(vec![status, Line::from(help.dark_gray())]), footer);
+}
@@ -0,0 +1,191 @@
+//! Visualization module.
+//!
+//! taps on the audio output for the ui. the audio callback only writes
+//! atomics to them, so reading one while drawing never blocks the audio.
+//!
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use rustfft::{Fft, FftPlanner};
+use rustfft::num_complex::Complex;
+
+/// Ring buffer of the last samples played, mixed down to mono.
+///
+/// written by the audio callback only, any thread can read it.
+pub struct OutputTap {
+    samples: Vec<AtomicU32>,
+    written: AtomicUsize,
+}
+
+impl Default for OutputTap { fn default() -> Self { Self::new() } }
+
+impl OutputTap {
+    pub const LEN: usize = 4096;
+
+    pub fn new() -> OutputTap {
+        OutputTap { samples: (0..Self::LEN).map(|_| AtomicU32::new(0)).collect(), written: AtomicUsize::new(0) }
+    }
+
+    pub fn push(&self, left: &[f32], right: &[f32]) {
+        let mut n = self.written.load(Ordering::Relaxed);
+        for (l, r) in left.iter().zip(right) {
+            self.samples[n % Self::LEN].store(((l + r) * 0.5).to_bits(), Ordering::Relaxed);
+            n = n.wrapping_add(1);
+        }
+        self.written.store(n, Ordering::Release);
+    }
+
+    /// The last `n` samples, oldest first. a push racing the read can tear
+    /// the oldest ones, which doesn't matter for drawing.
+    pub fn latest(&self, n: usize) -> Vec<f32> {
+        let n = n.min(Self::LEN);
+        let end = self.written.load(Ordering::Acquire);
+        (0..n).map(|i| {
+            let k = end.wrapping_sub(n - i) % Self::LEN;
+            f32::from_bits(self.samples[k].load(Ordering::Relaxed))
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MeterLevels { pub peak: [f32; 2], pub rms: [f32; 2], pub clipped: bool }
+
+/// Peak and rms level of each channel, with a clip indicator that stays
+/// on until it is reset.
+///
+/// updated by the audio callback only, any thread can read it.
+#[derive(Default)]
+pub struct Meter {
+    peak: [AtomicU32; 2],
+    rms: [AtomicU32; 2],
+    clipped: AtomicBool,
+}
+
+impl Meter {
+    // peaks fall back by this many dB per second.
+    pub const PEAK_FALL: f32 = 20.0;
+    // seconds the rms is averaged over.
+    pub const RMS_TIME: f32 = 0.3;
+
+    pub fn new() -> Meter { Meter::default() }
+
+    pub fn update(&self, left: &[f32], right: &[f32], sample_rate: f32) {
+        let duration = left.len() as f32 / sample_rate.max(1.0);
+        let fall = 10f32.powf(-Self::PEAK_FALL * duration / 20.0);
+        let smoothing = 1.0 - (-duration / Self::RMS_TIME).exp();
+        for (c, buf) in [left, right].into_iter().enumerate() {
+            if buf.is_empty() { continue; }
+            let peak = buf.iter().fold(0.0f32, |a, x| a.max(x.abs()));
+            let square = buf.iter().map(|x| x * x).sum::<f32>() / buf.len() as f32;
+            if peak > 1.0 { self.clipped.store(true, Ordering::Relaxed); }
+
+            let held = f32::from_bits(self.peak[c].load(Ordering::Relaxed)) * fall;
+            self.peak[c].store(peak.max(held).to_bits(), Ordering::Relaxed);
+            let rms = f32::from_bits(self.rms[c].load(Ordering::Relaxed));
+            let mean = rms * rms + (square - rms * rms) * smoothing;
+            self.rms[c].store(mean.max(0.0).sqrt().to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn levels(&self) -> MeterLevels {
+        let read = |a: &[AtomicU32; 2]| [0, 1].map(|c| f32::from_bits(a[c].load(Ordering::Relaxed)));
+        MeterLevels { peak: read(&self.peak), rms: read(&self.rms), clipped: self.clipped.load(Ordering::Relaxed) }
+    }
+
+    pub fn reset_clip(&self) { self.clipped.store(false, Ordering::Relaxed) }
+}
+
+/// Magnitude spectrum of the tapped output, in log spaced bands.
+pub struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+}
+
+impl Spectrum {
+    pub const SIZE: usize = 2048;
+    // bands span the audible range, levels are clamped to this floor.
+    pub const MIN_FREQ: f32 = 20.0;
+    pub const MAX_FREQ: f32 = 20000.0;
+    pub const FLOOR_DB: f32 = -80.0;
+
+    pub fn new() -> Spectrum {
+        let n = Self::SIZE;
+        // hann window, so a tone doesn't smear over the whole spectrum.
+        let window = (0..n).map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / n as f32).cos()).collect();
+        Spectrum { fft: FftPlanner::new().plan_fft_forward(n), window, buffer: vec![Complex::default(); n] }
+    }
+
+    /// Levels of `bands` bands between `MIN_FREQ` and `MAX_FREQ`, in dB
+    /// relative to a full scale sine, from the last `SIZE` samples.
+    pub fn analyze(&mut self, samples: &[f32], sample_rate: f32, bands: usize) -> Vec<f32> {
+        let n = Self::SIZE;
+        let start = samples.len().saturating_sub(n);
+        self.buffer.fill(Complex::default());
+        for ((b, x), w) in self.buffer.iter_mut().zip(&samples[start..]).zip(&self.window) {
+            b.re = x * w;
+        }
+        self.fft.process(&mut self.buffer);
+
+        // a full scale sine peaks at n / 4 once windowed.
+        let magnitude: Vec<f32> = self.buffer[..n / 2].iter().map(|c| c.norm() * 4.0 / n as f32).collect();
+        let bin = |f: f32| ((f * n as f32 / sample_rate.max(1.0)) as usize).min(n / 2 - 1);
+        let max = Self::MAX_FREQ.min(sample_rate / 2.0);
+        let ratio = (max / Self::MIN_FREQ).powf(1.0 / bands.max(1) as f32);
+        (0..bands).map(|k| {
+            let lo = bin(Self::MIN_FREQ * ratio.powi(k as i32));
+            let hi = bin(Self::MIN_FREQ * ratio.powi(k as i32 + 1)).max(lo + 1);
+            let peak = magnitude[lo..hi.min(n / 2)].iter().fold(0.0f32, |a, &m| a.max(m));
+            (20.0 * peak.max(1e-9).log10()).max(Self::FLOOR_DB)
+        }).collect()
+    }
+}
+
+impl Default for Spectrum { fn default() -> Self { Self::new() } }
+
+#[cfg(test)]
+mod visual_tests {
+    use super::*;
+
+    #[test]
+    fn test_spectrum_peak() {
+        let sr = 48000.0;
+        let tone: Vec<f32> = (0..Spectrum::SIZE).map(|i| (std::f32::consts::TAU * 1000.0 * i as f32 / sr).sin()).collect();
+        let bands = Spectrum::new().analyze(&tone, sr, 30);
+        let loudest = (0..bands.len()).max_by(|&a, &b| bands[a].total_cmp(&bands[b])).unwrap();
+        // the loudest band is the one around 1 kHz, at about full scale.
+        let center = Spectrum::MIN_FREQ * (Spectrum::MAX_FREQ / Spectrum::MIN_FREQ).powf((loudest as f32 + 0.5) / 30.0);
+        assert!((center / 1000.0).log2().abs() < 0.5, "{}", center);
+        assert!(bands[loudest] > -3.0 && bands[loudest] < 1.0);
+        assert!(bands[0] < -40.0);
+    }
+
+    #[test]
+    fn test_meter_levels_and_clip() {
+        let meter = Meter::new();
+        let sine: Vec<f32> = (0..4800).map(|i| 0.5 * (std::f32::consts::TAU * i as f32 / 48.0).sin()).collect();
+        (0..20).for_each(|_| meter.update(&sine, &[0.0; 4800], 48000.0));
+        let levels = meter.levels();
+        assert!((levels.peak[0] - 0.5).abs() < 1e-3);
+        assert!((levels.rms[0] - 0.5 / 2f32.sqrt()).abs() < 0.01);
+        assert_eq!(levels.rms[1], 0.0);
+        assert!(!levels.clipped);
+
+        // the clip stays latched after the level goes back down.
+        meter.update(&[1.5], &[0.0], 48000.0);
+        meter.update(&sine, &sine, 48000.0);
+        assert!(meter.levels().clipped);
+        meter.reset_clip();
+        assert!(!meter.levels().clipped);
+    }
+
+    #[test]
+    fn test_tap_keeps_latest_samples() {
+        let tap = OutputTap::new();
+        let ramp: Vec<f32> = (0..OutputTap::LEN + 10).map(|i| i as f32).collect();
+        tap.push(&ramp, &ramp);
+        let latest = tap.latest(3);
+        let last = ramp.len() as f32;
+        assert_eq!(latest, vec![last - 3.0, last - 2.0, last - 1.0]);
+    }
+}
@@ -0,0 +1,86 @@
+//! Watch module.
+//!
+//! reloads the preset, the keymap and the oscillator script in use when
+//! their files change, so a patch can be edited in a text editor and heard
+//! as it's saved. the directories are watched rather than the files, as
+//! editors often save by writing a new file over the old one.
+//!
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::audio::instrument::Instrument;
+use crate::keymap::Keymap;
+use crate::preset;
+
+/// Starts watching the presets, the scripts and the keymap at `keymap`, if
+/// one is in use. the files are watched until the watcher is dropped.
+pub fn watch_files(instrument: Arc<Mutex<Instrument>>, keymap: Option<PathBuf>) -> notify::Result<RecommendedWatcher> {
+    let presets = preset::presets_dir();
+    std::fs::create_dir_all(&presets)?;
+    let keymap = keymap.map(|path| path.canonicalize().unwrap_or(path));
+    let watched_keymap = keymap.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let Ok(event) = event else { return };
+        if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) { return; }
+        // files are read and parsed before the instrument is locked, only
+        // to apply them, so the audio isn't held up by the disk.
+        for path in &event.paths {
+            if keymap.as_deref() == Some(path.as_path()) {
+                let loaded = Keymap::load(path);
+                let mut instrument = instrument.lock().unwrap();
+                match loaded.and_then(|k| instrument.apply_keymap(&k)) {
+                    Ok(()) => instrument.set_status(format!("reloaded keymap {}", path.display())),
+                    Err(e) => instrument.set_status(format!("{}: {}", path.display(), e)),
+                }
+                continue;
+            }
+            #[cfg(feature = "script")]
+            {
+                use crate::audio::script::{script_path, ScriptWave};
+                let script = instrument.lock().unwrap().script().filter(|s| script_path(s) == *path);
+                if let Some(script) = script {
+                    let read = ScriptWave::read(&script);
+                    let mut instrument = instrument.lock().unwrap();
+                    match read {
+                        Ok(tables) => if instrument.reload_script(ScriptWave::new(script, tables)) {
+                            instrument.set_status(format!("reloaded script {}", path.display()));
+                        },
+                        Err(e) => instrument.set_status(format!("{}: {}", path.display(), e)),
+                    }
+                    continue;
+                }
+            }
+            let name = instrument.lock().unwrap().preset_name().to_string();
+            if *path != preset::preset_path(&name) { continue; }
+            let loaded = preset::load_patch(&name).map(|mut patch| {
+                let read = preset::read_files(&mut patch);
+                (patch, read)
+            });
+            let mut instrument = instrument.lock().unwrap();
+            // another preset was loaded meanwhile.
+            if instrument.preset_name() != name { continue; }
+            match loaded {
+                Ok((patch, read)) => if instrument.reload_preset(&patch) {
+                    instrument.set_status(match read {
+                        Ok(()) => format!("reloaded preset {}", name),
+                        Err(e) => format!("reloaded preset {}, could not read {}", name, e),
+                    });
+                },
+                Err(e) => instrument.set_status(format!("preset {}: {}", name, e)),
+            }
+        }
+    })?;
+    watcher.watch(&presets, RecursiveMode::NonRecursive)?;
+    #[cfg(feature = "script")]
+    {
+        let scripts = crate::audio::script::scripts_dir();
+        std::fs::create_dir_all(&scripts)?;
+        watcher.watch(&scripts, RecursiveMode::NonRecursive)?;
+    }
+    if let Some(dir) = watched_keymap.as_deref().and_then(Path::parent) {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}